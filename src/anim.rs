@@ -129,6 +129,20 @@ quick_error! {
         Format(msg: String) {
             display("{}", msg)
         }
+        Truncated(image: u16, what: String, end: u64, file_len: u64) {
+            display(
+                "Image {} {} ends at {:#x}, past the end of file ({:#x} bytes). \
+                The file may be truncated or corrupt",
+                image, what, end, file_len,
+            )
+        }
+        TruncatedHeader(entries: u16, min_len: u64, file_len: u64) {
+            display(
+                "Anim lists {} images, which need at least {:#x} bytes, but the file is \
+                {:#x} bytes. The file may be truncated or corrupt",
+                entries, min_len, file_len,
+            )
+        }
         Io(e: io::Error) {
             from()
             display("I/O error: {}", e)
@@ -145,6 +159,9 @@ quick_error! {
         UnknownTextureFormat(magic: u32) {
             display("Unknown texture format, magic {:08x}", magic)
         }
+        NoMipLevel(level: u32) {
+            display("Texture has no mip level {}", level)
+        }
         InvalidPalette {
             display("Palette must be 256 RGB0 entries")
         }
@@ -184,6 +201,8 @@ const ANIM_MAGIC: u32 = 0x4d494e41;
 
 impl Anim {
     pub fn read<R: Read + Seek + Send + 'static>(mut r: R) -> Result<Anim, Error> {
+        let file_len = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(0))?;
         let magic = r.read_u32::<LE>()?;
         if magic != ANIM_MAGIC {
             return Err(ErrKind::Format(format!("Incorrect magic {:08x}", magic)).into());
@@ -210,11 +229,12 @@ impl Anim {
                 layer_names.push(format!("Layer {}", i));
             }
         }
-        if ty == 1 {
-            r.seek(SeekFrom::Start(0x14c + 999 * 4))?;
+        let sprites_offset = if ty == 1 {
+            r.seek(SeekFrom::Start(0x14c + 999 * 4))?
         } else {
-            r.seek(SeekFrom::Start(0x14c))?;
-        }
+            r.seek(SeekFrom::Start(0x14c))?
+        };
+        check_sprite_count(entries, sprites_offset, file_len)?;
         let mut sprites = Vec::with_capacity(entries as usize);
         for i in 0..entries {
             // Note: Each sprite is expected to follow previous one's frame data
@@ -234,6 +254,7 @@ impl Anim {
             } else {
                 let textures = read_textures(&mut r, layers as u32)
                     .map_err(|e| ErrKind::TextureReadError(i, e))?;
+                check_data_bounds(i, &textures, frame_arr_offset, frame_count, file_len)?;
                 r.seek(SeekFrom::Start(frame_arr_offset as u64))?;
                 let frames = read_frames(&mut r, frame_count)?;
                 sprites.push(SpriteType::Data(SpriteData {
@@ -546,6 +567,30 @@ impl Anim {
         read_texture(&mut *read, &texture)
     }
 
+    /// Texture data of a layer as it is stored in the file, without decoding it.
+    pub fn raw_texture(&self, sprite: usize, layer: usize) -> Result<(Texture, Vec<u8>), Error> {
+        self.raw_texture_start(sprite, layer, u32::MAX)
+    }
+
+    /// Same as `raw_texture`, but reads at most `max_len` bytes from the start of the data.
+    pub fn raw_texture_start(
+        &self,
+        sprite: usize,
+        layer: usize,
+        max_len: u32,
+    ) -> Result<(Texture, Vec<u8>), Error> {
+        let texture = self.sprite_data(sprite)
+            .ok_or_else(|| ErrKind::NoSpriteData)?
+            .textures.get(layer).and_then(|x| x.as_ref())
+            .ok_or_else(|| ErrKind::NoLayer)?
+            .clone();
+        let mut read = self.read.lock().unwrap();
+        read.seek(SeekFrom::Start(texture.offset as u64))?;
+        let mut data = vec![0; texture.size.min(max_len) as usize];
+        read.read_exact(&mut data)?;
+        Ok((texture, data))
+    }
+
     pub fn texture_formats(&self, sprite: usize) -> Vec<Result<Option<TextureFormat>, Error>> {
         let mut read = self.read.lock().unwrap();
         let mut read = &mut *read;
@@ -591,6 +636,9 @@ fn sprite_values_sd(sprites: &[SpriteType], index: usize) -> Option<SpriteValues
 }
 
 const DDS_MAGIC: u32 = 0x20534444;
+/// Bytes needed from the start of a texture for `texture_dimensions`:
+/// DDS magic, header and the DX10 extension header.
+pub const TEXTURE_HEADER_LEN: u32 = 4 + 124 + 20;
 const BMP_MAGIC: u32 = 0x20504d42;
 
 pub fn texture_format<R: Read + Seek>(mut read: R, limit: u32) -> Result<TextureFormat, Error> {
@@ -614,6 +662,97 @@ pub fn texture_format<R: Read + Seek>(mut read: R, limit: u32) -> Result<Texture
     }
 }
 
+/// Dimensions stored in the texture header.
+/// None for monochrome bitmaps, which only have dimensions in the anim texture entry.
+pub fn texture_dimensions<R: Read + Seek>(
+    mut read: R,
+    limit: u32,
+) -> Result<Option<(u32, u32)>, Error> {
+    let magic = read.read_u32::<LE>()?;
+    if magic == DDS_MAGIC {
+        read.seek(SeekFrom::Current(-4))?;
+        let mut read = read.take(limit.into());
+        let dds = Dds::read(&mut read)
+            .map_err(|e| ErrKind::Dds("reading DDS", e.to_string()))?;
+        Ok(Some((dds.get_width(), dds.get_height())))
+    } else if magic == BMP_MAGIC {
+        Ok(None)
+    } else {
+        Err(ErrKind::UnknownTextureFormat(magic).into())
+    }
+}
+
+/// Number of mip levels stored in the texture, 1 if only the full size level exists.
+pub fn texture_mip_count<R: Read + Seek>(mut read: R, limit: u32) -> Result<u32, Error> {
+    let magic = read.read_u32::<LE>()?;
+    if magic == DDS_MAGIC {
+        read.seek(SeekFrom::Current(-4))?;
+        let mut read = read.take(limit.into());
+        let dds = Dds::read(&mut read)
+            .map_err(|e| ErrKind::Dds("reading DDS", e.to_string()))?;
+        Ok(dds.get_num_mipmap_levels().max(1))
+    } else if magic == BMP_MAGIC {
+        Ok(1)
+    } else {
+        Err(ErrKind::UnknownTextureFormat(magic).into())
+    }
+}
+
+/// Size in bytes of a single mip level.
+fn dds_level_size(format: D3DFormat, width: u32, height: u32) -> Option<usize> {
+    let blocks = || (((width + 3) / 4) * ((height + 3) / 4)) as usize;
+    match format {
+        D3DFormat::DXT1 => Some(blocks() * 8),
+        D3DFormat::DXT5 => Some(blocks() * 16),
+        D3DFormat::A8B8G8R8 => Some(width as usize * height as usize * 4),
+        _ => None,
+    }
+}
+
+/// Decodes mip `level` of the texture, level 0 being the full size texture.
+pub fn read_texture_mip<R: Read + Seek>(
+    mut read: R,
+    texture: &Texture,
+    level: u32,
+) -> Result<RgbaTexture, Error> {
+    if level == 0 {
+        return read_texture(read, texture);
+    }
+    let magic = read.read_u32::<LE>()?;
+    if magic != DDS_MAGIC {
+        return Err(ErrKind::NoMipLevel(level).into());
+    }
+    read.seek(SeekFrom::Current(-4))?;
+    let mut read = read.take(texture.size.into());
+    let dds = Dds::read(&mut read)
+        .map_err(|e| ErrKind::Dds("reading DDS", e.to_string()))?;
+    if level >= dds.get_num_mipmap_levels() {
+        return Err(ErrKind::NoMipLevel(level).into());
+    }
+    let format = dds.get_d3d_format().ok_or_else(|| ErrKind::NoDxtFormat)?;
+    let data = dds.get_data(0)
+        .map_err(|e| ErrKind::Dds("getting data", e.to_string()))?;
+    let level_dimensions = |level: u32| {
+        ((texture.width as u32 >> level).max(1), (texture.height as u32 >> level).max(1))
+    };
+    let mut offset = 0;
+    for i in 0..level {
+        let (width, height) = level_dimensions(i);
+        offset += dds_level_size(format, width, height)
+            .ok_or_else(|| ErrKind::UnsupportedDdsFormat(format))?;
+    }
+    let (width, height) = level_dimensions(level);
+    let size = dds_level_size(format, width, height)
+        .ok_or_else(|| ErrKind::UnsupportedDdsFormat(format))?;
+    let data = data.get(offset..offset + size).ok_or_else(|| ErrKind::NoMipLevel(level))?;
+    let data = decode_dxt(data, width, height, format)?;
+    Ok(RgbaTexture {
+        data,
+        width,
+        height,
+    })
+}
+
 pub fn read_texture<R: Read + Seek>(
     mut read: R,
     texture: &Texture,
@@ -950,6 +1089,43 @@ fn write_textures_patched<W: Write + Seek>(
     Ok(())
 }
 
+/// Checks that the file is large enough to contain headers of `entries` images,
+/// so that a truncated file isn't read as having fewer, garbage images.
+fn check_sprite_count(entries: u16, sprites_offset: u64, file_len: u64) -> Result<(), ErrKind> {
+    // Even a ref has 0xc bytes of header
+    let min_len = sprites_offset + u64::from(entries) * 0xc;
+    if min_len > file_len {
+        return Err(ErrKind::TruncatedHeader(entries, min_len, file_len));
+    }
+    Ok(())
+}
+
+/// Checks that texture and frame data of image `image` is within the file,
+/// parsing a truncated file would otherwise succeed until the textures get decoded.
+fn check_data_bounds(
+    image: u16,
+    textures: &[Option<Texture>],
+    frame_arr_offset: u32,
+    frame_count: u16,
+    file_len: u64,
+) -> Result<(), ErrKind> {
+    // 0x10 bytes per frame, see `read_frames`
+    let frames_end = u64::from(frame_arr_offset) + u64::from(frame_count) * 0x10;
+    if frames_end > file_len {
+        return Err(ErrKind::Truncated(image, "frame data".into(), frames_end, file_len));
+    }
+    for (layer, tex) in textures.iter().enumerate() {
+        if let Some(tex) = tex {
+            let end = u64::from(tex.offset) + u64::from(tex.size);
+            if end > file_len {
+                let what = format!("layer {} texture", layer);
+                return Err(ErrKind::Truncated(image, what, end, file_len));
+            }
+        }
+    }
+    Ok(())
+}
+
 fn read_frames<R: Read>(mut r: R, count: u16) -> Result<Vec<Frame>, Error> {
     (0..count).map(|_| {
         Ok(Frame {
@@ -979,3 +1155,65 @@ fn write_frames<W: Write>(
     }
     Ok(())
 }
+
+#[test]
+fn test_check_data_bounds() {
+    let textures = vec![
+        Some(Texture {
+            offset: 0x100,
+            size: 0x80,
+            width: 8,
+            height: 8,
+        }),
+        None,
+    ];
+    assert!(check_data_bounds(0, &textures, 0x50, 4, 0x180).is_ok());
+    match check_data_bounds(3, &textures, 0x50, 4, 0x17f) {
+        Err(ErrKind::Truncated(3, _, 0x180, 0x17f)) => (),
+        x => panic!("Unexpected result {:?}", x),
+    }
+    match check_data_bounds(3, &textures, 0x170, 2, 0x180) {
+        Err(ErrKind::Truncated(3, _, 0x190, 0x180)) => (),
+        x => panic!("Unexpected result {:?}", x),
+    }
+}
+
+#[test]
+fn test_read_texture_mip() {
+    let mut dds = Dds::new_d3d(ddsfile::NewD3dParams {
+        height: 4,
+        width: 4,
+        depth: None,
+        format: D3DFormat::A8B8G8R8,
+        mipmap_levels: Some(2),
+        caps2: None,
+    }).unwrap();
+    dds.data = vec![1; 4 * 4 * 4];
+    dds.data.extend_from_slice(&[2; 2 * 2 * 4]);
+    let mut data = Vec::new();
+    dds.write(&mut data).unwrap();
+    let texture = Texture {
+        offset: 0,
+        size: data.len() as u32,
+        width: 4,
+        height: 4,
+    };
+    assert_eq!(texture_mip_count(io::Cursor::new(&data), texture.size).unwrap(), 2);
+    let level0 = read_texture_mip(io::Cursor::new(&data), &texture, 0).unwrap();
+    assert_eq!((level0.width, level0.height), (4, 4));
+    assert!(level0.data.iter().all(|&x| x == 1));
+    let level1 = read_texture_mip(io::Cursor::new(&data), &texture, 1).unwrap();
+    assert_eq!((level1.width, level1.height), (2, 2));
+    assert_eq!(level1.data, vec![2; 2 * 2 * 4]);
+    assert!(read_texture_mip(io::Cursor::new(&data), &texture, 2).is_err());
+}
+
+#[test]
+fn test_check_sprite_count() {
+    assert!(check_sprite_count(3, 0x14c, 0x14c + 3 * 0xc).is_ok());
+    assert!(check_sprite_count(0, 0x14c, 0x14c).is_ok());
+    match check_sprite_count(999, 0x10e8, 0x2000) {
+        Err(ErrKind::TruncatedHeader(999, 0x3fbc, 0x2000)) => (),
+        x => panic!("Unexpected result {:?}", x),
+    }
+}
@@ -49,13 +49,29 @@ struct TexCoords {
     y: u32,
 }
 
+/// Area taken by one unique frame graphic in the packed atlas, before scaling.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PackedRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
 pub struct Layout {
     // One hashmap for each layer, equivalent frame data
     frames: Vec<HashMap<Rc<Frame>, Vec<(usize, (i32, i32))>>>,
     // layer id -> frame id
     frame_lookup: Vec<Vec<Option<(Rc<Frame>, i32, i32)>>>,
+    max_width: u32,
+    padding: u32,
+    alignment: u32,
 }
 
+/// Alignment of frame positions and sizes in the packed atlas, unless changed
+/// with `Layout::set_alignment`.
+pub const DEFAULT_ALIGNMENT: u32 = 8;
+
 pub struct LayoutResult {
     /// Same graphics can be used for multiple frames (with potentially different offsets)
     /// Contains unique graphics (in no specific order),
@@ -70,16 +86,72 @@ pub struct LayoutResult {
 }
 
 impl LayoutResult {
+    /// Makes the encoded textures `width` x `height` (before dividing by `encode` scale)
+    /// instead of the size of packed layout. Frames stay at the top left corner.
+    ///
+    /// `scale` is the largest scale the result will be encoded with, the texture
+    /// has to stay a multiple of 4 after dividing by it.
+    pub fn set_texture_size(&mut self, width: u32, height: u32, scale: u32) -> Result<(), Error> {
+        if width == 0 || height == 0 || width % 4 != 0 || height % 4 != 0 {
+            return Err(anyhow!("Texture dimensions must be nonzero multiples of 4"));
+        }
+        let (scaled_width, scaled_height) = (width / scale, height / scale);
+        if width % scale != 0 || height % scale != 0 ||
+            scaled_width % 4 != 0 || scaled_height % 4 != 0
+        {
+            return Err(anyhow!(
+                "Texture dimensions divided by {} must be multiples of 4, {}x{} is not",
+                scale, width, height,
+            ));
+        }
+        if self.texture_width > width || self.texture_height > height {
+            return Err(anyhow!(
+                "Frames need a {}x{} texture, which doesn't fit in {}x{}",
+                self.texture_width, self.texture_height, width, height,
+            ));
+        }
+        self.texture_width = width;
+        self.texture_height = height;
+        Ok(())
+    }
+
+    /// Dimensions of the textures `encode` creates with `scale`, after rounding them
+    /// to multiples of 4.
+    pub fn texture_size(&self, scale: u32) -> (u32, u32) {
+        let tex_width = (self.texture_width / scale).max(1);
+        let tex_height = (self.texture_height / scale).max(1);
+        (((tex_width - 1) | 3) + 1, ((tex_height - 1) | 3) + 1)
+    }
+
+    /// Rectangles of the unique frame graphics in the atlas (in no specific order).
+    pub fn packed_rects(&self) -> Vec<PackedRect> {
+        self.frames.iter().map(|(_, frame, coords)| PackedRect {
+            x: coords.x,
+            y: coords.y,
+            width: frame.width,
+            height: frame.height,
+        }).collect()
+    }
+
+    /// Fraction of the atlas area that is covered by frame graphics.
+    pub fn utilization(&self) -> f32 {
+        let atlas_area = self.texture_width as u64 * self.texture_height as u64;
+        if atlas_area == 0 {
+            return 0.0;
+        }
+        let used_area = self.packed_rects().iter()
+            .map(|x| x.width as u64 * x.height as u64)
+            .sum::<u64>();
+        used_area as f32 / atlas_area as f32
+    }
+
     pub fn encode(
         &self,
         first_layer: usize,
         layers: &[Option<anim::TextureFormat>],
         scale: u32,
     ) -> anim::TexChanges {
-        let tex_width = self.texture_width / scale;
-        let tex_height = self.texture_height / scale;
-        let tex_width = ((tex_width - 1) | 3) + 1;
-        let tex_height = ((tex_height - 1) | 3) + 1;
+        let (tex_width, tex_height) = self.texture_size(scale);
         let textures = layers.iter().enumerate().map(|(layer, x)| {
             x.map(|format| {
                 let layer = first_layer + layer;
@@ -139,7 +211,46 @@ impl Layout {
         Layout {
             frames: Vec::new(),
             frame_lookup: Vec::new(),
+            max_width: u32::MAX,
+            padding: 0,
+            alignment: DEFAULT_ALIGNMENT,
+        }
+    }
+
+    /// Makes `layout()` keep the packed texture at most `width` pixels wide, placing
+    /// frames on new rows instead. A frame that is wider than `width` by itself
+    /// still gets placed, making the texture wider.
+    ///
+    /// Unlimited by default.
+    pub fn set_max_width(&mut self, width: u32) {
+        self.max_width = width;
+    }
+
+    /// Makes `layout()` leave at least `padding` empty pixels between frames.
+    ///
+    /// 0 by default.
+    pub fn set_padding(&mut self, padding: u32) {
+        self.padding = padding;
+    }
+
+    /// Makes `layout()` align frame positions and sizes to `alignment` pixels,
+    /// which must be a power of two.
+    ///
+    /// `scale` is the largest scale that `encode` will be called with; the alignment
+    /// must be at least `4 * scale` so that frames don't share DXT blocks.
+    ///
+    /// `DEFAULT_ALIGNMENT` by default.
+    pub fn set_alignment(&mut self, alignment: u32, scale: u32) -> Result<(), Error> {
+        if !alignment.is_power_of_two() {
+            return Err(anyhow!("Alignment {} is not a power of two", alignment));
         }
+        if alignment < 4 * scale {
+            return Err(anyhow!(
+                "Alignment {} is too small, it must be at least {}", alignment, 4 * scale,
+            ));
+        }
+        self.alignment = alignment;
+        Ok(())
     }
 
     /// Data must be RGBA encoded
@@ -172,7 +283,76 @@ impl Layout {
         lookup[frame] = Some((frame_rc, coords.x_offset, coords.y_offset));
     }
 
-    pub fn layout(mut self) -> LayoutResult {
+    pub fn layout(self) -> LayoutResult {
+        let max_width = self.max_width;
+        let padding = self.padding;
+        let alignment = self.alignment;
+        let (mut layout_order, frame_count) = self.frame_groups();
+        // Place tallest frames first
+        layout_order.sort_by(|a, b| {
+            match (a.1.height, a.1.width).cmp(&(b.1.height, b.1.width)) {
+                std::cmp::Ordering::Equal => {
+                    // Use lowest found frame to break the tie
+                    let a_frame = a.0.iter().map(|x| x.0).min().unwrap_or(0);
+                    let b_frame = b.0.iter().map(|x| x.0).min().unwrap_or(0);
+                    a_frame.cmp(&b_frame)
+                }
+                x => x,
+            }
+        });
+
+        layout_frames(layout_order, alignment, max_width, padding, frame_count)
+    }
+
+    /// Places frames at texture positions of `existing` frames instead of packing
+    /// them to a new atlas. `texture_width`/`texture_height` and the frame coordinates
+    /// are in same units as the added frames.
+    ///
+    /// Errors unless every frame has the same size as the existing one.
+    pub fn layout_preserving(
+        self,
+        existing: &[anim::Frame],
+        texture_width: u32,
+        texture_height: u32,
+    ) -> Result<LayoutResult, Error> {
+        let (groups, frame_count) = self.frame_groups();
+        if frame_count > existing.len() {
+            return Err(anyhow!(
+                "Importing {} frames, but the sprite has {} frames", frame_count, existing.len(),
+            ));
+        }
+        let mut frames = Vec::with_capacity(existing.len());
+        for (uses, layer_frames) in groups {
+            for (f, offset) in uses {
+                let old = &existing[f];
+                let old_size = (u32::from(old.width), u32::from(old.height));
+                if (layer_frames.width, layer_frames.height) != old_size {
+                    return Err(anyhow!(
+                        "Frame {} is {}x{}, but the existing frame is {}x{}",
+                        f, layer_frames.width, layer_frames.height, old_size.0, old_size.1,
+                    ));
+                }
+                let coords = TexCoords {
+                    x: old.tex_x.into(),
+                    y: old.tex_y.into(),
+                };
+                if coords.x + old_size.0 > texture_width || coords.y + old_size.1 > texture_height {
+                    return Err(anyhow!("Frame {} is outside the existing texture", f));
+                }
+                frames.push((vec![(f, offset)], layer_frames.clone(), coords));
+            }
+        }
+        Ok(LayoutResult {
+            frames,
+            texture_width,
+            texture_height,
+            frame_count: existing.len(),
+        })
+    }
+
+    /// Groups frames that have same graphics on every layer.
+    /// Returns the groups and frame count.
+    fn frame_groups(mut self) -> (Vec<(Vec<(usize, FrameOffset)>, LayerFrames)>, usize) {
         let mut final_map: HashMap<LayerFrames, Vec<(usize, FrameOffset)>> = HashMap::new();
         let frame_count = self.frame_lookup.iter().map(|x| x.len()).max().unwrap_or(0);
         let dummy_frame = Rc::new(Frame {
@@ -240,21 +420,8 @@ impl Layout {
             assert!(f.is_empty());
         }
 
-        let mut layout_order = final_map.into_iter().map(|(k, v)| (v, k)).collect::<Vec<_>>();
-        // Place tallest frames first
-        layout_order.sort_by(|a, b| {
-            match (a.1.height, a.1.width).cmp(&(b.1.height, b.1.width)) {
-                std::cmp::Ordering::Equal => {
-                    // Use lowest found frame to break the tie
-                    let a_frame = a.0.iter().map(|x| x.0).min().unwrap_or(0);
-                    let b_frame = b.0.iter().map(|x| x.0).min().unwrap_or(0);
-                    a_frame.cmp(&b_frame)
-                }
-                x => x,
-            }
-        });
-
-        layout_frames(layout_order, 8, frame_count)
+        let groups = final_map.into_iter().map(|(k, v)| (v, k)).collect::<Vec<_>>();
+        (groups, frame_count)
     }
 
     pub fn write_grp(&self, width: u16, height: u16) -> Result<Vec<u8>, Error> {
@@ -286,20 +453,23 @@ impl Layout {
 fn layout_frames(
     mut frames: Vec<(Vec<(usize, FrameOffset)>, LayerFrames)>,
     alignment: u32,
+    max_width: u32,
+    padding: u32,
     frame_count: usize,
 ) -> LayoutResult {
     fn fits(
         placed: &[(Vec<(usize, FrameOffset)>, LayerFrames, TexCoords)],
         pos: &TexCoords,
         dimensions: &(u32, u32),
+        padding: u32,
     ) -> bool {
         let left = pos.x;
         let right = pos.x + dimensions.0;
         let top = pos.y;
         let bottom = pos.y + dimensions.1;
         !placed.iter().any(|&(_, ref f, ref tex)| {
-            (left < tex.x + f.width && right > tex.x) &&
-                (top < tex.y + f.height && bottom > tex.y)
+            (left < tex.x + f.width + padding && right + padding > tex.x) &&
+                (top < tex.y + f.height + padding && bottom + padding > tex.y)
         })
     }
 
@@ -319,7 +489,7 @@ fn layout_frames(
         let mut best_on_bottom: Option<(TexCoords, u32, u32, u32)> = None;
         for placed in &result {
             let right_pos = TexCoords {
-                x: round_to_alignment(placed.2.x + placed.1.width),
+                x: round_to_alignment(placed.2.x + placed.1.width + padding),
                 y: placed.2.y,
             };
             let width_add = (right_pos.x + width).saturating_sub(out_width);
@@ -336,7 +506,7 @@ fn layout_frames(
             };
             let bottom_pos = TexCoords {
                 x: placed.2.x,
-                y: round_to_alignment(placed.2.y + placed.1.height),
+                y: round_to_alignment(placed.2.y + placed.1.height + padding),
             };
             let height_add = (bottom_pos.y + height).saturating_sub(out_height);
             let bottom_width_add = (bottom_pos.x + width).saturating_sub(out_width);
@@ -353,38 +523,46 @@ fn layout_frames(
                 }
                 None => true,
             };
-            if right_better {
-                if fits(&result, &right_pos, &(width, height))  {
+            if right_better && right_pos.x + width <= max_width {
+                if fits(&result, &right_pos, &(width, height), padding)  {
                     best_on_right = Some((right_pos, right_squared, width_add));
                 }
             }
-            if bottom_better {
-                if fits(&result, &bottom_pos, &(width, height))  {
+            if bottom_better && bottom_pos.x + width <= max_width {
+                if fits(&result, &bottom_pos, &(width, height), padding)  {
                     best_on_bottom =
                         Some((bottom_pos, bottom_squared, height_add, bottom_width_add));
                 }
             }
         }
-        let coords = if let (Some(right), Some(bottom)) = (best_on_right, best_on_bottom) {
-            let take_right = {
-                if out_width + right.2 <= 256 {
-                    true
-                } else if out_width + right.2 <= out_width.next_power_of_two() {
-                    true
+        let coords = match (best_on_right, best_on_bottom) {
+            (Some(right), Some(bottom)) => {
+                let take_right = {
+                    if out_width + right.2 <= 256 {
+                        true
+                    } else if out_width + right.2 <= out_width.next_power_of_two() {
+                        true
+                    } else {
+                        out_width + right.2 < out_height + bottom.2
+                    }
+                };
+                if take_right {
+                    right.0
                 } else {
-                    out_width + right.2 < out_height + bottom.2
+                    bottom.0
                 }
-            };
-            if take_right {
-                right.0
-            } else {
-                bottom.0
             }
-        } else {
-            TexCoords {
+            (Some(right), None) => right.0,
+            (None, Some(bottom)) => bottom.0,
+            (None, None) if result.is_empty() => TexCoords {
                 x: 0,
                 y: 0,
-            }
+            },
+            // Only possible with `max_width`; start a new row below everything.
+            (None, None) => TexCoords {
+                x: 0,
+                y: round_to_alignment(out_height + padding),
+            },
         };
         out_width = out_width.max(coords.x + width);
         out_height = out_height.max(coords.y + height);
@@ -411,10 +589,11 @@ fn encode_monochrome(
     let mut out = vec![0; (width * height) as usize + 4];
     (&mut out[..]).write_u32::<LE>(BMP_MAGIC).unwrap();
     for (_, f, place) in frames {
-        let &(ref frame, ref offset) = &f.frames[layer];
-        if frame.data.is_empty() {
-            continue;
-        }
+        // Layers that no frame had graphics for aren't in `frames`.
+        let (frame, offset) = match f.frames.get(layer) {
+            Some((frame, offset)) if !frame.data.is_empty() => (frame, offset),
+            _ => continue,
+        };
         // + 4 for BMP_MAGIC
         let mut out_pos = (
             (place.y + offset.1 as u32) / scale * width + (place.x + offset.0 as u32) / scale
@@ -440,10 +619,11 @@ fn encode_dds_rgba(
 ) -> Vec<u8> {
     let mut out = vec![0; 4 * (width * height) as usize];
     for (_, f, place) in frames {
-        let &(ref frame, ref offset) = &f.frames[layer];
-        if frame.data.is_empty() {
-            continue;
-        }
+        // Layers that no frame had graphics for aren't in `frames`.
+        let (frame, offset) = match f.frames.get(layer) {
+            Some((frame, offset)) if !frame.data.is_empty() => (frame, offset),
+            _ => continue,
+        };
         let mut out_pos = 4 * (
             (place.y + offset.1 as u32) / scale * width + (place.x + offset.0 as u32) / scale
         ) as usize;
@@ -483,10 +663,11 @@ fn encode_dxt5(
     let mut tmp_buf = Vec::new();
     let mut in_buf = Vec::new();
     for (_, f, place) in frames {
-        let &(ref frame, ref offset) = &f.frames[layer];
-        if frame.data.is_empty() {
-            continue;
-        }
+        // Layers that no frame had graphics for aren't in `frames`.
+        let (frame, offset) = match f.frames.get(layer) {
+            Some((frame, offset)) if !frame.data.is_empty() => (frame, offset),
+            _ => continue,
+        };
 
         let place_x = (place.x + offset.0 as u32) / scale;
         let place_y = (place.y + offset.1 as u32) / scale;
@@ -580,10 +761,11 @@ fn encode_dxt1(
     let mut in_buf = Vec::new();
     let mut tmp_buf = Vec::new();
     for (_, f, place) in frames {
-        let &(ref frame, ref offset) = &f.frames[layer];
-        if frame.data.is_empty() {
-            continue;
-        }
+        // Layers that no frame had graphics for aren't in `frames`.
+        let (frame, offset) = match f.frames.get(layer) {
+            Some((frame, offset)) if !frame.data.is_empty() => (frame, offset),
+            _ => continue,
+        };
 
         let place_x = (place.x + offset.0 as u32) / scale;
         let place_y = (place.y + offset.1 as u32) / scale;
@@ -884,6 +1066,147 @@ mod test {
         assert_eq!(decoded.data, eq_data);
     }
 
+    #[test]
+    fn forced_texture_size() {
+        let mut layout = Layout::new();
+        let coords = FrameCoords {
+            x_offset: 0,
+            y_offset: 0,
+            width: 8,
+            height: 16,
+        };
+        layout.add_frame(0, 0, vec![255; 8 * 16 * 4], coords);
+        let mut result = layout.layout();
+        assert!(result.set_texture_size(4, 64, 1).is_err());
+        assert!(result.set_texture_size(62, 64, 1).is_err());
+        // 36 / 2 isn't a multiple of 4
+        assert!(result.set_texture_size(36, 64, 2).is_err());
+        result.set_texture_size(64, 32, 2).unwrap();
+        let hd = result.encode(0, &[Some(anim::TextureFormat::Dxt1)], 1);
+        let hd2 = result.encode(0, &[Some(anim::TextureFormat::Dxt1)], 2);
+        let (tex, _) = hd.textures[0].as_ref().unwrap();
+        assert_eq!((tex.width, tex.height), (64, 32));
+        let (tex, _) = hd2.textures[0].as_ref().unwrap();
+        assert_eq!((tex.width, tex.height), (32, 16));
+        assert_eq!((hd.frames[0].tex_x, hd.frames[0].tex_y), (0, 0));
+    }
+
+    #[test]
+    fn utilization() {
+        let mut layout = Layout::new();
+        let coords = FrameCoords {
+            x_offset: 0,
+            y_offset: 0,
+            width: 8,
+            height: 16,
+        };
+        layout.add_frame(0, 0, vec![255; 8 * 16 * 4], coords);
+        let mut result = layout.layout();
+        let rects = result.packed_rects();
+        assert_eq!(rects, vec![PackedRect { x: 0, y: 0, width: 8, height: 16 }]);
+        assert_eq!(result.utilization(), 1.0);
+        result.set_texture_size(16, 32, 1).unwrap();
+        assert_eq!(result.utilization(), 0.25);
+    }
+
+    #[test]
+    fn custom_alignment() {
+        let mut layout = Layout::new();
+        assert!(layout.set_alignment(0, 1).is_err());
+        assert!(layout.set_alignment(12, 1).is_err());
+        // Frames would share DXT blocks
+        assert!(layout.set_alignment(2, 1).is_err());
+        assert!(layout.set_alignment(4, 2).is_err());
+        layout.set_alignment(4, 1).unwrap();
+        layout.set_alignment(32, 2).unwrap();
+        for i in 0..3 {
+            let coords = FrameCoords {
+                x_offset: 0,
+                y_offset: 0,
+                width: 10 + i as u32,
+                height: 6,
+            };
+            let data = vec![255; (coords.width * coords.height * 4) as usize];
+            layout.add_frame(0, i, data, coords);
+        }
+        let changes = layout.layout().encode(0, &[Some(anim::TextureFormat::Rgba)], 1);
+        for frame in &changes.frames {
+            assert_eq!(frame.tex_x % 32, 0);
+            assert_eq!(frame.tex_y % 32, 0);
+        }
+    }
+
+    #[test]
+    fn max_width_and_padding() {
+        let mut layout = Layout::new();
+        layout.set_max_width(64);
+        layout.set_padding(2);
+        for i in 0..12 {
+            let coords = FrameCoords {
+                x_offset: 0,
+                y_offset: 0,
+                width: 14 + i as u32,
+                height: 16,
+            };
+            let data = vec![255; (coords.width * coords.height * 4) as usize];
+            layout.add_frame(0, i, data, coords);
+        }
+        let result = layout.layout();
+        assert!(result.texture_width <= 64);
+        let changes = result.encode(0, &[Some(anim::TextureFormat::Rgba)], 1);
+        let (tex, _) = changes.textures[0].as_ref().unwrap();
+        assert!(tex.width <= 64);
+        let frames = &changes.frames;
+        for (i, a) in frames.iter().enumerate() {
+            assert!(u32::from(a.tex_x) + u32::from(a.width) <= 64);
+            for b in &frames[i + 1..] {
+                let (a_x, a_y) = (u32::from(a.tex_x), u32::from(a.tex_y));
+                let (b_x, b_y) = (u32::from(b.tex_x), u32::from(b.tex_y));
+                let apart_x = a_x + u32::from(a.width) + 2 <= b_x ||
+                    b_x + u32::from(b.width) + 2 <= a_x;
+                let apart_y = a_y + u32::from(a.height) + 2 <= b_y ||
+                    b_y + u32::from(b.height) + 2 <= a_y;
+                assert!(apart_x || apart_y);
+            }
+        }
+    }
+
+    #[test]
+    fn preserved_layout() {
+        let existing = [(16, 0, 8, 4), (0, 0, 8, 8)].iter().map(|&(x, y, w, h)| anim::Frame {
+            tex_x: x,
+            tex_y: y,
+            x_off: 0,
+            y_off: 0,
+            width: w,
+            height: h,
+            unknown: 0,
+        }).collect::<Vec<_>>();
+        let make_layout = |sizes: &[(u32, u32)]| {
+            let mut layout = Layout::new();
+            for (i, &(width, height)) in sizes.iter().enumerate() {
+                let coords = FrameCoords {
+                    x_offset: 2,
+                    y_offset: 3,
+                    width,
+                    height,
+                };
+                layout.add_frame(0, i, vec![255; (width * height * 4) as usize], coords);
+            }
+            layout
+        };
+        let result = make_layout(&[(8, 4), (8, 8)]).layout_preserving(&existing, 32, 8).unwrap();
+        let changes = result.encode(0, &[Some(anim::TextureFormat::Rgba)], 1);
+        let (tex, _) = changes.textures[0].as_ref().unwrap();
+        assert_eq!((tex.width, tex.height), (32, 8));
+        assert_eq!((changes.frames[0].tex_x, changes.frames[0].tex_y), (16, 0));
+        assert_eq!((changes.frames[1].tex_x, changes.frames[1].tex_y), (0, 0));
+        assert_eq!((changes.frames[1].x_off, changes.frames[1].y_off), (2, 3));
+
+        assert!(make_layout(&[(8, 4), (4, 8)]).layout_preserving(&existing, 32, 8).is_err());
+        assert!(make_layout(&[(8, 4), (8, 8)]).layout_preserving(&existing, 16, 8).is_err());
+    }
+
     #[test]
     fn hd_hd2_offsets_tall() {
         let mut layout = Layout::new();
@@ -0,0 +1,195 @@
+//! Command line mode which doesn't create any windows (or GL contexts),
+//! so it can be used from build scripts.
+//!
+//! `animosity export <file> <sprite-id> <out-dir> [--type sd|hd|hd2] [--sheet]`
+//! `animosity metadata <file> <out.csv>`
+//! `animosity import-manifest <file> <manifest.json> <out-dir>`
+
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::files::Files;
+use crate::frame_export;
+use crate::frame_import;
+use crate::{Error, SpriteType};
+
+static USAGE: &str = "\
+Usage:
+    animosity <file>
+    animosity export <file> <sprite-id> <out-dir> [--type sd|hd|hd2] [--sheet]
+    animosity metadata <file> <out.csv>
+    animosity import-manifest <file> <manifest.json> <out-dir>";
+
+struct ExportArgs {
+    file: PathBuf,
+    sprite: usize,
+    out_dir: PathBuf,
+    ty: SpriteType,
+    sheet: bool,
+}
+
+/// Returns exit code if the arguments contained a command line subcommand,
+/// None if the GUI should be started instead.
+pub fn run(args: &[OsString]) -> Option<i32> {
+    let command = args.get(0)?.to_str()?;
+    let result = match command {
+        "export" => parse_export_args(&args[1..]).and_then(|args| export(&args)),
+        "metadata" => match &args[1..] {
+            [file, out] => metadata(file.as_ref(), out.as_ref()),
+            _ => Err(anyhow!("Expected 2 arguments for metadata\n{}", USAGE)),
+        },
+        "import-manifest" => match &args[1..] {
+            [file, manifest, out_dir] => {
+                import_manifest(file.as_ref(), manifest.as_ref(), out_dir.as_ref())
+            }
+            _ => Err(anyhow!("Expected 3 arguments for import-manifest\n{}", USAGE)),
+        },
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return Some(0);
+        }
+        _ => return None,
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            Some(1)
+        }
+    }
+}
+
+fn parse_sprite_type(text: &str) -> Result<SpriteType, Error> {
+    match &*text.to_ascii_lowercase() {
+        "sd" => Ok(SpriteType::Sd),
+        "hd" => Ok(SpriteType::Hd),
+        "hd2" => Ok(SpriteType::Hd2),
+        _ => Err(anyhow!("Invalid sprite type '{}', expected sd, hd or hd2", text)),
+    }
+}
+
+fn parse_export_args(args: &[OsString]) -> Result<ExportArgs, Error> {
+    let mut positional = Vec::new();
+    let mut ty = SpriteType::Hd;
+    let mut sheet = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--type") => {
+                let value = args.next()
+                    .and_then(|x| x.to_str())
+                    .ok_or_else(|| anyhow!("--type requires a value\n{}", USAGE))?;
+                ty = parse_sprite_type(value)?;
+            }
+            Some("--sheet") => sheet = true,
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 3 {
+        return Err(anyhow!("Expected 3 arguments for export\n{}", USAGE));
+    }
+    let sprite = positional[1].to_str()
+        .and_then(|x| x.parse::<usize>().ok())
+        .ok_or_else(|| anyhow!("Invalid sprite id {}", positional[1].to_string_lossy()))?;
+    Ok(ExportArgs {
+        file: positional[0].into(),
+        sprite,
+        out_dir: positional[2].into(),
+        ty,
+        sheet,
+    })
+}
+
+fn export(args: &ExportArgs) -> Result<(), Error> {
+    let (mut files, _) = Files::init(&args.file)
+        .with_context(|| format!("Unable to open {}", args.file.display()))?;
+    let ty = args.ty;
+    let file = files.file(args.sprite, ty)?
+        .ok_or_else(|| anyhow!("No {:?} file for sprite {}", ty, args.sprite))?;
+    std::fs::create_dir_all(&args.out_dir)
+        .with_context(|| format!("Unable to create {}", args.out_dir.display()))?;
+    frame_export::check_dir_writable(&args.out_dir)?;
+    let last_percent = std::cell::Cell::new(!0u32);
+    let report_progress = |step: f32| {
+        let percent = (step * 100.0) as u32;
+        if percent != last_percent.get() {
+            last_percent.set(percent);
+            print!("\r{}%", percent);
+            let _ = std::io::stdout().flush();
+        }
+    };
+    let warnings = frame_export::export_with_defaults(
+        &file,
+        args.sprite,
+        ty,
+        &args.out_dir,
+        args.sheet,
+        report_progress,
+    )?;
+    for warning in warnings {
+        eprintln!("\rWARNING: {}", warning);
+    }
+    println!("\rExported {:?} image {} to {}", ty, args.sprite, args.out_dir.display());
+    Ok(())
+}
+
+fn metadata(file: &Path, out: &Path) -> Result<(), Error> {
+    let (mut files, _) = Files::init(file)
+        .with_context(|| format!("Unable to open {}", file.display()))?;
+    let out_file = std::fs::File::create(out)
+        .with_context(|| format!("Unable to create {}", out.display()))?;
+    let mut out_file = std::io::BufWriter::new(out_file);
+    files.write_metadata_csv(&mut out_file)?;
+    out_file.flush()?;
+    println!("Wrote metadata of {} sprites to {}", files.sprites().len(), out.display());
+    Ok(())
+}
+
+/// Imports every entry of the manifest and saves the successfully imported ones
+/// in a single save to `out_dir`, keeping the files in `file`'s tree unchanged.
+fn import_manifest(file: &Path, manifest: &Path, out_dir: &Path) -> Result<(), Error> {
+    let (mut files, _) = Files::init(file)
+        .with_context(|| format!("Unable to open {}", file.display()))?;
+    let results = frame_import::import_from_manifest(&mut files, manifest)?;
+    let mut failed = 0;
+    for entry in &results {
+        match entry.result {
+            Ok(()) => println!("Imported sprite {}", entry.sprite),
+            Err(ref e) => {
+                eprintln!("Error: {:?}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed != results.len() {
+        files.save_to_dir(out_dir)
+            .with_context(|| format!("Unable to save to {}", out_dir.display()))?;
+        println!("Saved the imported sprites to {}", out_dir.display());
+    }
+    if failed != 0 {
+        return Err(anyhow!("{} of {} manifest entries failed", failed, results.len()));
+    }
+    println!("Imported {} sprites", results.len());
+    Ok(())
+}
+
+#[test]
+fn test_parse_export_args() {
+    let args = |x: &[&str]| x.iter().map(OsString::from).collect::<Vec<_>>();
+    let result = parse_export_args(&args(&["main_000.anim", "12", "out", "--type", "HD2"]))
+        .unwrap();
+    assert_eq!(result.sprite, 12);
+    assert_eq!(result.ty, SpriteType::Hd2);
+    assert_eq!(result.out_dir, PathBuf::from("out"));
+    assert!(!result.sheet);
+    let result = parse_export_args(&args(&["--sheet", "mainsd.anim", "0", "out"])).unwrap();
+    assert_eq!(result.ty, SpriteType::Hd);
+    assert!(result.sheet);
+    assert!(parse_export_args(&args(&["mainsd.anim", "0"])).is_err());
+    assert!(parse_export_args(&args(&["mainsd.anim", "x", "out"])).is_err());
+    assert!(parse_export_args(&args(&["mainsd.anim", "0", "out", "--type", "uhd"])).is_err());
+    assert!(run(&args(&["mainsd.anim"])).is_none());
+}
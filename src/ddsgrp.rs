@@ -5,6 +5,11 @@ use byteorder::{ReadBytesExt, LE, WriteBytesExt};
 
 use crate::anim::{self, ErrKind, Error};
 
+/// A .dds.grp file.
+///
+/// Only the frame headers are parsed when the file is opened; frame data is
+/// read on demand by seeking to `Frame::offset`, so large grps don't have to
+/// be kept in memory.
 #[allow(dead_code)]
 pub struct DdsGrp {
     pub frame_count: u16,
@@ -42,6 +47,8 @@ impl Frame {
 
 impl DdsGrp {
     pub fn read<R: Read + Seek + Send + 'static>(mut r: R) -> Result<DdsGrp, Error> {
+        let file_len = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(0))?;
         let _size = r.read_u32::<LE>()?;
         let frame_count = r.read_u16::<LE>()?;
         let flags = r.read_u8()?;
@@ -66,6 +73,13 @@ impl DdsGrp {
             r.read_exact(&mut palette[..])?;
             let mut offset = 0x40c;
             let frame_size = width as u32 * height as u32;
+            let data_end = u64::from(offset) + u64::from(frame_size) * u64::from(frame_count);
+            if data_end > file_len {
+                return Err(ErrKind::Format(format!(
+                    "Frame data would end at 0x{:x}, but the file is only 0x{:x} bytes",
+                    data_end, file_len,
+                )).into());
+            }
             for _ in 0..frame_count {
                 frames.push(Frame {
                     unknown: 0,
@@ -77,12 +91,19 @@ impl DdsGrp {
                 offset += frame_size;
             }
         } else {
-            for _ in 0..frame_count {
+            for i in 0..frame_count {
                 let unknown = r.read_u32::<LE>()?;
                 let width = r.read_u16::<LE>()?;
                 let height = r.read_u16::<LE>()?;
                 let size = r.read_u32::<LE>()?;
                 let offset = r.seek(SeekFrom::Current(0))? as u32;
+                // Skip over the data, it'll be read once the frame is needed.
+                if u64::from(offset) + u64::from(size) > file_len {
+                    return Err(ErrKind::Format(format!(
+                        "Frame {} data (0x{:x} bytes at 0x{:x}) extends past end of file",
+                        i, size, offset,
+                    )).into());
+                }
                 r.seek(SeekFrom::Current(size as i64))?;
                 frames.push(Frame {
                     unknown,
@@ -135,10 +156,29 @@ impl DdsGrp {
         }
     }
 
+    /// Frame data as it is stored in the file, either DDS or palette indices.
+    pub fn raw_frame(&self, frame: usize) -> Result<Vec<u8>, Error> {
+        let frame = self.frames.get(frame).ok_or_else(|| ErrKind::NoFrame)?;
+        let size = match self.has_palette() {
+            true => frame.width as usize * frame.height as usize,
+            false => frame.size as usize,
+        };
+        let mut read = self.read.lock().unwrap();
+        read.seek(SeekFrom::Start(frame.offset as u64))?;
+        let mut buffer = vec![0u8; size];
+        read.read_exact(&mut buffer[..])?;
+        Ok(buffer)
+    }
+
     pub fn texture_size(&self, frame: usize) -> Option<anim::Texture> {
         self.frames.get(frame).map(|x| x.to_anim_texture_coords())
     }
 
+    /// The unknown u32 stored at start of each frame header.
+    pub fn frame_unknown(&self, frame: usize) -> Option<u32> {
+        self.frames.get(frame).map(|x| x.unknown)
+    }
+
     pub fn texture_formats(&self) -> Vec<Result<Option<anim::TextureFormat>, Error>> {
         if self.has_palette() {
             vec![]
@@ -208,3 +248,34 @@ impl DdsGrp {
         Ok(())
     }
 }
+
+#[test]
+fn test_read_lazy_offsets() {
+    use std::io::Cursor;
+
+    let frame = |width, height| Frame {
+        unknown: 0,
+        width,
+        height,
+        size: 0,
+        offset: !0,
+    };
+    let frames = vec![
+        (frame(4, 4), vec![1u8; 24]),
+        (frame(8, 4), vec![2u8; 40]),
+    ];
+    let mut out = Cursor::new(Vec::new());
+    DdsGrp::write(&mut out, 1, &frames, None).unwrap();
+    let data = out.into_inner();
+
+    let grp = DdsGrp::read(Cursor::new(data.clone())).unwrap();
+    assert_eq!(grp.frame_count, 2);
+    assert_eq!(grp.frames[0].offset, 8 + 12);
+    assert_eq!(grp.frames[0].size, 24);
+    assert_eq!(grp.frames[1].offset, 8 + 12 + 24 + 12);
+    assert_eq!(grp.frames[1].size, 40);
+
+    // Truncated data is detected when opening the file
+    let truncated = data[..data.len() - 1].to_vec();
+    assert!(DdsGrp::read(Cursor::new(truncated)).is_err());
+}
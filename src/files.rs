@@ -1,9 +1,12 @@
 use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io::{self, BufReader, BufWriter, Cursor, Seek, Write, Read};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use anyhow::Context;
 use byteorder::{ByteOrder, ReadBytesExt, LE, LittleEndian};
@@ -13,6 +16,8 @@ use crate::anim_encoder::{self};
 use crate::anim_lit::{self, Lit};
 use crate::arc_error::ArcError;
 use crate::ddsgrp;
+use crate::texture_cache;
+use crate::texture_repack;
 use crate::{Error, SpriteType};
 
 pub static DEFAULT_HD_LAYER_NAMES: &[&str] = &[
@@ -30,6 +35,17 @@ pub static DEFAULT_SD_LAYER_NAMES: &[&str] = &[
     "teamcolor",
 ];
 
+/// How many times smaller than HD the HD2 sprites are, unless their anim header says otherwise.
+pub const DEFAULT_HD2_DIVISOR: u32 = 2;
+
+/// Converts scale of a HD2 anim header to how many times smaller it is than HD (scale 4).
+pub fn hd2_divisor_from_scale(scale: u8) -> u32 {
+    match scale {
+        1 | 2 | 4 => 4 / u32::from(scale),
+        _ => DEFAULT_HD2_DIVISOR,
+    }
+}
+
 fn default_hd_layer_names() -> Vec<String> {
     DEFAULT_HD_LAYER_NAMES.iter().map(|&x| String::from(x)).collect()
 }
@@ -227,16 +243,29 @@ impl SdGrpSizes {
     }
 }
 
+/// Maximum amount of separate files that `OpenFiles` keeps parsed at once.
+const OPEN_FILES_LIMIT: usize = 16;
+
+/// Parsed HD/HD2 anims and ddsgrps, ordered from least to most recently used.
+///
+/// Only caches what is on disk (or created from `edits`), so evicting files
+/// just means that they get read again when needed.
 struct OpenFiles {
     anim: Vec<(anim::Anim, usize, SpriteType)>,
     grp: Vec<(ddsgrp::DdsGrp, usize, SpriteType)>,
+    limit: usize,
 }
 
 impl OpenFiles {
     fn new() -> OpenFiles {
+        OpenFiles::with_limit(OPEN_FILES_LIMIT)
+    }
+
+    fn with_limit(limit: usize) -> OpenFiles {
         OpenFiles {
             anim: Vec::new(),
             grp: Vec::new(),
+            limit: limit.max(1),
         }
     }
 
@@ -244,6 +273,38 @@ impl OpenFiles {
         self.anim.clear();
         self.grp.clear();
     }
+
+    /// If the anim of `sprite` is open, makes it the most recently used one
+    /// (last in `self.anim`) and returns true.
+    fn touch_anim(&mut self, sprite: usize, ty: SpriteType) -> bool {
+        match self.anim.iter().position(|x| x.1 == sprite && x.2 == ty) {
+            Some(index) => {
+                let entry = self.anim.remove(index);
+                self.anim.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn push_anim(&mut self, anim: anim::Anim, sprite: usize, ty: SpriteType) -> &anim::Anim {
+        if self.anim.len() >= self.limit {
+            let evict = self.anim.len() + 1 - self.limit;
+            self.anim.drain(..evict);
+        }
+        self.anim.push((anim, sprite, ty));
+        &self.anim.last().unwrap().0
+    }
+
+    fn push_grp(&mut self, grp: ddsgrp::DdsGrp, sprite: usize, ty: SpriteType) -> &ddsgrp::DdsGrp {
+        self.grp.retain(|x| x.1 != sprite || x.2 != ty);
+        if self.grp.len() >= self.limit {
+            let evict = self.grp.len() + 1 - self.limit;
+            self.grp.drain(..evict);
+        }
+        self.grp.push((grp, sprite, ty));
+        &self.grp.last().unwrap().0
+    }
 }
 
 struct ImagesDat {
@@ -379,6 +440,33 @@ struct EditValues {
     tex_changes: Option<anim::TexChanges>,
 }
 
+/// An unsaved edit of a sprite, see `Files::pending_changes`.
+pub struct PendingChange {
+    pub sprite: usize,
+    pub ty: SpriteType,
+    /// Values currently on disk, None if the file doesn't exist yet or is a ddsgrp.
+    pub original: Option<anim::ValuesOrRef>,
+    /// None for ddsgrp edits.
+    pub edited: Option<anim::ValuesOrRef>,
+    /// If the edit replaces textures, frame count on disk (if any) and after the edit.
+    pub textures_replaced: Option<(Option<usize>, usize)>,
+}
+
+/// A file written by `Files::save`, and what it is expected to contain.
+#[derive(Debug, Clone)]
+pub struct SavedFile {
+    pub path: PathBuf,
+    contents: SavedContents,
+}
+
+#[derive(Debug, Clone)]
+enum SavedContents {
+    /// Sprite count, and (sprite, frame count) for sprites whose frame count is known.
+    Anim(u16, Vec<(usize, usize)>),
+    /// Frame count
+    DdsGrp(u16),
+}
+
 #[derive(Debug, Clone)]
 pub enum SpriteFiles {
     AnimSet(AnimFiles),
@@ -439,6 +527,10 @@ impl<'a> File<'a> {
         self.path
     }
 
+    pub fn sprite_type(&self) -> SpriteType {
+        self.sprite_type
+    }
+
     /// Few SD ddsgrps use the original .grp for dimensions, return path
     /// to that if it exists. Well, cmdicons is the only one I know of,
     /// wireframe does not use grp.
@@ -482,10 +574,18 @@ impl<'a> File<'a> {
                 return Ok(anim::read_texture(Cursor::new(&tex.1), &anim_tex)?.into());
             }
         }
+        let cache_key = |sprite| texture_cache::CacheKey {
+            path: self.path,
+            ty: self.sprite_type,
+            sprite,
+            layer,
+        };
         if let Some(Some(img_ref)) = self.image_ref {
             Ok(match self.location {
                 FileLocation::Multiple(_, ref mainsd) => {
-                    mainsd.texture(img_ref as usize, layer)?.into()
+                    let img_ref = img_ref as usize;
+                    texture_cache::cached(cache_key(img_ref), || mainsd.texture(img_ref, layer))?
+                        .into()
                 }
                 FileLocation::Separate(..) => {
                     return Err(anyhow!("Ref in HD sprite"));
@@ -497,16 +597,141 @@ impl<'a> File<'a> {
         } else {
             Ok(match self.location {
                 FileLocation::Multiple(sprite, ref mainsd) => {
-                    mainsd.texture(sprite, layer)?.into()
+                    texture_cache::cached(cache_key(sprite), || mainsd.texture(sprite, layer))?
+                        .into()
                 }
                 FileLocation::Separate(ref file) => {
-                    file.texture(0, layer)?.into()
+                    texture_cache::cached(cache_key(0), || file.texture(0, layer))?.into()
                 }
                 FileLocation::DdsGrp(ref grp) => grp.frame(layer)?,
             })
         }
     }
 
+    /// Encoded texture data of `layer`, same as it would be written to file.
+    pub fn raw_texture(&self, layer: usize) -> Result<(anim::Texture, Vec<u8>), Error> {
+        if let Some(ref tex) = self.textures {
+            return tex.get(layer).and_then(|x| x.clone())
+                .ok_or_else(|| anyhow!("No texture for layer {}", layer));
+        }
+        if let Some(Some(img_ref)) = self.image_ref {
+            match self.location {
+                FileLocation::Multiple(_, ref mainsd) => {
+                    Ok(mainsd.raw_texture(img_ref as usize, layer)?)
+                }
+                FileLocation::Separate(..) => Err(anyhow!("Ref in HD sprite")),
+                FileLocation::DdsGrp(..) => Err(anyhow!("Ref in ddsgrp")),
+            }
+        } else {
+            match self.location {
+                FileLocation::Multiple(sprite, ref mainsd) => {
+                    Ok(mainsd.raw_texture(sprite, layer)?)
+                }
+                FileLocation::Separate(ref file) => Ok(file.raw_texture(0, layer)?),
+                FileLocation::DdsGrp(..) => Err(anyhow!("Not an anim sprite")),
+            }
+        }
+    }
+
+    /// Reads the dimensions from the texture header of `layer`, without reading the
+    /// rest of the texture. None for monochrome textures which have no header.
+    pub fn texture_header_dimensions(&self, layer: usize) -> Result<Option<(u32, u32)>, Error> {
+        if let Some(ref textures) = self.textures {
+            let (tex, data) = textures.get(layer).and_then(|x| x.as_ref())
+                .ok_or_else(|| anyhow!("No texture for layer {}", layer))?;
+            return Ok(anim::texture_dimensions(Cursor::new(&data[..]), tex.size)?);
+        }
+        let header_len = anim::TEXTURE_HEADER_LEN;
+        let (tex, data) = if let Some(Some(img_ref)) = self.image_ref {
+            match self.location {
+                FileLocation::Multiple(_, ref mainsd) => {
+                    mainsd.raw_texture_start(img_ref as usize, layer, header_len)?
+                }
+                FileLocation::Separate(..) => return Err(anyhow!("Ref in HD sprite")),
+                FileLocation::DdsGrp(..) => return Err(anyhow!("Ref in ddsgrp")),
+            }
+        } else {
+            match self.location {
+                FileLocation::Multiple(sprite, ref mainsd) => {
+                    mainsd.raw_texture_start(sprite, layer, header_len)?
+                }
+                FileLocation::Separate(ref file) => file.raw_texture_start(0, layer, header_len)?,
+                FileLocation::DdsGrp(..) => return Err(anyhow!("Not an anim sprite")),
+            }
+        };
+        Ok(anim::texture_dimensions(Cursor::new(&data), tex.size.min(header_len))?)
+    }
+
+    /// Encoded textures of every layer, for building `TexChanges` that keep the
+    /// textures as they are.
+    pub fn raw_textures(&self) -> Result<Vec<Option<(anim::Texture, Vec<u8>)>>, Error> {
+        (0..self.layer_count())
+            .map(|layer| match self.texture_size(layer) {
+                Some(_) => self.raw_texture(layer)
+                    .with_context(|| format!("Reading texture of layer {}", layer))
+                    .map(Some),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Writes texture of `layer` as a .dds file, keeping its compressed format.
+    pub fn export_dds(&self, layer: usize, out: &mut impl Write) -> Result<(), Error> {
+        match self.texture_formats().into_iter().nth(layer) {
+            Some(Ok(Some(anim::TextureFormat::Monochrome))) => {
+                return Err(anyhow!(
+                    "Layer {} is monochrome, which cannot be represented as DDS", layer,
+                ));
+            }
+            Some(Ok(Some(_))) => (),
+            Some(Err(e)) => return Err(e.context(format!("Layer {} texture format", layer))),
+            Some(Ok(None)) | None => return Err(anyhow!("No texture for layer {}", layer)),
+        }
+        let (_, data) = self.raw_texture(layer)?;
+        let dds = ddsfile::Dds::read(&mut &data[..])
+            .map_err(|e| anyhow!("Invalid DDS data in layer {}: {}", layer, e))?;
+        dds.write(out)
+            .map_err(|e| anyhow!("Couldn't write DDS: {}", e))?;
+        Ok(())
+    }
+
+    /// Encoded texture of `layer`, for anims and non-paletted ddsgrps.
+    fn encoded_texture(&self, layer: usize) -> Result<(anim::Texture, Vec<u8>), Error> {
+        if let Some(ref tex) = self.grp_textures {
+            let tex = tex.get(layer).ok_or_else(|| anyhow!("No frame {}", layer))?;
+            return Ok((tex.0.to_anim_texture_coords(), tex.1.clone()));
+        }
+        match self.location {
+            FileLocation::DdsGrp(ref grp) => {
+                let texture = grp.texture_size(layer)
+                    .ok_or_else(|| anyhow!("No frame {}", layer))?;
+                Ok((texture, grp.raw_frame(layer)?))
+            }
+            _ => self.raw_texture(layer),
+        }
+    }
+
+    /// Amount of mip levels stored for `layer`, 1 if there is only the full size texture.
+    pub fn texture_mip_count(&self, layer: usize) -> Result<u32, Error> {
+        if self.palette().is_some() {
+            return Ok(1);
+        }
+        let (texture, data) = self.encoded_texture(layer)?;
+        Ok(anim::texture_mip_count(Cursor::new(&data), texture.size)?)
+    }
+
+    /// Decodes stored mip `level` of `layer`. Level 0 is the same as `texture()`.
+    pub fn texture_mip(&self, layer: usize, level: u32) -> Result<anim::RawTexture, Error> {
+        if level == 0 {
+            return self.texture(layer);
+        }
+        if self.palette().is_some() {
+            return Err(anyhow!("Paletted textures don't have mip levels"));
+        }
+        let (texture, data) = self.encoded_texture(layer)?;
+        Ok(anim::read_texture_mip(Cursor::new(&data), &texture, level)?.into())
+    }
+
     /// Gets the palette if the file has any
     /// (Only SD tileset vr4 usually has them)
     /// RGB0 format
@@ -564,6 +789,28 @@ impl<'a> File<'a> {
         self.location.texture_size(layer)
     }
 
+    /// How many times frame coordinates have to be divided to get texture pixels.
+    ///
+    /// HD2 frames use HD coordinates. HD2 is usually half of HD, but the ratio is read from
+    /// the anim header so that sprites with other HD2 resolutions display correctly.
+    pub fn texture_divisor(&self) -> u32 {
+        match (self.sprite_type, &self.location) {
+            (SpriteType::Hd2, FileLocation::Separate(anim)) => {
+                hd2_divisor_from_scale(anim.scale())
+            }
+            (SpriteType::Hd2, _) => DEFAULT_HD2_DIVISOR,
+            _ => 1,
+        }
+    }
+
+    /// Unknown field of a ddsgrp frame header, None for anims.
+    pub fn grp_frame_unknown(&self, frame: usize) -> Option<u32> {
+        if let Some(tex) = self.grp_textures {
+            return tex.get(frame).map(|x| x.0.unknown);
+        }
+        self.grp()?.frame_unknown(frame)
+    }
+
     pub fn texture_formats(&self) -> Vec<Result<Option<anim::TextureFormat>, Error>> {
         if let Some(ref tex) = self.textures {
             return tex.iter().map(|x| {
@@ -682,9 +929,34 @@ impl<'a> FileLocation<'a> {
     }
 }
 
+/// SD anim edits are written to mainsd instead of separate files.
+fn is_mainsd_edit((_sprite, ty): (usize, SpriteType), edit: &Edit) -> bool {
+    let is_anim = match edit {
+        Edit::Grp(..) => false,
+        Edit::Ref(..) | Edit::Values(..) => true,
+    };
+    ty == SpriteType::Sd && is_anim
+}
+
 fn load_mainsd(path: &Path) -> Result<anim::Anim, Error> {
-    let file = fs::File::open(path)?;
-    Ok(anim::Anim::read(file)?)
+    let file = fs::File::open(path)
+        .with_context(|| format!("Unable to open {}", path.display()))?;
+    let invalid = || format!("{} is not a valid mainSD.anim", path.display());
+    let anim = anim::Anim::read(file).with_context(invalid)?;
+    check_mainsd(&anim).with_context(invalid)?;
+    Ok(anim)
+}
+
+/// Checks that the anim header looks like mainSD, instead of e.g. a HD anim
+/// that was renamed.
+fn check_mainsd(anim: &anim::Anim) -> Result<(), Error> {
+    if anim.scale() != 1 {
+        return Err(anyhow!("Anim has scale {}, mainSD uses scale 1", anim.scale()));
+    }
+    if anim.sprites().len() < 2 {
+        return Err(anyhow!("Anim contains a single sprite, mainSD contains all images"));
+    }
+    Ok(())
 }
 
 fn anim_index_from_filename(filename: &str) -> Option<u16> {
@@ -723,14 +995,15 @@ fn test_anim_index_from_filename() {
     assert_eq!(anim_index_from_filename("main_0444.anim"), None);
 }
 
-/// Default HD anim layer names.
-///
-/// (Layer names of first anim that exists on disk, if any)
-fn hd_layer_names_from_root(
-    root: &Path,
-    sprite_count: u16,
-) -> Result<Vec<String>, Error> {
-    let files = std::fs::read_dir(root.join("anim"))?
+/// `main_NNN.anim` files in `dir` by their index.
+/// Returns an empty map if the directory doesn't exist.
+fn anim_files_in_dir(dir: &Path) -> Result<HashMap<u16, PathBuf>, Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(o) => o,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(Error::from(e).context(format!("Reading {}", dir.display()))),
+    };
+    let files = entries
         .filter_map(|entry| {
             entry.map(|entry| {
                 let file_name = entry.file_name();
@@ -741,18 +1014,52 @@ fn hd_layer_names_from_root(
             .transpose()
         })
         .collect::<Result<HashMap<u16, _>, _>>()?;
-    for i in 0..sprite_count {
-        if let Some(path) = files.get(&i) {
-            let file = fs::File::open(path)
-                .with_context(|| format!("Accessing {}", path.display()))?;
-            let anim = anim::Anim::read(file)
-                .with_context(|| format!("Reading {}", path.display()))?;
-            return Ok(anim.layer_names().into());
+    Ok(files)
+}
+
+/// Default HD anim layer names.
+///
+/// (Layer names of first anim that exists on disk, if any.
+/// HD2 anims are used if there are no HD anims)
+fn hd_layer_names_from_root(
+    root: &Path,
+    sprite_count: u16,
+) -> Result<Vec<String>, Error> {
+    for dir in &[root.join("anim"), root.join("HD2/anim")] {
+        let files = anim_files_in_dir(dir)?;
+        for i in 0..sprite_count {
+            if let Some(path) = files.get(&i) {
+                // Skip over unreadable files, they'll show an error once the sprite is selected.
+                let result = fs::File::open(path)
+                    .with_context(|| format!("Accessing {}", path.display()))
+                    .and_then(|file| {
+                        anim::Anim::read(file)
+                            .with_context(|| format!("Reading {}", path.display()))
+                    });
+                match result {
+                    Ok(anim) => return Ok(anim.layer_names().into()),
+                    Err(e) => warn!("Skipping {} for layer names: {:?}", path.display(), e),
+                }
+            }
         }
     }
     Ok(default_hd_layer_names())
 }
 
+/// Sprite count of an anim tree without mainsd, based on the highest
+/// `main_NNN.anim` index in the HD and HD2 directories. None if there are no anims.
+///
+/// (Sprite indices are image ids, so missing anims below the highest one are
+/// still listed)
+fn sprite_count_from_anim_dirs(file_root: &FileRoot) -> Result<Option<usize>, Error> {
+    let mut max = None;
+    for dir in &[file_root.hd_anim_dir(), file_root.hd2_anim_dir()] {
+        let dir_max = anim_files_in_dir(dir)?.keys().copied().max();
+        max = max.max(dir_max);
+    }
+    Ok(max.map(|x| x as usize + 1))
+}
+
 /// Generates sprite list for case when only mainsd (-like anim) is loaded.
 fn mainsd_sprites(sprite_count: u16) -> Vec<SpriteFiles> {
     (0..sprite_count).map(|i| {
@@ -799,6 +1106,26 @@ impl Files {
         }
     }
 
+    /// Sprite type that should be shown first after opening the files:
+    /// SD if there is a mainsd, otherwise HD unless only HD2 anims exist.
+    pub fn preferred_sprite_type(&self) -> SpriteType {
+        if self.mainsd_anim.is_some() {
+            return SpriteType::Sd;
+        }
+        match self.file_root {
+            Some(ref root) => {
+                let has_hd = anim_files_in_dir(&root.hd_anim_dir())
+                    .map(|x| !x.is_empty())
+                    .unwrap_or(true);
+                match has_hd {
+                    true => SpriteType::Hd,
+                    false => SpriteType::Hd2,
+                }
+            }
+            None => SpriteType::Sd,
+        }
+    }
+
     pub fn root_path(&self) -> Option<&Path> {
         self.file_root.as_ref().map(|x| Path::new(&x.root))
     }
@@ -806,8 +1133,15 @@ impl Files {
     /// Tries to load an entire anim tree structure, if files seem to be laid out like that.
     /// Otherwise just opens the file given.
     ///
+    /// `one_filename` can also be a directory containing the files extracted from CASC,
+    /// in which case the tree is loaded from the file `anim_file_in_root_dir` picks.
+    ///
     /// Returns sprite index if the filename is in anim/ or hd2/anim
     pub fn init(one_filename: &Path) -> Result<(Files, Option<usize>), Error> {
+        if one_filename.is_dir() {
+            let path = anim_file_in_root_dir(one_filename)?;
+            return Files::init(&path).map(|(files, _)| (files, None));
+        }
         if let Some(file_root) = file_root_from_file(one_filename) {
             let root = &file_root.root;
             let index = file_root.index;
@@ -823,8 +1157,10 @@ impl Files {
                     None
                 }
             };
-            let sprite_count = mainsd_anim.as_ref().map(|x| x.1.sprites().len())
-                .unwrap_or(999);
+            let sprite_count = match mainsd_anim {
+                Some(ref mainsd) => mainsd.1.sprites().len(),
+                None => sprite_count_from_anim_dirs(&file_root)?.unwrap_or(999),
+            };
             let images_dat = std::fs::read(root.join("arr/images.dat"))
                 .unwrap_or_else(|_| DEFAULT_IMAGES_DAT.into());
             let images_tbl = std::fs::read(root.join("arr/images.tbl"))
@@ -876,22 +1212,7 @@ impl Files {
             match one_filename.extension().map(|x| x == "anim").unwrap_or(false) {
                 true => {
                     let mainsd = load_mainsd(one_filename)?;
-                    let sd_layer_names = mainsd.layer_names().into();
-                    Ok((Files {
-                        sprites: mainsd_sprites(mainsd.sprites().len() as u16),
-                        mainsd_anim: Some((one_filename.into(), mainsd)),
-                        file_root: None,
-                        open_files: OpenFiles::new(),
-                        sd_grp_sizes: SdGrpSizes::new(),
-                        edits: HashMap::new(),
-                        images_dat: ImagesDat::empty(),
-                        images_tbl: Vec::new(),
-                        lit: None,
-                        images_rel: None,
-                        new_entry_count: None,
-                        sd_layer_names: sd_layer_names,
-                        hd_layer_names: default_hd_layer_names(),
-                    }, None))
+                    Ok((Files::from_mainsd_anim(one_filename.into(), mainsd), None))
                 }
                 false => {
                     Ok((Files {
@@ -914,6 +1235,35 @@ impl Files {
         }
     }
 
+    /// Opens a mainsd-like anim without any of the files that would be next to it.
+    /// `path` is where the anim will be written when saving.
+    pub fn from_mainsd<R: Read + Seek + Send + 'static>(
+        path: PathBuf,
+        read: R,
+    ) -> Result<Files, Error> {
+        let mainsd = anim::Anim::read(read)?;
+        Ok(Files::from_mainsd_anim(path, mainsd))
+    }
+
+    fn from_mainsd_anim(path: PathBuf, mainsd: anim::Anim) -> Files {
+        let sd_layer_names = mainsd.layer_names().into();
+        Files {
+            sprites: mainsd_sprites(mainsd.sprites().len() as u16),
+            mainsd_anim: Some((path, mainsd)),
+            file_root: None,
+            open_files: OpenFiles::new(),
+            sd_grp_sizes: SdGrpSizes::new(),
+            edits: HashMap::new(),
+            images_dat: ImagesDat::empty(),
+            images_tbl: Vec::new(),
+            lit: None,
+            images_rel: None,
+            new_entry_count: None,
+            sd_layer_names: sd_layer_names,
+            hd_layer_names: default_hd_layer_names(),
+        }
+    }
+
     pub fn file<'a>(
         &'a mut self,
         sprite: usize,
@@ -941,6 +1291,9 @@ impl Files {
                 return Ok(None);
             }
         };
+        if ty == SpriteType::Sd {
+            check_ref_chain(self.mainsd_anim.as_ref().map(|x| &x.1), &self.edits, sprite)?;
+        }
         let edit_values = self.edits.get(&(sprite, ty));
 
         match edit_values {
@@ -1086,6 +1439,156 @@ impl Files {
         &self.sprites[..]
     }
 
+    /// Returns anim sprites that don't have a HD or HD2 file, along with the missing types.
+    ///
+    /// Only checks file existence (or unsaved imports), so the files aren't opened.
+    pub fn sprites_missing_hd(&self) -> Vec<(usize, Vec<SpriteType>)> {
+        (0..self.sprites.len())
+            .filter(|&i| !matches!(self.sprites[i], SpriteFiles::DdsGrp(..)))
+            .filter_map(|i| {
+                let missing = [SpriteType::Hd, SpriteType::Hd2].iter()
+                    .cloned()
+                    .filter(|&ty| !separate_file_exists(&self.sprites, i, ty, &self.edits))
+                    .collect::<Vec<_>>();
+                match missing.is_empty() {
+                    true => None,
+                    false => Some((i, missing)),
+                }
+            })
+            .collect()
+    }
+
+    /// Writes a CSV row of metadata for every sprite and sprite type that exists.
+    pub fn write_metadata_csv<W: Write>(&mut self, mut out: W) -> Result<(), Error> {
+        writeln!(
+            out,
+            "image,type,is_ref,ref_image,layer_count,layer_names,frame_count,texture_sizes,error",
+        )?;
+        let types = [SpriteType::Sd, SpriteType::Hd, SpriteType::Hd2];
+        for i in 0..self.sprites.len() {
+            for &ty in &types {
+                let file = match self.file(i, ty) {
+                    Ok(Some(o)) => o,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        // Keep going so that one broken sprite doesn't hide the rest.
+                        let error = format!("Unable to open: {}", e);
+                        writeln!(out, "{},{:?},,,,,,,{}", i, ty, csv_field(&error))?;
+                        continue;
+                    }
+                };
+                let layer_names = file.layer_names();
+                let texture_sizes = (0..file.layer_count())
+                    .map(|layer| match file.texture_size(layer) {
+                        Some(tex) => format!("{}x{}", tex.width, tex.height),
+                        None => String::new(),
+                    })
+                    .collect::<Vec<_>>();
+                let image_ref = file.image_ref();
+                writeln!(
+                    out,
+                    "{},{:?},{},{},{},{},{},{},",
+                    i,
+                    ty,
+                    image_ref.is_some(),
+                    image_ref.map(|x| x.to_string()).unwrap_or_default(),
+                    file.layer_count(),
+                    csv_field(&layer_names.join(";")),
+                    file.frames().map(|x| x.len()).unwrap_or(0),
+                    csv_field(&texture_sizes.join(";")),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every sprite for data that the game is unlikely to handle,
+    /// returning a description of each problem found.
+    pub fn verify(&mut self) -> Vec<String> {
+        self.verify_with_progress(|_, _| ())
+    }
+
+    /// Same as `verify`, but calls `progress(sprites_checked, total_sprites)`
+    /// before each sprite.
+    pub fn verify_with_progress<F: FnMut(usize, usize)>(&mut self, mut progress: F) -> Vec<String> {
+        let mut findings = Vec::new();
+        let sprite_count = self.sprites.len();
+        let types = [SpriteType::Sd, SpriteType::Hd, SpriteType::Hd2];
+        for i in 0..sprite_count {
+            progress(i, sprite_count);
+            for &ty in &types {
+                let mut report = |msg: String| findings.push(format!("{} {:?}: {}", i, ty, msg));
+                let file = match self.file(i, ty) {
+                    Ok(Some(o)) => o,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        report(format!("Unable to open: {}", e));
+                        continue;
+                    }
+                };
+                if let Some(img_ref) = file.image_ref() {
+                    if img_ref as usize >= sprite_count {
+                        report(format!("References nonexistent sprite {}", img_ref));
+                    }
+                    continue;
+                }
+                if !file.is_anim() {
+                    continue;
+                }
+                let frames = file.frames().unwrap_or(&[]);
+                for layer in 0..file.layer_count() {
+                    let texture = match file.texture_size(layer) {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    if texture.width == 0 || texture.height == 0 {
+                        if !frames.is_empty() {
+                            report(format!(
+                                "Layer {} has a {}x{} texture, but there are {} frames",
+                                layer, texture.width, texture.height, frames.len(),
+                            ));
+                        }
+                        continue;
+                    }
+                    // HD2 frames are in HD units, the texture isn't.
+                    let divisor = file.texture_divisor();
+                    let texels = |pos: u16, size: u16| {
+                        u32::from(pos) / divisor + u32::from(size) / divisor
+                    };
+                    let outside = frames.iter()
+                        .filter(|f| {
+                            texels(f.tex_x, f.width) > u32::from(texture.width) ||
+                                texels(f.tex_y, f.height) > u32::from(texture.height)
+                        })
+                        .count();
+                    if outside != 0 {
+                        report(format!(
+                            "{} frames extend past the {}x{} texture of layer {}",
+                            outside, texture.width, texture.height, layer,
+                        ));
+                    }
+                    match file.texture_header_dimensions(layer) {
+                        Ok(Some((width, height))) => {
+                            if (width, height) !=
+                                (u32::from(texture.width), u32::from(texture.height))
+                            {
+                                report(format!(
+                                    "Layer {} is declared as {}x{}, but the texture is {}x{}",
+                                    layer, texture.width, texture.height, width, height,
+                                ));
+                            }
+                        }
+                        Ok(None) => (),
+                        Err(e) => {
+                            report(format!("Unable to read texture of layer {}: {}", layer, e));
+                        }
+                    }
+                }
+            }
+        }
+        findings
+    }
+
     pub fn mainsd(&self) -> Option<&anim::Anim> {
         self.mainsd_anim.as_ref().map(|x| &x.1)
     }
@@ -1173,6 +1676,33 @@ impl Files {
         }
     }
 
+    /// Replaces SD ref `sprite` with a copy of the sprite it refers to, so that it
+    /// can be edited separately.
+    pub fn deref_sprite(&mut self, sprite: usize, ty: SpriteType) -> Result<(), Error> {
+        if ty != SpriteType::Sd {
+            return Err(anyhow!("Only SD sprites can be refs"));
+        }
+        let edit = {
+            let file = self.file(sprite, ty)?
+                .ok_or_else(|| anyhow!("No file for sprite {}", sprite))?;
+            let target = file.image_ref()
+                .ok_or_else(|| anyhow!("Sprite {} is not a ref", sprite))?;
+            let values = file.sprite_values()
+                .ok_or_else(|| anyhow!("Referenced sprite {} has no values", target))?;
+            let frames = file.frames()
+                .ok_or_else(|| anyhow!("Referenced sprite {} has no frames", target))?
+                .to_vec();
+            let textures = file.raw_textures()
+                .with_context(|| format!("Reading textures of sprite {}", target))?;
+            Edit::Values(EditValues {
+                values,
+                tex_changes: Some(anim::TexChanges { frames, textures }),
+            })
+        };
+        self.edits.insert((sprite, ty), edit);
+        Ok(())
+    }
+
     pub fn set_tex_changes(
         &mut self,
         sprite: usize,
@@ -1209,6 +1739,266 @@ impl Files {
         }
     }
 
+    /// Decodes graphics of a single anim frame so it can be inserted to another sprite
+    /// with `insert_frame`.
+    pub fn extract_frame(
+        &mut self,
+        sprite: usize,
+        ty: SpriteType,
+        frame: usize,
+    ) -> Result<texture_repack::CopiedFrame, Error> {
+        let file = self.file(sprite, ty)?
+            .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+        let mut decoded = texture_repack::decode_frames(&file)?;
+        let frame_count = decoded.frames.len();
+        if frame >= frame_count {
+            return Err(anyhow!(
+                "Frame {} doesn't exist, sprite has {} frames", frame, frame_count,
+            ));
+        }
+        Ok(texture_repack::CopiedFrame {
+            sprite,
+            ty,
+            index: frame,
+            frame: decoded.frames.swap_remove(frame),
+            layer_names: decoded.layer_names,
+            divisor: decoded.divisor,
+        })
+    }
+
+    /// Inserts `copied` to frame `index` of an anim sprite, moving the following frames
+    /// forward. The sprite's textures are re-encoded in their current formats.
+    pub fn insert_frame(
+        &mut self,
+        sprite: usize,
+        ty: SpriteType,
+        index: usize,
+        copied: &texture_repack::CopiedFrame,
+    ) -> Result<(), Error> {
+        let file = self.file(sprite, ty)?
+            .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+        let mut decoded = texture_repack::decode_frames(&file)?;
+        let dimensions = file.sprite_values()
+            .map(|x| (x.width, x.height))
+            .unwrap_or((0, 0));
+        let frame = texture_repack::convert_copied_frame(
+            copied,
+            ty,
+            decoded.divisor,
+            &decoded.layer_names,
+        );
+        let index = index.min(decoded.frames.len());
+        decoded.frames.insert(index, frame);
+        let changes = texture_repack::encode_frames(&decoded)?;
+        self.set_tex_changes(sprite, ty, changes, dimensions);
+        Ok(())
+    }
+
+    /// Removes `frames` from an anim sprite, moving the following frames back.
+    /// The textures are re-encoded in their current formats.
+    ///
+    /// The frames are also removed from the other types (SD, HD, HD2) of the sprite,
+    /// so that their frame counts stay in sync. Returns the other types which were left
+    /// as is, as their frame count differs from `ty`.
+    pub fn remove_frames(
+        &mut self,
+        sprite: usize,
+        ty: SpriteType,
+        frames: &[usize],
+    ) -> Result<Vec<SpriteType>, Error> {
+        let file = self.file(sprite, ty)?
+            .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+        let frame_count = file.frames().map(|x| x.len()).unwrap_or(0);
+        if let Some(&bad) = frames.iter().find(|&&x| x >= frame_count) {
+            return Err(anyhow!(
+                "Frame {} doesn't exist, sprite has {} frames", bad, frame_count,
+            ));
+        }
+        if (0..frame_count).all(|x| frames.contains(&x)) {
+            return Err(anyhow!("Cannot remove every frame of a sprite"));
+        }
+        let mut changes = Vec::new();
+        let mut skipped = Vec::new();
+        for &other in &[SpriteType::Sd, SpriteType::Hd, SpriteType::Hd2] {
+            let file = match self.file(sprite, other) {
+                Ok(Some(file)) => file,
+                Ok(None) if other != ty => continue,
+                Err(e) if other != ty => {
+                    warn!("Not removing frames from {}/{:?}: {:?}", sprite, other, e);
+                    skipped.push(other);
+                    continue;
+                }
+                Ok(None) => return Err(anyhow!("No file for sprite {}/{:?}", sprite, ty)),
+                Err(e) => return Err(e),
+            };
+            if other != ty {
+                if !file.is_anim() || file.image_ref().is_some() {
+                    continue;
+                }
+                let other_count = file.frames().map(|x| x.len()).unwrap_or(0);
+                if other_count != frame_count {
+                    warn!(
+                        "Not removing frames from {}/{:?}, it has {} frames instead of {}",
+                        sprite, other, other_count, frame_count,
+                    );
+                    skipped.push(other);
+                    continue;
+                }
+            }
+            let mut decoded = texture_repack::decode_frames(&file)
+                .with_context(|| format!("{:?}", other))?;
+            let dimensions = file.sprite_values()
+                .map(|x| (x.width, x.height))
+                .unwrap_or((0, 0));
+            let mut index = 0;
+            decoded.frames.retain(|_| {
+                index += 1;
+                !frames.contains(&(index - 1))
+            });
+            let tex_changes = texture_repack::encode_frames(&decoded)
+                .with_context(|| format!("{:?}", other))?;
+            changes.push((other, tex_changes, dimensions));
+        }
+        for (other, tex_changes, dimensions) in changes {
+            self.set_tex_changes(sprite, other, tex_changes, dimensions);
+        }
+        Ok(skipped)
+    }
+
+    /// Sets the `unknown` (frame type) value of an anim frame, keeping the textures as they are.
+    ///
+    /// Returns false if the frame already had `value`.
+    pub fn set_frame_unknown(
+        &mut self,
+        sprite: usize,
+        ty: SpriteType,
+        frame: usize,
+        value: u32,
+    ) -> Result<bool, Error> {
+        self.edit_frame(sprite, ty, frame, |f| f.unknown = value)
+    }
+
+    /// Sets the offsets of an anim frame, keeping the textures as they are.
+    ///
+    /// Returns false if the frame already had the offsets.
+    pub fn set_frame_offset(
+        &mut self,
+        sprite: usize,
+        ty: SpriteType,
+        frame: usize,
+        x_off: i16,
+        y_off: i16,
+    ) -> Result<bool, Error> {
+        self.edit_frame(sprite, ty, frame, |f| {
+            f.x_off = x_off;
+            f.y_off = y_off;
+        })
+    }
+
+    /// Applies `edit` to a single frame of an anim sprite, keeping the textures as they are.
+    ///
+    /// Returns false if `edit` didn't change the frame.
+    fn edit_frame<F: Fn(&mut anim::Frame)>(
+        &mut self,
+        sprite: usize,
+        ty: SpriteType,
+        frame: usize,
+        edit: F,
+    ) -> Result<bool, Error> {
+        let existing = self.edits.get_mut(&(sprite, ty));
+        if let Some(Edit::Values(EditValues { tex_changes: Some(ref mut changes), .. })) = existing
+        {
+            let frame_count = changes.frames.len();
+            let f = changes.frames.get_mut(frame)
+                .ok_or_else(|| {
+                    anyhow!("Frame {} doesn't exist, sprite has {} frames", frame, frame_count)
+                })?;
+            let old = f.clone();
+            edit(f);
+            return Ok(*f != old);
+        }
+        let (changes, dimensions) = {
+            let file = self.file(sprite, ty)?
+                .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+            if !file.is_anim() {
+                return Err(anyhow!("Not an anim sprite"));
+            }
+            if file.image_ref().is_some() {
+                return Err(anyhow!("Sprite is a reference to another image"));
+            }
+            let mut frames = file.frames()
+                .ok_or_else(|| anyhow!("Sprite has no frames"))?
+                .to_vec();
+            let frame_count = frames.len();
+            let f = frames.get_mut(frame)
+                .ok_or_else(|| {
+                    anyhow!("Frame {} doesn't exist, sprite has {} frames", frame, frame_count)
+                })?;
+            let old = f.clone();
+            edit(f);
+            if *f == old {
+                return Ok(false);
+            }
+            let textures = file.raw_textures()?;
+            let dimensions = file.sprite_values()
+                .map(|x| (x.width, x.height))
+                .unwrap_or((0, 0));
+            (anim::TexChanges { frames, textures }, dimensions)
+        };
+        self.set_tex_changes(sprite, ty, changes, dimensions);
+        Ok(true)
+    }
+
+    /// Changes the scale stored in a grp's header, keeping its frames as they are.
+    ///
+    /// Scale is only metadata, so the frames aren't re-encoded.
+    /// Returns false if the grp already had `scale`.
+    pub fn set_grp_scale(&mut self, sprite: usize, scale: u8) -> Result<bool, Error> {
+        if !matches!(scale, 1 | 2 | 4) {
+            return Err(anyhow!("Invalid grp scale {}", scale));
+        }
+        let edit = self.edits.get_mut(&(sprite, SpriteType::Sd));
+        if let Some(Edit::Grp(_, ref mut edit_scale, _)) = edit {
+            let changed = *edit_scale != scale;
+            *edit_scale = scale;
+            return Ok(changed);
+        }
+        let (frames, palette) = {
+            let file = self.file(sprite, SpriteType::Sd)?
+                .ok_or_else(|| anyhow!("No file for sprite {}", sprite))?;
+            let grp = file.grp().ok_or_else(|| anyhow!("Sprite {} is not a grp", sprite))?;
+            if grp.scale == scale {
+                return Ok(false);
+            }
+            let frames = (0..grp.frames.len())
+                .map(|i| {
+                    let data = grp.raw_frame(i)
+                        .with_context(|| format!("Reading frame {}", i))?;
+                    Ok((grp.frames[i], data))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            (frames, grp.palette().map(|x| x.to_vec()))
+        };
+        self.set_grp_changes(sprite, frames, scale, palette);
+        Ok(true)
+    }
+
+    /// Sets scale of every grp sprite, returning the sprites which were changed.
+    pub fn set_all_grp_scales(&mut self, scale: u8) -> Result<Vec<usize>, Error> {
+        let grps = (0..self.sprites.len())
+            .filter(|&i| matches!(self.sprites[i], SpriteFiles::DdsGrp(..)))
+            .collect::<Vec<_>>();
+        let mut changed = Vec::new();
+        for sprite in grps {
+            if self.set_grp_scale(sprite, scale)
+                .with_context(|| format!("Setting scale of sprite {}", sprite))?
+            {
+                changed.push(sprite);
+            }
+        }
+        Ok(changed)
+    }
+
     pub fn set_grp_changes(
         &mut self,
         sprite: usize,
@@ -1283,27 +2073,119 @@ impl Files {
             self.new_entry_count.is_some()
     }
 
-    pub fn save(&mut self) -> Result<(), Error> {
-        let mut result = Ok(());
+    /// Lists unsaved sprite edits along with the on-disk values they replace,
+    /// sorted by sprite.
+    pub fn pending_changes(&mut self) -> Vec<PendingChange> {
+        // Empty so that files that don't exist yet aren't created from the edits.
+        let no_edits = HashMap::new();
+        let mut keys = self.edits.keys().cloned().collect::<Vec<_>>();
+        keys.sort_by_key(|&(sprite, ty)| (sprite, ty as u8));
+        keys.into_iter().filter_map(|(sprite, ty)| {
+            let on_disk = ty == SpriteType::Sd ||
+                separate_file_path(&self.sprites, sprite, ty).map(|x| x.is_file()) == Some(true);
+            let location = match on_disk {
+                true => file_location(
+                    self.mainsd_anim.as_ref().map(|x| &x.1),
+                    &mut self.open_files,
+                    &self.sprites,
+                    sprite,
+                    ty,
+                    &self.hd_layer_names,
+                    &no_edits,
+                ).unwrap_or_else(|e| {
+                    warn!("Couldn't read original {}/{:?}: {:?}", sprite, ty, e);
+                    None
+                }),
+                false => None,
+            };
+            let original = location.as_ref().and_then(|x| x.values_or_ref());
+            let original_frames = location.as_ref().and_then(|x| match *x {
+                FileLocation::DdsGrp(grp) => Some(grp.frames.len()),
+                _ => x.frames().map(|x| x.len()),
+            });
+            let (edited, new_frames) = match self.edits.get(&(sprite, ty))? {
+                Edit::Ref(img) => (Some(anim::ValuesOrRef::Ref(*img)), None),
+                Edit::Values(edit) => (
+                    Some(anim::ValuesOrRef::Values(edit.values)),
+                    edit.tex_changes.as_ref().map(|x| x.frames.len()),
+                ),
+                Edit::Grp(frames, ..) => (None, Some(frames.len())),
+            };
+            Some(PendingChange {
+                sprite,
+                ty,
+                original,
+                edited,
+                textures_replaced: new_frames.map(|x| (original_frames, x)),
+            })
+        }).collect()
+    }
+
+    /// Writes all changes to disk.
+    ///
+    /// Returns the anim/grp files that were written, which can be passed to
+    /// `verify_saved_files` to check that they can be read back.
+    pub fn save(&mut self) -> Result<Vec<SavedFile>, Error> {
+        self.save_with_progress(false, None, |_, _| ())
+    }
+
+    /// Same as `save`, but calls `progress(files_written, total_files)` every time
+    /// a file has been moved to its final location.
+    /// (mainsd, images.rel and main.lit count as files too)
+    ///
+    /// If `backup` is set, each overwritten file is first copied to `{name}.bak`,
+    /// unless that backup already exists.
+    /// The files are first written to `temp_dir`, or next to the files they replace
+    /// if it is None.
+    /// If moving any of the files fails, the files already replaced are restored.
+    pub fn save_with_progress<F: FnMut(usize, usize)>(
+        &mut self,
+        backup: bool,
+        temp_dir: Option<&Path>,
+        progress: F,
+    ) -> Result<Vec<SavedFile>, Error> {
+        self.save_files(backup, temp_dir, None, progress)
+    }
+
+    /// Same as `save`, but writes the changed files to `out_dir` at their paths relative
+    /// to the root directory, leaving the opened files unchanged.
+    /// The edits are discarded afterwards, as the opened files don't contain them.
+    pub fn save_to_dir(&mut self, out_dir: &Path) -> Result<Vec<SavedFile>, Error> {
+        self.save_files(false, Some(out_dir), Some(out_dir), |_, _| ())
+    }
+
+    fn save_files<F: FnMut(usize, usize)>(
+        &mut self,
+        backup: bool,
+        temp_dir: Option<&Path>,
+        out_dir: Option<&Path>,
+        mut progress: F,
+    ) -> Result<Vec<SavedFile>, Error> {
+        let start_time = Instant::now();
+        if let Some(dir) = temp_dir {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create {}", dir.display()))?;
+        }
+        let edit_count = self.edits.len();
+        let mut result: Result<(), Error> = Ok(());
+        let mut saved = Vec::new();
         {
             let mut temp_files = Vec::new();
-            let mut sd_edits = Vec::new();
-            let mut sd_textures = Vec::new();
+            let has_sd_edits = self.edits.iter().any(|(&key, edit)| is_mainsd_edit(key, edit));
             for (&(sprite, ty), edit) in self.edits.iter() {
                 let is_anim = match edit {
                     Edit::Grp(..) => false,
                     Edit::Ref(..) | Edit::Values(..) => true,
                 };
-                let is_mainsd_edit = ty == SpriteType::Sd && is_anim;
-                if !is_mainsd_edit {
+                if !is_mainsd_edit((sprite, ty), edit) {
                     let path = match separate_file_path(&self.sprites, sprite, ty) {
                         Some(s) => s,
                         None => {
                             return Err(anyhow!("No path for sprite {}/{:?}", sprite, ty));
                         }
                     };
-                    let out_path = temp_file_path(&path);
-                    if let Some(parent) = out_path.parent() {
+                    let out_path = temp_file_path(&path, temp_dir);
+                    if let Some(parent) = path.parent().filter(|_| out_dir.is_none()) {
                         if !parent.exists() {
                             fs::create_dir_all(&parent)
                                 .with_context(|| {
@@ -1346,9 +2228,22 @@ impl Files {
                                     },
                                     None => &[],
                                 };
+                                let frame_count = match edit.tex_changes {
+                                    Some(ref s) => Some(s.frames.len()),
+                                    None => anim.frames(0).map(|x| x.len()),
+                                };
+                                saved.push(SavedFile {
+                                    path: path.into(),
+                                    contents: SavedContents::Anim(
+                                        1,
+                                        frame_count.map(|x| (0, x)).into_iter().collect(),
+                                    ),
+                                });
+                                // Keep the existing scale, HD2 may not be the default
+                                // half of HD.
                                 anim.write_patched(
                                     &mut out,
-                                    scale,
+                                    anim.scale(),
                                     1,
                                     &layer_names,
                                     &[(0, anim::ValuesOrRef::Values(edit.values))],
@@ -1371,6 +2266,13 @@ impl Files {
                                 let sprites = [
                                     (anim::ValuesOrRef::Values(edit.values), tex_edit),
                                 ];
+                                saved.push(SavedFile {
+                                    path: path.into(),
+                                    contents: SavedContents::Anim(
+                                        1,
+                                        vec![(0, tex_edit.frames.len())],
+                                    ),
+                                });
                                 anim::Anim::write_new(
                                     &mut out,
                                     scale,
@@ -1386,46 +2288,27 @@ impl Files {
                     } else {
                         if let Edit::Grp(ref edits, scale, ref palette) = *edit {
                             ddsgrp::DdsGrp::write(&mut out, scale, &edits, palette.as_deref())?;
+                            saved.push(SavedFile {
+                                path: path.into(),
+                                contents: SavedContents::DdsGrp(edits.len() as u16),
+                            });
                         }
                     }
-                } else {
-                    match *edit {
-                        Edit::Ref(r) => {
-                            sd_edits.push((sprite, anim::ValuesOrRef::Ref(r)));
-                        }
-                        Edit::Values(ref e) => {
-                            sd_edits.push((sprite, anim::ValuesOrRef::Values(e.values)));
-                            if let Some(ref tex) = e.tex_changes {
-                                sd_textures.push((sprite, tex));
-                            }
-                        }
-                        Edit::Grp(..) => unreachable!(),
-                    }
                 }
             }
-            if !sd_edits.is_empty() {
-                if let Some((ref sd_path, ref sd)) = self.mainsd_anim {
-                    let sprite_count = self.new_entry_count
-                        .unwrap_or_else(|| sd.sprites().len() as u16);
-                    let layer_names = sd.layer_names();
-                    let out_path = temp_file_path(&sd_path);
+            if has_sd_edits {
+                if let Some((ref sd_path, _)) = self.mainsd_anim {
+                    let out_path = temp_file_path(&sd_path, temp_dir);
                     let mut out = fs::File::create(&out_path).with_context(|| {
                         format!("Unable to create {}", out_path.to_string_lossy())
                     })?;
-                    sd.write_patched(
-                        &mut out,
-                        sd.scale(),
-                        sprite_count,
-                        &layer_names,
-                        &sd_edits,
-                        &sd_textures,
-                    )?;
+                    saved.push(self.write_mainsd(&mut out)?);
                     temp_files.push((out_path, sd_path.clone()));
                 }
             }
             if let Some(lit) = self.lit() {
                 if lit.has_changes() {
-                    let out_path = temp_file_path(&lit.path);
+                    let out_path = temp_file_path(&lit.path, temp_dir);
                     let out = fs::File::create(&out_path).with_context(|| {
                         format!("Unable to create {}", out_path.to_string_lossy())
                     })?;
@@ -1441,14 +2324,16 @@ impl Files {
                         None => return Err(anyhow!("Can't save images.rel without root path")),
                     };
                     let root = &file_root.root;
-                    let _ = fs::create_dir(root.join("SD"));
+                    if out_dir.is_none() {
+                        let _ = fs::create_dir(root.join("SD"));
+                    }
                     // There are two images.rel files, SC:R seems to randomly select one of
                     // them so they need to be similar in things which matter <.<
                     // (They're not originally 100% similar)
                     let path1 = root.join("images.rel");
                     let path2 = root.join("SD/images.rel");
                     for &path in &[&path1, &path2] {
-                        let out_path = temp_file_path(&path);
+                        let out_path = temp_file_path(&path, temp_dir);
                         let out = fs::File::create(&out_path).with_context(|| {
                             format!("Unable to create {}", out_path.to_string_lossy())
                         })?;
@@ -1460,17 +2345,41 @@ impl Files {
             }
             self.open_files.clear();
             let mut sd_path = None;
-            if !sd_edits.is_empty() {
+            // Saving to `out_dir` won't replace the open mainsd
+            if has_sd_edits && out_dir.is_none() {
                 // Closing mainsd
                 sd_path = self.mainsd_anim.take().map(|x| x.0);
             }
-            for (temp, dest) in temp_files {
-                result = fs::rename(temp, dest);
+            let total_files = temp_files.len();
+            let mut replaced = Vec::with_capacity(total_files);
+            let root = self.root_path().map(|x| x.to_owned());
+            for (i, (temp, dest)) in temp_files.into_iter().enumerate() {
+                let dest = match out_dir {
+                    Some(dir) => output_file_path(dir, root.as_deref(), &dest),
+                    None => dest,
+                };
+                if let Some(parent) = dest.parent() {
+                    if out_dir.is_some() && !parent.exists() {
+                        if let Err(e) = fs::create_dir_all(parent) {
+                            let _ = fs::remove_file(&temp);
+                            result = Err(e)
+                                .with_context(|| format!("Unable to create {}", parent.display()));
+                            restore_replaced_files(&replaced);
+                            break;
+                        }
+                    }
+                }
+                result = replace_file(&temp, &dest, backup, &mut replaced);
                 if result.is_err() {
+                    restore_replaced_files(&replaced);
                     break;
                 }
+                progress(i + 1, total_files);
+            }
+            if result.is_ok() {
+                remove_old_files(&replaced);
             }
-            if !sd_edits.is_empty() {
+            if has_sd_edits {
                 if let Some(sd_path) = sd_path {
                     let mainsd = load_mainsd(&sd_path)?;
                     self.mainsd_anim = Some((sd_path, mainsd));
@@ -1492,7 +2401,57 @@ impl Files {
             }
         }
 
-        Ok(result?)
+        result?;
+        debug!(
+            "Saved {} edits to {} files in {:?}",
+            edit_count, saved.len(), start_time.elapsed(),
+        );
+        Ok(saved)
+    }
+
+    /// Writes mainsd with the pending SD anim edits applied to `out`.
+    ///
+    /// The edits are not cleared, `save` does that once all files have been written.
+    pub fn write_mainsd<W: Write + Seek>(&self, out: W) -> Result<SavedFile, Error> {
+        let (sd_path, sd) = self.mainsd_anim.as_ref()
+            .ok_or_else(|| anyhow!("No mainsd is open"))?;
+        let mut sd_edits = Vec::new();
+        let mut sd_textures = Vec::new();
+        for (&(sprite, ty), edit) in self.edits.iter() {
+            if !is_mainsd_edit((sprite, ty), edit) {
+                continue;
+            }
+            match *edit {
+                Edit::Ref(r) => {
+                    sd_edits.push((sprite, anim::ValuesOrRef::Ref(r)));
+                }
+                Edit::Values(ref e) => {
+                    sd_edits.push((sprite, anim::ValuesOrRef::Values(e.values)));
+                    if let Some(ref tex) = e.tex_changes {
+                        sd_textures.push((sprite, tex));
+                    }
+                }
+                Edit::Grp(..) => unreachable!(),
+            }
+        }
+        let sprite_count = self.new_entry_count
+            .unwrap_or_else(|| sd.sprites().len() as u16);
+        let layer_names = sd.layer_names();
+        sd.write_patched(
+            out,
+            sd.scale(),
+            sprite_count,
+            &layer_names,
+            &sd_edits,
+            &sd_textures,
+        )?;
+        let frame_counts = sd_textures.iter()
+            .map(|&(sprite, tex)| (sprite, tex.frames.len()))
+            .collect();
+        Ok(SavedFile {
+            path: sd_path.clone(),
+            contents: SavedContents::Anim(sprite_count, frame_counts),
+        })
     }
 
     /// Returns width/height of the grp that is referenced in images.dat.
@@ -1644,16 +2603,176 @@ fn image_grp_path(
     Ok(string)
 }
 
-fn temp_file_path(orig_file: &Path) -> PathBuf {
-    let mut buf: PathBuf = orig_file.into();
-    let temp_name = {
-        let orig_name = buf.file_name()
+/// A file that `save_with_progress` has moved over its destination.
+struct ReplacedFile {
+    path: PathBuf,
+    /// Backup that was made of the original file during this save.
+    backup: Option<PathBuf>,
+    /// The original file, kept until the save finishes so that it can be restored
+    /// regardless of backups. None if there was no file at `path` before saving.
+    old: Option<PathBuf>,
+}
+
+fn backup_file_path(orig_file: &Path) -> PathBuf {
+    let mut name = orig_file.file_name()
+        .map(|x| x.to_os_string())
+        .unwrap_or_default();
+    name.push(".bak");
+    orig_file.with_file_name(name)
+}
+
+/// Path that the original `orig_file` is moved to while it is being replaced.
+fn old_file_path(orig_file: &Path) -> PathBuf {
+    let orig_name = orig_file.file_name()
         .map(|x| x.to_string_lossy())
         .unwrap_or("".into());
-        format!("__temp__{}", orig_name)
+    orig_file.with_file_name(format!("__old__{}", orig_name))
+}
+
+/// Moves `temp` over `dest`, first copying `dest` to a backup if requested.
+/// The original `dest` is moved aside until `remove_old_files` or
+/// `restore_replaced_files` is called.
+fn replace_file(
+    temp: &Path,
+    dest: &Path,
+    backup: bool,
+    replaced: &mut Vec<ReplacedFile>,
+) -> Result<(), Error> {
+    let existed = dest.is_file();
+    let mut backup_path = None;
+    if backup && existed {
+        let path = backup_file_path(dest);
+        if path.exists() {
+            info!("Keeping existing backup {}", path.display());
+        } else {
+            fs::copy(dest, &path).with_context(|| {
+                format!("Unable to back up {} to {}", dest.display(), path.display())
+            })?;
+            backup_path = Some(path);
+        }
+    }
+    let old_path = match existed {
+        true => {
+            let path = old_file_path(dest);
+            let result = fs::rename(dest, &path)
+                .with_context(|| format!("Unable to move {} aside", dest.display()));
+            if let Err(e) = result {
+                if let Some(backup) = backup_path {
+                    let _ = fs::remove_file(backup);
+                }
+                return Err(e);
+            }
+            Some(path)
+        }
+        false => None,
+    };
+    let result = move_file(temp, dest)
+        .with_context(|| format!("Unable to replace {}", dest.display()));
+    if result.is_err() {
+        if let Some(ref path) = old_path {
+            if let Err(e) = fs::rename(path, dest) {
+                error!("Unable to restore {}: {}", dest.display(), e);
+            }
+        }
+        if let Some(path) = backup_path {
+            // The original wasn't changed, the backup isn't needed.
+            let _ = fs::remove_file(path);
+        }
+        return result;
+    }
+    replaced.push(ReplacedFile {
+        path: dest.into(),
+        backup: backup_path,
+        old: old_path,
+    });
+    Ok(())
+}
+
+/// Removes the originals that `replace_file` moved aside, once every file of
+/// the save has been replaced.
+fn remove_old_files(replaced: &[ReplacedFile]) {
+    for path in replaced.iter().filter_map(|x| x.old.as_ref()) {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Unable to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Undoes `replace_file` for files of a save that failed partway through,
+/// so that the files aren't left as a mix of old and new versions.
+fn restore_replaced_files(replaced: &[ReplacedFile]) {
+    for file in replaced.iter().rev() {
+        let result = match file.old {
+            Some(ref old) => fs::rename(old, &file.path),
+            None => fs::remove_file(&file.path),
+        };
+        match result {
+            Ok(()) => info!("Restored {}", file.path.display()),
+            Err(e) => error!("Unable to restore {}: {}", file.path.display(), e),
+        }
+    }
+}
+
+/// Renames `temp` to `dest`, or copies it next to `dest` and renames that if they are
+/// on different filesystems, so that `dest` is never left partially written.
+fn move_file(temp: &Path, dest: &Path) -> Result<(), Error> {
+    let rename_error = match fs::rename(temp, dest) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
     };
-    buf.set_file_name(temp_name);
-    buf
+    if temp.parent() == dest.parent() {
+        return Err(rename_error.into());
+    }
+    // Renaming fails if the temp directory is on another filesystem,
+    // and it isn't practical to tell that error apart from others.
+    info!(
+        "Unable to rename {} ({}), copying it instead", temp.display(), rename_error,
+    );
+    let copy = temp_file_path(dest, None);
+    let result = fs::copy(temp, &copy)
+        .with_context(|| {
+            format!(
+                "Unable to copy {} after renaming failed ({})", temp.display(), rename_error,
+            )
+        })
+        .and_then(|_| {
+            fs::rename(&copy, dest)
+                .with_context(|| format!("Unable to rename {}", copy.display()))
+        });
+    if let Err(e) = result {
+        if let Err(e) = fs::remove_file(&copy) {
+            warn!("Unable to remove {}: {}", copy.display(), e);
+        }
+        return Err(e);
+    }
+    if let Err(e) = fs::remove_file(temp) {
+        warn!("Unable to remove {} after copying: {}", temp.display(), e);
+    }
+    Ok(())
+}
+
+/// Path that the new version of `orig_file` is written to before replacing it.
+/// Path of `file` in `out_dir` when saving with `Files::save_to_dir`.
+fn output_file_path(out_dir: &Path, root: Option<&Path>, file: &Path) -> PathBuf {
+    match root.and_then(|root| file.strip_prefix(root).ok()) {
+        Some(relative) => out_dir.join(relative),
+        None => out_dir.join(file.file_name().unwrap_or_default()),
+    }
+}
+
+fn temp_file_path(orig_file: &Path, temp_dir: Option<&Path>) -> PathBuf {
+    let orig_name = orig_file.file_name()
+        .map(|x| x.to_string_lossy())
+        .unwrap_or("".into());
+    match temp_dir {
+        Some(dir) => {
+            // Files from different directories share names (e.g. HD and HD2 anims)
+            let mut hasher = DefaultHasher::new();
+            orig_file.hash(&mut hasher);
+            dir.join(format!("__temp__{:016x}_{}", hasher.finish(), orig_name))
+        }
+        None => orig_file.with_file_name(format!("__temp__{}", orig_name)),
+    }
 }
 
 fn file_location<'a>(
@@ -1681,8 +2800,7 @@ fn file_location<'a>(
                 .with_context(|| format!("Opening {}", f.display()))?;
             let grp = ddsgrp::DdsGrp::read(file)
                 .with_context(|| format!("Parsing {}", f.display()))?;
-            open_files.grp.push((grp, sprite, ty));
-            Ok(Some(FileLocation::DdsGrp(&open_files.grp.last().unwrap().0)))
+            Ok(Some(FileLocation::DdsGrp(open_files.push_grp(grp, sprite, ty))))
         }
         None => Ok(None),
     }
@@ -1718,6 +2836,28 @@ fn separate_file_path(sprites: &[SpriteFiles], sprite: usize, ty: SpriteType) ->
     Some(&path)
 }
 
+/// True if there is a HD/HD2 file for the sprite, either on disk or to be created
+/// from unsaved changes.
+fn separate_file_exists(
+    sprites: &[SpriteFiles],
+    sprite: usize,
+    ty: SpriteType,
+    edits: &HashMap<(usize, SpriteType), Edit>,
+) -> bool {
+    let path = match separate_file_path(sprites, sprite, ty) {
+        Some(p) => p,
+        None => return false,
+    };
+    if path.is_file() {
+        return true;
+    }
+    // Same as `file_location_hd`, which creates the file from edits if it doesn't exist
+    match edits.get(&(sprite, ty)) {
+        Some(Edit::Values(edit)) => edit.tex_changes.is_some(),
+        _ => false,
+    }
+}
+
 fn file_location_hd<'a>(
     open_files: &'a mut OpenFiles,
     sprites: &[SpriteFiles],
@@ -1726,8 +2866,8 @@ fn file_location_hd<'a>(
     layer_names: &[String],
     edits: &HashMap<(usize, SpriteType), Edit>,
 ) -> Result<Option<FileLocation<'a>>, Error> {
-    if let Some(index) = open_files.anim.iter().position(|x| x.1 == sprite && x.2 == ty) {
-        return Ok(Some(FileLocation::Separate(&open_files.anim[index].0)));
+    if open_files.touch_anim(sprite, ty) {
+        return Ok(Some(FileLocation::Separate(&open_files.anim.last().unwrap().0)));
     }
     let path = match separate_file_path(sprites, sprite, ty) {
         Some(p) => p,
@@ -1737,8 +2877,7 @@ fn file_location_hd<'a>(
         Ok(file) => {
             let anim = anim::Anim::read(file)
                 .with_context(|| format!("Reading {}", path.display()))?;
-            open_files.anim.push((anim, sprite, ty));
-            Ok(Some(FileLocation::Separate(&open_files.anim.last_mut().unwrap().0)))
+            Ok(Some(FileLocation::Separate(open_files.push_anim(anim, sprite, ty))))
         }
         Err(e) if e.kind() == io::ErrorKind::NotFound => {
             // May be reached if anims have been resized before written to disk
@@ -1763,9 +2902,8 @@ fn file_location_hd<'a>(
                     ).with_context(|| format!("Writing {}", path.display()))?;
                     out.set_position(0);
                     let anim = anim::Anim::read(out)?;
-                    open_files.anim.push((anim, sprite, ty));
                     return Ok(Some(
-                        FileLocation::Separate(&open_files.anim.last_mut().unwrap().0)
+                        FileLocation::Separate(open_files.push_anim(anim, sprite, ty))
                     ))
                 }
             }
@@ -1822,6 +2960,46 @@ impl FileRoot {
     }
 }
 
+/// Finds entry `name` in `dir`; case-insensitively if there isn't an exact match.
+fn find_dir_entry_casei(dir: &Path, name: &str) -> Option<PathBuf> {
+    let exact = dir.join(name);
+    if exact.exists() {
+        return Some(exact);
+    }
+    fs::read_dir(dir).ok()?
+        .filter_map(|x| x.ok())
+        .find(|x| x.file_name().to_str().map(|x| x.eq_ignore_ascii_case(name)) == Some(true))
+        .map(|x| x.path())
+}
+
+/// Returns the anim file that should be opened when a directory is opened.
+/// Prefers `SD/mainSD.anim`, but falls back to the lowest `main_NNN.anim` in
+/// `anim` or `HD2/anim` if the directory doesn't have SD files.
+fn anim_file_in_root_dir(dir: &Path) -> Result<PathBuf, Error> {
+    let mainsd = find_dir_entry_casei(dir, "SD")
+        .and_then(|sd| find_dir_entry_casei(&sd, "mainSD.anim"))
+        .filter(|x| x.is_file());
+    if let Some(path) = mainsd {
+        return Ok(path);
+    }
+    let hd_dirs = [
+        find_dir_entry_casei(dir, "anim"),
+        find_dir_entry_casei(dir, "HD2").and_then(|hd2| find_dir_entry_casei(&hd2, "anim")),
+    ];
+    for anim_dir in hd_dirs.iter().flatten() {
+        let mut files = anim_files_in_dir(anim_dir)?;
+        if let Some(&lowest) = files.keys().min() {
+            if let Some(path) = files.remove(&lowest) {
+                return Ok(path);
+            }
+        }
+    }
+    Err(anyhow!(
+        "{} doesn't contain SD/mainSD.anim, anim/main_NNN.anim or HD2/anim/main_NNN.anim",
+        dir.display(),
+    ))
+}
+
 fn file_root_from_file(file: &Path) -> Option<FileRoot> {
     let filename = file.file_name()
         .and_then(|f| f.to_str())?;
@@ -1938,6 +3116,120 @@ fn ddsgrp_linked_grp(path: &Path) -> Option<PathBuf> {
         })
 }
 
+/// Most refs `check_ref_chain` follows; the game itself only follows a single ref.
+const MAX_REF_CHAIN: usize = 16;
+
+/// Returns an error if following refs of SD `sprite` ends up in a cycle.
+fn check_ref_chain(
+    mainsd: Option<&anim::Anim>,
+    edits: &HashMap<(usize, SpriteType), Edit>,
+    sprite: usize,
+) -> Result<(), Error> {
+    let mut chain = vec![sprite];
+    let mut current = sprite;
+    loop {
+        let next = match edits.get(&(current, SpriteType::Sd)) {
+            Some(&Edit::Ref(img)) => img as usize,
+            Some(_) => return Ok(()),
+            None => match mainsd.and_then(|x| x.values_or_ref(current)) {
+                Some(anim::ValuesOrRef::Ref(img)) => img as usize,
+                _ => return Ok(()),
+            },
+        };
+        let is_cycle = chain.contains(&next);
+        chain.push(next);
+        if is_cycle || chain.len() > MAX_REF_CHAIN {
+            let text = chain.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" -> ");
+            return Err(match is_cycle {
+                true => anyhow!("Sprite references form a cycle: {}", text),
+                false => anyhow!("Sprite reference chain is too long: {}", text),
+            });
+        }
+        current = next;
+    }
+}
+
+/// Quotes a CSV field if it contains characters that need escaping.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+/// Re-reads files written by `Files::save`, checking that they parse and that
+/// their sprite/frame counts match what was written.
+pub fn verify_saved_files(files: &[SavedFile]) -> Result<(), Error> {
+    let mut errors = Vec::new();
+    for saved in files {
+        let result = fs::File::open(&saved.path)
+            .map_err(Error::from)
+            .and_then(|file| verify_saved_file(file, &saved.contents));
+        if let Err(e) = result {
+            errors.push(format!("{}: {:?}", saved.path.display(), e));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Saved files differ from what was written:\n{}", errors.join("\n")))
+    }
+}
+
+fn verify_saved_file<R: Read + Seek + Send + 'static>(
+    file: R,
+    contents: &SavedContents,
+) -> Result<(), Error> {
+    match *contents {
+        SavedContents::Anim(sprite_count, ref frame_counts) => {
+            let anim = anim::Anim::read(file).context("Unable to parse anim")?;
+            let actual = anim.sprites().len();
+            if actual != sprite_count as usize {
+                return Err(anyhow!("Expected {} sprites, got {}", sprite_count, actual));
+            }
+            for &(sprite, frame_count) in frame_counts {
+                let actual = anim.frames(sprite).map(|x| x.len()).unwrap_or(0);
+                if actual != frame_count {
+                    return Err(anyhow!(
+                        "Expected sprite {} to have {} frames, got {}",
+                        sprite, frame_count, actual,
+                    ));
+                }
+            }
+        }
+        SavedContents::DdsGrp(frame_count) => {
+            let grp = ddsgrp::DdsGrp::read(file).context("Unable to parse dds.grp")?;
+            if grp.frame_count != frame_count {
+                return Err(anyhow!(
+                    "Expected {} frames, got {}",
+                    frame_count, grp.frame_count,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_verify_saved_ddsgrp() {
+    let frame = ddsgrp::Frame {
+        unknown: 0,
+        width: 4,
+        height: 4,
+        size: 0,
+        offset: !0,
+    };
+    let frames = vec![(frame.clone(), vec![0u8; 16]), (frame, vec![0u8; 16])];
+    let mut out = Cursor::new(Vec::new());
+    ddsgrp::DdsGrp::write(&mut out, 1, &frames, None).unwrap();
+    let data = out.into_inner();
+    assert!(verify_saved_file(Cursor::new(data.clone()), &SavedContents::DdsGrp(2)).is_ok());
+    assert!(verify_saved_file(Cursor::new(data.clone()), &SavedContents::DdsGrp(3)).is_err());
+    let truncated = data[..data.len() / 2].to_vec();
+    assert!(verify_saved_file(Cursor::new(truncated), &SavedContents::DdsGrp(2)).is_err());
+}
+
 #[test]
 fn test_ddsgrp_linked_grp() {
     let normalize = |x: PathBuf| x.display().to_string().to_ascii_lowercase().replace("\\", "/");
@@ -2020,3 +3312,454 @@ fn test_file_root_from_file() {
     assert_eq!(root.region, Some(PathBuf::from("CN")));
     assert_eq!(root.skin, None);
 }
+
+#[test]
+fn test_replace_file_backup() {
+    let dir = std::env::temp_dir().join(format!("animosity_backup_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dest = dir.join("main_000.anim");
+    let temp = temp_file_path(&dest, None);
+    fs::write(&dest, b"old").unwrap();
+    fs::write(&temp, b"new").unwrap();
+    let mut replaced = Vec::new();
+    replace_file(&temp, &dest, true, &mut replaced).unwrap();
+    assert_eq!(fs::read(&dest).unwrap(), b"new");
+    assert_eq!(fs::read(dir.join("main_000.anim.bak")).unwrap(), b"old");
+    remove_old_files(&replaced);
+    assert!(!old_file_path(&dest).exists());
+    // Existing backups are not overwritten
+    fs::write(&temp, b"newer").unwrap();
+    let mut replaced2 = Vec::new();
+    replace_file(&temp, &dest, true, &mut replaced2).unwrap();
+    assert!(replaced2[0].backup.is_none());
+    assert_eq!(fs::read(dir.join("main_000.anim.bak")).unwrap(), b"old");
+
+    // Restores the version from before this save, not the older backup
+    restore_replaced_files(&replaced2);
+    assert_eq!(fs::read(&dest).unwrap(), b"new");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_restore_replaced_files_without_backup() {
+    let dir = std::env::temp_dir().join(format!("animosity_restore_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dest = dir.join("main_000.anim");
+    let new_dest = dir.join("main_001.anim");
+    fs::write(&dest, b"old").unwrap();
+    let mut replaced = Vec::new();
+    for path in &[&dest, &new_dest] {
+        let temp = temp_file_path(path, None);
+        fs::write(&temp, b"new").unwrap();
+        replace_file(&temp, path, false, &mut replaced).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"new");
+    }
+    assert!(!dir.join("main_000.anim.bak").exists());
+    restore_replaced_files(&replaced);
+    assert_eq!(fs::read(&dest).unwrap(), b"old");
+    assert!(!new_dest.exists());
+    assert!(!old_file_path(&dest).exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_save_to_dir() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_save_to_dir_test_{}", std::process::id()));
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd(dir.join("in/mainSD.anim"), mainsd).unwrap();
+    files.set_frame_unknown(1, SpriteType::Sd, 0, 5).unwrap();
+    files.save_to_dir(&dir.join("out")).unwrap();
+    assert!(!dir.join("in").exists());
+    let out = anim::Anim::read(fs::File::open(dir.join("out/mainSD.anim")).unwrap()).unwrap();
+    assert_eq!(out.frames(1).unwrap()[0].unknown, 5);
+    assert_eq!(out.frames(0).unwrap()[0].unknown, 0);
+    assert_eq!(
+        output_file_path(Path::new("out"), Some(Path::new("a/b")), Path::new("a/b/HD2/x.anim")),
+        Path::new("out/HD2/x.anim"),
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_temp_dir_paths() {
+    let temp_dir = Path::new("/tmp/animosity");
+    let hd = Path::new("/game/anim/main_000.anim");
+    let hd2 = Path::new("/game/HD2/anim/main_000.anim");
+    assert_eq!(temp_file_path(hd, None), Path::new("/game/anim/__temp__main_000.anim"));
+    let hd_temp = temp_file_path(hd, Some(temp_dir));
+    let hd2_temp = temp_file_path(hd2, Some(temp_dir));
+    assert_eq!(hd_temp.parent(), Some(temp_dir));
+    assert_ne!(hd_temp, hd2_temp);
+    assert!(hd_temp.to_string_lossy().ends_with("_main_000.anim"));
+}
+
+#[test]
+fn test_hd_layer_names_skip_corrupt() {
+    let dir = std::env::temp_dir().join(format!("animosity_corrupt_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("anim")).unwrap();
+    fs::write(dir.join("anim/main_000.anim"), b"ANIM\x01").unwrap();
+    fs::write(dir.join("anim/main_001.anim"), test_anim(4, 1)).unwrap();
+    let names = hd_layer_names_from_root(&dir, 2).unwrap();
+    assert_eq!(names, vec![String::from("diffuse"), String::from("teamcolor")]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hd2_only_tree() {
+    let dir = std::env::temp_dir().join(format!("animosity_hd2_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("HD2/anim")).unwrap();
+    fs::write(dir.join("HD2/anim/main_000.anim"), test_anim(2, 1)).unwrap();
+    fs::write(dir.join("HD2/anim/main_004.anim"), test_anim(2, 1)).unwrap();
+    let (files, index) = Files::init(&dir.join("HD2/anim/main_004.anim")).unwrap();
+    assert_eq!(index, Some(4));
+    assert_eq!(files.sprites().len(), 5);
+    assert_eq!(files.preferred_sprite_type(), SpriteType::Hd2);
+    assert_eq!(files.hd_layer_names, vec![String::from("diffuse"), String::from("teamcolor")]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hd2_repack_round_trip() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_hd2_repack_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("HD2/anim")).unwrap();
+    fs::write(dir.join("HD2/anim/main_000.anim"), test_anim(2, 1)).unwrap();
+    let (mut files, _) = Files::init(&dir.join("HD2/anim/main_000.anim")).unwrap();
+    // The 4x4 frame is in HD units, so it only has 2x2 pixels of the texture
+    let copied = files.extract_frame(0, SpriteType::Hd2, 0).unwrap();
+    assert_eq!(copied.frame.layers[0].as_ref().map(|x| x.len()), Some(2 * 2 * 4));
+    files.insert_frame(0, SpriteType::Hd2, 1, &copied).unwrap();
+    texture_repack::compact_textures(&mut files, 0, SpriteType::Hd2).unwrap();
+    let file = files.file(0, SpriteType::Hd2).unwrap().unwrap();
+    let texture = file.texture(0).unwrap();
+    assert_eq!((texture.width, texture.height), (4, 4));
+    let frames = file.frames().unwrap();
+    assert_eq!(frames.len(), 2);
+    for f in frames {
+        assert_eq!((f.width, f.height), (4, 4));
+        let (x, y) = (u32::from(f.tex_x) / 2, u32::from(f.tex_y) / 2);
+        assert!(x + 2 <= texture.width && y + 2 <= texture.height);
+        assert_eq!(texture.data[((y * texture.width + x) * 4 + 3) as usize], 255);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_anim_file_in_root_dir() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_root_dir_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("HD2/anim")).unwrap();
+    assert!(anim_file_in_root_dir(&dir).is_err());
+    fs::write(dir.join("HD2/anim/main_007.anim"), test_anim(2, 1)).unwrap();
+    fs::write(dir.join("HD2/anim/main_004.anim"), test_anim(2, 1)).unwrap();
+    assert_eq!(anim_file_in_root_dir(&dir).unwrap(), dir.join("HD2/anim/main_004.anim"));
+    fs::create_dir_all(dir.join("anim")).unwrap();
+    fs::write(dir.join("anim/main_002.anim"), test_anim(4, 1)).unwrap();
+    assert_eq!(anim_file_in_root_dir(&dir).unwrap(), dir.join("anim/main_002.anim"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Anim with `sprite_count` identical 4x4 sprites.
+#[cfg(test)]
+pub(crate) fn test_anim(scale: u8, sprite_count: usize) -> Vec<u8> {
+    let layer_names = vec![String::from("diffuse"), String::from("teamcolor")];
+    let data = anim_encoder::encode(&[255u8; 4 * 4 * 4], 4, 4, anim::TextureFormat::Dxt5);
+    let texture = anim::Texture {
+        offset: 0,
+        size: data.len() as u32,
+        width: 4,
+        height: 4,
+    };
+    let tex_changes = anim::TexChanges {
+        frames: vec![anim::Frame {
+            tex_x: 0,
+            tex_y: 0,
+            x_off: 0,
+            y_off: 0,
+            width: 4,
+            height: 4,
+            unknown: 0,
+        }],
+        textures: vec![Some((texture, data)), None],
+    };
+    let values = anim::ValuesOrRef::Values(SpriteValues {
+        width: 4,
+        height: 4,
+    });
+    let sprites = vec![(values, &tex_changes); sprite_count];
+    let mut out = Cursor::new(Vec::new());
+    anim::Anim::write_new(&mut out, scale, &layer_names, &sprites).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn test_open_files_lru() {
+    let anim = || anim::Anim::read(Cursor::new(test_anim(4, 1))).unwrap();
+    let open = |files: &OpenFiles| files.anim.iter().map(|x| x.1).collect::<Vec<_>>();
+    let mut files = OpenFiles::with_limit(2);
+    files.push_anim(anim(), 0, SpriteType::Hd);
+    files.push_anim(anim(), 1, SpriteType::Hd);
+    assert!(files.touch_anim(0, SpriteType::Hd));
+    assert!(!files.touch_anim(0, SpriteType::Hd2));
+    files.push_anim(anim(), 2, SpriteType::Hd);
+    assert_eq!(open(&files), vec![0, 2]);
+    assert!(!files.touch_anim(1, SpriteType::Hd));
+    files.push_anim(anim(), 1, SpriteType::Hd);
+    assert_eq!(open(&files), vec![2, 1]);
+    files.clear();
+    assert!(open(&files).is_empty());
+}
+
+#[test]
+fn test_mainsd_ref_round_trip() {
+    let layer_names = vec![String::from("diffuse"), String::from("teamcolor")];
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    files.set_ref_img(1, SpriteType::Sd, 0);
+    let mut out = Cursor::new(Vec::new());
+    let saved = files.write_mainsd(&mut out).unwrap();
+    assert_eq!(saved.path, Path::new("mainSD.anim"));
+
+    let written = anim::Anim::read(Cursor::new(out.into_inner())).unwrap();
+    assert_eq!(written.sprites().len(), 2);
+    assert_eq!(written.layer_names(), &layer_names[..]);
+    match written.sprites()[1] {
+        anim::SpriteType::Ref(0) => (),
+        _ => panic!("Sprite 1 was not written as a ref"),
+    }
+    assert_eq!(written.frames(0).map(|x| x.len()), Some(1));
+    assert!(Files::from_mainsd("mainSD.anim".into(), Cursor::new(Vec::new())).is_err());
+}
+
+#[test]
+fn test_set_frame_unknown() {
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    assert!(files.set_frame_unknown(1, SpriteType::Sd, 0, 5).unwrap());
+    assert!(!files.set_frame_unknown(1, SpriteType::Sd, 0, 5).unwrap());
+    assert!(files.set_frame_unknown(1, SpriteType::Sd, 1, 5).is_err());
+    {
+        let file = files.file(1, SpriteType::Sd).unwrap().unwrap();
+        assert_eq!(file.frames().unwrap()[0].unknown, 5);
+        assert!(file.texture_size(0).is_some());
+    }
+    let mut out = Cursor::new(Vec::new());
+    files.write_mainsd(&mut out).unwrap();
+    let written = anim::Anim::read(Cursor::new(out.into_inner())).unwrap();
+    assert_eq!(written.frames(0).unwrap()[0].unknown, 0);
+    assert_eq!(written.frames(1).unwrap()[0].unknown, 5);
+    assert!(written.texture_sizes(1).unwrap()[0].is_some());
+}
+
+#[test]
+fn test_deref_sprite() {
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    assert!(files.deref_sprite(1, SpriteType::Sd).is_err());
+    files.set_ref_img(1, SpriteType::Sd, 0);
+    let before = {
+        let file = files.file(1, SpriteType::Sd).unwrap().unwrap();
+        (file.frames().unwrap().to_vec(), file.texture(0).unwrap().data)
+    };
+    files.deref_sprite(1, SpriteType::Sd).unwrap();
+    {
+        let file = files.file(1, SpriteType::Sd).unwrap().unwrap();
+        assert_eq!(file.image_ref(), None);
+        assert_eq!(file.frames().unwrap(), &before.0[..]);
+        assert_eq!(file.texture(0).unwrap().data, before.1);
+    }
+    // Editing the copy doesn't affect the sprite it was copied from
+    assert!(files.set_frame_unknown(1, SpriteType::Sd, 0, 3).unwrap());
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    assert_eq!(file.frames().unwrap()[0].unknown, 0);
+}
+
+#[test]
+fn test_remove_frames() {
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    let copied = files.extract_frame(0, SpriteType::Sd, 0).unwrap();
+    for i in 1..3 {
+        files.insert_frame(0, SpriteType::Sd, i, &copied).unwrap();
+        files.set_frame_offset(0, SpriteType::Sd, i, i as i16 * 4, 0).unwrap();
+        files.set_frame_unknown(0, SpriteType::Sd, i, i as u32).unwrap();
+    }
+    assert!(files.remove_frames(0, SpriteType::Sd, &[3]).is_err());
+    assert!(files.remove_frames(0, SpriteType::Sd, &[0, 1, 2]).is_err());
+    let skipped = files.remove_frames(0, SpriteType::Sd, &[0, 2]).unwrap();
+    assert!(skipped.is_empty());
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    let frames = file.frames().unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!((frames[0].x_off, frames[0].width, frames[0].unknown), (4, 4, 1));
+    let decoded = texture_repack::decode_frames(&file).unwrap();
+    assert_eq!(decoded.frames[0].layers[0], Some(vec![255; 4 * 4 * 4]));
+    assert_eq!(decoded.frames[0].layers[1], None);
+    // Other sprites are not affected
+    let file = files.file(1, SpriteType::Sd).unwrap().unwrap();
+    assert_eq!(file.frames().unwrap().len(), 1);
+}
+
+#[test]
+fn test_set_frame_offset() {
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    assert!(files.set_frame_offset(0, SpriteType::Sd, 0, -3, 7).unwrap());
+    assert!(!files.set_frame_offset(0, SpriteType::Sd, 0, -3, 7).unwrap());
+    assert!(files.set_frame_unknown(0, SpriteType::Sd, 0, 2).unwrap());
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    let frame = &file.frames().unwrap()[0];
+    assert_eq!((frame.x_off, frame.y_off, frame.unknown), (-3, 7, 2));
+    assert_eq!((frame.tex_x, frame.width), (0, 4));
+}
+
+#[test]
+fn test_pending_changes() {
+    let mainsd = Cursor::new(test_anim(1, 3));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    assert!(files.pending_changes().is_empty());
+    files.set_ref_enabled(2, SpriteType::Sd, true);
+    files.set_ref_img(2, SpriteType::Sd, 0);
+    files.set_frame_unknown(0, SpriteType::Sd, 0, 2).unwrap();
+    let original = files.mainsd().unwrap().values_or_ref(1);
+    let changes = files.pending_changes();
+    assert_eq!(changes.len(), 2);
+    // Sorted by sprite
+    assert_eq!((changes[0].sprite, changes[0].ty), (0, SpriteType::Sd));
+    assert_eq!(changes[0].original, original);
+    assert_eq!(changes[0].edited, original);
+    assert_eq!(changes[0].textures_replaced, Some((Some(1), 1)));
+    assert_eq!((changes[1].sprite, changes[1].ty), (2, SpriteType::Sd));
+    assert_eq!(changes[1].original, original);
+    assert_eq!(changes[1].edited, Some(anim::ValuesOrRef::Ref(0)));
+    assert_eq!(changes[1].textures_replaced, None);
+}
+
+#[test]
+fn test_ref_cycle() {
+    let mainsd = Cursor::new(test_anim(1, 3));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    files.set_ref_enabled(1, SpriteType::Sd, true);
+    files.set_ref_img(1, SpriteType::Sd, 2);
+    assert!(files.file(1, SpriteType::Sd).is_ok());
+    files.set_ref_enabled(2, SpriteType::Sd, true);
+    files.set_ref_img(2, SpriteType::Sd, 1);
+    match files.file(1, SpriteType::Sd) {
+        Err(e) => assert!(e.to_string().contains("1 -> 2 -> 1"), "{}", e),
+        Ok(_) => panic!("Expected an error"),
+    }
+    assert!(files.file(2, SpriteType::Sd).is_err());
+    assert!(files.file(0, SpriteType::Sd).unwrap().is_some());
+}
+
+#[test]
+fn test_verify() {
+    let mainsd = Cursor::new(test_anim(1, 3));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    assert!(files.verify().is_empty());
+    files.set_ref_enabled(1, SpriteType::Sd, true);
+    files.set_ref_img(1, SpriteType::Sd, 7);
+    let findings = files.verify();
+    assert_eq!(findings.len(), 1);
+    assert!(findings[0].starts_with("1 Sd: References nonexistent sprite 7"), "{}", findings[0]);
+}
+
+#[test]
+fn test_texture_header_dimensions() {
+    let mainsd = Cursor::new(test_anim(1, 1));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    assert_eq!(file.texture_header_dimensions(0).unwrap(), Some((4, 4)));
+    assert!(file.texture_header_dimensions(1).is_err());
+}
+
+#[test]
+fn test_verify_hd2() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_hd2_verify_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("HD2/anim")).unwrap();
+    fs::write(dir.join("HD2/anim/main_000.anim"), test_anim(2, 1)).unwrap();
+    let (mut files, _) = Files::init(&dir.join("HD2/anim/main_000.anim")).unwrap();
+    let mut set_frame_size = |width, height| {
+        let textures = files.file(0, SpriteType::Hd2).unwrap().unwrap().raw_textures().unwrap();
+        let frames = vec![anim::Frame {
+            tex_x: 0,
+            tex_y: 0,
+            x_off: 0,
+            y_off: 0,
+            width,
+            height,
+            unknown: 0,
+        }];
+        files.set_tex_changes(0, SpriteType::Hd2, anim::TexChanges { frames, textures }, (4, 4));
+        files.verify()
+    };
+    // 8x8 in HD units covers the entire 4x4 HD2 texture
+    assert!(set_frame_size(8, 8).is_empty());
+    let findings = set_frame_size(10, 8);
+    assert_eq!(findings.len(), 1);
+    assert!(findings[0].starts_with("0 Hd2: 1 frames extend past"), "{}", findings[0]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_metadata_csv() {
+    let mainsd = Cursor::new(test_anim(1, 2));
+    let mut files = Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    let mut out = Vec::new();
+    files.write_metadata_csv(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("image,type,"));
+    assert!(lines[1].starts_with("0,Sd,false,,"));
+    assert!(lines[1].contains(",diffuse;teamcolor,"));
+    assert!(lines[2].starts_with("1,Sd,false,,"));
+    // Sprites that can't be opened get an error row
+    files.set_ref_enabled(0, SpriteType::Sd, true);
+    files.set_ref_img(0, SpriteType::Sd, 1);
+    files.set_ref_enabled(1, SpriteType::Sd, true);
+    files.set_ref_img(1, SpriteType::Sd, 0);
+    let mut out = Vec::new();
+    files.write_metadata_csv(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("0,Sd,,,,,,,Unable to open"), "{}", lines[1]);
+    assert!(lines[2].starts_with("1,Sd,,,,,,,Unable to open"), "{}", lines[2]);
+}
+
+#[test]
+fn test_csv_field() {
+    assert_eq!(csv_field("diffuse"), "diffuse");
+    assert_eq!(csv_field("a,b"), "\"a,b\"");
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
+
+#[test]
+fn test_check_mainsd() {
+    let read = |scale, count| anim::Anim::read(Cursor::new(test_anim(scale, count))).unwrap();
+    assert!(check_mainsd(&read(1, 3)).is_ok());
+    assert!(check_mainsd(&read(4, 3)).is_err());
+    assert!(check_mainsd(&read(1, 1)).is_err());
+    // Also checked when the anim is opened without the rest of the game files
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_lone_mainsd_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mainSD.anim");
+    fs::write(&path, test_anim(4, 3)).unwrap();
+    assert!(Files::init(&path).is_err());
+    fs::write(&path, test_anim(1, 3)).unwrap();
+    assert!(Files::init(&path).is_ok());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hd2_divisor_from_scale() {
+    assert_eq!(hd2_divisor_from_scale(2), 2);
+    assert_eq!(hd2_divisor_from_scale(1), 4);
+    assert_eq!(hd2_divisor_from_scale(4), 1);
+    assert_eq!(hd2_divisor_from_scale(0), DEFAULT_HD2_DIVISOR);
+    assert_eq!(hd2_divisor_from_scale(3), DEFAULT_HD2_DIVISOR);
+}
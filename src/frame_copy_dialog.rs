@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use gio::prelude::*;
+use gtk::prelude::*;
+
+use crate::int_entry::{IntSize, IntEntry};
+use crate::ui_helpers::*;
+use crate::{SpriteInfo, error_msg_box, info_msg_box, lookup_action};
+
+pub fn copy_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::ApplicationWindow) {
+    let tex_id = sprite_info.tex_id();
+    let frame_count = {
+        let mut files = match sprite_info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(file)) => file.frames().map(|x| x.len()).unwrap_or(0),
+            _ => return,
+        }
+    };
+    if frame_count == 0 {
+        error_msg_box(parent, "The sprite has no frames to copy");
+        return;
+    }
+
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    let frame_label = gtk::Label::new(Some(&format!("Frame (0 - {})", frame_count - 1)));
+    let frame_entry = IntEntry::new(IntSize::Int16);
+    frame_entry.set_value(0);
+
+    let ok_button = gtk::Button::with_label("Copy");
+    let cancel_button = gtk::Button::with_label("Cancel");
+    let w = window.clone();
+    cancel_button.connect_clicked(move |_| {
+        w.close();
+    });
+    let sprite_info = sprite_info.clone();
+    let frame_entry2 = frame_entry.clone();
+    let w = window.clone();
+    ok_button.connect_clicked(move |_| {
+        let frame = frame_entry2.get_value() as usize;
+        let result = {
+            let mut files = match sprite_info.files.try_lock() {
+                Ok(o) => o,
+                _ => return,
+            };
+            files.extract_frame(tex_id.0, tex_id.1, frame)
+        };
+        match result {
+            Ok(copied) => {
+                *sprite_info.copied_frame.borrow_mut() = Some(copied);
+                enable_paste(&sprite_info);
+                w.close();
+            }
+            Err(e) => {
+                error_msg_box(&w, &format!("Unable to copy frame: {:?}", e));
+            }
+        }
+    });
+
+    let bx = box_vertical(&[
+        &box_horizontal(&[
+            &frame_label,
+            frame_entry.widget(),
+        ]),
+        &gtk::Separator::new(gtk::Orientation::Horizontal),
+        &box_horizontal(&[
+            &ok_button,
+            &cancel_button,
+        ]),
+    ]);
+    window.add(&bx);
+    window.set_border_width(10);
+    window.set_title(&format!("Copy frame of {:?} image {}", tex_id.1, tex_id.0));
+    window.set_modal(true);
+    window.set_transient_for(Some(parent));
+    window.show_all();
+}
+
+pub fn paste_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::ApplicationWindow) {
+    let tex_id = sprite_info.tex_id();
+    let copied_desc = match *sprite_info.copied_frame.borrow() {
+        Some(ref s) => format!("{:?} image {} frame {}", s.ty, s.sprite, s.index),
+        None => return,
+    };
+    let frame_count = {
+        let mut files = match sprite_info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(file)) if file.is_anim() => file.frames().map(|x| x.len()).unwrap_or(0),
+            _ => {
+                error_msg_box(parent, "Frames can only be pasted to anim sprites");
+                return;
+            }
+        }
+    };
+
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    let source_label = gtk::Label::new(Some(&format!("Pasting {}", copied_desc)));
+    source_label.set_halign(gtk::Align::Start);
+    let index_label = gtk::Label::new(Some(&format!("Insert at (0 - {})", frame_count)));
+    let index_entry = IntEntry::new(IntSize::Int16);
+    index_entry.set_value(frame_count as u32);
+
+    let ok_button = gtk::Button::with_label("Paste");
+    let cancel_button = gtk::Button::with_label("Cancel");
+    let w = window.clone();
+    cancel_button.connect_clicked(move |_| {
+        w.close();
+    });
+    let sprite_info = sprite_info.clone();
+    let index_entry2 = index_entry.clone();
+    let w = window.clone();
+    ok_button.connect_clicked(move |_| {
+        let index = index_entry2.get_value() as usize;
+        let copied = match *sprite_info.copied_frame.borrow() {
+            Some(ref s) => s.clone(),
+            None => return,
+        };
+        let result = {
+            let mut files = match sprite_info.files.try_lock() {
+                Ok(o) => o,
+                _ => return,
+            };
+            let result = files.insert_frame(tex_id.0, tex_id.1, index, &copied);
+            if result.is_ok() {
+                sprite_info.draw_clear_all();
+                if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                    sprite_info.changed_ty(tex_id, &mut file);
+                }
+            }
+            result
+        };
+        match result {
+            Ok(()) => {
+                if let Some(a) = lookup_action(&sprite_info.sprite_actions, "is_dirty") {
+                    a.activate(Some(&true.to_variant()));
+                }
+                sprite_info.draw_area.queue_draw();
+                info_msg_box(&w, &format!("Pasted frame to index {}", index.min(frame_count)));
+                w.close();
+            }
+            Err(e) => {
+                error_msg_box(&w, &format!("Unable to paste frame: {:?}", e));
+            }
+        }
+    });
+
+    let bx = box_vertical(&[
+        &source_label,
+        &box_horizontal(&[
+            &index_label,
+            index_entry.widget(),
+        ]),
+        &gtk::Separator::new(gtk::Orientation::Horizontal),
+        &box_horizontal(&[
+            &ok_button,
+            &cancel_button,
+        ]),
+    ]);
+    window.add(&bx);
+    window.set_border_width(10);
+    window.set_title(&format!("Paste frame to {:?} image {}", tex_id.1, tex_id.0));
+    window.set_modal(true);
+    window.set_transient_for(Some(parent));
+    window.show_all();
+}
+
+fn enable_paste(sprite_info: &SpriteInfo) {
+    let ui = crate::ui();
+    if let Some(a) = lookup_action(&ui.app, "pasteFrame") {
+        a.set_enabled(sprite_info.copied_frame.borrow().is_some());
+    }
+}
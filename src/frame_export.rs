@@ -3,13 +3,15 @@ use std::convert::TryFrom;
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::{Path};
+use std::time::Instant;
 
 use anyhow::Context;
 
-use crate::anim::{Frame, RgbaTexture};
+use crate::anim::{self, Frame, RgbaTexture, TextureFormat};
 use crate::files;
-use crate::frame_info::{self, FrameInfo, FrameType};
+use crate::frame_info::{self, ContentRect, FrameInfo};
 use crate::normal_encoding;
+use crate::texture_repack;
 use crate::{SpriteType, Error};
 
 pub struct ExportLayer {
@@ -30,34 +32,515 @@ pub enum LayerExportMode {
     Normal,
 }
 
-// Different from integer division which rounds towards zero.
-fn div_round_down(val: i32, div: u32) -> i32 {
-    ((val as f32) / (div as f32)).floor() as i32
+/// Checks that files can be created in `path` by creating and removing a dummy file.
+pub fn check_dir_writable(path: &Path) -> Result<(), Error> {
+    if !path.is_dir() {
+        return Err(anyhow!("{} is not a directory", path.to_string_lossy()));
+    }
+    let test_path = path.join(".animosity_write_test");
+    File::create(&test_path)
+        .with_context(|| format!("{} is not writable", path.to_string_lossy()))?;
+    std::fs::remove_file(&test_path)
+        .with_context(|| format!("Couldn't remove {}", test_path.to_string_lossy()))?;
+    Ok(())
 }
 
-// Won't export layers with None prefix,
-// framedef_file is joined to path, as are the image names
-pub fn export_frames<F: Fn(f32)>(
+fn sprite_type_lowercase(ty: SpriteType) -> &'static str {
+    match ty {
+        SpriteType::Sd => "sd",
+        SpriteType::Hd => "hd",
+        SpriteType::Hd2 => "hd2",
+    }
+}
+
+/// Default filename for the atlas of a layer, e.g. `012_hd2_diffuse_atlas.png`
+pub fn atlas_filename(sprite: usize, ty: SpriteType, layer_name: &str) -> String {
+    format!("{:03}_{}_{}_atlas.png", sprite, sprite_type_lowercase(ty), layer_name)
+}
+
+/// Default filename for `export_contact_sheet`, e.g. `012_hd2_diffuse_sheet.png`
+pub fn contact_sheet_filename(sprite: usize, ty: SpriteType, layer_name: &str) -> String {
+    format!("{:03}_{}_{}_sheet.png", sprite, sprite_type_lowercase(ty), layer_name)
+}
+
+/// Same layers that the export dialog selects by default.
+pub fn default_export_layers(
+    layer_names: &[String],
+    has_texture: impl Fn(usize) -> bool,
+    prefix: &str,
+) -> Vec<ExportLayer> {
+    let mut result = Vec::new();
+    for (i, name) in layer_names.iter().enumerate() {
+        if !has_texture(i) {
+            continue;
+        }
+        let layer = |sub_id, suffix: &str, mode| ExportLayer {
+            id: i as u32,
+            sub_id,
+            prefix: format!("{}_{}", prefix, suffix),
+            name: name.clone(),
+            mode,
+        };
+        match &**name {
+            "normal" => result.push(layer(0, name.as_str(), LayerExportMode::Normal)),
+            "ao_depth" => {
+                result.push(layer(0, "ao", LayerExportMode::Green));
+                result.push(layer(1, "depth", LayerExportMode::Alpha));
+            }
+            _ => result.push(layer(0, name.as_str(), LayerExportMode::Rgba)),
+        }
+    }
+    result
+}
+
+/// Exports `file` to `path` using the default layers and file names of the export dialog.
+///
+/// Returns warnings from `export_frames`.
+pub fn export_with_defaults<F: Fn(f32)>(
     file: &files::File<'_>,
+    sprite: usize,
     ty: SpriteType,
-    width: i32,
-    height: i32,
     path: &Path,
-    framedef_file: &Path,
-    layers: &[ExportLayer],
-    single_image: bool,
+    sheet: bool,
     report_progress: F,
-) -> Result<(), Error> {
-    if !path.is_dir() {
-        return Err(anyhow!("{} is not a directory", path.to_string_lossy()));
+) -> Result<Vec<String>, Error> {
+    let type_lowercase = sprite_type_lowercase(ty);
+    if file.is_anim() {
+        let (width, height) = match file.dimensions() {
+            Ok(o) => o,
+            Err(e) => {
+                warn!("Cannot get sprite dimensions, frames may be misaligned: {:?}", e);
+                (0, 0)
+            }
+        };
+        let prefix = format!("{:03}_{}", sprite, type_lowercase);
+        let layers = default_export_layers(
+            &file.layer_names(),
+            |i| file.texture_size(i).is_some(),
+            &prefix,
+        );
+        let framedef = format!("frames_{:03}_{}.json", sprite, type_lowercase);
+        let sheet_mode = match sheet {
+            true => SheetMode::Grid { columns: 16 },
+            false => SheetMode::OnePerFrame,
+        };
+        export_frames(
+            file,
+            i32::from(width),
+            i32::from(height),
+            path,
+            Path::new(&framedef),
+            &layers,
+            sheet_mode,
+            FrameOrder::Storage,
+            FrameTypeExport::Auto,
+            ExportOptions::default(),
+            None,
+            report_progress,
+        )
+    } else {
+        let prefix = grp_prefix(file);
+        let framedef = format!("frames_{}.json", prefix);
+        export_grp(file, path, &prefix, Path::new(&framedef), sheet, report_progress)?;
+        Ok(Vec::new())
     }
+}
+
+fn grp_prefix(file: &files::File<'_>) -> String {
+    file.path().file_name()
+        .map(|x| x.to_string_lossy().replace(".dds.grp", "").replace(".dds.vr4", ""))
+        .unwrap_or_else(|| String::from("frames"))
+}
 
-    let scale_div = match ty {
-        SpriteType::Hd2 => 2u32,
-        _ => 1u32,
+pub struct BatchExportResult {
+    pub exported: usize,
+    /// Ref sprites and sprites without any textures.
+    pub skipped: usize,
+    pub failed: Vec<(usize, SpriteType, Error)>,
+}
+
+/// Exports every sprite of `types` to subdirectories of `path` named by
+/// image id and type, e.g. `012_hd2`. Errors of a single sprite don't stop
+/// the export, they are collected to the result instead.
+///
+/// `report_progress` is called with (sprites done, sprite count).
+pub fn export_all_sprites<F: FnMut(usize, usize)>(
+    files: &mut files::Files,
+    path: &Path,
+    types: &[SpriteType],
+    sheet: bool,
+    mut report_progress: F,
+) -> BatchExportResult {
+    let mut result = BatchExportResult {
+        exported: 0,
+        skipped: 0,
+        failed: Vec::new(),
     };
+    let sprite_count = files.sprites().len();
+    for sprite in 0..sprite_count {
+        report_progress(sprite, sprite_count);
+        let is_grp = matches!(files.sprites().get(sprite), Some(files::SpriteFiles::DdsGrp(..)));
+        // A grp is the same file regardless of type, export it only once.
+        let sprite_types = match is_grp {
+            true => &types[..types.len().min(1)],
+            false => types,
+        };
+        for &ty in sprite_types {
+            let file = match files.file(sprite, ty) {
+                Ok(Some(s)) => s,
+                Ok(None) => continue,
+                Err(e) => {
+                    result.failed.push((sprite, ty, e));
+                    continue;
+                }
+            };
+            let has_textures = match file.is_anim() {
+                true => (0..file.layer_count()).any(|i| file.texture_size(i).is_some()),
+                false => file.layer_count() != 0,
+            };
+            if file.image_ref().is_some() || !has_textures {
+                result.skipped += 1;
+                continue;
+            }
+            let dir = match is_grp {
+                true => path.join(grp_prefix(&file)),
+                false => path.join(format!("{:03}_{}", sprite, sprite_type_lowercase(ty))),
+            };
+            let export_result = std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Unable to create {}", dir.to_string_lossy()))
+                .and_then(|()| export_with_defaults(&file, sprite, ty, &dir, sheet, |_| ()));
+            match export_result {
+                Ok(warnings) => {
+                    for warning in warnings {
+                        warn!("{:?} image {}: {}", ty, sprite, warning);
+                    }
+                    result.exported += 1;
+                }
+                Err(e) => result.failed.push((sprite, ty, e)),
+            }
+        }
+    }
+    report_progress(sprite_count, sprite_count);
+    result
+}
+
+/// Rough guess of how much smaller the PNGs are than the raw RGBA data.
+/// Exported frames are mostly transparent, so they compress well.
+const PNG_COMPRESSION_RATIO: f64 = 0.3;
+
+pub struct ExportSizeEstimate {
+    /// Size of the uncompressed RGBA data of all images.
+    pub raw_bytes: u64,
+    /// Expected size of the written files.
+    pub estimated_bytes: u64,
+}
+
+/// Estimates how much `export_all_sprites` would write, using only frame
+/// counts and dimensions.
+///
+/// Still has to open every sprite file, so this should be called outside the GTK thread.
+pub fn estimate_export_all_size<F: FnMut(usize, usize)>(
+    files: &mut files::Files,
+    types: &[SpriteType],
+    mut report_progress: F,
+) -> ExportSizeEstimate {
+    let mut raw_bytes = 0u64;
+    let sprite_count = files.sprites().len();
+    for sprite in 0..sprite_count {
+        report_progress(sprite, sprite_count);
+        let is_grp = matches!(files.sprites().get(sprite), Some(files::SpriteFiles::DdsGrp(..)));
+        let sprite_types = match is_grp {
+            true => &types[..types.len().min(1)],
+            false => types,
+        };
+        for &ty in sprite_types {
+            let file = match files.file(sprite, ty) {
+                Ok(Some(s)) => s,
+                _ => continue,
+            };
+            if file.image_ref().is_some() {
+                continue;
+            }
+            raw_bytes += estimate_raw_export_size(&file);
+        }
+    }
+    ExportSizeEstimate {
+        raw_bytes,
+        estimated_bytes: (raw_bytes as f64 * PNG_COMPRESSION_RATIO) as u64,
+    }
+}
 
+fn estimate_raw_export_size(file: &files::File<'_>) -> u64 {
+    if !file.is_anim() {
+        return (0..file.layer_count())
+            .flat_map(|i| file.texture_size(i))
+            .map(|tex| u64::from(tex.width) * u64::from(tex.height) * 4)
+            .sum();
+    }
+    let frames = match file.frames() {
+        Some(s) => s,
+        None => return 0,
+    };
+    let scale_div = file.texture_divisor();
+    let (width, height) = file.dimensions().unwrap_or((0, 0));
+    let canvas = frame_canvas(frames, i32::from(width), i32::from(height), scale_div);
+    let layers = default_export_layers(&file.layer_names(), |i| file.texture_size(i).is_some(), "");
+    u64::from(canvas.width) * u64::from(canvas.height) * 4 *
+        frames.len() as u64 * layers.len() as u64
+}
+
+/// Writes the entire decoded texture of `layer` to `path` as is,
+/// without splitting it to frames like `export_frames` does.
+pub fn export_atlas(file: &files::File<'_>, layer: usize, path: &Path) -> Result<(), Error> {
+    let texture = file.texture(layer)
+        .with_context(|| format!("Couldn't read texture for layer {}", layer))?;
+    if texture.is_paletted {
+        return Err(anyhow!("Paletted textures are not supported"));
+    }
+    let out = File::create(path)
+        .with_context(|| format!("Unable to create {}", path.to_string_lossy()))?;
+    let out = BufWriter::new(out);
+    let mut encoder = png::Encoder::new(out, texture.width, texture.height);
+    encoder.set_color(png::ColorType::Rgba);
+    let mut encoder = encoder.write_header()?;
+    encoder.write_image_data(&texture.data)?;
+    Ok(())
+}
+
+/// Writes every frame of `layer` to a single PNG, in rows of `columns` cells with
+/// the frame index drawn in the top left corner of each cell.
+///
+/// Cells are separated by `padding` transparent pixels. Unlike `export_frames`,
+/// no frame info is written, as the labels make the image unsuitable for importing.
+pub fn export_contact_sheet(
+    file: &files::File<'_>,
+    layer: usize,
+    path: &Path,
+    columns: u32,
+    padding: u32,
+) -> Result<(), Error> {
+    if !file.is_anim() {
+        return Err(anyhow!("Contact sheets can only be exported from anim sprites"));
+    }
     let frames = file.frames().ok_or_else(|| anyhow!("Unable to get frames"))?;
+    if frames.is_empty() {
+        return Err(anyhow!("Sprite has no frames"));
+    }
+    let texture = file.texture(layer)
+        .with_context(|| format!("Couldn't read texture for layer {}", layer))?;
+    if texture.is_paletted {
+        return Err(anyhow!("Paletted textures are not supported"));
+    }
+    let texture = RgbaTexture {
+        data: texture.data,
+        width: texture.width,
+        height: texture.height,
+    };
+    let scale_div = file.texture_divisor();
+    let canvas = frame_canvas(frames, 0, 0, scale_div);
+    let (columns, rows) = SheetMode::Grid { columns }.sheet_size(frames.len() as u32)
+        .ok_or_else(|| anyhow!("No sheet size"))?;
+    let cell_width = canvas.width + padding;
+    let cell_height = canvas.height + padding;
+    let image_width = cell_width * columns + padding;
+    let image_height = cell_height * rows + padding;
+    let mut bytes = vec![0; image_width as usize * image_height as usize * 4];
+    let mut cell_positions = Vec::with_capacity(frames.len());
+    for (n, frame) in frames.iter().enumerate() {
+        let x = padding + (n as u32 % columns) * cell_width;
+        let y = padding + (n as u32 / columns) * cell_height;
+        cell_positions.push((x, y));
+        decode_frame_to_buf(
+            &mut bytes,
+            image_width,
+            &texture,
+            frame,
+            scale_div,
+            x,
+            y,
+            canvas.x_base,
+            canvas.y_base,
+        ).with_context(|| format!("Writing frame {}", n))?;
+    }
+    draw_frame_labels(&mut bytes, image_width, image_height, &cell_positions)
+        .context("Unable to draw frame labels")?;
+
+    let out = File::create(path)
+        .with_context(|| format!("Unable to create {}", path.to_string_lossy()))?;
+    let out = BufWriter::new(out);
+    let mut encoder = png::Encoder::new(out, image_width, image_height);
+    encoder.set_color(png::ColorType::Rgba);
+    let mut encoder = encoder.write_header()?;
+    encoder.write_image_data(&bytes)?;
+    Ok(())
+}
+
+/// Draws index `n` at `positions[n]` of the RGBA image `bytes`, on a dark box
+/// so that the labels stay readable over any frame.
+fn draw_frame_labels(
+    bytes: &mut [u8],
+    width: u32,
+    height: u32,
+    positions: &[(u32, u32)],
+) -> Result<(), Error> {
+    let mut surface =
+        cairo::ImageSurface::create(cairo::Format::ARgb32, width as i32, height as i32)?;
+    {
+        let cairo = cairo::Context::new(&surface)?;
+        cairo.set_font_size(12.0);
+        for (n, &(x, y)) in positions.iter().enumerate() {
+            let text = n.to_string();
+            let extents = cairo.text_extents(&text)?;
+            cairo.set_source_rgba(0.0, 0.0, 0.0, 0.75);
+            cairo.rectangle(x as f64, y as f64, extents.x_advance() + 4.0, 15.0);
+            cairo.fill()?;
+            cairo.set_source_rgb(1.0, 1.0, 1.0);
+            cairo.move_to(x as f64 + 2.0, y as f64 + 12.0);
+            cairo.show_text(&text)?;
+        }
+    }
+    surface.flush();
+    let stride = surface.stride() as usize;
+    let labels = surface.data()?;
+    let row_bytes = width as usize * 4;
+    for (row, out) in bytes.chunks_exact_mut(row_bytes).enumerate() {
+        let start = row * stride;
+        blend_argb32_over_rgba(out, &labels[start..start + row_bytes]);
+    }
+    Ok(())
+}
+
+/// Composites `src`, in cairo's premultiplied native endian ARGB32, over the
+/// non-premultiplied RGBA `dst`.
+fn blend_argb32_over_rgba(dst: &mut [u8], src: &[u8]) {
+    for (dst, src) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+        let pixel = u32::from_ne_bytes([src[0], src[1], src[2], src[3]]);
+        let src_alpha = pixel >> 24;
+        if src_alpha == 0 {
+            continue;
+        }
+        let src_color = [(pixel >> 16) & 0xff, (pixel >> 8) & 0xff, pixel & 0xff];
+        // How much of dst shows through src, out of 255
+        let dst_weight = u32::from(dst[3]) * (255 - src_alpha) / 255;
+        let out_alpha = src_alpha + dst_weight;
+        for (out, &color) in dst.iter_mut().zip(src_color.iter()) {
+            *out = ((color * 255 + u32::from(*out) * dst_weight) / out_alpha).min(255) as u8;
+        }
+        dst[3] = out_alpha as u8;
+    }
+}
+
+/// How `export_frames` places frames of a layer to images.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SheetMode {
+    /// Separate `{prefix}_{frame}.png` for every frame.
+    OnePerFrame,
+    /// Single `{prefix}.png` with all frames on one row.
+    HorizontalStrip,
+    /// Single `{prefix}.png` with frames in rows of `columns` frames.
+    Grid { columns: u32 },
+    /// Separate `{prefix}_{frame}.svg` for every frame, embedding the frame as PNG
+    /// and its offset/size as metadata.
+    SvgPerFrame,
+}
+
+impl SheetMode {
+    /// Returns (columns, rows) of the sheet, or None if frames are written
+    /// to separate images.
+    fn sheet_size(self, frame_count: u32) -> Option<(u32, u32)> {
+        let columns = match self {
+            SheetMode::OnePerFrame | SheetMode::SvgPerFrame => return None,
+            SheetMode::HorizontalStrip => frame_count,
+            SheetMode::Grid { columns } => columns.min(frame_count),
+        }.max(1);
+        let rows = ((frame_count + columns - 1) / columns).max(1);
+        Some((columns, rows))
+    }
+}
+
+/// Order in which frames are written by `export_frames`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FrameOrder {
+    /// Same order as the frames are stored in the file.
+    Storage,
+    /// Stable sort by frame type; frames with same type keep their storage order.
+    FrameType,
+}
+
+/// How `export_frames` writes frame types to the frame info.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FrameTypeExport {
+    /// Ranges, unless a frame type is used in non-contiguous frames.
+    Auto,
+    /// Ranges of consecutive frames with the same type.
+    Ranges,
+    /// Type of every frame separately.
+    PerFrame,
+}
+
+/// Size of the images `export_frames` writes frames to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FrameCanvasMode {
+    /// Every frame is placed on a canvas large enough for all frames of the sprite.
+    Uniform,
+    /// Every frame is written at its own size, with its offset in the frame info.
+    /// Requires one image per frame.
+    TightCrop,
+}
+
+impl Default for FrameCanvasMode {
+    fn default() -> FrameCanvasMode {
+        FrameCanvasMode::Uniform
+    }
+}
+
+/// Options affecting how `export_frames` writes the images.
+#[derive(Copy, Clone, Default)]
+pub struct ExportOptions {
+    pub canvas_mode: FrameCanvasMode,
+    /// Refer to layers by their names in the frame info instead of their indices.
+    pub layers_by_name: bool,
+    /// Pad the frame images to power of two dimensions.
+    pub pad_pow2: bool,
+    /// Multiply RGB of RGBA layers by alpha before writing.
+    pub premultiply: bool,
+}
+
+/// Returns indices to `frames` in the order they should be exported.
+fn export_order(frames: &[Frame], order: FrameOrder) -> Vec<usize> {
+    let mut result = (0..frames.len()).collect::<Vec<_>>();
+    if order == FrameOrder::FrameType {
+        result.sort_by_key(|&i| frames[i].unknown);
+    }
+    result
+}
+
+/// Returns layers of `layers` for which `has_texture(layer.id)` is false.
+///
+/// Frames of such layers would have nothing to export, so they must not be
+/// silently written out as empty images.
+fn missing_texture_layers<'a, F: Fn(usize) -> bool>(
+    layers: &'a [ExportLayer],
+    has_texture: F,
+) -> Vec<&'a ExportLayer> {
+    layers.iter().filter(|layer| !has_texture(layer.id as usize)).collect()
+}
+
+// Different from integer division which rounds towards zero.
+fn div_round_down(val: i32, div: u32) -> i32 {
+    ((val as f32) / (div as f32)).floor() as i32
+}
+
+/// Size of the image each frame is written to, and the sprite position
+/// corresponding to its top left corner.
+struct FrameCanvas {
+    x_base: i32,
+    y_base: i32,
+    width: u32,
+    height: u32,
+}
+
+fn frame_canvas(frames: &[Frame], width: i32, height: i32, scale_div: u32) -> FrameCanvas {
     let x_base = div_round_down(
         frames.iter().map(|x| i32::from(x.x_off)).min().unwrap_or(0).min(0i32),
         scale_div,
@@ -74,8 +557,129 @@ pub fn export_frames<F: Fn(f32)>(
         .map(|x| div_round_down(i32::from(x.y_off) + i32::from(x.height), scale_div))
         .max()
         .unwrap_or(1);
-    let frame_width = (x_max.max(width / scale_div as i32) - x_base) as u32;
-    let frame_height = (y_max.max(height / scale_div as i32) - y_base) as u32;
+    FrameCanvas {
+        x_base,
+        y_base,
+        width: (x_max.max(width / scale_div as i32) - x_base) as u32,
+        height: (y_max.max(height / scale_div as i32) - y_base) as u32,
+    }
+}
+
+/// Canvas covering only `frame`. Empty frames get a 1x1 canvas, as PNGs can't be empty.
+fn frame_crop_canvas(frame: &Frame, scale_div: u32) -> FrameCanvas {
+    FrameCanvas {
+        x_base: div_round_down(i32::from(frame.x_off), scale_div),
+        y_base: div_round_down(i32::from(frame.y_off), scale_div),
+        width: (u32::from(frame.width) / scale_div).max(1),
+        height: (u32::from(frame.height) / scale_div).max(1),
+    }
+}
+
+/// Grows the canvas to power of two dimensions, keeping the frames centered.
+/// Also returns where the unpadded canvas is in the padded one.
+fn pad_canvas_pow2(canvas: FrameCanvas) -> (FrameCanvas, ContentRect) {
+    let width = canvas.width.max(1).next_power_of_two();
+    let height = canvas.height.max(1).next_power_of_two();
+    let left = (width - canvas.width) / 2;
+    let top = (height - canvas.height) / 2;
+    let content = ContentRect {
+        x: left,
+        y: top,
+        width: canvas.width,
+        height: canvas.height,
+    };
+    let padded = FrameCanvas {
+        x_base: canvas.x_base - left as i32,
+        y_base: canvas.y_base - top as i32,
+        width,
+        height,
+    };
+    (padded, content)
+}
+
+// Won't export layers with None prefix,
+// framedef_file is joined to path, as are the image names
+//
+// With `FrameOrder::Storage` the exported frame n is always frame n of `file.frames()`,
+// so exporting the same file twice produces identical output.
+//
+// With `options.canvas_mode` set to `TightCrop` each image only covers its frame,
+// and the frame info records per-frame offsets instead of a common one.
+//
+// With `options.pad_pow2` the frame images are padded to power of two dimensions, and the
+// frame info records the unpadded area so that importing ignores the padding.
+//
+// With `only_frames` only those frames are exported, numbered and described in the
+// frame info as if the sprite had no other frames.
+//
+// With `options.premultiply` the RGB of RGBA layers is multiplied by alpha before writing.
+//
+// Returns warnings about things that the frame info could not represent faithfully.
+pub fn export_frames<F: Fn(f32)>(
+    file: &files::File<'_>,
+    width: i32,
+    height: i32,
+    path: &Path,
+    framedef_file: &Path,
+    layers: &[ExportLayer],
+    sheet_mode: SheetMode,
+    frame_order: FrameOrder,
+    frame_type_export: FrameTypeExport,
+    options: ExportOptions,
+    only_frames: Option<&[usize]>,
+    report_progress: F,
+) -> Result<Vec<String>, Error> {
+    let ExportOptions { canvas_mode, layers_by_name, pad_pow2, premultiply } = options;
+    if !path.is_dir() {
+        return Err(anyhow!("{} is not a directory", path.to_string_lossy()));
+    }
+    let start_time = Instant::now();
+
+    let scale_div = file.texture_divisor();
+
+    if file.is_anim() {
+        let missing = missing_texture_layers(layers, |id| file.texture_size(id).is_some());
+        if !missing.is_empty() {
+            let names = missing.iter()
+                .map(|x| format!("{} ({})", x.name, x.id))
+                .collect::<Vec<_>>();
+            return Err(anyhow!("Layers have no texture: {}", names.join(", ")));
+        }
+    }
+
+    if only_frames.is_some() && sheet_mode.sheet_size(1).is_some() {
+        return Err(anyhow!("Exporting only some frames requires one image per frame"));
+    }
+    let tight_crop = canvas_mode == FrameCanvasMode::TightCrop;
+    if tight_crop && sheet_mode.sheet_size(1).is_some() {
+        return Err(anyhow!("Cropping frames to their content requires one image per frame"));
+    }
+    if tight_crop && pad_pow2 {
+        return Err(anyhow!("Cropped frames cannot be padded to power of two size"));
+    }
+
+    let stored_frames = file.frames().ok_or_else(|| anyhow!("Unable to get frames"))?;
+    let mut order = export_order(stored_frames, frame_order);
+    if let Some(only) = only_frames {
+        order.retain(|i| only.contains(i));
+    }
+    let frames = order.iter()
+        .map(|&i| stored_frames[i].clone())
+        .collect::<Vec<_>>();
+    let canvas = frame_canvas(&frames, width, height, scale_div);
+    let (canvas, content_rect) = match pad_pow2 {
+        true => {
+            let (canvas, content) = pad_canvas_pow2(canvas);
+            (canvas, Some(content))
+        }
+        false => (canvas, None),
+    };
+    let FrameCanvas {
+        x_base,
+        y_base,
+        width: frame_width,
+        height: frame_height,
+    } = canvas;
     let mut multi_frame_images = Vec::new();
     let mut step = 1.0;
     let step_count = (layers.len() * frames.len()) as f32;
@@ -90,24 +694,30 @@ pub fn export_frames<F: Fn(f32)>(
             height: texture.height,
         };
         match layer.mode {
-            LayerExportMode::Rgba => (),
+            LayerExportMode::Rgba => {
+                if premultiply {
+                    premultiply_alpha(&mut texture.data);
+                }
+            }
             LayerExportMode::Green => texture_make_single_channel(&mut texture, 1),
             LayerExportMode::Alpha => texture_make_single_channel(&mut texture, 3),
             LayerExportMode::Normal => texture_make_normal_decoded(&mut texture),
         }
-        if single_image {
+        if let Some((columns, rows)) = sheet_mode.sheet_size(frames.len() as u32) {
             assert!(frames.len() > 0);
-            let image_width = frame_width * frames.len().min(16) as u32;
-            let image_height = frame_height * (1 + frames.len() / 16) as u32;
+            let image_width = frame_width * columns;
+            let image_height = frame_height * rows;
             let path = &path.join(format!("{}.png", layer.prefix));
             let out = File::create(path)
                 .with_context(|| format!("Unable to create {}", path.to_string_lossy()))?;
             let out = BufWriter::new(out);
             let buffer_size = image_width * image_height * 4;
             let mut bytes = vec![0; buffer_size as usize];
+            let mut frame_positions = Vec::with_capacity(frames.len());
             for (n, frame) in frames.iter().enumerate() {
-                let x = (n as u32 % 16) * frame_width;
-                let y = (n as u32 / 16) * frame_height;
+                let x = (n as u32 % columns) * frame_width;
+                let y = (n as u32 / columns) * frame_height;
+                frame_positions.push((x, y));
                 decode_frame_to_buf(
                     &mut bytes,
                     image_width,
@@ -137,20 +747,51 @@ pub fn export_frames<F: Fn(f32)>(
                 frame_width,
                 frame_height,
                 frame_size_overrides: HashMap::default(),
+                frame_positions,
             });
         } else {
             for (n, frame) in frames.iter().enumerate() {
-                let path = path.join(format!("{}_{:03}.png", layer.prefix, n));
-                write_frame(
-                    &path,
+                let extension = match sheet_mode {
+                    SheetMode::SvgPerFrame => "svg",
+                    _ => "png",
+                };
+                let path = path.join(format!("{}_{:03}.{}", layer.prefix, n, extension));
+                let canvas = match tight_crop {
+                    true => frame_crop_canvas(frame, scale_div),
+                    false => FrameCanvas {
+                        x_base,
+                        y_base,
+                        width: frame_width,
+                        height: frame_height,
+                    },
+                };
+                let png = frame_png(
                     &texture,
                     &frame,
                     scale_div,
-                    frame_width,
-                    frame_height,
-                    x_base,
-                    y_base,
+                    canvas.width,
+                    canvas.height,
+                    canvas.x_base,
+                    canvas.y_base,
                 ).with_context(|| format!("Writing frame {}", n))?;
+                let data = match sheet_mode {
+                    SheetMode::SvgPerFrame => {
+                        let svg = frame_svg(
+                            &png,
+                            n,
+                            &frame,
+                            scale_div,
+                            canvas.width,
+                            canvas.height,
+                            canvas.x_base,
+                            canvas.y_base,
+                        );
+                        svg.into_bytes()
+                    }
+                    _ => png,
+                };
+                std::fs::write(&path, &data)
+                    .with_context(|| format!("Unable to write {}", path.to_string_lossy()))?;
                 report_progress(step / step_count);
                 step += 1.0;
             }
@@ -178,32 +819,100 @@ pub fn export_frames<F: Fn(f32)>(
                 },
             })
             .collect(),
-        frame_types: Vec::new(),
+        frame_types: frame_info::frame_type_ranges(frames.iter().map(|x| x.unknown)),
+        per_frame_types: Vec::new(),
+        per_frame_offsets: Vec::new(),
         multi_frame_images,
+        sprite: Some(sprite_description(file)),
+        content_rect,
+        layers_by_name,
     };
-    let mut start = 0;
-    let mut first_unk = frames.get(0).map(|x| x.unknown).unwrap_or(0);
-    for (i, f) in frames.iter().enumerate() {
-        if f.unknown != first_unk {
-            frame_info.frame_types.push(FrameType {
-                first_frame: start as u32,
-                last_frame: i as u32,
-                frame_type: first_unk,
-            });
-            start = i + 1;
-            first_unk = frames.get(start).map(|x| x.unknown).unwrap_or(0);
-        }
+    if tight_crop {
+        frame_info.per_frame_offsets = frames.iter()
+            .map(|frame| {
+                let canvas = frame_crop_canvas(frame, scale_div);
+                frame_info::FrameOffset {
+                    offset_x: canvas.x_base,
+                    offset_y: canvas.y_base,
+                }
+            })
+            .collect();
     }
-    if start < frames.len() {
-        frame_info.frame_types.push(FrameType {
-            first_frame: start as u32,
-            last_frame: frames.len() as u32 - 1,
-            frame_type: first_unk,
-        });
+    let mut warnings = Vec::new();
+    if premultiply && layers.iter().any(|x| x.mode == LayerExportMode::Rgba) {
+        warnings.push(String::from(
+            "Images have premultiplied alpha, which importing them back does not undo",
+        ));
     }
-    serde_json::to_writer_pretty(&mut frame_info_file, &frame_info)?;
+    let split_types = frame_info::noncontiguous_frame_types(&frame_info.frame_types);
+    let per_frame_types = match frame_type_export {
+        FrameTypeExport::Auto => !split_types.is_empty(),
+        FrameTypeExport::Ranges => false,
+        FrameTypeExport::PerFrame => true,
+    };
+    if per_frame_types {
+        frame_info.per_frame_types = frames.iter().map(|x| x.unknown).collect();
+    } else if !split_types.is_empty() {
+        let msg = format!(
+            "Frame types {:?} are used in non-contiguous frames, \
+            the frame info has {} separate frame type ranges",
+            split_types, frame_info.frame_types.len(),
+        );
+        warn!("{}", msg);
+        warnings.push(msg);
+    }
+    serde_json::to_writer_pretty(&mut frame_info_file, &frame_info.to_json()?)?;
 
-    Ok(())
+    debug!(
+        "Exported {} frames ({}x{}) of {} layers in {:?}",
+        frames.len(), frame_width, frame_height, layers.len(), start_time.elapsed(),
+    );
+    Ok(warnings)
+}
+
+/// Finds frames of `file` that differ from the anim at `reference_path`,
+/// for exporting only those with `export_frames`.
+pub fn differing_frames(file: &files::File<'_>, reference_path: &Path) -> Result<Vec<usize>, Error> {
+    let reference = File::open(reference_path)
+        .with_context(|| format!("Unable to open {}", reference_path.display()))?;
+    let reference = anim::Anim::read(reference)
+        .with_context(|| format!("Unable to read {}", reference_path.display()))?;
+    let current = texture_repack::decode_frames(file)?;
+    let ref_divisor = match file.sprite_type() {
+        SpriteType::Hd2 => files::hd2_divisor_from_scale(reference.scale()),
+        _ => 1,
+    };
+    texture_repack::differing_frames(&current, &reference, ref_divisor)
+}
+
+fn sprite_description(file: &files::File<'_>) -> frame_info::SpriteDescription {
+    let values = file.sprite_values();
+    let texture_formats = file.texture_formats().into_iter()
+        .enumerate()
+        .map(|(i, format)| {
+            let format = match format {
+                Ok(o) => o,
+                Err(e) => {
+                    warn!("Couldn't get texture format of layer {}: {:?}", i, e);
+                    None
+                }
+            };
+            format.map(|x| {
+                match x {
+                    TextureFormat::Dxt1 => "dxt1",
+                    TextureFormat::Dxt5 => "dxt5",
+                    TextureFormat::Rgba => "rgba",
+                    TextureFormat::Monochrome => "monochrome",
+                }.into()
+            })
+        })
+        .collect();
+    frame_info::SpriteDescription {
+        width: values.as_ref().map(|x| x.width).unwrap_or(0),
+        height: values.as_ref().map(|x| x.height).unwrap_or(0),
+        image_ref: file.image_ref(),
+        texture_formats,
+    }
 }
 
 fn decode_frame_to_buf(
@@ -243,8 +952,8 @@ fn decode_frame_to_buf(
     Ok(())
 }
 
-fn write_frame(
-    path: &Path,
+/// Encodes `frame` as a PNG of `out_width` x `out_height`, placed relative to `x_base`/`y_base`.
+fn frame_png(
     texture: &RgbaTexture,
     frame: &Frame,
     scale_div: u32,
@@ -252,11 +961,7 @@ fn write_frame(
     out_height: u32,
     x_base: i32,
     y_base: i32,
-) -> Result<(), Error> {
-    let out = File::create(&path)
-        .with_context(|| format!("Unable to create {}", path.to_string_lossy()))?;
-    let out = BufWriter::new(out);
-
+) -> Result<Vec<u8>, Error> {
     let tex_x = frame.tex_x / scale_div as u16;
     let tex_y = frame.tex_y / scale_div as u16;
     let frame_width = u32::from(frame.width) / scale_div;
@@ -284,11 +989,75 @@ fn write_frame(
         (0..blank_bottom * out_width).flat_map(|_| [0, 0, 0, 0].iter().cloned())
     );
 
-    let mut encoder = png::Encoder::new(out, out_width, out_height);
-    encoder.set_color(png::ColorType::Rgba);
-    let mut encoder = encoder.write_header()?;
-    encoder.write_image_data(&bytes)?;
-    Ok(())
+    let mut out = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut out, out_width, out_height);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut encoder = encoder.write_header()?;
+        encoder.write_image_data(&bytes)?;
+    }
+    Ok(out)
+}
+
+/// SVG document for a frame image made by `frame_png`.
+///
+/// The view box is placed so that the sprite origin is at (0, 0), and the frame's
+/// index, offset and size (in exported pixels) are written as `animosity:frame` metadata.
+fn frame_svg(
+    png: &[u8],
+    index: usize,
+    frame: &Frame,
+    scale_div: u32,
+    out_width: u32,
+    out_height: u32,
+    x_base: i32,
+    y_base: i32,
+) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <svg xmlns=\"http://www.w3.org/2000/svg\" \
+        xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+        xmlns:animosity=\"urn:animosity\" \
+        width=\"{w}\" height=\"{h}\" viewBox=\"{x} {y} {w} {h}\">\n  \
+        <metadata>\n    \
+        <animosity:frame index=\"{index}\" x_offset=\"{x_off}\" y_offset=\"{y_off}\" \
+        width=\"{frame_w}\" height=\"{frame_h}\" frame_type=\"{frame_type}\"/>\n  \
+        </metadata>\n  \
+        <image x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" \
+        xlink:href=\"data:image/png;base64,{data}\"/>\n\
+        </svg>\n",
+        w = out_width,
+        h = out_height,
+        x = x_base,
+        y = y_base,
+        index = index,
+        x_off = div_round_down(frame.x_off as i32, scale_div),
+        y_off = div_round_down(frame.y_off as i32, scale_div),
+        frame_w = u32::from(frame.width) / scale_div,
+        frame_h = u32::from(frame.height) / scale_div,
+        frame_type = frame.unknown,
+        data = base64_encode(png),
+    )
+}
+
+/// Standard base64 with padding.
+fn base64_encode(data: &[u8]) -> String {
+    const CHARS: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let value = (u32::from(chunk[0]) << 16) |
+            (u32::from(chunk.get(1).copied().unwrap_or(0)) << 8) |
+            u32::from(chunk.get(2).copied().unwrap_or(0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[((value >> (18 - i * 6)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
 }
 
 pub fn export_grp<F: Fn(f32)>(
@@ -391,6 +1160,7 @@ pub fn export_grp<F: Fn(f32)>(
             frame_width,
             frame_height,
             frame_size_overrides,
+            frame_positions: Vec::new(),
         });
     } else {
         let step_count = layer_count as f32;
@@ -422,6 +1192,7 @@ pub fn export_grp<F: Fn(f32)>(
                 frame_width: texture.width,
                 frame_height: texture.height,
                 frame_size_overrides: HashMap::default(),
+                frame_positions: Vec::new(),
             });
             report_progress(step / step_count);
             step += 1.0;
@@ -442,7 +1213,12 @@ pub fn export_grp<F: Fn(f32)>(
             encoding: frame_info::LayerEncoding::Raw,
         }],
         frame_types: Vec::new(),
+        per_frame_types: Vec::new(),
+        per_frame_offsets: Vec::new(),
         multi_frame_images,
+        sprite: None,
+        content_rect: None,
+        layers_by_name: false,
     };
     serde_json::to_writer_pretty(&mut frame_info_file, &frame_info)?;
 
@@ -468,6 +1244,16 @@ fn texture_make_single_channel(texture: &mut RgbaTexture, channel: u8) {
     }
 }
 
+/// Multiplies RGB of each RGBA pixel by its alpha.
+fn premultiply_alpha(data: &mut [u8]) {
+    for chunk in data.chunks_exact_mut(4) {
+        let alpha = u32::from(chunk[3]);
+        for val in &mut chunk[..3] {
+            *val = ((u32::from(*val) * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
 fn texture_make_normal_decoded(texture: &mut RgbaTexture) {
     for chunk in texture.data.chunks_mut(4) {
         let (x, y, z) = normal_encoding::decode_normal(chunk[0], chunk[3]);
@@ -477,3 +1263,167 @@ fn texture_make_normal_decoded(texture: &mut RgbaTexture) {
         chunk[3] = 255;
     }
 }
+
+#[test]
+fn test_premultiply_alpha() {
+    let mut data = vec![255, 128, 10, 255, 200, 100, 50, 128, 90, 80, 70, 0];
+    premultiply_alpha(&mut data);
+    assert_eq!(data, vec![255, 128, 10, 255, 100, 50, 25, 128, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_export_order() {
+    let frame = |unknown| Frame {
+        tex_x: 0,
+        tex_y: 0,
+        x_off: 0,
+        y_off: 0,
+        width: 0,
+        height: 0,
+        unknown,
+    };
+    let frames = [frame(2), frame(1), frame(2), frame(0), frame(1)];
+    assert_eq!(export_order(&frames, FrameOrder::Storage), vec![0, 1, 2, 3, 4]);
+    assert_eq!(export_order(&frames, FrameOrder::FrameType), vec![3, 1, 4, 0, 2]);
+    assert_eq!(export_order(&[], FrameOrder::FrameType), Vec::<usize>::new());
+}
+
+#[test]
+fn test_missing_texture_layers() {
+    let layer = |id| ExportLayer {
+        id,
+        sub_id: 0,
+        prefix: format!("layer{}", id),
+        name: format!("layer{}", id),
+        mode: LayerExportMode::Rgba,
+    };
+    // Sprite where layers 0 and 2 have textures, but frames are also defined for layer 1
+    let texture_sizes = [Some(16u32), None, Some(16)];
+    let has_texture = |id: usize| texture_sizes.get(id).map(|x| x.is_some()).unwrap_or(false);
+    let layers = vec![layer(0), layer(1), layer(2), layer(5)];
+    let missing = missing_texture_layers(&layers, has_texture);
+    assert_eq!(missing.iter().map(|x| x.id).collect::<Vec<_>>(), vec![1, 5]);
+    assert!(missing_texture_layers(&layers[..1], has_texture).is_empty());
+}
+
+#[test]
+fn test_blend_argb32_over_rgba() {
+    let argb = |a: u32, r: u32, g: u32, b: u32| (a << 24 | r << 16 | g << 8 | b).to_ne_bytes();
+    let mut dst = vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 0, 0];
+    let src = [argb(0, 0, 0, 0), argb(255, 255, 255, 255), argb(128, 0, 0, 128)].concat();
+    blend_argb32_over_rgba(&mut dst, &src);
+    // Transparent labels keep the frame, opaque ones replace it
+    assert_eq!(&dst[..8], &[255, 0, 0, 255, 255, 255, 255, 255]);
+    // Over a transparent pixel the color gets unpremultiplied
+    assert_eq!(&dst[8..], &[0, 0, 255, 128]);
+}
+
+#[test]
+fn test_sheet_size() {
+    assert_eq!(SheetMode::OnePerFrame.sheet_size(10), None);
+    assert_eq!(SheetMode::HorizontalStrip.sheet_size(10), Some((10, 1)));
+    assert_eq!(SheetMode::Grid { columns: 16 }.sheet_size(16), Some((16, 1)));
+    assert_eq!(SheetMode::Grid { columns: 16 }.sheet_size(17), Some((16, 2)));
+    assert_eq!(SheetMode::Grid { columns: 4 }.sheet_size(3), Some((3, 1)));
+}
+
+#[test]
+fn test_base64_encode() {
+    assert_eq!(base64_encode(b""), "");
+    assert_eq!(base64_encode(b"f"), "Zg==");
+    assert_eq!(base64_encode(b"fo"), "Zm8=");
+    assert_eq!(base64_encode(b"foo"), "Zm9v");
+    assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    assert_eq!(base64_encode(&[0xff, 0xfe]), "//4=");
+}
+
+#[test]
+fn test_frame_svg() {
+    let frame = Frame {
+        tex_x: 0,
+        tex_y: 0,
+        x_off: -8,
+        y_off: 4,
+        width: 16,
+        height: 12,
+        unknown: 3,
+    };
+    let svg = frame_svg(b"foo", 5, &frame, 2, 20, 10, -6, -2);
+    assert!(svg.contains(r#"viewBox="-6 -2 20 10""#));
+    assert!(svg.contains(r#"<image x="-6" y="-2" width="20" height="10""#));
+    assert!(svg.contains(
+        r#"index="5" x_offset="-4" y_offset="2" width="8" height="6" frame_type="3""#
+    ));
+    assert!(svg.contains("data:image/png;base64,Zm9v"));
+}
+
+#[test]
+fn test_pad_canvas_pow2() {
+    let canvas = FrameCanvas {
+        x_base: -3,
+        y_base: 0,
+        width: 100,
+        height: 64,
+    };
+    let (padded, content) = pad_canvas_pow2(canvas);
+    assert_eq!((padded.width, padded.height), (128, 64));
+    assert_eq!((padded.x_base, padded.y_base), (-17, 0));
+    assert_eq!(content, ContentRect { x: 14, y: 0, width: 100, height: 64 });
+}
+
+#[test]
+fn test_frame_crop_canvas() {
+    let frame = Frame {
+        tex_x: 0,
+        tex_y: 0,
+        x_off: -7,
+        y_off: 12,
+        width: 20,
+        height: 10,
+        unknown: 0,
+    };
+    let canvas = frame_crop_canvas(&frame, 2);
+    assert_eq!((canvas.x_base, canvas.y_base), (-4, 6));
+    assert_eq!((canvas.width, canvas.height), (10, 5));
+    let empty = Frame { width: 0, height: 0, ..frame };
+    let canvas = frame_crop_canvas(&empty, 1);
+    assert_eq!((canvas.width, canvas.height), (1, 1));
+}
+
+#[test]
+fn test_export_only_frames() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_export_only_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mainsd = std::io::Cursor::new(files::test_anim(1, 1));
+    let mut files = files::Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    let copied = files.extract_frame(0, SpriteType::Sd, 0).unwrap();
+    files.insert_frame(0, SpriteType::Sd, 1, &copied).unwrap();
+    files.set_frame_unknown(0, SpriteType::Sd, 1, 3).unwrap();
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    let layers = default_export_layers(
+        &file.layer_names(),
+        |i| file.texture_size(i).is_some(),
+        "000_sd",
+    );
+    export_frames(
+        &file,
+        4,
+        4,
+        &dir,
+        Path::new("frames.json"),
+        &layers,
+        SheetMode::OnePerFrame,
+        FrameOrder::Storage,
+        FrameTypeExport::Auto,
+        ExportOptions::default(),
+        Some(&[1]),
+        |_| (),
+    ).unwrap();
+    // Frame 1 is written as the only frame, so the export can be imported back
+    let frame_info = frame_info::parse_frame_info(&dir.join("frames.json")).unwrap();
+    assert_eq!(frame_info.frame_count, 1);
+    assert_eq!(frame_info.frame_types[0].frame_type, 3);
+    crate::frame_import::check_frame_files(&frame_info, &dir, 1.0).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,5 +1,5 @@
 use std::cell::{Cell, RefCell};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -7,7 +7,8 @@ use anyhow::Context;
 use gtk;
 use gtk::prelude::*;
 
-use crate::frame_export::{self, LayerExportMode};
+use crate::combo_box_enum::ComboBoxEnum;
+use crate::frame_export::{self, FrameTypeExport, LayerExportMode, SheetMode};
 use crate::int_entry::{self, TextEntry};
 use crate::select_dir;
 use crate::ui_helpers::*;
@@ -26,7 +27,8 @@ struct LayerCheckboxState {
 
 pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWindow) {
     enum Progress {
-        Done(Result<(), Error>),
+        /// Warnings, and the number of differing frames if exporting against a reference.
+        Done(Result<(Vec<String>, Option<usize>), Error>),
         Progress(f32),
     }
 
@@ -53,11 +55,110 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
     };
 
     let is_anim = file.is_anim();
-    let single_image_check = if is_anim {
-        SavedCheckbox::new("frame_export_single_image", "One image per layer")
-    } else {
-        SavedCheckbox::new("frame_export_single_image", "Single image")
-    };
+    // Only used for grps, anims use `sheet_mode`
+    let single_image_check = SavedCheckbox::new("frame_export_single_image", "Single image");
+
+    static SHEET_MODES: &[(SheetMode, &str)] = &[
+        (SheetMode::OnePerFrame, "One image per frame"),
+        (SheetMode::HorizontalStrip, "One image per layer, horizontal strip"),
+        (SheetMode::Grid { columns: 8 }, "One image per layer, 8 columns"),
+        (SheetMode::Grid { columns: 16 }, "One image per layer, 16 columns"),
+        (SheetMode::SvgPerFrame, "One SVG per frame, with embedded PNG"),
+    ];
+    let sheet_mode = ComboBoxEnum::new(SHEET_MODES);
+    let saved_mode = select_dir::read_config_entry_int("frame_export_sheet_mode")
+        .and_then(|x| SHEET_MODES.get(x as usize))
+        .map(|x| x.0)
+        .unwrap_or(SheetMode::OnePerFrame);
+    sheet_mode.set_active(&saved_mode);
+    sheet_mode.connect_changed(|mode| {
+        if let Some(index) = SHEET_MODES.iter().position(|x| Some(x.0) == mode) {
+            select_dir::set_config_entry("frame_export_sheet_mode", index as i64);
+        }
+    });
+    let sheet_mode_bx = label_section("Frame images", sheet_mode.widget());
+    sheet_mode_bx.set_tooltip_text(Some("\
+        Sprite sheet modes write all frames of a layer to a single image.\n\
+        Position of each frame in the image is saved to the frame info file.\n\
+        SVG mode embeds each frame image in a .svg with the frame offset and size\n\
+        as metadata; it cannot be imported back."));
+
+    static FRAME_TYPE_MODES: &[(FrameTypeExport, &str)] = &[
+        (FrameTypeExport::Auto, "Automatic"),
+        (FrameTypeExport::Ranges, "Frame ranges"),
+        (FrameTypeExport::PerFrame, "Every frame separately"),
+    ];
+    let frame_type_mode = ComboBoxEnum::new(FRAME_TYPE_MODES);
+    let saved_mode = select_dir::read_config_entry_int("frame_export_frame_types")
+        .and_then(|x| FRAME_TYPE_MODES.get(x as usize))
+        .map(|x| x.0)
+        .unwrap_or(FrameTypeExport::Auto);
+    frame_type_mode.set_active(&saved_mode);
+    frame_type_mode.connect_changed(|mode| {
+        if let Some(index) = FRAME_TYPE_MODES.iter().position(|x| Some(x.0) == mode) {
+            select_dir::set_config_entry("frame_export_frame_types", index as i64);
+        }
+    });
+    let frame_type_mode_bx = label_section("Frame types", frame_type_mode.widget());
+    frame_type_mode_bx.set_tooltip_text(Some("\
+        Automatic writes frame types as ranges, unless the same frame type is used\n\
+        in separate parts of the animation; then the type of every frame is written."));
+
+    let sort_frames_check = SavedCheckbox::new(
+        "frame_export_sort_by_type",
+        "Sort frames by frame type",
+    );
+    sort_frames_check.widget().set_tooltip_text(Some("\
+        Frames are exported in the order they are stored in the file by default.\n\
+        If enabled, frames are grouped by their frame type instead."));
+
+    let layers_by_name_check = SavedCheckbox::new(
+        "frame_export_layers_by_name",
+        "Refer to layers by name in frame info",
+    );
+    layers_by_name_check.widget().set_tooltip_text(Some("\
+        Layers are referred to by their index in the frame info file by default.\n\
+        If enabled, layer names are used instead, and importing maps them to \
+        the layers that have same names in the imported sprite."));
+
+    let pad_pow2_check = SavedCheckbox::new(
+        "frame_export_pad_pow2",
+        "Pad images to power of two size",
+    );
+    pad_pow2_check.widget().set_tooltip_text(Some("\
+        Adds transparent pixels around the frames so that the image dimensions are\n\
+        powers of two. The frame info records the padding, so importing ignores it."));
+
+    let tight_crop_check = SavedCheckbox::new(
+        "frame_export_tight_crop",
+        "Crop each frame to its own size",
+    );
+    tight_crop_check.widget().set_tooltip_text(Some("\
+        Writes every frame image at the size of the frame instead of a canvas shared\n\
+        by all frames. The frame info records the offset of each frame.\n\
+        Requires one image per frame."));
+
+    let premultiply_check = SavedCheckbox::new(
+        "frame_export_premultiply",
+        "Premultiply alpha",
+    );
+    premultiply_check.widget().set_tooltip_text(Some("\
+        Multiplies color of the RGBA layers by alpha, for programs that expect\n\
+        premultiplied images. Importing the images does not undo this."));
+
+    let reference_select = Rc::new(
+        select_dir::SelectFile::new(&window, "export_diff_reference", "Anim files", "*.anim")
+    );
+    let reference_section = label_section_with_enable_check(
+        "Only export frames that differ from",
+        reference_select.widget(),
+        "export_diff_reference_enabled",
+        false,
+    );
+    reference_section.widget().set_tooltip_text(Some("\
+        Compares each frame to the same frame of the selected .anim file,\n\
+        and only writes frames whose size, offset or pixels differ.\n\
+        The written frames are numbered from 0, and the frame info only describes them."));
 
     // Sprite dimensions are only used for anim;
     // if it errors display the error as a warning.
@@ -207,7 +308,29 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
                 }
             }
         }
-        label_section("Layers to export", &grid)
+        let selection_bx = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        let selection_actions: [(&str, fn(bool) -> bool); 3] = [
+            ("Select all", |_| true),
+            ("Select none", |_| false),
+            ("Invert", |x| !x),
+        ];
+        for &(label, fun) in &selection_actions {
+            let button = gtk::Button::with_label(label);
+            let checkboxes2 = checkboxes.clone();
+            button.connect_clicked(move |_| {
+                for layer in checkboxes2.borrow().iter() {
+                    // Layers without texture are left unchecked
+                    if layer.check.is_sensitive() {
+                        layer.check.set_active(fun(layer.check.is_active()));
+                    }
+                }
+            });
+            selection_bx.pack_start(&button, false, false, 0);
+        }
+        let layers_bx = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        layers_bx.pack_start(&selection_bx, false, false, 0);
+        layers_bx.pack_start(&grid, false, false, 0);
+        label_section("Layers to export", &layers_bx)
     } else {
         let (entry, frame) = int_entry::entry();
         frame.set_hexpand(true);
@@ -238,6 +361,57 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
     let framedef_bx = label_section("Write miscellaneous frame info to..", &framedef_frame);
 
     let button_bx = gtk::Box::new(gtk::Orientation::Horizontal, 15);
+    let dds_button = gtk::Button::with_label("Export as DDS");
+    dds_button.set_tooltip_text(Some("\
+        Writes textures of the selected layers as .dds files without decoding them,\n\
+        keeping their DXT1/DXT5 compression."));
+    let s = this.clone();
+    let w = window.clone();
+    let dir_entry = dir_select.entry.clone();
+    let checkboxes2 = checkboxes.clone();
+    let layer_names2 = layer_names.clone();
+    dds_button.connect_clicked(move |_| {
+        let path: PathBuf = String::from(dir_entry.text()).into();
+        if let Err(e) = frame_export::check_dir_writable(&path) {
+            error_msg_box(&w, &format!("Unable to export DDS: {:?}", e));
+            return;
+        }
+        let mut layers = checkboxes2.borrow()
+            .iter()
+            .filter(|x| x.check.is_active() && x.check.is_visible())
+            .map(|x| x.layer as usize)
+            .collect::<Vec<_>>();
+        layers.dedup();
+        if layers.is_empty() {
+            info_msg_box(&w, "No layers selected");
+            return;
+        }
+        let tex_id = s.tex_id();
+        let mut files = match s.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let file = match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(o)) => o,
+            _ => return,
+        };
+        let result = layers.iter().try_for_each(|&layer| {
+            let name = layer_names2.get(layer).map(|x| &**x).unwrap_or("unknown");
+            let filename = path.join(format!("{:03}_{}_{}.dds", tex_id.0, type_lowercase, name));
+            let out = std::fs::File::create(&filename)
+                .with_context(|| format!("Unable to create {}", filename.display()))?;
+            let mut out = std::io::BufWriter::new(out);
+            file.export_dds(layer, &mut out)
+                .with_context(|| format!("Writing {}", filename.display()))
+        });
+        match result {
+            Ok(()) => {
+                let msg = format!("Wrote {} DDS files to {}", layers.len(), path.display());
+                info_msg_box(&w, &msg);
+            }
+            Err(e) => error_msg_box(&w, &format!("Unable to export DDS: {:?}", e)),
+        }
+    });
     let ok_button = gtk::Button::with_label("Export");
     let cancel_button = gtk::Button::with_label("Cancel");
     let w = window.clone();
@@ -247,6 +421,15 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
     let s = this.clone();
     let w = window.clone();
     let single_image_check2 = single_image_check.clone();
+    let sheet_mode2 = sheet_mode.clone();
+    let sort_frames_check2 = sort_frames_check.clone();
+    let layers_by_name_check2 = layers_by_name_check.clone();
+    let pad_pow2_check2 = pad_pow2_check.clone();
+    let tight_crop_check2 = tight_crop_check.clone();
+    let premultiply_check2 = premultiply_check.clone();
+    let frame_type_mode2 = frame_type_mode.clone();
+    let reference_select2 = reference_select.clone();
+    let reference_section2 = reference_section.clone();
     let progress = gtk::ProgressBar::new();
     let progress2 = progress.clone();
     let waiting_for_thread = Rc::new(Cell::new(false));
@@ -258,6 +441,10 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
             return;
         }
         let path: PathBuf = dir_select.text().into();
+        if let Err(e) = frame_export::check_dir_writable(&path) {
+            error_msg_box(&w, &format!("Unable to export frames: {:?}", e));
+            return;
+        }
 
         let tex_id = s.tex_id();
         let mut files = match s.files.try_lock() {
@@ -273,6 +460,7 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
         let (send, recv) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
         let files_arc = s.files.clone();
         let frame_count;
+        let mut layer_count = 0;
         let path2 = path.clone();
         if is_anim {
             let layers_to_export = checkboxes
@@ -293,9 +481,34 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
                     })
                 })
                 .collect::<Vec<_>>();
-            frame_count = layers_to_export.len() *
-                file.frames().map(|x| x.len()).unwrap_or(0);
-            let single_image = single_image_check2.is_active();
+            layer_count = layers_to_export.len();
+            frame_count = layer_count * file.frames().map(|x| x.len()).unwrap_or(0);
+            let reference: Option<PathBuf> = match reference_section2.is_active() {
+                true => {
+                    let text = reference_select2.text();
+                    if text.is_empty() {
+                        info_msg_box(&w, "No reference file selected");
+                        return;
+                    }
+                    Some(text.into())
+                }
+                false => None,
+            };
+            let sheet_mode = sheet_mode2.active().unwrap_or(SheetMode::OnePerFrame);
+            let frame_order = match sort_frames_check2.is_active() {
+                true => frame_export::FrameOrder::FrameType,
+                false => frame_export::FrameOrder::Storage,
+            };
+            let frame_type_export = frame_type_mode2.active().unwrap_or(FrameTypeExport::Auto);
+            let options = frame_export::ExportOptions {
+                canvas_mode: match tight_crop_check2.is_active() {
+                    true => frame_export::FrameCanvasMode::TightCrop,
+                    false => frame_export::FrameCanvasMode::Uniform,
+                },
+                layers_by_name: layers_by_name_check2.is_active(),
+                pad_pow2: pad_pow2_check2.is_active(),
+                premultiply: premultiply_check2.is_active(),
+            };
             std::thread::spawn(move || {
                 let send2 = send.clone();
                 let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
@@ -303,18 +516,28 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
                     let file = files.file(tex_id.0, tex_id.1)?
                         .ok_or_else(|| anyhow!("No file?"))?;
 
+                    let differing = match reference {
+                        Some(ref reference) => {
+                            Some(frame_export::differing_frames(&file, reference)?)
+                        }
+                        None => None,
+                    };
                     let (width, height) = dimensions;
-                    frame_export::export_frames(
+                    let warnings = frame_export::export_frames(
                         &file,
-                        tex_id.1,
                         i32::from(width),
                         i32::from(height),
                         &path2,
                         &framedef,
                         &layers_to_export,
-                        single_image,
+                        sheet_mode,
+                        frame_order,
+                        frame_type_export,
+                        options,
+                        differing.as_deref(),
                         |step| send.send(Progress::Progress(step)).unwrap(),
-                    )
+                    )?;
+                    Ok((warnings, differing.map(|x| x.len())))
                 })).unwrap_or_else(|e| Err(error_from_panic(e)));
                 let _ = send2.send(Progress::Done(result));
             });
@@ -338,7 +561,7 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
                         &framedef,
                         single_image,
                         |step| send.send(Progress::Progress(step)).unwrap(),
-                    )
+                    ).map(|()| (Vec::new(), None))
                 })).unwrap_or_else(|e| Err(error_from_panic(e)));
                 let _ = send2.send(Progress::Done(result));
             });
@@ -358,11 +581,23 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
                     part.set_sensitive(true);
                 }
                 match result {
-                    Ok(()) => {
-                        let msg = format!(
-                            "Wrote {} frames to {}",
-                            frame_count, path.to_string_lossy(),
-                        );
+                    Ok((warnings, differing)) => {
+                        let mut msg = match differing {
+                            Some(differing) => format!(
+                                "{} of {} frames differ from the reference\n\
+                                Wrote {} frames to {}",
+                                differing, frame_count / layer_count.max(1),
+                                differing * layer_count, path.to_string_lossy(),
+                            ),
+                            None => format!(
+                                "Wrote {} frames to {}",
+                                frame_count, path.to_string_lossy(),
+                            ),
+                        };
+                        for warning in &warnings {
+                            msg.push_str("\n\nWarning: ");
+                            msg.push_str(warning);
+                        }
                         info_msg_box(&window, &msg);
                         window.close();
                     }
@@ -381,13 +616,27 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
     });
     button_bx.pack_end(&cancel_button, false, false, 0);
     button_bx.pack_end(&ok_button, false, false, 0);
+    if is_anim {
+        button_bx.pack_start(&dds_button, false, false, 0);
+    }
     let opt_error_label;
     let mut input_parts: Vec<&dyn BoxableWidget>  = vec![
         &filename_bx,
         &framedef_bx,
-        single_image_check.widget(),
-        &layers_bx,
     ];
+    if is_anim {
+        input_parts.push(&sheet_mode_bx);
+        input_parts.push(&frame_type_mode_bx);
+        input_parts.push(sort_frames_check.widget());
+        input_parts.push(layers_by_name_check.widget());
+        input_parts.push(tight_crop_check.widget());
+        input_parts.push(pad_pow2_check.widget());
+        input_parts.push(premultiply_check.widget());
+        input_parts.push(reference_section.widget());
+    } else {
+        input_parts.push(single_image_check.widget());
+    }
+    input_parts.push(&layers_bx);
     if let Some(Err(ref error)) = dimensions_result {
         opt_error_label = gtk::Label::new(Some(&format!("{:?}", error)));
         input_parts.push(&opt_error_label);
@@ -417,6 +666,216 @@ pub fn frame_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWind
     window.show_all();
 }
 
+/// Exports frames of every sprite with the default layers, each to its own subdirectory.
+pub fn batch_export_dialog(this: &Arc<SpriteInfo>, parent: &gtk::ApplicationWindow) {
+    enum Progress {
+        /// Export waits for the size to be confirmed with the sender.
+        Estimate(frame_export::ExportSizeEstimate, std::sync::mpsc::Sender<bool>),
+        Estimating(usize, usize),
+        /// `None` if the export was cancelled after the estimate.
+        Done(Option<frame_export::BatchExportResult>),
+        Progress(usize, usize),
+    }
+
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    let dir_select = select_dir::SelectDir::new(&window, "batch_export_frames");
+    let filename_bx = label_section("Output directory", &dir_select.widget());
+    let types = [
+        (SpriteType::Sd, SavedCheckbox::new_with_default("batch_export_sd", "SD", true)),
+        (SpriteType::Hd, SavedCheckbox::new_with_default("batch_export_hd", "HD", true)),
+        (SpriteType::Hd2, SavedCheckbox::new_with_default("batch_export_hd2", "HD2", true)),
+    ];
+    let types_bx = label_section("Include", &box_horizontal(&[
+        types[0].1.widget(),
+        types[1].1.widget(),
+        types[2].1.widget(),
+    ]));
+    let sheet_check = SavedCheckbox::new("batch_export_sheet", "One image per layer");
+    sheet_check.widget().set_tooltip_text(Some("\
+        Writes all frames of a layer to a single image with 16 columns,\n\
+        or a single image per grp."));
+    let progress = gtk::ProgressBar::new();
+    progress.set_show_text(true);
+
+    let button_bx = gtk::Box::new(gtk::Orientation::Horizontal, 15);
+    let ok_button = gtk::Button::with_label("Export");
+    let cancel_button = gtk::Button::with_label("Cancel");
+    let w = window.clone();
+    cancel_button.connect_clicked(move |_| {
+        w.close();
+    });
+    button_bx.pack_end(&cancel_button, false, false, 0);
+    button_bx.pack_end(&ok_button, false, false, 0);
+    let rest_bx = box_vertical(&[
+        &filename_bx,
+        &types_bx,
+        sheet_check.widget(),
+    ]);
+
+    let waiting_for_thread = Rc::new(Cell::new(false));
+    let waiting_for_thread2 = waiting_for_thread.clone();
+    let s = this.clone();
+    let w = window.clone();
+    let progress2 = progress.clone();
+    let rest_bx2 = rest_bx.clone();
+    let button_bx2 = button_bx.clone();
+    ok_button.connect_clicked(move |_| {
+        if waiting_for_thread.get() {
+            return;
+        }
+        let path: PathBuf = dir_select.text().into();
+        if let Err(e) = frame_export::check_dir_writable(&path) {
+            error_msg_box(&w, &format!("Unable to export frames: {:?}", e));
+            return;
+        }
+        let types = types.iter()
+            .filter(|x| x.1.is_active())
+            .map(|x| x.0)
+            .collect::<Vec<_>>();
+        if types.is_empty() {
+            info_msg_box(&w, "No sprite types selected");
+            return;
+        }
+        let sheet = sheet_check.is_active();
+        let files_arc = s.files.clone();
+        let (send, recv) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let path2 = path.clone();
+        std::thread::spawn(move || {
+            let estimate = frame_export::estimate_export_all_size(
+                &mut files_arc.lock(),
+                &types,
+                |done, total| send.send(Progress::Estimating(done, total)).unwrap(),
+            );
+            let (confirm_send, confirm_recv) = std::sync::mpsc::channel();
+            let _ = send.send(Progress::Estimate(estimate, confirm_send));
+            if confirm_recv.recv() != Ok(true) {
+                let _ = send.send(Progress::Done(None));
+                return;
+            }
+            let mut files = files_arc.lock();
+            let result = frame_export::export_all_sprites(
+                &mut files,
+                &path2,
+                &types,
+                sheet,
+                |done, total| send.send(Progress::Progress(done, total)).unwrap(),
+            );
+            let _ = send.send(Progress::Done(Some(result)));
+        });
+        waiting_for_thread.set(true);
+        rest_bx2.set_sensitive(false);
+        button_bx2.set_sensitive(false);
+        let window = w.clone();
+        let progress = progress2.clone();
+        let waiting_for_thread = waiting_for_thread.clone();
+        let rest_bx = rest_bx2.clone();
+        let button_bx = button_bx2.clone();
+        recv.attach(None, move |status| match status {
+            Progress::Estimating(done, total) => {
+                progress.set_fraction(done as f64 / total.max(1) as f64);
+                progress.set_text(Some("Estimating size..."));
+                glib::Continue(true)
+            }
+            Progress::Estimate(estimate, confirm) => {
+                progress.set_fraction(0.0);
+                progress.set_text(None);
+                let _ = confirm.send(confirm_export_size(&window, &path, &estimate));
+                glib::Continue(true)
+            }
+            Progress::Done(None) => {
+                waiting_for_thread.set(false);
+                rest_bx.set_sensitive(true);
+                button_bx.set_sensitive(true);
+                glib::Continue(false)
+            }
+            Progress::Done(Some(result)) => {
+                waiting_for_thread.set(false);
+                let mut msg = format!(
+                    "Exported {} sprites to {}\n{} sprites were skipped, {} failed",
+                    result.exported, path.to_string_lossy(), result.skipped, result.failed.len(),
+                );
+                for (sprite, ty, error) in result.failed.iter().take(20) {
+                    msg.push_str(&format!("\n{:?} image {}: {:#}", ty, sprite, error));
+                }
+                if result.failed.len() > 20 {
+                    msg.push_str(&format!("\n(And {} more)", result.failed.len() - 20));
+                }
+                if result.failed.is_empty() {
+                    info_msg_box(&window, &msg);
+                } else {
+                    error_msg_box(&window, &msg);
+                }
+                window.close();
+                glib::Continue(false)
+            }
+            Progress::Progress(done, total) => {
+                progress.set_fraction(done as f64 / total.max(1) as f64);
+                progress.set_text(Some(&format!("{} / {}", done, total)));
+                glib::Continue(true)
+            }
+        });
+    });
+
+    let bx = box_vertical(&[
+        &rest_bx,
+        &progress,
+        &button_bx,
+    ]);
+    window.add(&bx);
+    window.set_border_width(10);
+    window.set_default_width(350);
+    window.set_title("Export frames of all sprites");
+    window.connect_delete_event(move |_, _| {
+        Inhibit(waiting_for_thread2.get())
+    });
+    window.set_modal(true);
+    window.set_transient_for(Some(parent));
+    window.show_all();
+}
+
+/// Shows the estimated output size and free space of `path`, returns true if
+/// the user wants to continue.
+fn confirm_export_size(
+    parent: &gtk::Window,
+    path: &Path,
+    estimate: &frame_export::ExportSizeEstimate,
+) -> bool {
+    let free = gio::File::for_path(path)
+        .query_filesystem_info("filesystem::free", gio::Cancellable::NONE)
+        .ok()
+        .map(|info| info.attribute_uint64("filesystem::free"));
+    let mut msg = format!(
+        "The export is estimated to take {} ({} uncompressed).",
+        glib::format_size(estimate.estimated_bytes),
+        glib::format_size(estimate.raw_bytes),
+    );
+    let message_type = match free {
+        Some(free) => {
+            msg.push_str(&format!("\n{} is available.", glib::format_size(free)));
+            if free < estimate.estimated_bytes {
+                msg.push_str("\n\nThe exported files will likely not fit on the disk.");
+                gtk::MessageType::Warning
+            } else {
+                gtk::MessageType::Question
+            }
+        }
+        None => gtk::MessageType::Question,
+    };
+    msg.push_str("\nContinue?");
+    let dialog = gtk::MessageDialog::new(
+        Some(parent),
+        gtk::DialogFlags::MODAL,
+        message_type,
+        gtk::ButtonsType::None,
+        &msg,
+    );
+    dialog.add_button("Export", gtk::ResponseType::Ok);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    let result = dialog.run();
+    dialog.close();
+    result == gtk::ResponseType::Ok
+}
+
 #[derive(Clone)]
 pub struct SavedCheckbox {
     check: gtk::CheckButton,
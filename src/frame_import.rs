@@ -1,14 +1,18 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::convert::TryFrom;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
 use anyhow::Context;
 use image::{GenericImageView, RgbaImage};
 use rayon::prelude::*;
+use serde_derive::Deserialize;
 
 use crate::anim;
 use crate::anim_encoder;
@@ -18,8 +22,76 @@ use crate::frame_info::{self, FrameInfo};
 use crate::grp::GrpWriter;
 use crate::grp_decode;
 use crate::normal_encoding;
+use crate::texture_repack;
 use crate::{SpriteType, Error};
 
+/// Options affecting how input images are interpreted.
+#[derive(Copy, Clone, Default)]
+pub struct ImportOptions {
+    /// Treat 4-channel input as BGRA and swap it to RGBA.
+    pub swap_bgra: bool,
+    /// Bit n set = set alpha of layer n fully opaque before encoding.
+    pub force_opaque_layers: u32,
+    /// Output texture size instead of the packed atlas size, for anim imports.
+    /// HD2 textures are half of this.
+    pub texture_size: Option<(u32, u32)>,
+    /// Write frames to the texture positions of the existing frames
+    /// instead of packing a new atlas, for anim imports.
+    pub preserve_layout: bool,
+    /// Bit n set = keep the existing texture of layer n instead of importing it.
+    /// Layout has to be preserved for the existing texture to match the frames.
+    /// The layer may be in the frame info, but the import dialog removes it from there
+    /// so that its images aren't loaded just to be replaced.
+    pub skip_layers: u32,
+    /// Pixels with alpha at most this are considered empty when cropping frames to their
+    /// bounding box. Only affects anim imports, ddsgrp frames are not cropped.
+    pub alpha_threshold: u8,
+    /// Alignment of frames in a newly packed atlas, instead of
+    /// `anim_encoder::DEFAULT_ALIGNMENT`. Must be a power of two.
+    pub alignment: Option<u16>,
+}
+
+/// Error returned by imports when their `cancel` flag has been set.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Import was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+fn check_cancel(cancel: &AtomicBool) -> Result<(), Error> {
+    match cancel.load(Ordering::Relaxed) {
+        true => Err(Cancelled.into()),
+        false => Ok(()),
+    }
+}
+
+impl ImportOptions {
+    fn force_opaque(&self, layer: u32) -> bool {
+        layer < 32 && self.force_opaque_layers & (1 << layer) != 0
+    }
+
+    fn skip_layer(&self, layer: usize) -> bool {
+        layer < 32 && self.skip_layers & (1 << layer) != 0
+    }
+
+    /// `scale` is the largest scale the layout will be encoded with.
+    fn new_layout(&self, scale: u32) -> Result<anim_encoder::Layout, Error> {
+        let mut layout = anim_encoder::Layout::new();
+        let alignment = match self.alignment {
+            Some(s) => u32::from(s),
+            None if 4 * scale > anim_encoder::DEFAULT_ALIGNMENT => 4 * scale,
+            None => return Ok(layout),
+        };
+        layout.set_alignment(alignment, scale).context("Invalid texture alignment")?;
+        Ok(layout)
+    }
+}
+
 // If `format` isn't set it is assumed to be paletted, in which case the first image must
 // have one in it.
 pub fn import_frames_grp<F: Fn(f32) + Sync>(
@@ -32,8 +104,14 @@ pub fn import_frames_grp<F: Fn(f32) + Sync>(
     scale: u8,
     // For writing grp for SD ddsgrp cmdicon imports
     linked_grp_path: Option<&Path>,
+    options: ImportOptions,
+    cancel: &AtomicBool,
     report_progress: F,
 ) -> Result<(), Error> {
+    if !matches!(scale, 1 | 2 | 4) {
+        return Err(anyhow!("Invalid grp scale {}, expected 1, 2 or 4", scale));
+    }
+    check_frame_files(frame_info, dir, frame_scale)?;
     let image_data_cache = Mutex::new(ImageDataCache::new());
     let tls = thread_local::ThreadLocal::new();
     let step = AtomicUsize::new(1);
@@ -46,10 +124,11 @@ pub fn import_frames_grp<F: Fn(f32) + Sync>(
     let write_grp = linked_grp_path.is_some();
     let mut frames = (0..frame_info.frame_count).into_par_iter()
         .map(|i| {
+            check_cancel(cancel)?;
             let tls_cache = tls.get_or(|| RefCell::new(TlsImageDataCache::default()));
             let mut tls_cache = tls_cache.borrow_mut();
             let mut frame_reader =
-                FrameReader::new(dir, &image_data_cache, &mut tls_cache, needs_palette);
+                FrameReader::new(dir, &image_data_cache, &mut tls_cache, needs_palette, options);
 
             let (data, width, height, frame_palette) =
                 frame_reader.read_frame(frame_info, 0, i, frame_scale)?;
@@ -85,6 +164,7 @@ pub fn import_frames_grp<F: Fn(f32) + Sync>(
         })
         .collect::<Result<Vec<_>, Error>>()?;
     frames.sort_by_key(|x| x.0);
+    check_cancel(cancel)?;
 
     // Write cmdicons.grp for SD cmdicons etc
     if let Some(linked_grp_path) = linked_grp_path {
@@ -121,6 +201,7 @@ struct FrameReader<'a> {
     image_data_cache: &'a Mutex<ImageDataCache>,
     tls_cache: &'a mut TlsImageDataCache,
     use_palette: bool,
+    options: ImportOptions,
 }
 
 // ImageDataCache is the "root" object, but it won't keep anything alive by itself.
@@ -192,6 +273,7 @@ impl ImageDataCache {
         &mut self,
         filename: &Path,
         paletted: bool,
+        options: ImportOptions,
     ) -> Result<TlsImageDataCache, Error> {
         let mut strong_loaded =
             self.loaded.iter().filter_map(|x| x.upgrade()).collect::<Vec<_>>();
@@ -212,7 +294,7 @@ impl ImageDataCache {
         } else {
             let file = File::open(&filename)
                 .with_context(|| format!("Unable to open {}", filename.to_string_lossy()))?;
-            let image = load_png(BufReader::new(file), paletted)
+            let image = load_png(BufReader::new(file), paletted, options)
                 .with_context(|| format!("Unable to load PNG {}", filename.to_string_lossy()))?;
             let arc = Arc::new((image, filename.into(), AtomicUsize::new(self.load_count)));
             self.loaded.push(Arc::downgrade(&arc));
@@ -224,7 +306,11 @@ impl ImageDataCache {
     }
 }
 
-fn load_png<R: Read>(reader: BufReader<R>, paletted: bool) -> Result<ImageData, Error> {
+fn load_png<R: Read>(
+    reader: BufReader<R>,
+    paletted: bool,
+    options: ImportOptions,
+) -> Result<ImageData, Error> {
     let mut decoder = png::Decoder::new(reader);
     if !paletted {
         // If we don't want palette, expand it to RGB
@@ -246,7 +332,7 @@ fn load_png<R: Read>(reader: BufReader<R>, paletted: bool) -> Result<ImageData,
         Ok(ImageData::Paletted(buf, info.width, info.height, palette))
     } else {
         let info = reader.info();
-        let rgba = arbitrary_png_to_rgba(buf, &info)?;
+        let rgba = arbitrary_png_to_rgba(buf, &info, options.swap_bgra)?;
         let image = image::ImageBuffer::from_raw(info.width, info.height, rgba)
             .ok_or_else(|| anyhow!("Couldn't create image from raw bytes"))?;
         Ok(ImageData::Image(image))
@@ -261,10 +347,27 @@ fn rgb_to_rgb0(input: &[u8]) -> Vec<u8> {
     out
 }
 
-fn arbitrary_png_to_rgba(buf: Vec<u8>, info: &png::Info) -> Result<Vec<u8>, Error> {
-    if info.bit_depth != png::BitDepth::Eight {
-        return Err(anyhow!("Bit depth {:?} not supported", info.bit_depth));
+fn arbitrary_png_to_rgba(
+    buf: Vec<u8>,
+    info: &png::Info,
+    swap_bgra: bool,
+) -> Result<Vec<u8>, Error> {
+    let mut rgba = png_to_rgba(buf, info)?;
+    if swap_bgra {
+        for pixel in rgba.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
     }
+    Ok(rgba)
+}
+
+fn png_to_rgba(buf: Vec<u8>, info: &png::Info) -> Result<Vec<u8>, Error> {
+    let buf = match info.bit_depth {
+        png::BitDepth::Eight => buf,
+        // Samples are big-endian, keep the high byte
+        png::BitDepth::Sixteen => buf.chunks_exact(2).map(|x| x[0]).collect(),
+        _ => return Err(anyhow!("Bit depth {:?} not supported", info.bit_depth)),
+    };
     match info.color_type {
         png::ColorType::Rgba => Ok(buf),
         png::ColorType::Rgb => {
@@ -316,12 +419,14 @@ impl<'a> FrameReader<'a> {
         image_data_cache: &'a Mutex<ImageDataCache>,
         tls_cache: &'a mut TlsImageDataCache,
         use_palette: bool,
+        options: ImportOptions,
     ) -> FrameReader<'a> {
         FrameReader {
             dir,
             image_data_cache,
             tls_cache,
             use_palette,
+            options,
         }
     }
 
@@ -376,15 +481,8 @@ impl<'a> FrameReader<'a> {
         let layer = frame_info.layers.iter()
             .find(|x| x.id == layer_id && x.sub_id == sublayer)
             .ok_or_else(|| anyhow!("No layer {}:{}", layer_id, sublayer))?;
-        let layer_prefix = &layer.filename_prefix;
-        let multi_frame_image = frame_info.multi_frame_images.iter()
-            .filter(|x| x.layer == layer_id && x.sublayer == sublayer)
-            .find(|x| frame >= x.first_frame && frame < x.first_frame + x.frame_count);
-        let filename = if let Some(multi_frame) = multi_frame_image {
-            (&multi_frame.path).into()
-        } else {
-            self.dir.join(format!("{}_{:03}.png", layer_prefix, frame))
-        };
+        let multi_frame_image = multi_frame_image(frame_info, layer, frame);
+        let filename = frame_image_path(self.dir, frame_info, layer, frame);
         let image = match self.tls_cache.get(&filename) {
             Some(s) => s,
             None => {
@@ -393,7 +491,7 @@ impl<'a> FrameReader<'a> {
                 // where 8 threads load a same 300MB PNG at once
                 // and 7 of them end up being discarded.
                 let mut main_cache = self.image_data_cache.lock().unwrap();
-                *self.tls_cache = main_cache.load_png(&filename, self.use_palette)?;
+                *self.tls_cache = main_cache.load_png(&filename, self.use_palette, self.options)?;
                 self.tls_cache.get(&filename)
                     .ok_or_else(|| {
                         anyhow!("{} didn't load properly to cache???", filename.display())
@@ -402,15 +500,21 @@ impl<'a> FrameReader<'a> {
         };
         let (x, y, width, height) = if let Some(multi_frame) = multi_frame_image {
             let index = frame - multi_frame.first_frame;
-            let frames_per_row = image.width() / multi_frame.frame_width;
-            if frames_per_row * multi_frame.frame_width != image.width() {
-                return Err(anyhow!(
-                    "Image width {} not multiple of frame width {}",
-                    image.width(), multi_frame.frame_width,
-                ));
-            }
-            let x = (index % frames_per_row) * multi_frame.frame_width;
-            let y = (index / frames_per_row) * multi_frame.frame_height;
+            let (x, y) = match multi_frame.frame_positions.get(index as usize) {
+                Some(&pos) => pos,
+                None => {
+                    let frames_per_row = image.width() / multi_frame.frame_width;
+                    if frames_per_row * multi_frame.frame_width != image.width() {
+                        return Err(anyhow!(
+                            "Image width {} not multiple of frame width {}",
+                            image.width(), multi_frame.frame_width,
+                        ));
+                    }
+                    let x = (index % frames_per_row) * multi_frame.frame_width;
+                    let y = (index / frames_per_row) * multi_frame.frame_height;
+                    (x, y)
+                }
+            };
             let (width, height) = match multi_frame.frame_size_overrides.get(&frame) {
                 Some(&s) => s,
                 None => (multi_frame.frame_width, multi_frame.frame_height),
@@ -505,6 +609,8 @@ struct LayerAddCtx<'a, F: Fn(f32) + Sync> {
     dir: &'a Path,
     frame_scale: f32,
     scale: u32,
+    options: ImportOptions,
+    cancel: &'a AtomicBool,
     report_progress: &'a F,
 }
 
@@ -522,81 +628,99 @@ impl<'a, F: Fn(f32) + Sync> LayerAddCtx<'a, F> {
         let report_progress = self.report_progress;
         let step = &self.step;
         let step_count = self.step_count;
+        let options = self.options;
+        let cancel = self.cancel;
+        let max_frame_bounds = &self.max_frame_bounds;
+        // The frames are resized by `frame_scale` before they are bounded.
+        let content_rect = frame_info.content_rect
+            .map(|rect| scale_content_rect(rect, frame_scale));
 
         let image_data_cache = Mutex::new(ImageDataCache::new());
         let tls = thread_local::ThreadLocal::new();
         let layer = self.first_layer + i as usize;
+        // Decoding and bounding the frames is done in parallel, the results are
+        // added to layout afterwards in frame order so that the packing stays deterministic.
         let frames = (0..frame_info.frame_count).into_par_iter()
             .map(|f| {
+                check_cancel(cancel)?;
                 let tls_cache = tls.get_or(|| RefCell::new(TlsImageDataCache::default()));
                 let mut tls_cache = tls_cache.borrow_mut();
                 let mut frame_reader =
-                    FrameReader::new(dir, &image_data_cache, &mut tls_cache, false);
+                    FrameReader::new(dir, &image_data_cache, &mut tls_cache, false, options);
 
                 let (data, width, height, _palette) = if merge_ao_depth {
                     frame_reader.read_ao_depth_merged_frame(frame_info, i, f, frame_scale)
                 } else {
                     frame_reader.read_frame(frame_info, i, f, frame_scale)
                 }.with_context(|| format!("Reading frame #{}", f))?;
-                let step = step.fetch_add(1, Ordering::Relaxed);
-                report_progress((step as f32) / step_count);
-                Ok((f, data, width, height))
-            })
-            .collect::<Result<Vec<_>, Error>>()?;
-        for (f, data, width, height) in frames {
-            self.image_width = self.image_width.max(width);
-            self.image_height = self.image_height.max(height);
-            let bounds = if alpha_bounding_box {
-                let mut bounds = rgba_bounds(&data, width, height);
-                if bounds.right > bounds.left && bounds.bottom > bounds.top {
-                    // Round left / top bounds to even. HD2 imports don't like
-                    // odd x/y.
-                    bounds.left &= !1;
-                    bounds.top &= !1;
-                    while self.max_frame_bounds.len() <= f as usize {
-                        self.max_frame_bounds.push(None);
+                let bounds = if alpha_bounding_box {
+                    let mut bounds = rgba_bounds(&data, width, height, options.alpha_threshold);
+                    if let Some(rect) = content_rect {
+                        bounds = clip_bounds(bounds, rect);
+                    }
+                    if bounds.right > bounds.left && bounds.bottom > bounds.top {
+                        // Round left / top bounds to even. HD2 imports don't like
+                        // odd x/y.
+                        bounds.left &= !1;
+                        bounds.top &= !1;
                     }
-                    let old = self.max_frame_bounds.get(f as usize)
+                    bounds
+                } else {
+                    let mut bounds = max_frame_bounds.get(f as usize)
                         .cloned()
                         .flatten()
+                        .filter(|b| b.left < width && b.top < height)
                         .unwrap_or_else(|| Bounds {
-                            left: u32::MAX,
-                            top: u32::MAX,
+                            left: 0,
+                            top: 0,
                             right: 0,
                             bottom: 0,
                         });
-                    let new = Bounds {
-                        left: old.left.min(bounds.left),
-                        top: old.top.min(bounds.top),
-                        right: old.right.max(bounds.right),
-                        bottom: old.bottom.max(bounds.bottom),
-                    };
-                    self.max_frame_bounds[f as usize] = Some(new);
+                    if bounds.right >= width {
+                        bounds.right = width;
+                    }
+                    if bounds.bottom >= height {
+                        bounds.bottom = height;
+                    }
+                    bounds
+                };
+                let mut bounded = bound_data(&data, width, height, &bounds);
+                if options.force_opaque(i) {
+                    set_opaque(&mut bounded.data);
                 }
-                bounds
-            } else {
-                let mut bounds = self.max_frame_bounds.get(f as usize)
+                let step = step.fetch_add(1, Ordering::Relaxed);
+                report_progress((step as f32) / step_count);
+                Ok((f, bounds, bounded, width, height))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        for (f, bounds, mut bounded, width, height) in frames {
+            self.image_width = self.image_width.max(width);
+            self.image_height = self.image_height.max(height);
+            if alpha_bounding_box && bounds.right > bounds.left && bounds.bottom > bounds.top {
+                while self.max_frame_bounds.len() <= f as usize {
+                    self.max_frame_bounds.push(None);
+                }
+                let old = self.max_frame_bounds.get(f as usize)
                     .cloned()
                     .flatten()
-                    .filter(|b| b.left < width && b.top < height)
                     .unwrap_or_else(|| Bounds {
-                        left: 0,
-                        top: 0,
+                        left: u32::MAX,
+                        top: u32::MAX,
                         right: 0,
                         bottom: 0,
                     });
-                if bounds.right >= width {
-                    bounds.right = width;
-                }
-                if bounds.bottom >= height {
-                    bounds.bottom = height;
-                }
-                bounds
-            };
+                let new = Bounds {
+                    left: old.left.min(bounds.left),
+                    top: old.top.min(bounds.top),
+                    right: old.right.max(bounds.right),
+                    bottom: old.bottom.max(bounds.bottom),
+                };
+                self.max_frame_bounds[f as usize] = Some(new);
+            }
 
-            let mut bounded = bound_data(&data, width, height, &bounds);
-            let x_offset = (frame_info.offset_x as f32 * frame_scale) as i32;
-            let y_offset = (frame_info.offset_y as f32 * frame_scale) as i32;
+            let (offset_x, offset_y) = frame_info.frame_offset(f);
+            let x_offset = (offset_x as f32 * frame_scale) as i32;
+            let y_offset = (offset_y as f32 * frame_scale) as i32;
             bounded.coords.x_offset =
                 bounded.coords.x_offset.saturating_add(x_offset) * scale as i32;
             bounded.coords.y_offset =
@@ -610,6 +734,164 @@ impl<'a, F: Fn(f32) + Sync> LayerAddCtx<'a, F> {
     }
 }
 
+fn multi_frame_image<'a>(
+    frame_info: &'a FrameInfo,
+    layer: &frame_info::Layer,
+    frame: u32,
+) -> Option<&'a frame_info::MultiFrameImage> {
+    frame_info.multi_frame_images.iter()
+        .filter(|x| x.layer == layer.id && x.sublayer == layer.sub_id)
+        .find(|x| frame >= x.first_frame && frame < x.first_frame + x.frame_count)
+}
+
+/// Path of the PNG that contains `frame` of `layer`.
+fn frame_image_path(
+    dir: &Path,
+    frame_info: &FrameInfo,
+    layer: &frame_info::Layer,
+    frame: u32,
+) -> PathBuf {
+    if let Some(multi_frame) = multi_frame_image(frame_info, layer, frame) {
+        (&multi_frame.path).into()
+    } else {
+        dir.join(format!("{}_{:03}.png", layer.filename_prefix, frame))
+    }
+}
+
+/// Checks that every PNG referred by `frame_info` exists before starting to load them,
+/// reporting all missing files at once.
+///
+/// Also warns about images that are too large to be placed at the frame info's offset.
+pub fn check_frame_files(
+    frame_info: &FrameInfo,
+    dir: &Path,
+    frame_scale: f32,
+) -> Result<(), Error> {
+    let mut paths = Vec::new();
+    // (Index to `paths`, frame)
+    let mut path_frames = Vec::new();
+    for layer in &frame_info.layers {
+        for frame in 0..frame_info.frame_count {
+            let path = frame_image_path(dir, frame_info, layer, frame);
+            let index = match paths.iter().position(|x| *x == path) {
+                Some(i) => i,
+                None => {
+                    paths.push(path);
+                    paths.len() - 1
+                }
+            };
+            if !path_frames.contains(&(index, frame)) {
+                path_frames.push((index, frame));
+            }
+        }
+    }
+    let missing = paths.iter()
+        .filter(|x| !x.is_file())
+        .map(|x| x.to_string_lossy())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(anyhow!("{} images are missing:\n{}", missing.len(), missing.join("\n")));
+    }
+    for (i, path) in paths.iter().enumerate() {
+        let dimensions = File::open(path).ok()
+            .and_then(|file| png::Decoder::new(BufReader::new(file)).read_info().ok())
+            .map(|reader| (reader.info().width, reader.info().height));
+        if let Some((width, height)) = dimensions {
+            let frames = path_frames.iter().filter(|x| x.0 == i).map(|x| x.1);
+            for frame in frames {
+                let (offset_x, offset_y) = frame_info.frame_offset(frame);
+                let right = offset_x as f32 + width as f32 * frame_scale;
+                let bottom = offset_y as f32 + height as f32 * frame_scale;
+                if right > i16::MAX as f32 || bottom > i16::MAX as f32 {
+                    warn!(
+                        "{} is {}x{}, which cannot be fully represented at offset {},{}",
+                        path.display(), width, height, offset_x, offset_y,
+                    );
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_layers<F: Fn(f32) + Sync>(
+    layout: &mut anim_encoder::Layout,
+    frame_info: &FrameInfo,
+    dir: &Path,
+    first_layer: usize,
+    frame_scale: f32,
+    scale: u32,
+    options: ImportOptions,
+    cancel: &AtomicBool,
+    report_progress: F,
+) -> Result<(u32, u32), Error> {
+    // Try to minimize amount of memory used by keeping PNGs loaded,
+    // so never parallelize layers (as they are expected to always be
+    // separate files)
+    let mut ctx = LayerAddCtx {
+        step: AtomicUsize::new(1),
+        step_count: frame_info.layers.len() as f32 * frame_info.frame_count as f32,
+        image_width: 0,
+        image_height: 0,
+        max_frame_bounds: Vec::with_capacity(frame_info.frame_count as usize),
+        layout,
+        frame_info,
+        first_layer,
+        dir,
+        frame_scale,
+        scale,
+        options,
+        cancel,
+        report_progress: &report_progress,
+    };
+    fn is_merge_ao_depth(
+        layer: &frame_info::Layer,
+    ) -> bool {
+        layer.name == "ao_depth" && layer.encoding == frame_info::LayerEncoding::SingleChannel
+    }
+
+    for layer in &frame_info.layers {
+        let alpha_used = layer_has_alpha_bounding_box(&layer.name);
+        if layer.sub_id == 0 && alpha_used {
+            let merge_ao_depth = is_merge_ao_depth(layer);
+            ctx.add_layer(layer.id, true, merge_ao_depth)?;
+        }
+    }
+    for layer in &frame_info.layers {
+        let alpha_used = layer_has_alpha_bounding_box(&layer.name);
+        if layer.sub_id == 0 && !alpha_used {
+            let merge_ao_depth = is_merge_ao_depth(layer);
+            ctx.add_layer(layer.id, false, merge_ao_depth)?;
+        }
+    }
+
+    Ok((ctx.image_width, ctx.image_height))
+}
+
+/// Packs frames of `frame_info` to a new atlas the same way `import_frames` does,
+/// without changing any files. Used to show the resulting texture size before importing.
+///
+/// `hd2_divisor` is the HD2 divisor when the atlas is also used for HD2, otherwise 1.
+/// Returns `Cancelled` error if `cancel` gets set while the frames are being loaded.
+pub fn preview_layout(
+    frame_info: &FrameInfo,
+    dir: &Path,
+    frame_scale: f32,
+    hd2_divisor: u32,
+    options: ImportOptions,
+    cancel: &AtomicBool,
+) -> Result<anim_encoder::LayoutResult, Error> {
+    check_frame_files(frame_info, dir, frame_scale)?;
+    let mut layout = options.new_layout(hd2_divisor)?;
+    add_layers(&mut layout, frame_info, dir, 0, frame_scale, 1, options, cancel, |_| ())?;
+    let mut result = layout.layout();
+    if let Some((width, height)) = options.texture_size {
+        result.set_texture_size(width, height, hd2_divisor)?;
+    }
+    Ok(result)
+}
+
 pub fn import_frames<F: Fn(f32) + Sync>(
     files: &mut files::Files,
     frame_info: &FrameInfo,
@@ -622,65 +904,74 @@ pub fn import_frames<F: Fn(f32) + Sync>(
     sprite: usize,
     ty: SpriteType,
     grp_path: Option<&Path>,
+    options: ImportOptions,
+    cancel: &AtomicBool,
     report_progress: F,
 ) -> Result<(), Error> {
-    fn add_layers<F: Fn(f32) + Sync>(
-        layout: &mut anim_encoder::Layout,
-        frame_info: &FrameInfo,
-        dir: &Path,
-        first_layer: usize,
-        frame_scale: f32,
-        scale: u32,
-        report_progress: F,
-    ) -> Result<(u32, u32), Error> {
-        // Try to minimize amount of memory used by keeping PNGs loaded,
-        // so never parallelize layers (as they are expected to always be
-        // separate files)
-        let mut ctx = LayerAddCtx {
-            step: AtomicUsize::new(1),
-            step_count: frame_info.layers.len() as f32 * frame_info.frame_count as f32,
-            image_width: 0,
-            image_height: 0,
-            max_frame_bounds: Vec::with_capacity(frame_info.frame_count as usize),
-            layout,
-            frame_info,
-            first_layer,
-            dir,
-            frame_scale,
-            scale,
-            report_progress: &report_progress,
-        };
-        fn is_merge_ao_depth(
-            layer: &frame_info::Layer,
-        ) -> bool {
-            layer.name == "ao_depth" && layer.encoding == frame_info::LayerEncoding::SingleChannel
-        }
-
-        for layer in &frame_info.layers {
-            let alpha_used = layer_has_alpha_bounding_box(&layer.name);
-            if layer.sub_id == 0 && alpha_used {
-                let merge_ao_depth = is_merge_ao_depth(layer);
-                ctx.add_layer(layer.id, true, merge_ao_depth)?;
-            }
-        }
-        for layer in &frame_info.layers {
-            let alpha_used = layer_has_alpha_bounding_box(&layer.name);
-            if layer.sub_id == 0 && !alpha_used {
-                let merge_ao_depth = is_merge_ao_depth(layer);
-                ctx.add_layer(layer.id, false, merge_ao_depth)?;
-            }
-        }
-
-        Ok((ctx.image_width, ctx.image_height))
-    }
-
+    let start_time = Instant::now();
     let hd2_frame_info = match (hd2_frame_info, hd2_dir) {
         (Some(a), Some(b)) => Some((a, b)),
         _ => None,
     };
+    if let Some((hd2, _)) = hd2_frame_info {
+        // The import dialog imports HD2 from the HD frames, with nothing to compare.
+        if !std::ptr::eq(frame_info, hd2) {
+            check_hd2_frame_info(frame_info, hd2)?;
+        }
+    }
+    if options.skip_layers != 0 {
+        if frame_info.layers.is_empty() {
+            return Err(anyhow!("Every layer is skipped, select at least one layer to import"));
+        }
+        if !options.preserve_layout {
+            return Err(anyhow!(
+                "Keeping textures of skipped layers requires keeping the existing frame layout"
+            ));
+        }
+    }
+    check_frame_files(frame_info, dir, frame_scale)?;
+    if let Some((hd2, hd2_dir)) = hd2_frame_info {
+        check_frame_files(hd2, hd2_dir, hd2_frame_scale.unwrap_or(1.0))
+            .context("HD2 frames")?;
+    }
 
     let layer_count = formats.len();
-    let mut layout = anim_encoder::Layout::new();
+    let (target_layer_count, layer_names) = match files.file(sprite, ty) {
+        Ok(Some(file)) if file.is_anim() => {
+            (file.layer_count().min(layer_count), file.layer_names().into_owned())
+        }
+        _ => {
+            let names = match ty {
+                SpriteType::Sd => files::DEFAULT_SD_LAYER_NAMES,
+                _ => files::DEFAULT_HD_LAYER_NAMES,
+            };
+            (layer_count, names.iter().map(|&x| String::from(x)).collect())
+        }
+    };
+    let frame_info: &FrameInfo = &resolve_layer_names(frame_info, &layer_names)?;
+    let hd2_frame_info = match hd2_frame_info {
+        Some((hd2, hd2_dir)) => {
+            let hd2 = resolve_layer_names(hd2, &layer_names).context("HD2 frame info")?;
+            Some((hd2, hd2_dir))
+        }
+        None => None,
+    };
+    let hd2_frame_info = hd2_frame_info.as_ref().map(|(a, b)| (&**a, *b));
+    let hd2_divisor = match hd2_frame_info {
+        Some(_) => match files.file(sprite, SpriteType::Hd2) {
+            Ok(Some(file)) => file.texture_divisor(),
+            _ => files::DEFAULT_HD2_DIVISOR,
+        },
+        None => files::DEFAULT_HD2_DIVISOR,
+    };
+    check_layer_count(frame_info, target_layer_count)?;
+    if let Some((hd2, _)) = hd2_frame_info {
+        check_layer_count(hd2, target_layer_count).context("HD2 frame info")?;
+    }
+    let mut layout = match hd2_frame_info {
+        Some(_) => options.new_layout(hd2_divisor)?,
+        None => options.new_layout(1)?,
+    };
     let progress_mul = match hd2_frame_info.is_some() {
         true => 0.5,
         false => 1.0,
@@ -692,6 +983,8 @@ pub fn import_frames<F: Fn(f32) + Sync>(
         0,
         frame_scale,
         1,
+        options,
+        cancel,
         |step| report_progress(step * progress_mul),
     )?;
     if let Some((hd2, dir)) = hd2_frame_info {
@@ -701,10 +994,13 @@ pub fn import_frames<F: Fn(f32) + Sync>(
             dir,
             layer_count,
             hd2_frame_scale.unwrap_or(1.0),
-            2,
+            hd2_divisor,
+            options,
+            cancel,
             |step| report_progress(0.5 + step * 0.5),
         )?;
     }
+    check_cancel(cancel)?;
     if let Some(grp_path) = grp_path {
         if let Some(parent) = grp_path.parent() {
             let _ = std::fs::create_dir_all(parent);
@@ -718,7 +1014,51 @@ pub fn import_frames<F: Fn(f32) + Sync>(
         std::fs::write(grp_path, &grp)
             .with_context(|| format!("Couldn't write {}", grp_path.display()))?;
     }
-    let layout_result = layout.layout();
+    debug!(
+        "Loaded {} frames of {} layers ({}x{}) in {:?}",
+        frame_info.frame_count, frame_info.layers.len(), width, height, start_time.elapsed(),
+    );
+    let mut layout_result = match options.preserve_layout {
+        true => {
+            if options.texture_size.is_some() {
+                return Err(anyhow!("Cannot force texture size while keeping the existing layout"));
+            }
+            let main_ty = match hd2_frame_info.is_some() {
+                true => SpriteType::Hd,
+                false => ty,
+            };
+            let (existing, texture) = existing_layout(files, sprite, main_ty)?;
+            if existing.len() != frame_info.frame_count as usize {
+                return Err(anyhow!(
+                    "Importing {} frames, but the sprite has {} frames",
+                    frame_info.frame_count, existing.len(),
+                ));
+            }
+            if hd2_frame_info.is_some() {
+                let (hd2_existing, _) = existing_layout(files, sprite, SpriteType::Hd2)?;
+                let same_positions = hd2_existing.len() == existing.len() &&
+                    hd2_existing.iter().zip(&existing).all(|(a, b)| {
+                        (a.tex_x, a.tex_y, a.width, a.height) ==
+                            (b.tex_x, b.tex_y, b.width, b.height)
+                    });
+                if !same_positions {
+                    return Err(anyhow!(
+                        "HD2 frames are laid out differently from HD, cannot keep both layouts"
+                    ));
+                }
+            }
+            layout.layout_preserving(&existing, texture.0, texture.1)
+                .context("Frames don't match the existing layout")?
+        }
+        false => layout.layout(),
+    };
+    if let Some((width, height)) = options.texture_size {
+        let scale = match hd2_frame_info {
+            Some(_) => hd2_divisor,
+            None => 1,
+        };
+        layout_result.set_texture_size(width, height, scale)?;
+    }
 
     let formats = formats.iter().enumerate().map(|(i, &f)| {
         if frame_info.layers.iter().any(|x| x.id as usize == i) {
@@ -736,25 +1076,37 @@ pub fn import_frames<F: Fn(f32) + Sync>(
 
     let mut changes = layout_result.encode(0, &formats, 1);
     let frame_count = changes.frames.len() as u32;
-    for ty in &frame_info.frame_types {
-        for f in ty.first_frame..ty.last_frame + 1 {
-            if let Some(f) = changes.frames.get_mut(f as usize) {
-                f.unknown = ty.frame_type;
-            }
+    if let Some(tex) = changes.textures.iter().find_map(|x| x.as_ref()) {
+        debug!(
+            "Encoded {}x{} textures, total time {:?}",
+            tex.0.width, tex.0.height, start_time.elapsed(),
+        );
+    }
+    for (i, f) in changes.frames.iter_mut().enumerate() {
+        if let Some(frame_type) = frame_info.frame_type(i as u32) {
+            f.unknown = frame_type;
         }
     }
-    // width and height are already scaled by frame_scale
-    let wh_scaled = (width as u16, height as u16);
-    files.set_tex_changes(sprite, ty, changes, wh_scaled);
-    if let Some((hd2, _dir)) = hd2_frame_info {
-        let mut changes = layout_result.encode(layer_count, &formats, 2);
-        for ty in &hd2.frame_types {
-            for f in ty.first_frame..ty.last_frame + 1 {
-                if let Some(f) = changes.frames.get_mut(f as usize) {
-                    f.unknown = ty.frame_type;
+    keep_skipped_textures(files, sprite, ty, &mut changes, options)?;
+    let hd2_changes = match hd2_frame_info {
+        Some((hd2, _dir)) => {
+            let mut changes = layout_result.encode(layer_count, &formats, hd2_divisor);
+            for (i, f) in changes.frames.iter_mut().enumerate() {
+                if let Some(frame_type) = hd2.frame_type(i as u32) {
+                    f.unknown = frame_type;
                 }
             }
+            keep_skipped_textures(files, sprite, SpriteType::Hd2, &mut changes, options)?;
+            Some(changes)
         }
+        None => None,
+    };
+    // Last chance to cancel, the sprite isn't modified before this.
+    check_cancel(cancel)?;
+    // width and height are already scaled by frame_scale
+    let wh_scaled = (width as u16, height as u16);
+    files.set_tex_changes(sprite, ty, changes, wh_scaled);
+    if let Some(changes) = hd2_changes {
         files.set_tex_changes(sprite, SpriteType::Hd2, changes, wh_scaled);
     }
 
@@ -770,6 +1122,383 @@ pub fn import_frames<F: Fn(f32) + Sync>(
     Ok(())
 }
 
+/// Entry of a batch import manifest, which is a JSON array of these.
+///
+/// Relative paths are relative to the manifest file.
+#[derive(Deserialize)]
+pub struct ManifestEntry {
+    pub sprite: usize,
+    /// "sd", "hd" or "hd2"
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub frame_info_path: PathBuf,
+    /// Directory of the frame images, defaults to directory of the frame info.
+    #[serde(default)]
+    pub image_dir: Option<PathBuf>,
+    /// Texture format of each layer ("dxt1", "dxt5", "rgba", "monochrome").
+    /// Ddsgrps use only the first one, and are imported as paletted if it is empty.
+    pub formats: Vec<String>,
+}
+
+/// Result of importing a single manifest entry.
+pub struct ManifestResult {
+    pub sprite: usize,
+    pub ty: Option<SpriteType>,
+    pub result: Result<(), Error>,
+}
+
+fn parse_manifest<R: Read>(r: R, base_dir: &Path) -> Result<Vec<ManifestEntry>, Error> {
+    let mut entries: Vec<ManifestEntry> = serde_json::from_reader(r)?;
+    for entry in &mut entries {
+        entry.frame_info_path = base_dir.join(&entry.frame_info_path);
+        if let Some(ref mut dir) = entry.image_dir {
+            *dir = base_dir.join(&*dir);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn parse_texture_format(text: &str) -> Result<anim::TextureFormat, Error> {
+    match &*text.to_ascii_lowercase() {
+        "dxt1" => Ok(anim::TextureFormat::Dxt1),
+        "dxt5" => Ok(anim::TextureFormat::Dxt5),
+        "rgba" => Ok(anim::TextureFormat::Rgba),
+        "monochrome" => Ok(anim::TextureFormat::Monochrome),
+        _ => Err(anyhow!(
+            "Invalid texture format '{}', expected dxt1, dxt5, rgba or monochrome", text,
+        )),
+    }
+}
+
+/// Imports every entry of a manifest, continuing past entries that fail.
+///
+/// Returns an error only if the manifest couldn't be read. The changes are not saved.
+pub fn import_from_manifest(
+    files: &mut files::Files,
+    manifest_path: &Path,
+) -> Result<Vec<ManifestResult>, Error> {
+    let file = File::open(manifest_path)
+        .with_context(|| format!("Unable to open {}", manifest_path.display()))?;
+    let base_dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+    let entries = parse_manifest(BufReader::new(file), base_dir)
+        .with_context(|| format!("Invalid manifest {}", manifest_path.display()))?;
+    let results = entries.iter().enumerate().map(|(i, entry)| {
+        let ty = match &*entry.ty.to_ascii_lowercase() {
+            "sd" => Some(SpriteType::Sd),
+            "hd" => Some(SpriteType::Hd),
+            "hd2" => Some(SpriteType::Hd2),
+            _ => None,
+        };
+        let result = match ty {
+            Some(ty) => import_manifest_entry(files, entry, ty),
+            None => Err(anyhow!("Invalid sprite type '{}', expected sd, hd or hd2", entry.ty)),
+        };
+        ManifestResult {
+            sprite: entry.sprite,
+            ty,
+            result: result.with_context(|| {
+                format!("Manifest entry {}: sprite {} ({})", i, entry.sprite, entry.ty)
+            }),
+        }
+    }).collect();
+    Ok(results)
+}
+
+fn import_manifest_entry(
+    files: &mut files::Files,
+    entry: &ManifestEntry,
+    ty: SpriteType,
+) -> Result<(), Error> {
+    let frame_info = frame_info::parse_frame_info(&entry.frame_info_path)
+        .with_context(|| format!("Unable to read {}", entry.frame_info_path.display()))?;
+    let dir = match entry.image_dir {
+        Some(ref s) => s.as_path(),
+        None => entry.frame_info_path.parent().unwrap_or_else(|| Path::new(".")),
+    };
+    let formats = entry.formats.iter()
+        .map(|x| parse_texture_format(x))
+        .collect::<Result<Vec<_>, Error>>()?;
+    if entry.sprite >= files.sprites().len() {
+        return Err(anyhow!("Sprite {} doesn't exist", entry.sprite));
+    }
+    if files.is_anim() {
+        import_frames(
+            files,
+            &frame_info,
+            None,
+            dir,
+            None,
+            1.0,
+            None,
+            &formats,
+            entry.sprite,
+            ty,
+            None,
+            ImportOptions::default(),
+            &AtomicBool::new(false),
+            |_| (),
+        )
+    } else {
+        let scale = match ty {
+            SpriteType::Sd => 1,
+            SpriteType::Hd2 => 2,
+            SpriteType::Hd => 4,
+        };
+        import_frames_grp(
+            files,
+            &frame_info,
+            dir,
+            1.0,
+            formats.first().copied(),
+            entry.sprite,
+            scale,
+            None,
+            ImportOptions::default(),
+            &AtomicBool::new(false),
+            |_| (),
+        )
+    }
+}
+
+/// Replaces textures of layers in `options.skip_layers` with the current encoded textures.
+fn keep_skipped_textures(
+    files: &mut files::Files,
+    sprite: usize,
+    ty: SpriteType,
+    changes: &mut anim::TexChanges,
+    options: ImportOptions,
+) -> Result<(), Error> {
+    if options.skip_layers == 0 {
+        return Ok(());
+    }
+    let file = files.file(sprite, ty)?
+        .ok_or_else(|| anyhow!("No existing {:?} sprite to keep textures of", ty))?;
+    for (layer, texture) in changes.textures.iter_mut().enumerate() {
+        if options.skip_layer(layer) && file.texture_size(layer).is_some() {
+            let existing = file.raw_texture(layer)
+                .with_context(|| format!("Unable to read existing texture of layer {}", layer))?;
+            *texture = Some(existing);
+        }
+    }
+    Ok(())
+}
+
+/// Returns current frames and texture dimensions of an anim sprite.
+fn existing_layout(
+    files: &mut files::Files,
+    sprite: usize,
+    ty: SpriteType,
+) -> Result<(Vec<anim::Frame>, (u32, u32)), Error> {
+    let file = files.file(sprite, ty)?
+        .filter(|x| x.is_anim())
+        .ok_or_else(|| anyhow!("No existing {:?} anim sprite to keep layout of", ty))?;
+    let frames = file.frames()
+        .ok_or_else(|| anyhow!("Existing {:?} sprite has no frames", ty))?
+        .to_vec();
+    let texture = (0..file.layer_count())
+        .find_map(|i| file.texture_size(i))
+        .ok_or_else(|| anyhow!("Existing {:?} sprite has no textures", ty))?;
+    Ok((frames, (u32::from(texture.width), u32::from(texture.height))))
+}
+
+/// Replaces texture of `layer` with an edited version of the entire atlas.
+///
+/// The image must have same dimensions as the current texture, as the frame
+/// rectangles are kept as they are. The layer is re-encoded in its current format,
+/// other layers are kept unchanged.
+pub fn import_atlas(
+    files: &mut files::Files,
+    sprite: usize,
+    ty: SpriteType,
+    layer: usize,
+    path: &Path,
+) -> Result<(), Error> {
+    let file = files.file(sprite, ty)?
+        .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+    if !file.is_anim() {
+        return Err(anyhow!("Atlas import is only supported for anim sprites"));
+    }
+    if file.image_ref().is_some() {
+        return Err(anyhow!("Sprite is a reference to another image"));
+    }
+    let format = match file.texture_formats().get(layer) {
+        Some(Ok(Some(format))) => *format,
+        Some(Err(e)) => return Err(anyhow!("Layer {} texture format: {:?}", layer, e)),
+        _ => return Err(anyhow!("Layer {} has no texture", layer)),
+    };
+    let size = file.texture_size(layer)
+        .ok_or_else(|| anyhow!("Layer {} has no texture", layer))?;
+    let input = File::open(path)
+        .with_context(|| format!("Unable to open {}", path.display()))?;
+    let image = match load_png(BufReader::new(input), false, ImportOptions::default())? {
+        ImageData::Image(image) => image,
+        ImageData::Paletted(..) => return Err(anyhow!("Expected an RGBA image")),
+    };
+    let (width, height) = (u32::from(size.width), u32::from(size.height));
+    if image.width() != width || image.height() != height {
+        return Err(anyhow!(
+            "{} is {}x{}, but the texture is {}x{}",
+            path.display(), image.width(), image.height(), width, height,
+        ));
+    }
+    let bytes = anim_encoder::encode(&image.into_raw(), width, height, format);
+    let textures = (0..file.layer_count()).map(|i| {
+        if i == layer {
+            Ok(Some((anim::Texture {
+                offset: !0,
+                size: bytes.len() as u32,
+                width: size.width,
+                height: size.height,
+            }, bytes.clone())))
+        } else if file.texture_size(i).is_some() {
+            file.raw_texture(i)
+                .with_context(|| format!("Couldn't read texture for layer {}", i))
+                .map(Some)
+        } else {
+            Ok(None)
+        }
+    }).collect::<Result<Vec<_>, Error>>()?;
+    let frames = file.frames()
+        .ok_or_else(|| anyhow!("Sprite has no frames"))?
+        .to_vec();
+    let dimensions = file.sprite_values()
+        .map(|x| (x.width, x.height))
+        .unwrap_or((0, 0));
+    files.set_tex_changes(sprite, ty, anim::TexChanges {
+        frames,
+        textures,
+    }, dimensions);
+    Ok(())
+}
+
+/// Replaces a single frame of `layer` with `path`, keeping every other frame as is.
+///
+/// `path` is expected to be laid out like an exported frame image, so the offsets
+/// are relative to the leftmost / topmost frame of the sprite.
+/// The whole atlas is re-encoded, with other frames taken from the existing textures.
+pub fn replace_frame(
+    files: &mut files::Files,
+    sprite: usize,
+    ty: SpriteType,
+    layer: usize,
+    frame_index: usize,
+    path: &Path,
+) -> Result<(), Error> {
+    let file = files.file(sprite, ty)?
+        .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+    if file.image_ref().is_some() {
+        return Err(anyhow!("Sprite is a reference to another image"));
+    }
+    let mut decoded = texture_repack::decode_frames(&file)?;
+    let frame_count = decoded.frames.len();
+    if decoded.formats.get(layer).copied().flatten().is_none() {
+        return Err(anyhow!("Layer {} has no texture", layer));
+    }
+    let scale = decoded.divisor as i32;
+    // Same base as what frame export uses for the image offsets, in texels.
+    let x_base = decoded.frames.iter().map(|x| i32::from(x.frame.x_off)).min().unwrap_or(0);
+    let y_base = decoded.frames.iter().map(|x| i32::from(x.frame.y_off)).min().unwrap_or(0);
+    let x_base = x_base.min(0).div_euclid(scale);
+    let y_base = y_base.min(0).div_euclid(scale);
+    let decoded_frame = decoded.frames.get_mut(frame_index)
+        .ok_or_else(|| anyhow!("Sprite has only {} frames", frame_count))?;
+
+    let input = File::open(path)
+        .with_context(|| format!("Unable to open {}", path.display()))?;
+    let image = match load_png(BufReader::new(input), false, ImportOptions::default())? {
+        ImageData::Image(image) => image,
+        ImageData::Paletted(..) => return Err(anyhow!("Expected an RGBA image")),
+    };
+    let (width, height) = image.dimensions();
+    let data = image.into_raw();
+    let bounds = rgba_bounds(&data, width, height, 0);
+    let replacement = bound_data(&data, width, height, &bounds);
+    let new_rect = TexelRect {
+        x: replacement.coords.x_offset + x_base,
+        y: replacement.coords.y_offset + y_base,
+        width: replacement.coords.width,
+        height: replacement.coords.height,
+    };
+    // The frame has to cover both the new graphics and the other layers of the frame.
+    let frame = &decoded_frame.frame;
+    let old_rect = TexelRect {
+        x: i32::from(frame.x_off).div_euclid(scale),
+        y: i32::from(frame.y_off).div_euclid(scale),
+        width: u32::from(frame.width) / scale as u32,
+        height: u32::from(frame.height) / scale as u32,
+    };
+    let others_empty = decoded_frame.layers.iter().enumerate()
+        .all(|(i, x)| i == layer || x.is_none());
+    let rect = match (others_empty || old_rect.is_empty(), new_rect.is_empty()) {
+        (true, _) => new_rect,
+        (false, true) => old_rect,
+        (false, false) => old_rect.union(&new_rect),
+    };
+    for (i, data) in decoded_frame.layers.iter_mut().enumerate() {
+        if i == layer {
+            *data = Some(place_rgba(&replacement.data, &new_rect, &rect));
+        } else if let Some(data) = data {
+            *data = place_rgba(data, &old_rect, &rect);
+        }
+    }
+    let frame = &mut decoded_frame.frame;
+    frame.x_off = i16::try_from(rect.x * scale).context("Frame offset too large")?;
+    frame.y_off = i16::try_from(rect.y * scale).context("Frame offset too large")?;
+    frame.width = u16::try_from(rect.width * scale as u32).context("Frame too large")?;
+    frame.height = u16::try_from(rect.height * scale as u32).context("Frame too large")?;
+
+    let changes = texture_repack::encode_frames(&decoded)?;
+    let dimensions = file.sprite_values()
+        .map(|x| (x.width, x.height))
+        .unwrap_or((0, 0));
+    files.set_tex_changes(sprite, ty, changes, dimensions);
+    Ok(())
+}
+
+/// Area of a frame in texture pixels.
+#[derive(Copy, Clone)]
+struct TexelRect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl TexelRect {
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    fn union(&self, other: &TexelRect) -> TexelRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width as i32).max(other.x + other.width as i32);
+        let bottom = (self.y + self.height as i32).max(other.y + other.height as i32);
+        TexelRect {
+            x,
+            y,
+            width: (right - x) as u32,
+            height: (bottom - y) as u32,
+        }
+    }
+}
+
+/// Copies RGBA `data` covering `from` to a transparent image covering `to`,
+/// which must contain `from` unless `from` is empty.
+fn place_rgba(data: &[u8], from: &TexelRect, to: &TexelRect) -> Vec<u8> {
+    let mut out = vec![0; (to.width * to.height) as usize * 4];
+    if from.is_empty() {
+        return out;
+    }
+    let x = (from.x - to.x) as usize;
+    let row_len = from.width as usize * 4;
+    for (row, input) in data.chunks_exact(row_len).enumerate() {
+        let start = ((from.y - to.y) as usize + row) * to.width as usize * 4 + x * 4;
+        out[start..start + row_len].copy_from_slice(input);
+    }
+    out
+}
+
 pub fn import_grp_to_anim<F: Fn(f32) + Sync>(
     files: &mut files::Files,
     sprite: usize,
@@ -779,14 +1508,73 @@ pub fn import_grp_to_anim<F: Fn(f32) + Sync>(
     use_teamcolor: bool,
     report_progress: F,
 ) -> Result<(), Error> {
+    let frame_count = grp_decode::frame_count(grp)?;
+    let (width, height) = grp_decode::width_height(grp)?;
+    import_rgba_frames_to_anim(
+        files,
+        sprite,
+        frame_count,
+        (width, height),
+        |frame| {
+            if use_teamcolor {
+                let (frame_data, teamcolor) =
+                    grp_decode::decode_grp_to_rgba_and_teamcolor(grp, frame, palette)
+                        .with_context(|| format!("Invalid GRP, cannot decode frame {}", frame))?;
+                Ok((frame_data, Some(teamcolor)))
+            } else {
+                let frame_data = grp_decode::decode_grp_to_rgba(grp, frame, palette)
+                    .with_context(|| format!("Invalid GRP, cannot decode frame {}", frame))?;
+                Ok((frame_data, None))
+            }
+        },
+        format,
+        use_teamcolor,
+        report_progress,
+    )
+}
+
+/// Imports frames of a GIF decoded with `decode_gif` as a GRP would be imported.
+pub fn import_gif_to_anim<F: Fn(f32) + Sync>(
+    files: &mut files::Files,
+    sprite: usize,
+    frames: &[RgbaImage],
+    format: anim::TextureFormat,
+    report_progress: F,
+) -> Result<(), Error> {
+    let (frame_count, width, height) = gif_dimensions(frames)?;
+    import_rgba_frames_to_anim(
+        files,
+        sprite,
+        frame_count,
+        (width, height),
+        |frame| Ok((gif_grp_frame(&frames[frame as usize]), None)),
+        format,
+        false,
+        report_progress,
+    )
+}
+
+/// `decode_frame` returns RGBA data of the entire frame, and
+/// teamcolor mask if `use_teamcolor` is set.
+fn import_rgba_frames_to_anim<D, F>(
+    files: &mut files::Files,
+    sprite: usize,
+    frame_count: u16,
+    (width, height): (u16, u16),
+    decode_frame: D,
+    format: anim::TextureFormat,
+    use_teamcolor: bool,
+    report_progress: F,
+) -> Result<(), Error>
+where D: Fn(u16) -> Result<(grp_decode::GrpFrame, Option<Vec<u8>>), Error> + Sync,
+      F: Fn(f32) + Sync,
+{
     let variants = [
         (SpriteType::Sd, 1),
         (SpriteType::Hd2, 2),
         (SpriteType::Hd, 4),
     ];
 
-    let frame_count = grp_decode::frame_count(grp)?;
-    let (width, height) = grp_decode::width_height(grp)?;
     let mut layout = anim_encoder::Layout::new();
     let formats = [
         Some(format),
@@ -795,16 +1583,7 @@ pub fn import_grp_to_anim<F: Fn(f32) + Sync>(
     let step = AtomicUsize::new(1);
     let frames = (0..frame_count).into_par_iter()
         .map(|frame| {
-            let (frame_data, teamcolor) = if use_teamcolor {
-                let (frame_data, teamcolor) =
-                    grp_decode::decode_grp_to_rgba_and_teamcolor(grp, frame, palette)
-                        .with_context(|| format!("Invalid GRP, cannot decode frame {}", frame))?;
-                (frame_data, Some(teamcolor))
-            } else {
-                let frame_data = grp_decode::decode_grp_to_rgba(grp, frame, palette)
-                    .with_context(|| format!("Invalid GRP, cannot decode frame {}", frame))?;
-                (frame_data, None)
-            };
+            let (frame_data, teamcolor) = decode_frame(frame)?;
 
             let mut entries = Vec::new();
             for (i, &(_sprite_type, scale)) in variants.iter().enumerate() {
@@ -813,7 +1592,7 @@ pub fn import_grp_to_anim<F: Fn(f32) + Sync>(
                     scale_rgba(&frame_data.data, frame_data.width, frame_data.height, scale);
                 let width = frame_data.width * scale;
                 let height = frame_data.height * scale;
-                let bounds = rgba_bounds(&data, width, height);
+                let bounds = rgba_bounds(&data, width, height, 0);
                 let mut bounded = bound_data(&data, width, height, &bounds);
                 bounded.coords.x_offset *= reverse_scale as i32;
                 bounded.coords.y_offset *= reverse_scale as i32;
@@ -938,16 +1717,7 @@ pub fn import_grp_to_ddsgrp<F: Fn(f32) + Sync>(
             (result.data, result.width, result.height)
         };
         report_progress(frame as f32 / frame_count as f32);
-        let frame = ddsgrp::Frame {
-            unknown: 0,
-            width: u16::try_from(width)
-                .map_err(|_| anyhow!("Frame {} width too large", frame))?,
-            height: u16::try_from(height)
-                .map_err(|_| anyhow!("Frame {} width too large", frame))?,
-            size: data.len() as u32,
-            offset: !0,
-        };
-        Ok((frame, data))
+        ddsgrp_frame(frame, data, width, height)
     }).collect::<Result<Vec<_>, Error>>()?;
 
     let palette = if format.is_none() {
@@ -960,6 +1730,138 @@ pub fn import_grp_to_ddsgrp<F: Fn(f32) + Sync>(
     Ok(())
 }
 
+pub fn import_gif_to_ddsgrp<F: Fn(f32) + Sync>(
+    files: &mut files::Files,
+    sprite: usize,
+    frames: &[RgbaImage],
+    format: anim::TextureFormat,
+    scale: u8,
+    report_progress: F,
+) -> Result<(), Error> {
+    let (frame_count, _, _) = gif_dimensions(frames)?;
+    let frames = frames.iter().enumerate().map(|(frame, image)| {
+        let width = image.width();
+        let height = image.height();
+        let data = anim_encoder::encode(image.as_raw(), width, height, format);
+        report_progress(frame as f32 / frame_count as f32);
+        ddsgrp_frame(frame as u16, data, width, height)
+    }).collect::<Result<Vec<_>, Error>>()?;
+    files.set_grp_changes(sprite, frames, scale, None);
+    Ok(())
+}
+
+fn ddsgrp_frame(
+    frame: u16,
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+) -> Result<(ddsgrp::Frame, Vec<u8>), Error> {
+    let header = ddsgrp::Frame {
+        unknown: 0,
+        width: u16::try_from(width)
+            .map_err(|_| anyhow!("Frame {} width too large", frame))?,
+        height: u16::try_from(height)
+            .map_err(|_| anyhow!("Frame {} width too large", frame))?,
+        size: data.len() as u32,
+        offset: !0,
+    };
+    Ok((header, data))
+}
+
+/// Decodes every frame of an animated GIF to a RGBA image of the entire GIF,
+/// with the earlier frames composited below as the frame disposal methods specify.
+pub fn decode_gif(data: &[u8]) -> Result<Vec<RgbaImage>, Error> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(data).context("Invalid GIF")?;
+    let width = u32::from(decoder.width());
+    let height = u32::from(decoder.height());
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().context("Invalid GIF frame")? {
+        frames.push(GifFrame {
+            left: frame.left.into(),
+            top: frame.top.into(),
+            width: frame.width.into(),
+            height: frame.height.into(),
+            data: frame.buffer.to_vec(),
+            dispose: frame.dispose,
+        });
+    }
+    if frames.is_empty() {
+        return Err(anyhow!("GIF has no frames"));
+    }
+    Ok(composite_gif_frames(width, height, &frames))
+}
+
+struct GifFrame {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+    /// RGBA, fully transparent pixels show the previous canvas.
+    data: Vec<u8>,
+    dispose: gif::DisposalMethod,
+}
+
+fn composite_gif_frames(width: u32, height: u32, frames: &[GifFrame]) -> Vec<RgbaImage> {
+    let mut canvas = RgbaImage::new(width, height);
+    let mut result = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let previous = match frame.dispose {
+            gif::DisposalMethod::Previous => Some(canvas.clone()),
+            _ => None,
+        };
+        let rows = frame.data.chunks_exact(frame.width.max(1) as usize * 4);
+        for (y, row) in (frame.top..).zip(rows).filter(|x| x.0 < height) {
+            let pixels = row.chunks_exact(4);
+            for (x, pixel) in (frame.left..).zip(pixels).filter(|x| x.0 < width) {
+                if pixel[3] != 0 {
+                    canvas.get_pixel_mut(x, y).0.copy_from_slice(pixel);
+                }
+            }
+        }
+        result.push(canvas.clone());
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                let right = (frame.left + frame.width).min(width);
+                let bottom = (frame.top + frame.height).min(height);
+                for y in frame.top..bottom {
+                    for x in frame.left..right {
+                        *canvas.get_pixel_mut(x, y) = image::Rgba([0, 0, 0, 0]);
+                    }
+                }
+            }
+            gif::DisposalMethod::Previous => {
+                if let Some(previous) = previous {
+                    canvas = previous;
+                }
+            }
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => (),
+        }
+    }
+    result
+}
+
+fn gif_dimensions(frames: &[RgbaImage]) -> Result<(u16, u16, u16), Error> {
+    let first = frames.first().ok_or_else(|| anyhow!("GIF has no frames"))?;
+    let frame_count = u16::try_from(frames.len()).context("Too many frames")?;
+    let width = u16::try_from(first.width()).context("GIF is too wide")?;
+    let height = u16::try_from(first.height()).context("GIF is too tall")?;
+    Ok((frame_count, width, height))
+}
+
+fn gif_grp_frame(image: &RgbaImage) -> grp_decode::GrpFrame {
+    grp_decode::GrpFrame {
+        data: image.as_raw().clone(),
+        left: 0,
+        top: 0,
+        right: image.width(),
+        bottom: image.height(),
+        width: image.width(),
+        height: image.height(),
+    }
+}
+
 fn scale_rgba(input: &[u8], width: u32, height: u32, scale: u32) -> Vec<u8> {
     let vec = input.into();
     if scale == 1 {
@@ -978,10 +1880,60 @@ fn scale_rgba(input: &[u8], width: u32, height: u32, scale: u32) -> Vec<u8> {
     result.into_raw()
 }
 
-fn rgba_bounds(data: &[u8], width: u32, height: u32) -> Bounds {
+/// Scales `rect` from the exported image pixels to pixels of an image resized by
+/// `frame_scale`, rounding outwards so that no content gets cut.
+fn scale_content_rect(
+    rect: frame_info::ContentRect,
+    frame_scale: f32,
+) -> frame_info::ContentRect {
+    if frame_scale == 1.0 {
+        return rect;
+    }
+    let floor = |x: u32| (x as f32 * frame_scale).floor() as u32;
+    let ceil = |x: u32| (x as f32 * frame_scale).ceil() as u32;
+    let x = floor(rect.x);
+    let y = floor(rect.y);
+    frame_info::ContentRect {
+        x,
+        y,
+        width: ceil(rect.x.saturating_add(rect.width)) - x,
+        height: ceil(rect.y.saturating_add(rect.height)) - y,
+    }
+}
+
+/// Limits `bounds` to be inside `rect`, keeping empty bounds empty.
+fn clip_bounds(bounds: Bounds, rect: frame_info::ContentRect) -> Bounds {
+    let left = bounds.left.max(rect.x);
+    let top = bounds.top.max(rect.y);
+    let right = bounds.right.min(rect.x.saturating_add(rect.width));
+    let bottom = bounds.bottom.min(rect.y.saturating_add(rect.height));
+    if right <= left || bottom <= top {
+        return Bounds {
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+        };
+    }
+    Bounds {
+        left,
+        top,
+        right,
+        bottom,
+    }
+}
+
+/// Bounding box of pixels that have alpha greater than `alpha_threshold`.
+pub(crate) fn rgba_bounds(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    alpha_threshold: u8,
+) -> Bounds {
     assert_eq!(data.len(), 4 * (width * height) as usize);
+    let empty = |alpha: u8| alpha <= alpha_threshold;
     let top = match data.chunks(width as usize * 4)
-        .position(|x| !x.chunks(4).all(|x| x[3] == 0))
+        .position(|x| !x.chunks(4).all(|x| empty(x[3])))
     {
         Some(s) => s as u32,
         None => return Bounds {
@@ -992,12 +1944,12 @@ fn rgba_bounds(data: &[u8], width: u32, height: u32) -> Bounds {
         },
     };
     let bottom = height - data.chunks(width as usize * 4).rev()
-        .position(|x| !x.chunks(4).all(|x| x[3] == 0)).unwrap() as u32;
+        .position(|x| !x.chunks(4).all(|x| empty(x[3]))).unwrap() as u32;
     let left = (0..width)
-        .find(|x| !(top..bottom).all(|y| data[(y * width + x) as usize * 4 + 3] == 0))
+        .find(|x| !(top..bottom).all(|y| empty(data[(y * width + x) as usize * 4 + 3])))
         .unwrap();
     let right = 1 + (0..width).rev()
-        .find(|x| !(top..bottom).all(|y| data[(y * width + x) as usize * 4 + 3] == 0))
+        .find(|x| !(top..bottom).all(|y| empty(data[(y * width + x) as usize * 4 + 3])))
         .unwrap();
     Bounds {
         top,
@@ -1007,7 +1959,13 @@ fn rgba_bounds(data: &[u8], width: u32, height: u32) -> Bounds {
     }
 }
 
-fn bound_data(data: &[u8], width: u32, _height: u32, bounds: &Bounds) -> Bounded {
+fn set_opaque(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        pixel[3] = 0xff;
+    }
+}
+
+pub(crate) fn bound_data(data: &[u8], width: u32, _height: u32, bounds: &Bounds) -> Bounded {
     let Bounds {
         left,
         right,
@@ -1037,15 +1995,211 @@ fn bound_data(data: &[u8], width: u32, _height: u32, bounds: &Bounds) -> Bounded
 }
 
 #[cfg(test)]
-fn rgba_bounding_box(data: &[u8], width: u32, height: u32) -> Bounded {
-    let bounds = rgba_bounds(data, width, height);
+fn rgba_bounding_box(data: &[u8], width: u32, height: u32, alpha_threshold: u8) -> Bounded {
+    let bounds = rgba_bounds(data, width, height, alpha_threshold);
     bound_data(data, width, height, &bounds)
 }
 
+/// Maps layer names of a frame info referring to layers by name to
+/// indices of `layer_names`.
+fn resolve_layer_names<'a>(
+    frame_info: &'a FrameInfo,
+    layer_names: &[String],
+) -> Result<Cow<'a, FrameInfo>, Error> {
+    if !frame_info.layers_by_name {
+        return Ok(Cow::Borrowed(frame_info));
+    }
+    let mut frame_info = frame_info.clone();
+    frame_info.resolve_layer_names(layer_names)?;
+    Ok(Cow::Owned(frame_info))
+}
+
+/// Errors if `frame_info` refers to layers past what the target sprite can hold.
+fn check_layer_count(frame_info: &FrameInfo, layer_count: usize) -> Result<(), Error> {
+    let max_layer = frame_info.layers.iter().map(|x| x.id).max();
+    match max_layer {
+        Some(max) if max as usize >= layer_count => Err(anyhow!(
+            "Frame info references layer {}, but the sprite only has {} layers",
+            max, layer_count,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that HD and HD2 frame infos have the same frame count and layers.
+fn check_hd2_frame_info(hd: &FrameInfo, hd2: &FrameInfo) -> Result<(), Error> {
+    // Placeholder ids of layers referred by name can't be compared.
+    let by_name = hd.layers_by_name || hd2.layers_by_name;
+    let layer_keys = |frame_info: &FrameInfo| {
+        let mut keys = frame_info.layers.iter().map(|layer| {
+            let key = match by_name {
+                true => layer.name.clone(),
+                false => layer.id.to_string(),
+            };
+            match layer.sub_id {
+                0 => key,
+                sub_id => format!("{} (sublayer {})", key, sub_id),
+            }
+        }).collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys
+    };
+    let mut problems = Vec::new();
+    if hd.frame_count != hd2.frame_count {
+        problems.push(format!(
+            "HD has {} frames, but HD2 has {} frames", hd.frame_count, hd2.frame_count,
+        ));
+    }
+    let hd_layers = layer_keys(hd);
+    let hd2_layers = layer_keys(hd2);
+    let missing_from = |layers: &[String], other: &[String]| {
+        layers.iter().filter(|x| !other.contains(x)).cloned().collect::<Vec<_>>()
+    };
+    let only_hd = missing_from(&hd_layers, &hd2_layers);
+    if !only_hd.is_empty() {
+        problems.push(format!("Layers missing from HD2: {}", only_hd.join(", ")));
+    }
+    let only_hd2 = missing_from(&hd2_layers, &hd_layers);
+    if !only_hd2.is_empty() {
+        problems.push(format!("Layers missing from HD: {}", only_hd2.join(", ")));
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!("HD and HD2 frame infos don't match:\n{}", problems.join("\n")));
+    }
+    Ok(())
+}
+
+/// Frame info with raw layers of `layer_ids`, for tests.
+#[cfg(test)]
+fn test_frame_info(frame_count: u32, layer_ids: &[u32]) -> FrameInfo {
+    FrameInfo {
+        frame_count,
+        layers: layer_ids.iter().map(|&id| test_layer(id)).collect(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+fn test_layer(id: u32) -> frame_info::Layer {
+    frame_info::Layer {
+        id,
+        sub_id: 0,
+        filename_prefix: format!("layer{}", id),
+        encoding: frame_info::LayerEncoding::Raw,
+        name: format!("name{}", id),
+    }
+}
+
+#[test]
+fn test_check_hd2_frame_info() {
+    let hd = test_frame_info(3, &[0, 2]);
+    let mut hd2 = hd.clone();
+    hd2.layers.reverse();
+    assert!(check_hd2_frame_info(&hd, &hd2).is_ok());
+    hd2.frame_count = 2;
+    hd2.layers[0] = test_layer(1);
+    let err = format!("{}", check_hd2_frame_info(&hd, &hd2).unwrap_err());
+    assert!(err.contains("HD has 3 frames, but HD2 has 2 frames"));
+    assert!(err.contains("Layers missing from HD2: 2"));
+    assert!(err.contains("Layers missing from HD: 1"));
+}
+
+#[test]
+fn test_check_layer_count() {
+    let mut frame_info = test_frame_info(1, &[0, 1]);
+    assert!(check_layer_count(&frame_info, 2).is_ok());
+    frame_info.layers.push(test_layer(3));
+    assert!(check_layer_count(&frame_info, 2).is_err());
+    assert!(check_layer_count(&frame_info, 4).is_ok());
+}
+
+#[test]
+fn test_swap_bgra() {
+    let pixels = [10u8, 20, 30, 40, 50, 60, 70, 80];
+    let mut png_data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png_data, 2, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&pixels).unwrap();
+    }
+    let load = |swap_bgra| {
+        let options = ImportOptions {
+            swap_bgra,
+            ..Default::default()
+        };
+        match load_png(BufReader::new(&png_data[..]), false, options).unwrap() {
+            ImageData::Image(image) => image.into_raw(),
+            ImageData::Paletted(..) => panic!("Expected RGBA image"),
+        }
+    };
+    assert_eq!(load(false), pixels);
+    assert_eq!(load(true), [30, 20, 10, 40, 70, 60, 50, 80]);
+}
+
+#[test]
+fn test_16bit_png() {
+    let encode = |color, pixels: &[u16]| {
+        let mut png_data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png_data, 2, 1);
+            encoder.set_color(color);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().unwrap();
+            let bytes = pixels.iter().flat_map(|x| x.to_be_bytes()).collect::<Vec<u8>>();
+            writer.write_image_data(&bytes).unwrap();
+        }
+        match load_png(BufReader::new(&png_data[..]), false, ImportOptions::default()).unwrap() {
+            ImageData::Image(image) => image.into_raw(),
+            ImageData::Paletted(..) => panic!("Expected RGBA image"),
+        }
+    };
+    let rgba = [0x1234, 0x56ff, 0xff00, 0x00ff, 0x8000, 0x7fff, 0x0100, 0xffff];
+    assert_eq!(
+        encode(png::ColorType::Rgba, &rgba),
+        [0x12, 0x56, 0xff, 0x00, 0x80, 0x7f, 0x01, 0xff],
+    );
+    assert_eq!(
+        encode(png::ColorType::Rgb, &rgba[..6]),
+        [0x12, 0x56, 0xff, 0xff, 0x00, 0x80, 0x7f, 0xff],
+    );
+    assert_eq!(
+        encode(png::ColorType::GrayscaleAlpha, &rgba[..4]),
+        [0x12, 0x12, 0x12, 0x56, 0xff, 0xff, 0xff, 0x00],
+    );
+}
+
+#[test]
+fn test_clip_bounds() {
+    let rect = frame_info::ContentRect { x: 4, y: 4, width: 8, height: 8 };
+    let bounds = |left, top, right, bottom| Bounds { left, top, right, bottom };
+    let clipped = clip_bounds(bounds(0, 6, 10, 20), rect);
+    assert_eq!((clipped.left, clipped.top, clipped.right, clipped.bottom), (4, 6, 10, 12));
+    let clipped = clip_bounds(bounds(0, 0, 3, 3), rect);
+    assert_eq!((clipped.left, clipped.top, clipped.right, clipped.bottom), (0, 0, 0, 0));
+}
+
+#[test]
+fn test_scale_content_rect() {
+    let rect = frame_info::ContentRect { x: 5, y: 4, width: 10, height: 9 };
+    assert_eq!(scale_content_rect(rect, 1.0), rect);
+    // HD2 import of a HD export, content still starts from the pixel that
+    // contains exported (5, 4)
+    let scaled = scale_content_rect(rect, 0.5);
+    assert_eq!(scaled, frame_info::ContentRect { x: 2, y: 2, width: 6, height: 5 });
+    let scaled = scale_content_rect(rect, 0.25);
+    assert_eq!(scaled, frame_info::ContentRect { x: 1, y: 1, width: 3, height: 3 });
+    let bounds = Bounds { left: 2, top: 2, right: 8, bottom: 7 };
+    let clipped = clip_bounds(bounds, scale_content_rect(rect, 0.5));
+    assert_eq!((clipped.left, clipped.top, clipped.right, clipped.bottom), (2, 2, 8, 7));
+}
+
 #[test]
 fn test_rgba_bounding_box() {
     let data = vec![1; 40 * 70 * 4];
-    let result = rgba_bounding_box(&data, 40, 70);
+    let result = rgba_bounding_box(&data, 40, 70, 0);
     assert_eq!(result.coords.x_offset, 0);
     assert_eq!(result.coords.y_offset, 0);
     assert_eq!(result.coords.width, 40);
@@ -1056,7 +2210,7 @@ fn test_rgba_bounding_box() {
     data[4 * (32 * 40 + 35) + 1] = 6;
     data[4 * (32 * 40 + 35) + 2] = 7;
     data[4 * (32 * 40 + 35) + 3] = 8;
-    let result = rgba_bounding_box(&data, 40, 70);
+    let result = rgba_bounding_box(&data, 40, 70, 0);
     assert_eq!(result.coords.x_offset, 35);
     assert_eq!(result.coords.y_offset, 32);
     assert_eq!(result.coords.width, 1);
@@ -1067,7 +2221,7 @@ fn test_rgba_bounding_box() {
     data[4 * (2 * 40 + 5) + 1] = 60;
     data[4 * (2 * 40 + 5) + 2] = 70;
     data[4 * (2 * 40 + 5) + 3] = 80;
-    let result = rgba_bounding_box(&data, 40, 70);
+    let result = rgba_bounding_box(&data, 40, 70, 0);
     assert_eq!(result.coords.x_offset, 5);
     assert_eq!(result.coords.y_offset, 2);
     assert_eq!(result.coords.width, 31);
@@ -1078,12 +2232,32 @@ fn test_rgba_bounding_box() {
     for &x in &result.data[4..out_len - 4] {
         assert_eq!(x, 0);
     }
+
+    // Faint pixels around the 80 alpha pixel are ignored with a threshold
+    data[4 * (40 + 3) + 3] = 2;
+    data[4 * (60 * 40 + 39) + 3] = 16;
+    let result = rgba_bounding_box(&data, 40, 70, 0);
+    assert_eq!(result.coords.x_offset, 3);
+    assert_eq!(result.coords.y_offset, 1);
+    assert_eq!(result.coords.width, 37);
+    assert_eq!(result.coords.height, 60);
+    let result = rgba_bounding_box(&data, 40, 70, 16);
+    assert_eq!(result.coords.x_offset, 5);
+    assert_eq!(result.coords.y_offset, 2);
+    assert_eq!(result.coords.width, 1);
+    assert_eq!(result.coords.height, 1);
+    assert_eq!(result.data, &[50, 60, 70, 80]);
+    let result = rgba_bounding_box(&data, 40, 70, 8);
+    assert_eq!(result.coords.x_offset, 5);
+    assert_eq!(result.coords.y_offset, 2);
+    assert_eq!(result.coords.width, 35);
+    assert_eq!(result.coords.height, 59);
 }
 
 #[test]
 fn test_empty_rgba_bounding_box() {
     let data = vec![0; 40 * 70 * 4];
-    let result = rgba_bounding_box(&data, 40, 70);
+    let result = rgba_bounding_box(&data, 40, 70, 0);
     assert_eq!(result.coords.x_offset, 0);
     assert_eq!(result.coords.y_offset, 0);
     assert_eq!(result.coords.width, 0);
@@ -1091,15 +2265,215 @@ fn test_empty_rgba_bounding_box() {
     assert_eq!(result.data.len(), 0);
 }
 
-struct Bounded {
-    data: Vec<u8>,
-    coords: anim_encoder::FrameCoords,
+pub(crate) struct Bounded {
+    pub data: Vec<u8>,
+    pub coords: anim_encoder::FrameCoords,
 }
 
 #[derive(Copy, Clone, Debug)]
-struct Bounds {
-    left: u32,
-    top: u32,
-    right: u32,
-    bottom: u32,
+pub(crate) struct Bounds {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+#[test]
+fn test_check_frame_files_lists_missing() {
+    let layer = |id: u32, prefix: &str| frame_info::Layer {
+        id,
+        sub_id: 0,
+        filename_prefix: prefix.into(),
+        encoding: frame_info::LayerEncoding::Raw,
+        name: String::new(),
+    };
+    let frame_info = FrameInfo {
+        frame_count: 2,
+        layers: vec![layer(0, "a_diffuse"), layer(1, "a_teamcolor")],
+        ..Default::default()
+    };
+    let dir = Path::new("nonexistent_animosity_test_dir");
+    let error = check_frame_files(&frame_info, dir, 1.0).unwrap_err().to_string();
+    assert!(error.starts_with("4 images are missing"));
+    assert!(error.contains("a_diffuse_000.png"));
+    assert!(error.contains("a_teamcolor_001.png"));
+}
+
+#[test]
+fn test_parse_manifest() {
+    let text = r#"[
+        {"sprite": 12, "type": "hd", "frame_info_path": "marine/frame_info.json",
+            "formats": ["dxt5", "dxt1"]},
+        {"sprite": 3, "type": "sd", "frame_info_path": "/abs/fi.json",
+            "image_dir": "images", "formats": []}
+    ]"#;
+    let entries = parse_manifest(text.as_bytes(), Path::new("build")).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[0].sprite, &*entries[0].ty), (12, "hd"));
+    assert_eq!(entries[0].frame_info_path, Path::new("build/marine/frame_info.json"));
+    assert!(entries[0].image_dir.is_none());
+    assert_eq!(entries[1].image_dir.as_deref(), Some(Path::new("build/images")));
+    assert!(parse_manifest(r#"[{"sprite": 1}]"#.as_bytes(), Path::new(".")).is_err());
+    assert!(parse_texture_format("DXT5").is_ok());
+    assert!(parse_texture_format("bc7").is_err());
+}
+
+#[test]
+fn test_force_opaque() {
+    let options = ImportOptions {
+        force_opaque_layers: 0b101,
+        ..Default::default()
+    };
+    assert!(options.force_opaque(0));
+    assert!(!options.force_opaque(1));
+    assert!(options.force_opaque(2));
+    assert!(!options.force_opaque(40));
+    let mut pixels = [1u8, 2, 3, 0, 4, 5, 6, 128];
+    set_opaque(&mut pixels);
+    assert_eq!(pixels, [1, 2, 3, 255, 4, 5, 6, 255]);
+}
+
+#[test]
+fn test_skip_layer() {
+    let options = ImportOptions {
+        skip_layers: 0b10,
+        ..Default::default()
+    };
+    assert!(!options.skip_layer(0));
+    assert!(options.skip_layer(1));
+    assert!(!options.skip_layer(2));
+    assert!(!options.skip_layer(40));
+}
+
+#[test]
+fn test_replace_frame() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_replace_frame_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mainsd = std::io::Cursor::new(files::test_anim(1, 1));
+    let mut files = files::Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    files.set_frame_unknown(0, SpriteType::Sd, 0, 2).unwrap();
+    // No other layer has a texture, so the frame shrinks to the 2x2 opaque pixels
+    let mut image = RgbaImage::new(6, 4);
+    for (x, y) in [(3, 1), (4, 1), (3, 2), (4, 2)] {
+        image.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
+    }
+    let path = dir.join("frame.png");
+    image.save(&path).unwrap();
+    replace_frame(&mut files, 0, SpriteType::Sd, 0, 0, &path).unwrap();
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    let frame = &file.frames().unwrap()[0];
+    assert_eq!((frame.x_off, frame.y_off, frame.width, frame.height), (3, 1, 2, 2));
+    assert_eq!(frame.unknown, 2);
+    assert!(file.texture_size(1).is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_composite_gif_frames() {
+    let frame = |left, top, width, height, color: [u8; 4], dispose| GifFrame {
+        left,
+        top,
+        width,
+        height,
+        data: color.iter().cloned().cycle().take((width * height * 4) as usize).collect(),
+        dispose,
+    };
+    let red = [255, 0, 0, 255];
+    let green = [0, 255, 0, 255];
+    let blue = [0, 0, 255, 255];
+    let clear = [0, 0, 0, 0];
+    let frames = [
+        frame(0, 0, 2, 2, red, gif::DisposalMethod::Keep),
+        frame(1, 1, 1, 1, green, gif::DisposalMethod::Previous),
+        frame(0, 0, 1, 1, blue, gif::DisposalMethod::Background),
+        // Transparent pixels leave the canvas as is
+        frame(0, 0, 2, 1, clear, gif::DisposalMethod::Keep),
+    ];
+    let result = composite_gif_frames(2, 2, &frames);
+    assert_eq!(result.len(), 4);
+    let pixel = |i: usize, x, y| result[i].get_pixel(x, y).0;
+    assert_eq!(pixel(0, 1, 1), red);
+    assert_eq!(pixel(1, 1, 1), green);
+    assert_eq!(pixel(1, 0, 0), red);
+    // Green was disposed back to red
+    assert_eq!(pixel(2, 1, 1), red);
+    assert_eq!(pixel(2, 0, 0), blue);
+    // Blue was disposed to background
+    assert_eq!(pixel(3, 0, 0), clear);
+    assert_eq!(pixel(3, 1, 0), red);
+}
+
+#[test]
+fn test_check_cancel() {
+    let cancel = AtomicBool::new(false);
+    assert!(check_cancel(&cancel).is_ok());
+    cancel.store(true, Ordering::Relaxed);
+    let err = check_cancel(&cancel).context("Import HD frames").unwrap_err();
+    assert!(err.downcast_ref::<Cancelled>().is_some());
+}
+
+#[test]
+fn test_tight_crop_round_trip() {
+    use crate::frame_export::{self, FrameCanvasMode, FrameOrder, FrameTypeExport, SheetMode};
+
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_tight_crop_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mainsd = std::io::Cursor::new(files::test_anim(1, 1));
+    let mut files = files::Files::from_mainsd("mainSD.anim".into(), mainsd).unwrap();
+    let copied = files.extract_frame(0, SpriteType::Sd, 0).unwrap();
+    files.insert_frame(0, SpriteType::Sd, 1, &copied).unwrap();
+    files.set_frame_offset(0, SpriteType::Sd, 0, 2, 1).unwrap();
+    files.set_frame_offset(0, SpriteType::Sd, 1, 0, 3).unwrap();
+    {
+        let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+        let layers = frame_export::default_export_layers(
+            &file.layer_names(),
+            |i| file.texture_size(i).is_some(),
+            "000_sd",
+        );
+        frame_export::export_frames(
+            &file,
+            8,
+            8,
+            &dir,
+            Path::new("frames.json"),
+            &layers,
+            SheetMode::OnePerFrame,
+            FrameOrder::Storage,
+            FrameTypeExport::Auto,
+            frame_export::ExportOptions {
+                canvas_mode: FrameCanvasMode::TightCrop,
+                ..Default::default()
+            },
+            None,
+            |_| (),
+        ).unwrap();
+    }
+    let frame_info = frame_info::parse_frame_info(&dir.join("frames.json")).unwrap();
+    assert_eq!(frame_info.frame_offset(0), (2, 1));
+    assert_eq!(frame_info.frame_offset(1), (0, 3));
+    let formats = [anim::TextureFormat::Dxt5, anim::TextureFormat::Dxt5];
+    import_frames(
+        &mut files,
+        &frame_info,
+        None,
+        &dir,
+        None,
+        1.0,
+        None,
+        &formats,
+        0,
+        SpriteType::Sd,
+        None,
+        ImportOptions::default(),
+        &AtomicBool::new(false),
+        |_| (),
+    ).unwrap();
+    let file = files.file(0, SpriteType::Sd).unwrap().unwrap();
+    let frames = file.frames().unwrap();
+    let placement = |f: &anim::Frame| (f.x_off, f.y_off, f.width, f.height);
+    assert_eq!(frames.iter().map(placement).collect::<Vec<_>>(), [(2, 1, 4, 4), (0, 3, 4, 4)]);
+    std::fs::remove_dir_all(&dir).unwrap();
 }
@@ -4,6 +4,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use anyhow::Context;
 use gio::prelude::*;
@@ -24,7 +26,7 @@ use crate::{
     label_section, lookup_action, error_msg_box, info_msg_box, SpriteInfo, SpriteType, Error,
     error_from_panic,
 };
-use crate::files::{DEFAULT_HD_LAYER_NAMES, DEFAULT_SD_LAYER_NAMES};
+use crate::files::{DEFAULT_HD2_DIVISOR, DEFAULT_HD_LAYER_NAMES, DEFAULT_SD_LAYER_NAMES};
 
 use crate::ui_helpers::*;
 use crate::util::{OptionExt, SliceExt};
@@ -32,6 +34,9 @@ use crate::util::{OptionExt, SliceExt};
 enum Progress {
     Done(Result<u32, Error>),
     Progress(f32),
+    /// Changes have been applied to the sprite, so the rest of the import can't be
+    /// cancelled without leaving it partially imported.
+    Committed,
 }
 
 pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::ApplicationWindow) {
@@ -46,8 +51,13 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
     let ddsgrp_linked_grp;
     let grp_scale;
     let had_palette;
+    let hd2_divisor;
     {
         if is_anim {
+            hd2_divisor = match files.file(tex_id.0, SpriteType::Hd2) {
+                Ok(Some(file)) => file.texture_divisor(),
+                _ => DEFAULT_HD2_DIVISOR,
+            };
             {
                 let file = match files.file(tex_id.0, SpriteType::Hd) {
                     Ok(Some(o)) => o,
@@ -68,6 +78,7 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                 _ => return,
             };
             tex_formats = file.texture_formats();
+            hd2_divisor = DEFAULT_HD2_DIVISOR;
             ddsgrp_path = Some(file.path().to_owned());
             had_palette = file.palette().is_some();
             ddsgrp_linked_grp = file.ddsgrp_linked_grp();
@@ -77,7 +88,7 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
 
     let window = gtk::Window::new(gtk::WindowType::Toplevel);
 
-    let inputs = FrameInputs::new(window.clone());
+    let inputs = FrameInputs::new(window.clone(), hd2_divisor);
 
     let checkboxes = OutLayerCheckboxes::new();
     let mut grp_format = None;
@@ -135,7 +146,7 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
         grp_scale_bx = None;
     } else {
         let entry = IntEntry::new(IntSize::Int8);
-        entry.set_value(grp_scale.unwrap_or(0).into());
+        entry.set_value(grp_scale.unwrap_or(1).into());
         let labeled = label_section("Ingame scale", &entry.frame);
         labeled.set_tooltip_text(Some("\
             Selects the scale value saved within file.\n\
@@ -172,14 +183,159 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
         None
     };
 
+    let texture_width = IntEntry::new(IntSize::Int16);
+    let texture_height = IntEntry::new(IntSize::Int16);
+    let size_entries = [
+        (&texture_width, "import_texture_width"),
+        (&texture_height, "import_texture_height"),
+    ];
+    for (entry, key) in size_entries {
+        let value = read_config_entry_int(key).and_then(|x| u32::try_from(x).ok());
+        entry.set_value(value.unwrap_or(1024));
+    }
+    let texture_size_section = match is_anim {
+        true => Some(label_section_with_enable_check(
+            "Force HD texture size",
+            &box_horizontal(&[
+                &gtk::Label::new(Some("Width")),
+                &box_expand(texture_width.widget()),
+                &gtk::Label::new(Some("Height")),
+                &box_expand(texture_height.widget()),
+            ]),
+            "import_force_texture_size",
+            false,
+        )),
+        false => None,
+    };
+    if let Some(ref section) = texture_size_section {
+        section.widget().set_tooltip_text(Some("\
+            Places the frames in a texture of exactly this size instead of the smallest \
+            one they fit in. HD2 texture will be half of this.\n\
+            Import fails if the frames don't fit."));
+    }
+
+    let preserve_layout_checkbox =
+        SavedCheckbox::new("import_preserve_layout", "Keep existing atlas layout");
+    preserve_layout_checkbox.widget().set_tooltip_text(Some("\
+        Writes the frames to the same texture positions as the current frames instead of \
+        packing a new atlas.\n\
+        The imported frames must have same count and sizes as the current ones."));
+
+    let swap_bgra_checkbox = SavedCheckbox::new("import_swap_bgra", "Input images are BGRA");
+    swap_bgra_checkbox.widget().set_tooltip_text(Some("\
+        Swaps red and blue channels of the input images.\n\
+        Only needed if the images were saved by a tool which wrote BGRA data as RGBA."));
+
+    let alpha_threshold = gtk::SpinButton::with_range(0.0, 254.0, 1.0);
+    alpha_threshold.set_value(read_config_entry_int("import_alpha_threshold").unwrap_or(0) as f64);
+    let alpha_threshold_bx = label_section("Alpha threshold", &alpha_threshold);
+    alpha_threshold_bx.set_tooltip_text(Some("\
+        Pixels with alpha at most this value are treated as empty when cropping \
+        frames to their bounding box.\n\
+        Increase to exclude faint anti-aliased edges or noise from the frame size.\n\
+        0 keeps every pixel that isn't fully transparent."));
+
+    // Smaller alignment would make HD2 frames share DXT blocks.
+    // Stepping down from the minimum goes to 0 (default) instead.
+    let min_alignment = 4 * hd2_divisor as i32;
+    let clamp_alignment = move |value: i32, stepped_down: bool| match value {
+        x if x > 0 && x < min_alignment && stepped_down => 0,
+        x if x > 0 && x < min_alignment => min_alignment,
+        x => x,
+    };
+    let alignment = gtk::SpinButton::with_range(0.0, 256.0, 1.0);
+    let saved_alignment = read_config_entry_int("import_alignment").unwrap_or(0) as i32;
+    alignment.set_value(clamp_alignment(saved_alignment, false) as f64);
+    alignment.connect_value_changed(move |s| {
+        let value = s.value_as_int();
+        let clamped = clamp_alignment(value, value == min_alignment - 1);
+        if clamped != value {
+            s.set_value(clamped as f64);
+        }
+    });
+    let alignment_bx = label_section("Texture alignment (0 = default)", &alignment);
+    alignment_bx.set_tooltip_text(Some(&format!("\
+        Frames in the packed texture are placed at, and padded to, multiples of this \
+        many pixels.\n\
+        Must be a power of two, and at least {}. 0 uses the default alignment.",
+        min_alignment,
+    )));
+    let advanced = gtk::Expander::new(Some("Advanced"));
+    advanced.add(&alignment_bx);
+
+    // Shared by the import and the atlas size shown for the selected frame info.
+    let import_options = {
+        let swap_bgra_checkbox = swap_bgra_checkbox.clone();
+        let alpha_threshold = alpha_threshold.clone();
+        let alignment = alignment.clone();
+        let preserve_layout_checkbox = preserve_layout_checkbox.clone();
+        Rc::new(move || frame_import::ImportOptions {
+            swap_bgra: swap_bgra_checkbox.is_active(),
+            alpha_threshold: alpha_threshold.value_as_int().clamp(0, 254) as u8,
+            alignment: Some(alignment.value_as_int().clamp(0, 256) as u16).filter(|&x| x != 0),
+            preserve_layout: is_anim && preserve_layout_checkbox.is_active(),
+            ..Default::default()
+        })
+    };
+    let forced_texture_size = {
+        let texture_size_section = texture_size_section.clone();
+        let texture_width = texture_width.clone();
+        let texture_height = texture_height.clone();
+        Rc::new(move || match texture_size_section.as_ref().map(|x| x.is_active()) {
+            Some(true) => Some((texture_width.get_value(), texture_height.get_value())),
+            _ => None,
+        })
+    };
+    if is_anim {
+        let import_options = import_options.clone();
+        let forced_texture_size = forced_texture_size.clone();
+        let import_hd_checkbox = import_hd_checkbox.clone();
+        let import_sd_checkbox = import_sd_checkbox.clone();
+        inputs.set_atlas_preview(move || AtlasPreview {
+            options: import_options(),
+            texture_size: forced_texture_size(),
+            hd: import_hd_checkbox.as_ref().map(|x| x.is_active()).unwrap_or(false),
+            sd: import_sd_checkbox.as_ref().map(|x| x.is_active()).unwrap_or(false),
+        });
+        let refresh = || {
+            let inputs = inputs.clone();
+            move || inputs.refresh_atlas_sizes()
+        };
+        preserve_layout_checkbox.connect_toggled(refresh());
+        if let Some(ref check) = import_hd_checkbox {
+            check.connect_toggled(refresh());
+        }
+        if let Some(ref section) = import_sd_checkbox {
+            section.connect_toggled(refresh());
+        }
+        if let Some(ref section) = texture_size_section {
+            section.connect_toggled(refresh());
+        }
+        for spin in [&alpha_threshold, &alignment] {
+            let refresh = refresh();
+            spin.connect_value_changed(move |_| refresh());
+        }
+    }
+
+    let timing_csv = Rc::new(
+        select_dir::SelectFile::new(&window, "import_timing_csv", "CSV files", "*.csv")
+    );
+    let timing_csv_bx = label_section(
+        "Frame timing CSV (optional, only checked, durations are not saved)",
+        timing_csv.widget(),
+    );
+    timing_csv_bx.set_tooltip_text(Some("\
+        CSV file with rows of `frame index, duration`, one row for each frame.\n\
+        The file is checked to match the imported frames.\n\
+        (Anim files have no field for frame durations, so they are not saved yet)"));
+
     let button_bx = gtk::Box::new(gtk::Orientation::Horizontal, 15);
     let ok_button = gtk::Button::with_label("Import");
     ok_button.set_sensitive(true);
     let cancel_button = gtk::Button::with_label("Cancel");
-    let w = window.clone();
-    cancel_button.connect_clicked(move |_| {
-        w.close();
-    });
+    // Set by the cancel button while an import is running, checked by the import between frames
+    let cancel = Arc::new(AtomicBool::new(false));
+    let waiting_for_thread = Rc::new(Cell::new(false));
     let sprite_info = sprite_info.clone();
     let w = window.clone();
     let checkboxes2 = checkboxes.clone();
@@ -206,13 +362,29 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
 
     let progress = gtk::ProgressBar::new();
     let progress2 = progress.clone();
-    let waiting_for_thread = Rc::new(Cell::new(false));
+    let window2 = window.clone();
+    let cancel2 = cancel.clone();
+    let waiting_for_thread2 = waiting_for_thread.clone();
+    cancel_button.connect_clicked(move |button| {
+        if waiting_for_thread2.get() {
+            cancel2.store(true, Ordering::Relaxed);
+            button.set_sensitive(false);
+            button.set_label("Cancelling...");
+        } else {
+            window2.close();
+        }
+    });
+    let cancel_button2 = cancel_button.clone();
+    let ok_button2 = ok_button.clone();
     let waiting_for_thread2 = waiting_for_thread.clone();
     let rest_of_ui: Rc<RefCell<Vec<gtk::Box>>> = Rc::new(RefCell::new(Vec::new()));
     let rest_of_ui2 = rest_of_ui.clone();
     let ddsgrp_make_linked_grp2 = ddsgrp_make_linked_grp.clone();
     let import_hd_checkbox2 = import_hd_checkbox.clone();
     let import_sd_checkbox2 = import_sd_checkbox.clone();
+    let alpha_threshold2 = alpha_threshold.clone();
+    let alignment2 = alignment.clone();
+    let timing_csv2 = timing_csv.clone();
     let files_root: Option<PathBuf> = files.root_path().map(|x| x.into());
     let inputs2 = inputs.clone();
     ok_button.connect_clicked(move |_| {
@@ -229,14 +401,39 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
             Some(s) => s,
             None => return,
         };
+        let timing_path = timing_csv2.text();
+        if !timing_path.is_empty() {
+            let result = std::fs::File::open(&timing_path)
+                .map_err(Error::from)
+                .and_then(|mut file| {
+                    frame_info::parse_timing_csv(&mut file, frame_info.frame_count)
+                });
+            match result {
+                Ok(durations) => debug!("Frame durations: {:?}", durations),
+                Err(e) => {
+                    error_msg_box(&w, &format!("Invalid timing CSV {}: {:?}", timing_path, e));
+                    return;
+                }
+            }
+        }
         let (hd_fi, sd_fi) = split_frame_info_hd_sd(&frame_info, &checkboxes2);
+        let frame_count = frame_info.frame_count;
 
         let (send, recv) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
         let files_arc = sprite_info.files.clone();
-        let frame_scales = match inputs.scales().should() {
+        let frame_scales = match inputs.scales(hd2_divisor).should() {
             Some(s) => s,
             None => return,
         };
+        let alpha_threshold = alpha_threshold2.value_as_int().clamp(0, 254);
+        set_config_entry("import_alpha_threshold", alpha_threshold);
+        let alignment = alignment2.value_as_int().clamp(0, 256) as u16;
+        if alignment != 0 && !alignment.is_power_of_two() {
+            error_msg_box(&w, &format!("Texture alignment {} is not a power of two", alignment));
+            return;
+        }
+        set_config_entry("import_alignment", alignment);
+        let options = import_options();
         if is_anim {
             let import_sd = import_sd_checkbox2
                 .as_ref()
@@ -246,6 +443,24 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                 .as_ref()
                 .map(|x| x.is_active())
                 .unwrap_or(false);
+            let (hd_opaque, sd_opaque) = checkboxes2.force_opaque_layers();
+            let (hd_skip, sd_skip) = checkboxes2.skipped_layers();
+            let texture_size = forced_texture_size();
+            if let Some(size) = texture_size {
+                set_config_entry("import_texture_width", size.0);
+                set_config_entry("import_texture_height", size.1);
+            }
+            let hd_options = frame_import::ImportOptions {
+                force_opaque_layers: hd_opaque,
+                skip_layers: hd_skip,
+                texture_size,
+                ..options
+            };
+            let sd_options = frame_import::ImportOptions {
+                force_opaque_layers: sd_opaque,
+                skip_layers: sd_skip,
+                ..options
+            };
             let formats = checkboxes2.get_formats();
             let (hd_formats, sd_formats) = match formats {
                 Ok(o) => o,
@@ -264,9 +479,10 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                 return;
             }
 
-            let frame_count = frame_info.frame_count;
-
             let grp_filename = sd_grp_widget.grp_filename(&files_root);
+            // Reset before the thread starts, so it can't see a cancel of an earlier import
+            cancel.store(false, Ordering::Relaxed);
+            let cancel = cancel.clone();
             std::thread::spawn(move || {
                 let send2 = send.clone();
                 let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
@@ -293,9 +509,19 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                             tex_id.0,
                             SpriteType::Hd,
                             None,
+                            hd_options,
+                            &cancel,
                             |step| send.send(Progress::Progress(hd_step(step))).unwrap(),
                         ).context("Import HD frames")?;
                     }
+                    // HD changes are applied now, so SD import can't be cancelled anymore
+                    let cancel = match import_hd && import_sd {
+                        true => {
+                            send.send(Progress::Committed).unwrap();
+                            Arc::new(AtomicBool::new(false))
+                        }
+                        false => cancel,
+                    };
                     // SD
                     if import_sd {
                         frame_import::import_frames(
@@ -310,6 +536,8 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                             tex_id.0,
                             SpriteType::Sd,
                             grp_filename.as_ref().map(|x| &**x),
+                            sd_options,
+                            &cancel,
                             |step| send.send(Progress::Progress(sd_step(step))).unwrap(),
                         ).context("Import SD frames")?;
                     }
@@ -330,7 +558,15 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                     return;
                 }
             };
-            let scale = grp_scale_entry.as_ref().unwrap().get_value() as u8;
+            let scale = grp_scale_entry.as_ref().unwrap().get_value();
+            let scale = match u8::try_from(scale) {
+                Ok(o) if matches!(o, 1 | 2 | 4) => o,
+                _ => {
+                    let msg = format!("Invalid ingame scale {}, expected 1, 2 or 4", scale);
+                    error_msg_box(&w, msg);
+                    return;
+                }
+            };
             let make_linked_grp = ddsgrp_make_linked_grp2
                 .as_ref()
                 .map(|x| x.is_active())
@@ -340,6 +576,8 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                 false => None,
             };
             let frame_info = FrameInfo::clone(&frame_info);
+            cancel.store(false, Ordering::Relaxed);
+            let cancel = cancel.clone();
             std::thread::spawn(move || {
                 let send2 = send.clone();
                 let frame_count = frame_info.frame_count;
@@ -365,6 +603,8 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                         tex_id.0,
                         scale,
                         linked_grp_path.as_deref(),
+                        options,
+                        &cancel,
                         |step| send.send(Progress::Progress(step)).unwrap(),
                     )?;
                     Ok(())
@@ -375,10 +615,15 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
         let rest_of_ui = rest_of_ui2.clone();
         let window = w.clone();
         let progress = progress2.clone();
+        let cancel_button = cancel_button2.clone();
+        let ok_button = ok_button2.clone();
         waiting_for_thread.set(true);
         for part in rest_of_ui.borrow().iter() {
             part.set_sensitive(false);
         }
+        ok_button.set_sensitive(false);
+        progress.set_show_text(true);
+        progress.set_text(Some(&format!("Importing {} frames, 0%", frame_count)));
         let waiting_for_thread = waiting_for_thread.clone();
         let sprite_info = sprite_info.clone();
         let files_arc = sprite_info.files.clone();
@@ -388,6 +633,10 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                 for part in rest_of_ui.borrow().iter() {
                     part.set_sensitive(true);
                 }
+                ok_button.set_sensitive(true);
+                cancel_button.set_sensitive(true);
+                cancel_button.set_label("Cancel");
+                cancel_button.set_tooltip_text(None);
                 match result {
                     Ok(frame_count) => {
                         let mut files = files_arc.lock();
@@ -404,6 +653,10 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                         sprite_info.lighting.select_sprite(tex_id.0);
                         window.close();
                     }
+                    Err(e) if e.downcast_ref::<frame_import::Cancelled>().is_some() => {
+                        progress.set_fraction(0.0);
+                        progress.set_text(Some("Cancelled"));
+                    }
                     Err(e) => {
                         let msg = format!("Unable to import frames: {:?}", e);
                         error_msg_box(&window, msg);
@@ -412,7 +665,16 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
                 glib::Continue(false)
             }
             Progress::Progress(step) => {
+                // `step` covers all of HD, HD2 and SD imports, so it doesn't
+                // map to a frame count.
                 progress.set_fraction(step as f64);
+                let text = format!("Importing {} frames, {:.0}%", frame_count, step * 100.0);
+                progress.set_text(Some(&text));
+                glib::Continue(true)
+            }
+            Progress::Committed => {
+                cancel_button.set_sensitive(false);
+                cancel_button.set_tooltip_text(Some("HD frames have already been imported"));
                 glib::Continue(true)
             }
         });
@@ -432,15 +694,29 @@ pub fn frame_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicat
     if let Some(ref check) = import_hd_checkbox {
         rest_bx.pack_start(check.widget(), false, false, 0);
     }
+    if let Some(ref section) = texture_size_section {
+        rest_bx.pack_start(section.widget(), false, false, 0);
+    }
+    if is_anim {
+        rest_bx.pack_start(preserve_layout_checkbox.widget(), false, false, 0);
+    }
     if let Some(ref check) = import_sd_checkbox {
         rest_bx.pack_start(check.widget(), false, false, 0);
     }
+    rest_bx.pack_start(swap_bgra_checkbox.widget(), false, false, 0);
+    if is_anim {
+        rest_bx.pack_start(&alpha_threshold_bx, false, false, 0);
+    }
+    if is_anim {
+        rest_bx.pack_start(&timing_csv_bx, false, false, 0);
+        rest_bx.pack_start(&advanced, false, false, 0);
+    }
     let bx = box_vertical(&[
         &rest_bx,
         &progress,
         &button_bx,
     ]);
-    *rest_of_ui.borrow_mut() = vec![rest_bx, button_bx];
+    *rest_of_ui.borrow_mut() = vec![rest_bx];
     window.add(&bx);
     window.set_border_width(10);
     window.set_default_width(350);
@@ -568,6 +844,22 @@ struct FrameInputsInner {
     frame_info_update_callbacks: RefCell<Vec<Box<dyn FnMut(&FrameInputs)>>>,
     input_state: FrameInputState,
     window: gtk::Window,
+    /// Used for showing the HD2 atlas size.
+    hd2_divisor: u32,
+    /// Returns the import settings that the atlas size is shown for.
+    /// Not set for ddsgrp imports, which don't pack an atlas.
+    atlas_preview: RefCell<Option<Box<dyn Fn() -> AtlasPreview>>>,
+    /// Pending delayed `refresh_atlas_sizes` call.
+    atlas_refresh: RefCell<Option<glib::SourceId>>,
+}
+
+/// Import settings that affect the atlas size of the imported frames.
+struct AtlasPreview {
+    options: frame_import::ImportOptions,
+    /// Forced HD texture size
+    texture_size: Option<(u32, u32)>,
+    hd: bool,
+    sd: bool,
 }
 
 /// Controls for single input choice
@@ -575,6 +867,10 @@ struct FrameInputControls {
     file_select: Rc<select_dir::SelectFile>,
     input_scale: ScaleChooser,
     framedef_status: gtk::Label,
+    /// Incremented for each atlas size calculation, so that only the latest one is shown.
+    atlas_request: Rc<Cell<u32>>,
+    /// Set to stop the atlas size calculation that is currently running.
+    atlas_cancel: RefCell<Arc<AtomicBool>>,
 }
 
 struct FrameInputState {
@@ -582,7 +878,7 @@ struct FrameInputState {
 }
 
 impl FrameInputs {
-    pub fn new(window: gtk::Window) -> FrameInputs {
+    pub fn new(window: gtk::Window, hd2_divisor: u32) -> FrameInputs {
         let count = read_config_entry_int("frame_inputs_count")
             .unwrap_or(1)
             .clamp(1, 3) as u32;
@@ -595,6 +891,9 @@ impl FrameInputs {
             frame_info_update_callbacks: RefCell::new(Vec::new()),
             input_state: FrameInputState::new(),
             window,
+            hd2_divisor,
+            atlas_preview: RefCell::new(None),
+            atlas_refresh: RefCell::new(None),
         });
         let this = FrameInputs(this);
         while this.0.count.get() < count {
@@ -642,6 +941,8 @@ impl FrameInputs {
             file_select: file_select.clone(),
             input_scale,
             framedef_status,
+            atlas_request: Rc::new(Cell::new(0)),
+            atlas_cancel: RefCell::new(Arc::new(AtomicBool::new(false))),
         });
 
         let index = i as usize - 1;
@@ -661,7 +962,7 @@ impl FrameInputs {
         };
         let frame_info = match parse_frame_info(Path::new(filename)) {
             Ok(o) => {
-                status.set_text("");
+                self.show_atlas_size(index, filename, &o);
                 Some(o)
             }
             Err(e) => {
@@ -674,6 +975,131 @@ impl FrameInputs {
         self.frame_info_updated();
     }
 
+    fn set_atlas_preview(&self, preview: impl Fn() -> AtlasPreview + 'static) {
+        *self.0.atlas_preview.borrow_mut() = Some(Box::new(preview));
+        self.refresh_atlas_sizes();
+    }
+
+    /// Recalculates atlas sizes after the import settings have changed.
+    ///
+    /// Waits a moment before starting so that e.g. holding a spin button
+    /// doesn't repack the frames on every step.
+    fn refresh_atlas_sizes(&self) {
+        if let Some(source) = self.0.atlas_refresh.borrow_mut().take() {
+            source.remove();
+        }
+        let this = self.clone();
+        let source = glib::timeout_add_local(Duration::from_millis(300), move || {
+            this.0.atlas_refresh.borrow_mut().take();
+            this.refresh_atlas_sizes_now();
+            glib::Continue(false)
+        });
+        *self.0.atlas_refresh.borrow_mut() = Some(source);
+    }
+
+    fn refresh_atlas_sizes_now(&self) {
+        let count = self.0.input_controls.borrow().len();
+        for index in 0..count {
+            let filename = match self.0.input_controls.borrow().get(index) {
+                Some(s) => s.file_select.text(),
+                None => continue,
+            };
+            if let Some(frame_info) = self.frame_info(index) {
+                self.show_atlas_size(index, &filename, &frame_info);
+            }
+        }
+    }
+
+    /// Packs the frames in a background thread with the current import settings and shows
+    /// the resulting texture sizes in the status label once done.
+    fn show_atlas_size(&self, index: usize, filename: &str, frame_info: &FrameInfo) {
+        let controls = self.0.input_controls.borrow();
+        let (status, scale, request, cancel) = match controls.should_get(index) {
+            Some(s) => {
+                // Stop the previous calculation, its result won't be shown anyway.
+                let cancel = Arc::new(AtomicBool::new(false));
+                s.atlas_cancel.replace(cancel.clone()).store(true, Ordering::Relaxed);
+                let status = s.framedef_status.clone();
+                (status, s.input_scale.active(), s.atlas_request.clone(), cancel)
+            }
+            None => return,
+        };
+        drop(controls);
+        let request_id = request.get().wrapping_add(1);
+        request.set(request_id);
+        let preview = match self.0.atlas_preview.borrow().as_ref() {
+            Some(preview) => preview(),
+            None => {
+                status.set_text("");
+                return;
+            }
+        };
+        if preview.options.preserve_layout {
+            status.set_text("Frames are written to the existing atlas layout");
+            return;
+        }
+        if !preview.hd && !preview.sd {
+            status.set_text("");
+            return;
+        }
+        let hd2_divisor = self.0.hd2_divisor;
+        let scale = scale.unwrap_or(ScaleValue::Scale4);
+        let (hd_scale, _, sd_scale) = frame_scales(scale, hd2_divisor);
+        let dir = match Path::new(filename).parent() {
+            Some(s) => s.to_owned(),
+            None => return,
+        };
+        status.set_text("Calculating atlas size...");
+        let (send, recv) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let frame_info = frame_info.clone();
+        std::thread::spawn(move || {
+            let calculate = || -> Result<_, Error> {
+                let mut lines = Vec::new();
+                if preview.hd {
+                    let options = frame_import::ImportOptions {
+                        texture_size: preview.texture_size,
+                        ..preview.options
+                    };
+                    let layout = frame_import::preview_layout(
+                        &frame_info, &dir, hd_scale, hd2_divisor, options, &cancel,
+                    )?;
+                    let (hd, hd2) = (layout.texture_size(1), layout.texture_size(hd2_divisor));
+                    lines.push(format!(
+                        "HD atlas: {}x{} (HD2 {}x{}), {:.0}% used",
+                        hd.0, hd.1, hd2.0, hd2.1, layout.utilization() * 100.0,
+                    ));
+                }
+                if preview.sd {
+                    let layout = frame_import::preview_layout(
+                        &frame_info, &dir, sd_scale, 1, preview.options, &cancel,
+                    )?;
+                    let sd = layout.texture_size(1);
+                    lines.push(format!(
+                        "SD atlas: {}x{}, {:.0}% used",
+                        sd.0, sd.1, layout.utilization() * 100.0,
+                    ));
+                }
+                Ok(lines.join("\n"))
+            };
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(calculate))
+                .unwrap_or_else(|e| Err(error_from_panic(e)));
+            let _ = send.send(result);
+        });
+        recv.attach(None, move |result: Result<String, Error>| {
+            // Another file or other settings may have been selected while this was
+            // being calculated.
+            if request.get() != request_id {
+                return glib::Continue(false);
+            }
+            let text = match result {
+                Ok(o) => o,
+                Err(e) => format!("Unable to calculate atlas size: {:?}", e),
+            };
+            status.set_text(&text);
+            glib::Continue(false)
+        });
+    }
+
     /// Called once any of the state in input FrameInfos is replaced
     fn frame_info_updated(&self) {
         let mut cbs = self.0.frame_info_update_callbacks.replace(Vec::new());
@@ -707,16 +1133,23 @@ impl FrameInputs {
         self.0.bx.upcast_ref()
     }
 
-    pub fn scales(&self) -> Option<(f32, f32, f32)> {
+    /// Returns amount that input frames need to be scaled by for HD, HD2 and SD output.
+    pub fn scales(&self, hd2_divisor: u32) -> Option<(f32, f32, f32)> {
         let scale = self.0.input_controls.borrow().should_get(0)?.input_scale.active()?;
-        Some(match scale {
-            ScaleValue::Scale4 => (1.0, 0.5, 0.25),
-            ScaleValue::Scale2 => (2.0, 1.0, 0.5),
-            ScaleValue::Scale1 => (4.0, 2.0, 1.0),
-        })
+        Some(frame_scales(scale, hd2_divisor))
     }
 }
 
+/// Amount that frames of input `scale` need to be scaled by for HD, HD2 and SD output.
+fn frame_scales(scale: ScaleValue, hd2_divisor: u32) -> (f32, f32, f32) {
+    let input = match scale {
+        ScaleValue::Scale4 => 4.0,
+        ScaleValue::Scale2 => 2.0,
+        ScaleValue::Scale1 => 1.0,
+    };
+    (4.0 / input, 4.0 / hd2_divisor as f32 / input, 1.0 / input)
+}
+
 impl FrameInputState {
     fn new() -> FrameInputState {
         FrameInputState {
@@ -761,6 +1194,9 @@ fn split_frame_info_hd_sd(
 
     let mut hd = frame_info.clone();
     let mut sd = frame_info.clone();
+    // The ids below are indices to the default layer names
+    hd.layers_by_name = false;
+    sd.layers_by_name = false;
     hd.layers.clear();
     sd.layers.clear();
     hd.multi_frame_images.clear();
@@ -797,7 +1233,13 @@ struct OutLayerCheckboxes(Rc<OutLayerCheckboxesInner>);
 
 struct OutLayerCheckboxesInner {
     bx: gtk::Box,
-    checkboxes: Vec<(gtk::CheckButton, ComboBoxEnum<anim::TextureFormat>, &'static str)>,
+    /// Enabled, format, force opaque, layer name
+    checkboxes: Vec<(
+        gtk::CheckButton,
+        ComboBoxEnum<anim::TextureFormat>,
+        SavedCheckbox,
+        &'static str,
+    )>,
 }
 
 impl OutLayerCheckboxes {
@@ -815,10 +1257,19 @@ impl OutLayerCheckboxes {
 
         let mut checkboxes = Vec::new();
 
+        let opaque_header = gtk::Label::new(Some("Force opaque"));
+        opaque_header.set_tooltip_text(Some("\
+            Sets alpha of every pixel to fully opaque before encoding.\n\
+            Useful for layers that should be solid, but whose images have stray alpha."));
+        grid.attach(&opaque_header, 3, 0, 1, 1);
+
         for (i, &name) in layer_names.iter().enumerate() {
             let row = i as i32 + 1;
 
             let checkbox = gtk::CheckButton::new();
+            checkbox.set_tooltip_text(Some("\
+                Unchecking a layer that is in the frame info keeps its current texture.\n\
+                This requires keeping the existing atlas layout."));
             grid.attach(&checkbox, 0, row, 1, 1);
 
             let label = gtk::Label::new(Some(name));
@@ -826,8 +1277,11 @@ impl OutLayerCheckboxes {
             label.set_halign(gtk::Align::Start);
             let format = ComboBoxEnum::new(FORMATS_ANIM);
             grid.attach(format.widget(), 2, row, 1, 1);
+            let force_opaque = SavedCheckbox::new(format!("import_force_opaque_{}", name), "");
+            force_opaque.widget().set_halign(gtk::Align::Center);
+            grid.attach(force_opaque.widget(), 3, row, 1, 1);
 
-            checkboxes.push((checkbox, format, name));
+            checkboxes.push((checkbox, format, force_opaque, name));
         }
         let bx = label_section("Layers", &grid);
 
@@ -844,7 +1298,7 @@ impl OutLayerCheckboxes {
 
     pub fn layer_enabled(&self, name: &str) -> bool {
         self.0.checkboxes.iter()
-            .find(|x| x.2 == name)
+            .find(|x| x.3 == name)
             .map(|x| x.0.is_active())
             .unwrap_or(false)
     }
@@ -854,7 +1308,7 @@ impl OutLayerCheckboxes {
             DEFAULT_HD_LAYER_NAMES.iter().map(|_| anim::TextureFormat::Monochrome).collect();
         let mut sd: Vec<anim::TextureFormat> =
             DEFAULT_SD_LAYER_NAMES.iter().map(|_| anim::TextureFormat::Monochrome).collect();
-        for &(ref check, ref format, name) in self.0.checkboxes.iter() {
+        for &(ref check, ref format, _, name) in self.0.checkboxes.iter() {
             let format = if let Some(format) = format.active() {
                 format
             } else {
@@ -874,12 +1328,51 @@ impl OutLayerCheckboxes {
         Ok((hd, sd))
     }
 
+    /// Returns bitmasks of HD and SD layer indices that should be imported fully opaque.
+    pub fn force_opaque_layers(&self) -> (u32, u32) {
+        let mut hd = 0;
+        let mut sd = 0;
+        for &(ref check, _, ref force_opaque, name) in self.0.checkboxes.iter() {
+            if !check.is_active() || !force_opaque.is_active() {
+                continue;
+            }
+            if let Some(idx) = DEFAULT_HD_LAYER_NAMES.iter().position(|&x| x == name) {
+                hd |= 1 << idx;
+            }
+            if let Some(idx) = DEFAULT_SD_LAYER_NAMES.iter().position(|&x| x == name) {
+                sd |= 1 << idx;
+            }
+        }
+        (hd, sd)
+    }
+
+    /// Returns bitmasks of HD and SD layer indices which are in the frame info,
+    /// but were unchecked to keep their current textures.
+    pub fn skipped_layers(&self) -> (u32, u32) {
+        let mut hd = 0;
+        let mut sd = 0;
+        for &(ref check, _, _, name) in self.0.checkboxes.iter() {
+            // Layers not in the frame info are not sensitive
+            if !check.is_sensitive() || check.is_active() {
+                continue;
+            }
+            if let Some(idx) = DEFAULT_HD_LAYER_NAMES.iter().position(|&x| x == name) {
+                hd |= 1 << idx;
+            }
+            if let Some(idx) = DEFAULT_SD_LAYER_NAMES.iter().position(|&x| x == name) {
+                sd |= 1 << idx;
+            }
+        }
+        (hd, sd)
+    }
+
     pub fn disable(&self) {
-        for &(ref check, ref format, _name) in self.0.checkboxes.iter() {
+        for &(ref check, ref format, ref force_opaque, _name) in self.0.checkboxes.iter() {
             check.set_sensitive(false);
             check.set_active(false);
             format.set_sensitive(false);
             format.clear_active();
+            force_opaque.widget().set_sensitive(false);
         }
     }
 
@@ -888,11 +1381,14 @@ impl OutLayerCheckboxes {
         layer: &frame_info::Layer,
         tex_formats: &[Result<Option<anim::TextureFormat>, Error>],
     ) {
-        for (i, &(ref check, ref format, name)) in self.0.checkboxes.iter().enumerate() {
+        for (i, &(ref check, ref format, ref force_opaque, name)) in
+            self.0.checkboxes.iter().enumerate()
+        {
             if name == layer.name {
                 check.set_sensitive(true);
                 check.set_active(true);
                 format.set_sensitive(true);
+                force_opaque.widget().set_sensitive(true);
                 let tex_f = tex_formats.get(i)
                     .and_then(|x| x.as_ref().ok())
                     .and_then(|x| x.as_ref());
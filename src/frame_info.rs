@@ -9,14 +9,31 @@ use serde::Deserialize;
 
 use crate::Error;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Default, Serialize)]
 pub struct FrameInfo {
     pub frame_count: u32,
     pub offset_x: i32,
     pub offset_y: i32,
     pub layers: Vec<Layer>,
     pub frame_types: Vec<FrameType>,
+    /// Frame type of each frame. If not empty, used instead of `frame_types`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub per_frame_types: Vec<u32>,
+    /// Position of each frame's image relative to the sprite origin. If not empty,
+    /// used instead of `offset_x`/`offset_y`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub per_frame_offsets: Vec<FrameOffset>,
     pub multi_frame_images: Vec<MultiFrameImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<SpriteDescription>,
+    /// Area of the images that has frame content, if the images were padded on export.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_rect: Option<ContentRect>,
+    /// Refer to layers by name instead of index when written with `to_json`.
+    /// Set when parsing a file that does so, in which case layer ids are only
+    /// placeholders until `resolve_layer_names` is called.
+    #[serde(skip)]
+    pub layers_by_name: bool,
 }
 
 #[derive(Clone, Deserialize)]
@@ -27,11 +44,110 @@ pub struct FrameInfoDeserialize {
     pub layers: Vec<serde_json::Value>,
     pub frame_types: Vec<FrameType>,
     #[serde(default)]
-    pub multi_frame_images: Vec<MultiFrameImage>,
+    pub per_frame_types: Vec<u32>,
+    #[serde(default)]
+    pub per_frame_offsets: Vec<FrameOffset>,
+    #[serde(default)]
+    pub multi_frame_images: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub sprite: Option<SpriteDescription>,
+    #[serde(default)]
+    pub content_rect: Option<ContentRect>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FrameOffset {
+    pub offset_x: i32,
+    pub offset_y: i32,
+}
+
+/// Rectangle in image pixels. Pixels outside it are ignored on import.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ContentRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Sprite-level values of an exported anim sprite.
+/// Not needed for importing frames, but describes the rest of the sprite.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpriteDescription {
+    pub width: u16,
+    pub height: u16,
+    /// Image that this sprite refers to, if it is a ref.
+    pub image_ref: Option<u16>,
+    /// Texture format of each layer ("dxt1", "dxt5", "rgba", "monochrome"),
+    /// None if the layer has no texture.
+    pub texture_formats: Vec<Option<String>>,
+}
+
+impl FrameInfo {
+    /// Frame type of `frame`, if the frame info specifies one.
+    pub fn frame_type(&self, frame: u32) -> Option<u32> {
+        if !self.per_frame_types.is_empty() {
+            return self.per_frame_types.get(frame as usize).copied();
+        }
+        self.frame_types.iter()
+            .find(|x| x.first_frame <= frame && x.last_frame >= frame)
+            .map(|x| x.frame_type)
+    }
+
+    /// Position of the top left corner of `frame`'s image relative to the sprite origin.
+    pub fn frame_offset(&self, frame: u32) -> (i32, i32) {
+        match self.per_frame_offsets.get(frame as usize) {
+            Some(o) => (o.offset_x, o.offset_y),
+            None => (self.offset_x, self.offset_y),
+        }
+    }
+
+    /// Serializes the frame info, replacing layer ids with names if `layers_by_name` is set.
+    pub fn to_json(&self) -> Result<serde_json::Value, Error> {
+        let mut value = serde_json::to_value(self)?;
+        if self.layers_by_name {
+            let layers = value.get_mut("layers").and_then(|x| x.as_array_mut());
+            for layer in layers.into_iter().flatten() {
+                if let Some(layer) = layer.as_object_mut() {
+                    layer.remove("id");
+                }
+            }
+            let images = value.get_mut("multi_frame_images").and_then(|x| x.as_array_mut());
+            for (image, mfi) in images.into_iter().flatten().zip(&self.multi_frame_images) {
+                let name = self.layers.iter()
+                    .find(|x| x.id == mfi.layer)
+                    .map(|x| x.name.clone())
+                    .ok_or_else(|| anyhow!("No layer {} for {}", mfi.layer, mfi.path))?;
+                image["layer"] = name.into();
+            }
+        }
+        Ok(value)
+    }
+
+    /// Replaces layer ids of a frame info using layer names with
+    /// indices of those names in `layer_names`.
+    pub fn resolve_layer_names(&mut self, layer_names: &[String]) -> Result<(), Error> {
+        let mut id_map = Vec::with_capacity(self.layers.len());
+        for layer in &mut self.layers {
+            let index = layer_names.iter().position(|x| *x == layer.name)
+                .ok_or_else(|| anyhow!("The sprite has no layer named '{}'", layer.name))?;
+            id_map.push((layer.id, index as u32));
+            layer.id = index as u32;
+        }
+        for mfi in &mut self.multi_frame_images {
+            if let Some(&(_, new)) = id_map.iter().find(|x| x.0 == mfi.layer) {
+                mfi.layer = new;
+            }
+        }
+        self.layers_by_name = false;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Layer {
+    /// Not written when the frame info refers to layers by name.
+    #[serde(default)]
     pub id: u32,
     /// Only meaningful for ao_depth; 0 = ao, 1 = depth if encoding is SingleChannel
     /// Otherwise 0 is the entire layer.
@@ -63,11 +179,193 @@ fn layer_name_from_prefix(prefix: &str) -> String {
     }
 }
 
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum FieldType {
+    Unsigned,
+    Integer,
+    String,
+    Array,
+}
+
+impl FieldType {
+    fn matches(self, value: &serde_json::Value) -> bool {
+        match self {
+            FieldType::Unsigned => value.as_u64().filter(|&x| x <= u32::MAX as u64).is_some(),
+            FieldType::Integer => {
+                value.as_i64().filter(|&x| x >= i32::MIN as i64 && x <= i32::MAX as i64).is_some()
+            }
+            FieldType::String => value.is_string(),
+            FieldType::Array => value.is_array(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldType::Unsigned => "a non-negative integer",
+            FieldType::Integer => "an integer",
+            FieldType::String => "a string",
+            FieldType::Array => "an array",
+        }
+    }
+}
+
+/// Checks that `object` has `required` keys and that they, as well as any `optional` keys
+/// that are present, have the expected types.
+fn check_fields(
+    object: &serde_json::Value,
+    required: &[(&str, FieldType)],
+    optional: &[(&str, FieldType)],
+) -> Result<(), Error> {
+    if !object.is_object() {
+        return Err(anyhow!("Expected an object, got {}", object));
+    }
+    for &(key, ty) in required {
+        if object.get(key).is_none() {
+            return Err(anyhow!("Missing required key '{}' (expected {})", key, ty.name()));
+        }
+    }
+    for &(key, ty) in required.iter().chain(optional) {
+        match object.get(key) {
+            Some(value) if !ty.matches(value) => {
+                return Err(anyhow!("'{}' must be {}, got {}", key, ty.name(), value));
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Checks the structure of a frame info file before deserializing it,
+/// so that errors can name the offending key.
+fn check_frame_info_json(value: &serde_json::Value) -> Result<(), Error> {
+    use self::FieldType::*;
+    check_fields(
+        value,
+        &[
+            ("frame_count", Unsigned),
+            ("offset_x", Integer),
+            ("offset_y", Integer),
+            ("layers", Array),
+            ("frame_types", Array),
+        ],
+        &[
+            ("per_frame_types", Array),
+            ("per_frame_offsets", Array),
+            ("multi_frame_images", Array),
+        ],
+    )?;
+    let array = |key: &str| value.get(key).and_then(|x| x.as_array()).into_iter().flatten();
+    for (i, layer) in array("layers").enumerate() {
+        if layer.is_object() {
+            check_fields(
+                layer,
+                &[("sub_id", Unsigned), ("filename_prefix", String)],
+                &[("id", Unsigned), ("name", String)],
+            ).with_context(|| format!("Layer {}", i))?;
+        }
+    }
+    for (i, frame_type) in array("frame_types").enumerate() {
+        check_fields(
+            frame_type,
+            &[("first_frame", Unsigned), ("last_frame", Unsigned), ("frame_type", Unsigned)],
+            &[],
+        ).with_context(|| format!("Frame type range {}", i))?;
+    }
+    for (i, frame_type) in array("per_frame_types").enumerate() {
+        if !Unsigned.matches(frame_type) {
+            return Err(anyhow!(
+                "'per_frame_types' entry {} must be {}, got {}", i, Unsigned.name(), frame_type,
+            ));
+        }
+    }
+    for (i, offset) in array("per_frame_offsets").enumerate() {
+        check_fields(offset, &[("offset_x", Integer), ("offset_y", Integer)], &[])
+            .with_context(|| format!("Frame offset {}", i))?;
+    }
+    for (i, image) in array("multi_frame_images").enumerate() {
+        check_fields(
+            image,
+            &[
+                ("first_frame", Unsigned),
+                ("frame_count", Unsigned),
+                ("path", String),
+                ("frame_width", Unsigned),
+                ("frame_height", Unsigned),
+            ],
+            &[("sublayer", Unsigned), ("frame_positions", Array)],
+        ).with_context(|| format!("Image {}", i))?;
+        match image.get("layer") {
+            Some(layer) if Unsigned.matches(layer) || layer.is_string() => (),
+            Some(layer) => {
+                return Err(anyhow!(
+                    "Image {}: 'layer' must be a layer index or name, got {}", i, layer,
+                ));
+            }
+            None => return Err(anyhow!("Image {}: Missing required key 'layer'", i)),
+        }
+    }
+    Ok(())
+}
+
+/// Checks that frame ranges and layer references of a parsed frame info are consistent.
+fn check_frame_info_ranges(info: &FrameInfo) -> Result<(), Error> {
+    for (i, range) in info.frame_types.iter().enumerate() {
+        if range.first_frame > range.last_frame || range.last_frame >= info.frame_count {
+            return Err(anyhow!(
+                "Frame type range {}: frames {}..={} are not within 0..{} ('frame_count')",
+                i, range.first_frame, range.last_frame, info.frame_count,
+            ));
+        }
+    }
+    if !info.per_frame_types.is_empty() && info.per_frame_types.len() != info.frame_count as usize
+    {
+        return Err(anyhow!(
+            "'per_frame_types' has {} entries, but 'frame_count' is {}",
+            info.per_frame_types.len(), info.frame_count,
+        ));
+    }
+    if !info.per_frame_offsets.is_empty() &&
+        info.per_frame_offsets.len() != info.frame_count as usize
+    {
+        return Err(anyhow!(
+            "'per_frame_offsets' has {} entries, but 'frame_count' is {}",
+            info.per_frame_offsets.len(), info.frame_count,
+        ));
+    }
+    for (i, image) in info.multi_frame_images.iter().enumerate() {
+        let defined = info.layers.iter().any(|x| x.id == image.layer && x.sub_id == image.sublayer);
+        if !defined {
+            let mut ids = info.layers.iter().map(|x| x.id).collect::<Vec<_>>();
+            ids.sort();
+            ids.dedup();
+            let ids = ids.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ");
+            let layer = match image.sublayer {
+                0 => image.layer.to_string(),
+                sub => format!("{} (sublayer {})", image.layer, sub),
+            };
+            return Err(anyhow!(
+                "Image {} refers to layer {}, but 'layers' only defines {} layers ({})",
+                i, layer, info.layers.len(), ids,
+            ));
+        }
+        let end = image.first_frame as u64 + image.frame_count as u64;
+        if end > info.frame_count as u64 {
+            return Err(anyhow!(
+                "Image {}: frames {}..{} are not within 0..{} ('frame_count')",
+                i, image.first_frame, end, info.frame_count,
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn parse_from_reader<R: Read>(r: &mut R) -> Result<FrameInfo, Error> {
+    let value: serde_json::Value = serde_json::from_reader(r)?;
+    check_frame_info_json(&value)?;
     // layers used to be (u32, String) instead of Layer,
     // accept both
-    let base: FrameInfoDeserialize = serde_json::from_reader(r)?;
-    let layers = base.layers.iter().enumerate().map(|(i, x)| {
+    let base: FrameInfoDeserialize = serde_json::from_value(value)?;
+    let mut layers = base.layers.iter().enumerate().map(|(i, x)| {
         fn parse(x: &serde_json::Value) -> Result<Layer, Error> {
             match x {
                 serde_json::Value::Array(ref vals) => {
@@ -99,14 +397,89 @@ fn parse_from_reader<R: Read>(r: &mut R) -> Result<FrameInfo, Error> {
         }
         Ok(layer)
     }).collect::<Result<Vec<_>, Error>>()?;
-    Ok(FrameInfo {
+    // Layers that are referred by name get placeholder ids
+    // based on order of the names in this file.
+    let layers_by_name = base.layers.iter().any(|x| x.is_object() && x.get("id").is_none());
+    let mut names = Vec::new();
+    if layers_by_name {
+        for layer in &mut layers {
+            layer.id = match names.iter().position(|x| *x == layer.name) {
+                Some(s) => s as u32,
+                None => {
+                    names.push(layer.name.clone());
+                    names.len() as u32 - 1
+                }
+            };
+        }
+    }
+    let multi_frame_images = base.multi_frame_images.into_iter().enumerate().map(|(i, mut x)| {
+        if let Some(name) = x.get("layer").and_then(|x| x.as_str()) {
+            let id = names.iter().position(|x| x == name)
+                .ok_or_else(|| anyhow!("Image {} refers to unknown layer '{}'", i, name))?;
+            x["layer"] = id.into();
+        }
+        MultiFrameImage::deserialize(x).with_context(|| anyhow!("Image {}", i))
+    }).collect::<Result<Vec<_>, Error>>()?;
+    let info = FrameInfo {
         frame_count: base.frame_count,
         offset_x: base.offset_x,
         offset_y: base.offset_y,
         layers,
         frame_types: base.frame_types,
-        multi_frame_images: base.multi_frame_images,
-    })
+        per_frame_types: base.per_frame_types,
+        per_frame_offsets: base.per_frame_offsets,
+        multi_frame_images,
+        sprite: base.sprite,
+        content_rect: base.content_rect,
+        layers_by_name,
+    };
+    check_frame_info_ranges(&info)?;
+    Ok(info)
+}
+
+/// Parses a CSV of `frame index, duration` rows, returning duration for each frame.
+///
+/// A header row is allowed, as are rows in any order, but every frame in
+/// `0..frame_count` must have exactly one row.
+pub fn parse_timing_csv<R: Read>(r: &mut R, frame_count: u32) -> Result<Vec<u32>, Error> {
+    let mut text = String::new();
+    r.read_to_string(&mut text)?;
+    let mut durations = vec![None; frame_count as usize];
+    let mut row_count = 0u32;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split(',').map(|x| x.trim().trim_matches('"'));
+        let index = fields.next().unwrap_or("");
+        let duration = fields.next()
+            .ok_or_else(|| anyhow!("Line {}: Expected 2 columns", i + 1))?;
+        let index = match index.parse::<u32>() {
+            Ok(o) => o,
+            // Header
+            Err(_) if i == 0 => continue,
+            Err(_) => return Err(anyhow!("Line {}: Invalid frame index '{}'", i + 1, index)),
+        };
+        let duration = duration.parse::<u32>()
+            .map_err(|_| anyhow!("Line {}: Invalid duration '{}'", i + 1, duration))?;
+        let entry = durations.get_mut(index as usize)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Line {}: Frame {} is out of range (0 - {})",
+                    i + 1, index, frame_count.saturating_sub(1),
+                )
+            })?;
+        if entry.is_some() {
+            return Err(anyhow!("Line {}: Frame {} is listed multiple times", i + 1, index));
+        }
+        *entry = Some(duration);
+        row_count += 1;
+    }
+    if row_count != frame_count {
+        return Err(anyhow!("CSV has {} rows, but there are {} frames", row_count, frame_count));
+    }
+    Ok(durations.into_iter().map(|x| x.unwrap_or(0)).collect())
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -116,6 +489,39 @@ pub struct FrameType {
     pub frame_type: u32,
 }
 
+/// Collapses per-frame types to runs of consecutive frames with the same type.
+pub fn frame_type_ranges<I: IntoIterator<Item = u32>>(frame_types: I) -> Vec<FrameType> {
+    let mut result: Vec<FrameType> = Vec::new();
+    for (i, frame_type) in frame_types.into_iter().enumerate() {
+        let i = i as u32;
+        match result.last_mut() {
+            Some(last) if last.frame_type == frame_type => last.last_frame = i,
+            _ => result.push(FrameType {
+                first_frame: i,
+                last_frame: i,
+                frame_type,
+            }),
+        }
+    }
+    result
+}
+
+/// Returns frame types which are used by more than one range.
+pub fn noncontiguous_frame_types(ranges: &[FrameType]) -> Vec<u32> {
+    let mut seen = Vec::with_capacity(ranges.len());
+    let mut result = Vec::new();
+    for range in ranges {
+        if seen.contains(&range.frame_type) {
+            if !result.contains(&range.frame_type) {
+                result.push(range.frame_type);
+            }
+        } else {
+            seen.push(range.frame_type);
+        }
+    }
+    result
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct MultiFrameImage {
     pub layer: u32,
@@ -130,6 +536,10 @@ pub struct MultiFrameImage {
     // Only relevant to grps
     #[serde(default)]
     pub frame_size_overrides: HashMap<u32, (u32, u32)>,
+    /// Top-left corner of each frame in the image, starting from `first_frame`.
+    /// If empty, frames are assumed to be in rows filling the image width.
+    #[serde(default)]
+    pub frame_positions: Vec<(u32, u32)>,
 }
 
 #[test]
@@ -251,3 +661,220 @@ fn backwards_compat() {
     assert_eq!(result.layers[4].name, "normal");
     assert_eq!(result.layers[6].name, "ao_depth");
 }
+
+#[test]
+fn frame_type_runs() {
+    let ranges = frame_type_ranges(vec![1, 1, 2, 1, 1]);
+    let ranges = ranges.iter()
+        .map(|x| (x.first_frame, x.last_frame, x.frame_type))
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, vec![(0, 1, 1), (2, 2, 2), (3, 4, 1)]);
+    assert!(frame_type_ranges(Vec::new()).is_empty());
+    let ranges = frame_type_ranges(vec![1, 2, 1, 2, 3]);
+    assert_eq!(noncontiguous_frame_types(&ranges), vec![1, 2]);
+    let ranges = frame_type_ranges(vec![1, 1, 2, 3, 3]);
+    assert!(noncontiguous_frame_types(&ranges).is_empty());
+}
+
+#[test]
+fn per_frame_types() {
+    let text = r#"{
+        "frame_count": 3,
+        "offset_x": 0,
+        "offset_y": 0,
+        "layers": [],
+        "frame_types": [{"first_frame": 0, "last_frame": 2, "frame_type": 1}],
+        "per_frame_types": [4, 5, 4]
+    }"#;
+    let mut result = parse_from_reader(&mut text.as_bytes()).unwrap();
+    assert_eq!(result.frame_type(0), Some(4));
+    assert_eq!(result.frame_type(1), Some(5));
+    assert_eq!(result.frame_type(3), None);
+    result.per_frame_types.clear();
+    assert_eq!(result.frame_type(1), Some(1));
+    assert_eq!(result.frame_type(3), None);
+    // Not written when empty
+    let json = serde_json::to_string(&result).unwrap();
+    assert!(!json.contains("per_frame_types"));
+    assert!(!json.contains("sprite"));
+}
+
+#[test]
+fn sprite_description() {
+    let text = r#"{
+        "frame_count": 1,
+        "offset_x": 0,
+        "offset_y": 0,
+        "layers": [],
+        "frame_types": [],
+        "sprite": {
+            "width": 64,
+            "height": 48,
+            "image_ref": null,
+            "texture_formats": ["dxt5", null]
+        }
+    }"#;
+    let result = parse_from_reader(&mut text.as_bytes()).unwrap();
+    let sprite = result.sprite.as_ref().unwrap();
+    assert_eq!((sprite.width, sprite.height), (64, 48));
+    assert_eq!(sprite.image_ref, None);
+    assert_eq!(sprite.texture_formats, vec![Some("dxt5".to_string()), None]);
+    let json = serde_json::to_string(&result).unwrap();
+    let result = parse_from_reader(&mut json.as_bytes()).unwrap();
+    assert_eq!(result.sprite.unwrap().texture_formats.len(), 2);
+}
+
+#[test]
+fn timing_csv() {
+    let text = "frame,duration\n1, 80\n0,40\n\n2,\"120\"\n";
+    assert_eq!(parse_timing_csv(&mut text.as_bytes(), 3).unwrap(), vec![40, 80, 120]);
+    // Row count mismatch
+    assert!(parse_timing_csv(&mut text.as_bytes(), 4).is_err());
+    // Out of range frame
+    assert!(parse_timing_csv(&mut text.as_bytes(), 2).is_err());
+    assert!(parse_timing_csv(&mut "0,40\n0,40\n".as_bytes(), 2).is_err());
+    assert!(parse_timing_csv(&mut "0,40\nx,40\n".as_bytes(), 2).is_err());
+    assert!(parse_timing_csv(&mut "0\n".as_bytes(), 1).is_err());
+}
+
+#[test]
+fn exported_json_round_trip() {
+    // Same structure that `frame_export::export_frames` writes
+    let info = FrameInfo {
+        frame_count: 4,
+        offset_x: -12,
+        offset_y: 7,
+        layers: vec![
+            Layer {
+                id: 0,
+                sub_id: 0,
+                filename_prefix: "000_hd_diffuse".into(),
+                encoding: LayerEncoding::Raw,
+                name: "diffuse".into(),
+            },
+            Layer {
+                id: 6,
+                sub_id: 1,
+                filename_prefix: "000_hd_depth".into(),
+                encoding: LayerEncoding::SingleChannel,
+                name: "ao_depth".into(),
+            },
+        ],
+        frame_types: frame_type_ranges(vec![1, 1, 3, 3]),
+        multi_frame_images: vec![MultiFrameImage {
+            layer: 6,
+            sublayer: 1,
+            first_frame: 0,
+            frame_count: 4,
+            path: "000_hd_depth.png".into(),
+            frame_width: 32,
+            frame_height: 16,
+            frame_size_overrides: HashMap::default(),
+            frame_positions: vec![(0, 0), (32, 0), (0, 16), (32, 16)],
+        }],
+        ..Default::default()
+    };
+    let json = serde_json::to_string_pretty(&info).unwrap();
+    let result = parse_from_reader(&mut json.as_bytes()).unwrap();
+    assert_eq!(result.frame_count, 4);
+    assert_eq!((result.offset_x, result.offset_y), (-12, 7));
+    let layers = result.layers.iter()
+        .map(|x| {
+            let single_channel = x.encoding == LayerEncoding::SingleChannel;
+            (x.id, x.sub_id, &*x.filename_prefix, single_channel)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(layers, vec![(0, 0, "000_hd_diffuse", false), (6, 1, "000_hd_depth", true)]);
+    assert_eq!(result.layers[1].name, "ao_depth");
+    let ranges = result.frame_types.iter()
+        .map(|x| (x.first_frame, x.last_frame, x.frame_type))
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, vec![(0, 1, 1), (2, 3, 3)]);
+    assert_eq!(result.frame_type(3), Some(3));
+    assert_eq!(result.frame_offset(2), (-12, 7));
+    let image = &result.multi_frame_images[0];
+    assert_eq!((image.layer, image.sublayer, image.frame_count), (6, 1, 4));
+    assert_eq!(image.frame_positions, info.multi_frame_images[0].frame_positions);
+}
+
+#[test]
+fn layers_by_name() {
+    let layer = |id, sub_id, name: &str| Layer {
+        id,
+        sub_id,
+        filename_prefix: format!("000_{}", name),
+        encoding: LayerEncoding::Raw,
+        name: name.into(),
+    };
+    let image = |layer, sublayer| MultiFrameImage {
+        layer,
+        sublayer,
+        first_frame: 0,
+        frame_count: 1,
+        path: format!("{}_{}.png", layer, sublayer),
+        frame_width: 8,
+        frame_height: 8,
+        frame_size_overrides: HashMap::default(),
+        frame_positions: Vec::new(),
+    };
+    let info = FrameInfo {
+        frame_count: 1,
+        layers: vec![layer(2, 0, "teamcolor"), layer(6, 0, "ao_depth"), layer(6, 1, "ao_depth")],
+        multi_frame_images: vec![image(6, 1), image(2, 0)],
+        layers_by_name: true,
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&info.to_json().unwrap()).unwrap();
+    assert!(!json.contains("\"id\""));
+    assert!(json.contains("\"layer\":\"teamcolor\""));
+    let mut result = parse_from_reader(&mut json.as_bytes()).unwrap();
+    assert!(result.layers_by_name);
+    let names = ["diffuse", "teamcolor"].iter().map(|&x| String::from(x)).collect::<Vec<_>>();
+    assert!(result.clone().resolve_layer_names(&names).is_err());
+    let names = crate::files::DEFAULT_HD_LAYER_NAMES.iter()
+        .map(|&x| String::from(x))
+        .collect::<Vec<_>>();
+    result.resolve_layer_names(&names).unwrap();
+    let ids = result.layers.iter().map(|x| (x.id, x.sub_id)).collect::<Vec<_>>();
+    assert_eq!(ids, vec![(2, 0), (6, 0), (6, 1)]);
+    let images = result.multi_frame_images.iter()
+        .map(|x| (x.layer, x.sublayer))
+        .collect::<Vec<_>>();
+    assert_eq!(images, vec![(6, 1), (2, 0)]);
+    // Index-based output is unchanged
+    let json = serde_json::to_string(&result.to_json().unwrap()).unwrap();
+    assert!(json.contains("\"id\":2"));
+}
+
+#[test]
+fn validation_errors() {
+    let parse = |text: &str| {
+        parse_from_reader(&mut text.as_bytes()).err().map(|e| format!("{:#}", e))
+    };
+    let base = |rest: &str| format!(r#"{{
+        "offset_x": -4,
+        "offset_y": 0,
+        "layers": [[0, "000_diffuse"], [1, "000_bright"], [2, "000_teamcolor"], [3, "000_e"]],
+        "frame_types": [{{"first_frame": 0, "last_frame": 1, "frame_type": 0}}],
+        {}
+    }}"#, rest);
+    let image = |layer: &str, frame_width: &str| base(&format!(r#"
+        "frame_count": 2,
+        "multi_frame_images": [{{
+            "layer": {}, "first_frame": 0, "frame_count": 2, "path": "a.png",
+            "frame_width": {}, "frame_height": 8
+        }}]"#, layer, frame_width));
+    assert!(parse(&image("3", "8")).is_none());
+    let err = parse(&base(r#""per_frame_types": []"#)).unwrap();
+    assert!(err.contains("'frame_count'"), "{}", err);
+    let err = parse(&base(r#""frame_count": "2""#)).unwrap();
+    assert!(err.contains("'frame_count' must be a non-negative integer"), "{}", err);
+    let err = parse(&image("9", "8")).unwrap();
+    assert!(err.contains("layer 9"), "{}", err);
+    assert!(err.contains("only defines 4 layers"), "{}", err);
+    let err = parse(&image("0", "-8")).unwrap();
+    assert!(err.contains("Image 0"), "{}", err);
+    assert!(err.contains("'frame_width'"), "{}", err);
+    let err = parse(&base(r#""frame_count": 1"#)).unwrap();
+    assert!(err.contains("Frame type range 0"), "{}", err);
+}
@@ -63,9 +63,17 @@ pub fn grp_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicatio
     let window = gtk::Window::new(gtk::WindowType::Toplevel);
 
     let grp_select = Rc::new(
-        select_dir::SelectFile::new(&window, "import_grp", "StarCraft GRP sprite", "*.grp")
+        select_dir::SelectFile::new(
+            &window,
+            "import_grp",
+            "StarCraft GRP sprite or GIF",
+            "*.grp;*.gif",
+        )
     );
     let grp_section = label_section("Input GRP", grp_select.widget());
+    grp_section.set_tooltip_text(Some("\
+        Animated GIFs can be imported as well, each GIF frame becoming a frame of the sprite.\n\
+        Palette and teamcolor options are not used with GIFs."));
 
     static PALETTES: &[(Option<Palette>, &str)] = &[
         (Some(palette::BADLANDS), "Regular (badlands.wpe)"),
@@ -161,6 +169,7 @@ pub fn grp_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicatio
             }
         };
         let grp_path = grp_select2.text();
+        let is_gif = grp_path.to_ascii_lowercase().ends_with(".gif");
         let grp = match std::fs::read(&grp_path) {
             Ok(o) => o,
             Err(e) => {
@@ -216,6 +225,17 @@ pub fn grp_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicatio
                 let send2 = send.clone();
                 let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
                     let mut files = files_arc.lock();
+                    if is_gif {
+                        let frames = frame_import::decode_gif(&grp)?;
+                        frame_import::import_gif_to_anim(
+                            &mut files,
+                            tex_id.0,
+                            &frames,
+                            format,
+                            |step| send2.send(Progress::Progress(step)).unwrap(),
+                        )?;
+                        return Ok(frames.len() as u32);
+                    }
                     frame_import::import_grp_to_anim(
                         &mut files,
                         tex_id.0,
@@ -224,15 +244,30 @@ pub fn grp_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicatio
                         format,
                         use_teamcolor,
                         |step| send2.send(Progress::Progress(step)).unwrap(),
-                    )
+                    )?;
+                    Ok(frame_count)
                 })).unwrap_or_else(|e| Err(error_from_panic(e)));
-                let _ = send.send(Progress::Done(result.map(|()| frame_count)));
+                let _ = send.send(Progress::Done(result));
             });
         } else {
             std::thread::spawn(move || {
                 let send2 = send.clone();
                 let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
                     let mut files = files_arc.lock();
+                    if is_gif {
+                        let format = format
+                            .ok_or_else(|| anyhow!("GIFs cannot be imported as paletted"))?;
+                        let frames = frame_import::decode_gif(&grp)?;
+                        frame_import::import_gif_to_ddsgrp(
+                            &mut files,
+                            tex_id.0,
+                            &frames,
+                            format,
+                            grp_scale,
+                            |step| send2.send(Progress::Progress(step)).unwrap(),
+                        )?;
+                        return Ok(frames.len() as u32);
+                    }
                     frame_import::import_grp_to_ddsgrp(
                         &mut files,
                         tex_id.0,
@@ -241,9 +276,10 @@ pub fn grp_import_dialog(sprite_info: &Arc<SpriteInfo>, parent: &gtk::Applicatio
                         format,
                         grp_scale,
                         |step| send2.send(Progress::Progress(step)).unwrap(),
-                    )
+                    )?;
+                    Ok(frame_count)
                 })).unwrap_or_else(|e| Err(error_from_panic(e)));
-                let _ = send.send(Progress::Done(result.map(|()| frame_count)));
+                let _ = send.send(Progress::Done(result));
             });
         }
         let rest_of_ui = rest_of_ui2.clone();
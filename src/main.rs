@@ -8,11 +8,13 @@ mod anim;
 mod anim_lit;
 mod anim_encoder;
 mod arc_error;
+mod cli;
 mod combo_box_enum;
 mod ddsgrp;
 mod default_grp_sizes;
 mod edit_entry_count;
 mod frame_export;
+mod frame_copy_dialog;
 mod frame_export_dialog;
 mod frame_import;
 mod frame_import_dialog;
@@ -29,12 +31,17 @@ mod render;
 mod render_settings;
 mod select_dir;
 mod shaders;
+mod sprite_notes;
+mod texture_cache;
+mod texture_repack;
+mod thumbnails;
 mod util;
 mod widget_lighting;
 #[allow(dead_code)] mod ui_helpers;
 
 use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc};
@@ -47,10 +54,15 @@ use gtk::prelude::*;
 use anyhow::{Context, Error};
 use glium::texture::{Texture1d, Texture2d};
 
+use crate::combo_box_enum::ComboBoxEnum;
 use crate::files::SpriteFiles;
 use crate::int_entry::{IntEntry, IntSize};
 use crate::recurse_checked_mutex::Mutex;
-use crate::render::{Color, Rect, RenderState, TextureId};
+use crate::render::{
+    Background, Channel, Color, LineStyle, Rect, RenderState, TextureId, WindowToPixel,
+};
+use crate::thumbnails::Thumbnails;
+use crate::ui_helpers::{apply_ui_scale, scaled};
 
 fn init_log() -> Result<(), fern::InitError> {
     if cfg!(debug_assertions) {
@@ -109,6 +121,10 @@ fn main() {
         init_panic_handler();
     }
     let _ = init_log();
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
     let name = format!("animosity.pid_{}", std::process::id());
     let app = gtk::Application::new(Some(&*name), gio::ApplicationFlags::HANDLES_COMMAND_LINE);
     app.connect_startup(|app| {
@@ -142,6 +158,8 @@ struct Ui {
 thread_local! {
     static UI: RefCell<Option<Rc<Ui>>> = RefCell::new(None);
     static CSS: gtk::CssProvider = init_css_provider();
+    /// "Open recent" submenu, rebuilt whenever a file is opened.
+    static RECENT_MENU: gio::Menu = gio::Menu::new();
     static STATE: RefCell<State> = RefCell::new(State {
         files: Arc::new(Mutex::new(files::Files::empty())),
     });
@@ -168,6 +186,8 @@ impl Ui {
             };
             self.list.list.push(&name);
         }
+        self.list.thumbnails.reset(files.sprites().len());
+        self.info.load_notes(files.root_path());
         self.list.list.columns_autosize();
         self.main_window.set_title(&title(files.root_path(), false));
     }
@@ -193,7 +213,10 @@ struct ScrolledList {
 
 impl ScrolledList {
     fn new() -> ScrolledList {
-        let store = gtk::ListStore::new(&[glib::Type::STRING]);
+        let store = gtk::ListStore::new(&[
+            glib::Type::STRING,
+            gtk::gdk_pixbuf::Pixbuf::static_type(),
+        ]);
         let list = gtk::TreeView::with_model(&store);
         let col = gtk::TreeViewColumn::new();
         let renderer = gtk::CellRendererText::new();
@@ -217,6 +240,15 @@ impl ScrolledList {
         self.store.clear();
     }
 
+    /// Shows the pixbufs of column 1 before the row text.
+    fn show_thumbnails(&self) {
+        if let Some(col) = self.list.column(0) {
+            let renderer = gtk::CellRendererPixbuf::new();
+            CellLayoutExt::pack_start(&col, &renderer, false);
+            TreeViewColumnExt::add_attribute(&col, &renderer, "pixbuf", 1);
+        }
+    }
+
     fn select(&self, index: usize) {
         let path = gtk::TreePath::from_indicesv(&[index as i32]);
         let none: Option<&gtk::TreeViewColumn> = None;
@@ -231,16 +263,51 @@ impl ScrolledList {
         let iter = self.store.append();
         self.store.set_value(&iter, 0, &value.to_value());
     }
+
+    /// Appends " (error)" to the row text, if it wasn't already there.
+    fn mark_error(&self, index: usize) {
+        let path = gtk::TreePath::from_indicesv(&[index as i32]);
+        let iter = match self.store.iter(&path) {
+            Some(s) => s,
+            None => return,
+        };
+        let text = self.store.value(&iter, 0).get::<String>().unwrap_or_default();
+        if !text.ends_with(" (error)") {
+            self.store.set_value(&iter, 0, &format!("{} (error)", text).to_value());
+        }
+    }
+}
+
+/// Marks sprite in the sprite list as failing to open.
+fn mark_sprite_error(index: usize) {
+    UI.with(|x| {
+        if let Some(ui) = x.borrow().as_ref() {
+            ui.list.list.mark_error(index);
+        }
+    });
 }
 
 struct SpriteList {
     list: ScrolledList,
+    thumbnails: Rc<Thumbnails>,
 }
 
 impl SpriteList {
     fn new(linked_info: Arc<SpriteInfo>) -> SpriteList {
         let list = ScrolledList::new();
-        list.root.set_min_content_width(80);
+        list.root.set_min_content_width(scaled(80));
+        list.show_thumbnails();
+        let thumbnails = Thumbnails::new(linked_info.files.clone(), list.store.clone(), 1);
+
+        let thumbs = thumbnails.clone();
+        list.list.connect_draw(move |s, _| {
+            if let Some((start, end)) = s.visible_range() {
+                let start = start.indices().get(0).cloned().unwrap_or(0);
+                let end = end.indices().get(0).cloned().unwrap_or(0);
+                thumbs.request(start as usize, end as usize);
+            }
+            Inhibit(false)
+        });
 
         let info = linked_info.clone();
         list.list.connect_cursor_changed(move |s| {
@@ -253,6 +320,7 @@ impl SpriteList {
         });
         SpriteList {
             list,
+            thumbnails,
         }
     }
 
@@ -270,6 +338,8 @@ struct SpriteValues {
     height: Arc<IntEntry>,
     texture_dimensions: gtk::Label,
     frame_count_label: gtk::Label,
+    grp_unknown_label: gtk::Label,
+    layer_info_label: gtk::Label,
     rel_type: Arc<IntEntry>,
     rel_image: Arc<IntEntry>,
 }
@@ -290,6 +360,14 @@ impl SpriteValues {
         let texture_dimensions = gtk::Label::new(Some("Texture size: 0x0"));
         texture_dimensions.set_width_chars(20);
         let frame_count_label = gtk::Label::new(Some("0 frames"));
+        let grp_unknown_label = gtk::Label::new(None);
+        grp_unknown_label.set_tooltip_text(Some("Unknown value in the ddsgrp frame header"));
+        grp_unknown_label.set_no_show_all(true);
+        let layer_info_label = gtk::Label::new(None);
+        layer_info_label.set_xalign(0.0);
+        layer_info_label.set_selectable(true);
+        // Also shows why the sprite couldn't be opened, which can be long.
+        layer_info_label.set_line_wrap(true);
         let unk3_label = gtk::Label::new(Some("Dimensions"));
         let unk3_bx = gtk::Box::new(gtk::Orientation::Horizontal, 0);
         let width = IntEntry::new(IntSize::Int16);
@@ -333,6 +411,8 @@ impl SpriteValues {
         bx.pack_start(ref_index.widget(), false, false, 0);
         bx.pack_start(&texture_dimensions, false, false, 0);
         bx.pack_start(&frame_count_label, false, false, 0);
+        bx.pack_start(&grp_unknown_label, false, false, 0);
+        bx.pack_start(&label_section("Layer textures", &layer_info_label), false, false, 0);
         bx.pack_start(&unk3_label, false, false, 0);
         unk3_bx.pack_start(width.widget(), true, true, 0);
         unk3_bx.pack_start(height.widget(), true, true, 0);
@@ -346,6 +426,8 @@ impl SpriteValues {
             height,
             texture_dimensions,
             frame_count_label,
+            grp_unknown_label,
+            layer_info_label,
             rel_type,
             rel_image,
         }
@@ -458,6 +540,23 @@ impl SpriteValues {
                 }
             });
         }
+        let l = self.layer_info_label.clone();
+        if let Some(a) = lookup_action(sprite_actions, "layer_info") {
+            a.connect_activate(move |_, param| {
+                if let Some(text) = param.as_ref().and_then(|x| x.str()) {
+                    l.set_text(text);
+                }
+            });
+        }
+        let l = self.grp_unknown_label.clone();
+        if let Some(a) = lookup_action(sprite_actions, "grp_frame_unknown") {
+            a.connect_activate(move |_, param| {
+                if let Some(text) = param.as_ref().and_then(|x| x.str()) {
+                    l.set_text(&format!("Frame unknown: {}", text));
+                    l.set_visible(!text.is_empty());
+                }
+            });
+        }
     }
 
     fn widget(&self) -> gtk::Widget {
@@ -468,6 +567,7 @@ impl SpriteValues {
 struct SpriteSelector {
     bx: gtk::Box,
     list: ScrolledList,
+    type_buttons: [gtk::RadioButton; 3],
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
@@ -484,8 +584,8 @@ impl SpriteSelector {
         let hd = gtk::RadioButton::with_label_from_widget(&sd, "HD");
         let hd2 = gtk::RadioButton::with_label_from_widget(&sd, "HD2");
         let list = ScrolledList::new();
-        list.root.set_min_content_height(200);
-        list.root.set_min_content_width(80);
+        list.root.set_min_content_height(scaled(200));
+        list.root.set_min_content_width(scaled(80));
         list.list.connect_cursor_changed(move |s| {
             let index = s.selection().selected()
                 .and_then(|(model, iter)| model.path(&iter))
@@ -505,9 +605,20 @@ impl SpriteSelector {
         SpriteSelector {
             bx,
             list,
+            type_buttons: [sd, hd, hd2],
         }
     }
 
+    /// Activates the radio button of `ty`, selecting it if the action is enabled.
+    fn select_type(&self, ty: SpriteType) {
+        let index = match ty {
+            SpriteType::Sd => 0,
+            SpriteType::Hd => 1,
+            SpriteType::Hd2 => 2,
+        };
+        self.type_buttons[index].set_active(true);
+    }
+
     fn widget(&self) -> gtk::Widget {
         self.bx.clone().upcast()
     }
@@ -527,6 +638,129 @@ pub struct SpriteInfo {
     lighting: Arc<widget_lighting::SpriteLighting>,
     lighting_expander: gtk::Expander,
     render_settings: Rc<render_settings::RenderSettingsWidget>,
+    /// Frame copied with "Copy frame", if any.
+    copied_frame: RefCell<Option<texture_repack::CopiedFrame>>,
+    /// Sprite that was selected before the current one.
+    previous_sprite: Cell<Option<usize>>,
+    play_button: gtk::ToggleButton,
+    frame_rate: gtk::SpinButton,
+    playback_timer: RefCell<Option<glib::SourceId>>,
+    /// Frame shown while the animation is played.
+    current_frame: AtomicUsize,
+    view_zoom: Cell<f32>,
+    /// Offset of the preview in window pixels.
+    view_pan: Cell<(f32, f32)>,
+    /// Last mouse position while dragging the preview.
+    drag_position: Cell<Option<(f64, f64)>>,
+    /// Window to texture transform of the last rendered preview.
+    preview_transform: Cell<Option<WindowToPixel>>,
+    /// Frames selected by clicking on the preview, sorted.
+    selected_frames: RefCell<Vec<usize>>,
+    /// X and Y offset of the selected frame, editable when a single frame is selected.
+    offset_entries: [gtk::SpinButton; 2],
+    /// Set while `offset_entries` are being updated to match the selected frame.
+    updating_offset_entries: Cell<bool>,
+    /// Shows the pixel under the cursor.
+    pixel_label: gtk::Label,
+    /// Decoded texture used for `pixel_label`, kept to avoid decoding it on every
+    /// mouse move.
+    hover_texture: RefCell<Option<(TextureId, anim::RawTexture)>>,
+    /// Stored mip level of the texture that is shown.
+    mip_level: gtk::SpinButton,
+    background: Cell<Background>,
+    /// Channel of the sprite shown in the preview, not saved across sessions.
+    channel: Cell<Channel>,
+    /// Whether texture is a monochrome layer, cached as checking has to read the texture.
+    monochrome_textures: RefCell<Vec<(TextureId, bool)>>,
+    /// Layers that failed to decode, kept so that they aren't decoded again on every draw.
+    layer_errors: RefCell<Vec<(TextureId, String)>>,
+    /// Problems with layers other than the selected one, drawn over the preview.
+    render_warnings: RefCell<Vec<String>>,
+    /// Why the selected sprite couldn't be opened, shown in place of the layer info
+    /// by the next `changed_ty`.
+    open_error: RefCell<Option<String>>,
+    /// Toggles drawing of the texture and frame bounds, not saved across sessions.
+    frame_boxes_check: gtk::CheckButton,
+    notes: RefCell<sprite_notes::SpriteNotes>,
+    notes_view: gtk::TextView,
+    /// Sprite whose notes are shown in `notes_view`.
+    notes_sprite: Cell<Option<usize>>,
+    /// Set while `notes_view` is being filled with the selected sprite's notes.
+    loading_notes: Cell<bool>,
+}
+
+/// Format and size of each layer's texture, one layer per line.
+fn layer_texture_info(file: &files::File<'_>) -> String {
+    let formats = file.texture_formats();
+    file.layer_names().iter().enumerate().map(|(i, name)| {
+        let format = match formats.get(i) {
+            Some(Ok(Some(format))) => match format {
+                anim::TextureFormat::Dxt1 => "DXT1",
+                anim::TextureFormat::Dxt5 => "DXT5",
+                anim::TextureFormat::Rgba => "RGBA",
+                anim::TextureFormat::Monochrome => "Mono",
+            },
+            Some(Err(_)) => "Invalid",
+            _ => return format!("{}: none", name),
+        };
+        match file.texture_size(i) {
+            Some(size) => format!("{}: {} {}x{}", name, format, size.width, size.height),
+            None => format!("{}: none", name),
+        }
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// Scales `image` to `width`x`height` without any filtering.
+fn upscale_nearest(image: &anim::RawTexture, width: u32, height: u32) -> anim::RawTexture {
+    let bpp = if image.is_paletted { 1 } else { 4 };
+    let mut data = Vec::with_capacity(width as usize * height as usize * bpp);
+    for y in 0..height {
+        let src_y = (y as u64 * image.height as u64 / height.max(1) as u64) as usize;
+        for x in 0..width {
+            let src_x = (x as u64 * image.width as u64 / width.max(1) as u64) as usize;
+            let pos = (src_y * image.width as usize + src_x) * bpp;
+            match image.data.get(pos..pos + bpp) {
+                Some(pixel) => data.extend_from_slice(pixel),
+                None => data.extend((0..bpp).map(|_| 0)),
+            }
+        }
+    }
+    anim::RawTexture {
+        data,
+        width,
+        height,
+        is_paletted: image.is_paletted,
+    }
+}
+
+/// Draws `lines` in the bottom left corner of the draw area, over the rendered sprite.
+fn draw_error_badge(cairo: &cairo::Context, height: f64, lines: &[String]) {
+    cairo.set_font_size(12.0);
+    for (i, line) in lines.iter().rev().enumerate() {
+        let y = height - 16.0 * (i + 1) as f64;
+        if let Ok(extents) = cairo.text_extents(line) {
+            cairo.set_source_rgba(0.0, 0.0, 0.0, 0.75);
+            cairo.rectangle(0.0, y, extents.x_advance() + 8.0, 16.0);
+            let _ = cairo.fill();
+        }
+        cairo.set_source_rgb(1.0, 0.4, 0.4);
+        cairo.move_to(4.0, y + 12.0);
+        if let Err(e) = cairo.show_text(line) {
+            println!("Cairo error {}", e);
+        }
+    }
+}
+
+/// Draws (error) text on the draw area, one line at a time.
+fn draw_text_lines(cairo: &cairo::Context, text: &str) {
+    cairo.set_source_rgb(0.0, 0.0, 0.0);
+    cairo.set_font_size(15.0);
+    for (i, line) in text.lines().enumerate() {
+        cairo.move_to(0.0, 20.0 + 20.0 * i as f64);
+        if let Err(e) = cairo.show_text(&line) {
+            println!("Cairo error {}", e);
+        }
+    }
 }
 
 fn lookup_action<G: IsA<gio::ActionMap>>(group: &G, name: &str) -> Option<gio::SimpleAction> {
@@ -546,10 +780,101 @@ impl SpriteInfo {
         let selector = SpriteSelector::new(sprite_actions.clone().upcast());
         let values = SpriteValues::new();
         let draw_area = gtk::DrawingArea::new();
+        let play_button = gtk::ToggleButton::with_label("Play");
+        let frame_rate = gtk::SpinButton::with_range(1.0, 60.0, 1.0);
+        frame_rate.set_value(15.0);
+        let reset_view_button = gtk::Button::with_label("Reset view");
+        reset_view_button.set_tooltip_text(Some("Reset zoom and position (Home)"));
+        let go_to_frame = gtk::Entry::new();
+        go_to_frame.set_width_chars(6);
+        go_to_frame.set_placeholder_text(Some("Frame"));
+        go_to_frame.set_tooltip_text(Some("Type a frame number and press Enter to select it"));
+        let pixel_label = gtk::Label::new(None);
+        let backgrounds = [
+            (Background::Black, "Black", "black"),
+            (Background::Magenta, "Magenta", "magenta"),
+            (Background::Checkerboard, "Checkerboard", "checkerboard"),
+        ];
+        let background = select_dir::read_config_entry("preview_background")
+            .and_then(|name| backgrounds.iter().find(|x| x.2 == name).map(|x| x.0))
+            .unwrap_or(Background::Black);
+        let black = gtk::RadioButton::with_label(backgrounds[0].1);
+        let magenta = gtk::RadioButton::with_label_from_widget(&black, backgrounds[1].1);
+        let checkerboard = gtk::RadioButton::with_label_from_widget(&black, backgrounds[2].1);
+        let background_buttons = [black, magenta, checkerboard];
+        let background_bx = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        background_bx.style_context().add_class("linked");
+        for (button, &(bg, _, _)) in background_buttons.iter().zip(backgrounds.iter()) {
+            // Draw as toggle buttons instead of radio circles
+            button.set_mode(false);
+            button.set_active(bg == background);
+            background_bx.pack_start(button, false, false, 0);
+        }
+        background_bx.set_tooltip_text(Some("Background shown behind the sprite"));
+        let mip_level = gtk::SpinButton::with_range(0.0, 0.0, 1.0);
+        mip_level.set_sensitive(false);
+        mip_level.set_tooltip_text(Some("Mip level stored in the texture to display"));
+        static CHANNELS: &[(Channel, &str)] = &[
+            (Channel::Rgba, "RGBA"),
+            (Channel::Alpha, "Alpha"),
+            (Channel::Red, "Red"),
+            (Channel::Green, "Green"),
+            (Channel::Blue, "Blue"),
+        ];
+        let channel_combo = ComboBoxEnum::new(CHANNELS);
+        channel_combo.set_active(&Channel::Rgba);
+        channel_combo.widget().set_tooltip_text(Some("Channel of the sprite to show"));
+        let frame_boxes_check = gtk::CheckButton::with_label("Frame bounds");
+        frame_boxes_check.set_active(true);
+        frame_boxes_check.set_tooltip_text(Some("Draw texture and frame bounds (B)"));
+        let offset_entries = [
+            gtk::SpinButton::with_range(i16::MIN as f64, i16::MAX as f64, 1.0),
+            gtk::SpinButton::with_range(i16::MIN as f64, i16::MAX as f64, 1.0),
+        ];
+        for entry in &offset_entries {
+            entry.set_sensitive(false);
+            entry.set_tooltip_text(Some(
+                "Offset of the selected frame, can also be moved with Alt + arrow keys"
+            ));
+        }
+        let playback_bx = box_horizontal(&[
+            &play_button,
+            &gtk::Label::new(Some("Frames per second")),
+            &frame_rate,
+            &reset_view_button,
+            &gtk::Label::new(Some("Go to")),
+            &go_to_frame,
+            &gtk::Label::new(Some("Mip")),
+            &mip_level,
+            &gtk::Label::new(Some("Offset")),
+            &offset_entries[0],
+            &offset_entries[1],
+            &frame_boxes_check,
+            channel_combo.widget(),
+            &background_bx,
+            &pixel_label,
+        ]);
+        let draw_bx = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        draw_bx.pack_start(&draw_area, true, true, 0);
+        draw_bx.pack_start(&playback_bx, false, false, 0);
+        let notes_view = gtk::TextView::new();
+        notes_view.set_wrap_mode(gtk::WrapMode::WordChar);
+        notes_view.set_sensitive(false);
+        notes_view.set_tooltip_text(Some(&format!(
+            "Notes about this sprite, saved to {} in the root directory",
+            sprite_notes::NOTES_FILENAME,
+        )));
+        let none: Option<&gtk::Adjustment> = None;
+        let notes_scroll = gtk::ScrolledWindow::new(none, none);
+        notes_scroll.set_min_content_height(scaled(60));
+        notes_scroll.add(&notes_view);
+        let notes_frame = gtk::Frame::new(Some("Notes"));
+        notes_frame.add(&notes_scroll);
         data_bx.pack_start(&selector.widget(), false, false, 0);
         data_bx.pack_start(&values.widget(), false, false, 0);
+        data_bx.pack_start(&notes_frame, true, true, 0);
         sprite_bx.pack_start(&data_bx, false, false, 0);
-        sprite_bx.pack_start(&draw_area, true, true, 0);
+        sprite_bx.pack_start(&draw_bx, true, true, 0);
         let files = gtk::TextView::new();
         let none: Option<&gtk::TextTagTable> = None;
         let file_list = gtk::TextBuffer::new(none);
@@ -617,10 +942,233 @@ impl SpriteInfo {
             lighting,
             lighting_expander: expander,
             render_settings,
+            copied_frame: RefCell::new(None),
+            previous_sprite: Cell::new(None),
+            play_button: play_button.clone(),
+            frame_rate: frame_rate.clone(),
+            playback_timer: RefCell::new(None),
+            current_frame: AtomicUsize::new(0),
+            view_zoom: Cell::new(1.0),
+            view_pan: Cell::new((0.0, 0.0)),
+            drag_position: Cell::new(None),
+            preview_transform: Cell::new(None),
+            selected_frames: RefCell::new(Vec::new()),
+            offset_entries: offset_entries.clone(),
+            updating_offset_entries: Cell::new(false),
+            pixel_label,
+            hover_texture: RefCell::new(None),
+            mip_level: mip_level.clone(),
+            background: Cell::new(background),
+            channel: Cell::new(Channel::Rgba),
+            monochrome_textures: RefCell::new(Vec::new()),
+            layer_errors: RefCell::new(Vec::new()),
+            open_error: RefCell::new(None),
+            render_warnings: RefCell::new(Vec::new()),
+            frame_boxes_check: frame_boxes_check.clone(),
+            notes: RefCell::new(sprite_notes::SpriteNotes::empty()),
+            notes_view: notes_view.clone(),
+            notes_sprite: Cell::new(None),
+            loading_notes: Cell::new(false),
         });
         SpriteInfo::create_sprite_actions(&result, &result.sprite_actions.clone().upcast());
         values.connect_actions(&result.sprite_actions);
 
+        let this = result.clone();
+        play_button.connect_toggled(move |button| {
+            this.restart_playback_timer();
+            button.set_label(if button.is_active() { "Pause" } else { "Play" });
+            this.draw_area.queue_draw();
+        });
+        let this = result.clone();
+        frame_rate.connect_value_changed(move |_| {
+            this.restart_playback_timer();
+        });
+        for (button, &(bg, _, name)) in background_buttons.iter().zip(backgrounds.iter()) {
+            let this = result.clone();
+            button.connect_toggled(move |button| {
+                if button.is_active() {
+                    this.background.set(bg);
+                    select_dir::set_config_entry("preview_background", name);
+                    this.draw_area.queue_draw();
+                }
+            });
+        }
+        let this = result.clone();
+        mip_level.connect_value_changed(move |_| {
+            this.draw_clear_all();
+            this.draw_area.queue_draw();
+        });
+        if let Some(buffer) = notes_view.buffer() {
+            let this = result.clone();
+            buffer.connect_changed(move |buffer| {
+                if this.loading_notes.get() {
+                    return;
+                }
+                if let Some(sprite) = this.notes_sprite.get() {
+                    let (start, end) = buffer.bounds();
+                    let text = buffer.text(&start, &end, false);
+                    this.notes.borrow_mut().set(sprite, &text);
+                }
+            });
+        }
+        let this = result.clone();
+        notes_view.connect_focus_out_event(move |_, _| {
+            this.save_notes();
+            Inhibit(false)
+        });
+        let this = result.clone();
+        channel_combo.connect_changed(move |channel| {
+            this.channel.set(channel.unwrap_or(Channel::Rgba));
+            this.draw_area.queue_draw();
+        });
+        let this = result.clone();
+        frame_boxes_check.connect_toggled(move |_| {
+            this.draw_area.queue_draw();
+        });
+        for entry in &offset_entries {
+            let this = result.clone();
+            entry.connect_value_changed(move |_| {
+                if this.updating_offset_entries.get() {
+                    return;
+                }
+                let x_off = this.offset_entries[0].value_as_int() as i16;
+                let y_off = this.offset_entries[1].value_as_int() as i16;
+                set_selected_frame_offset(&this, &ui().main_window, x_off, y_off);
+            });
+        }
+        let this = result.clone();
+        reset_view_button.connect_clicked(move |_| {
+            this.reset_view();
+        });
+        let this = result.clone();
+        go_to_frame.connect_activate(move |entry| {
+            let text = entry.text();
+            let result = text.trim().parse::<usize>()
+                .map_err(|_| anyhow!("'{}' is not a frame number", text))
+                .and_then(|frame| this.go_to_frame(frame));
+            if let Err(e) = result {
+                error_msg_box(&ui().main_window, format!("{}", e));
+            }
+        });
+
+        draw_area.set_can_focus(true);
+        draw_area.add_events(
+            gdk::EventMask::SCROLL_MASK |
+                gdk::EventMask::BUTTON_PRESS_MASK |
+                gdk::EventMask::BUTTON_RELEASE_MASK |
+                gdk::EventMask::POINTER_MOTION_MASK |
+                gdk::EventMask::LEAVE_NOTIFY_MASK |
+                gdk::EventMask::KEY_PRESS_MASK
+        );
+        let this = result.clone();
+        draw_area.connect_scroll_event(move |_, event| {
+            let zoom = this.view_zoom.get();
+            let zoom = match event.direction() {
+                gdk::ScrollDirection::Up => zoom * 1.25,
+                gdk::ScrollDirection::Down => zoom / 1.25,
+                _ => return Inhibit(false),
+            };
+            this.view_zoom.set(zoom.max(0.125).min(32.0));
+            this.draw_area.queue_draw();
+            Inhibit(true)
+        });
+        let this = result.clone();
+        draw_area.connect_button_press_event(move |s, event| {
+            s.grab_focus();
+            if event.button() == 1 {
+                this.drag_position.set(Some(event.position()));
+                let (x, y) = event.position();
+                let extend = event.state().contains(gdk::ModifierType::SHIFT_MASK);
+                this.click_frame(this.frame_at(x, y), extend);
+            }
+            if event.button() == 3 {
+                let (x, y) = event.position();
+                if let Some(frame) = this.frame_at(x, y) {
+                    let menu = this.clone().make_frame_rclick_menu(frame);
+                    menu.set_attach_widget(Some(s));
+                    menu.popup_at_pointer(Some(event));
+                    return Inhibit(true);
+                }
+            }
+            Inhibit(false)
+        });
+        let this = result.clone();
+        draw_area.connect_button_release_event(move |_, event| {
+            if event.button() == 1 {
+                this.drag_position.set(None);
+            }
+            Inhibit(false)
+        });
+        let this = result.clone();
+        draw_area.connect_motion_notify_event(move |_, event| {
+            if let Some((old_x, old_y)) = this.drag_position.get() {
+                let (x, y) = event.position();
+                let (pan_x, pan_y) = this.view_pan.get();
+                this.view_pan.set((pan_x + (x - old_x) as f32, pan_y + (y - old_y) as f32));
+                this.drag_position.set(Some((x, y)));
+                this.draw_area.queue_draw();
+            }
+            let (x, y) = event.position();
+            let text = this.pixel_at(x, y).unwrap_or_default();
+            this.pixel_label.set_text(&text);
+            Inhibit(false)
+        });
+        let this = result.clone();
+        draw_area.connect_leave_notify_event(move |_, _| {
+            this.pixel_label.set_text("");
+            Inhibit(false)
+        });
+        let this = result.clone();
+        draw_area.connect_key_press_event(move |_, event| {
+            if event.keyval() == gdk::keys::constants::Home {
+                this.reset_view();
+                return Inhibit(true);
+            }
+            if event.keyval() == gdk::keys::constants::b {
+                let check = &this.frame_boxes_check;
+                check.set_active(!check.is_active());
+                return Inhibit(true);
+            }
+            if event.keyval() == gdk::keys::constants::Delete {
+                if !this.selected_frames.borrow().is_empty() {
+                    delete_selected_frames(&this, &ui().main_window);
+                }
+                return Inhibit(true);
+            }
+            if event.state().contains(gdk::ModifierType::MOD1_MASK) {
+                // Alt + arrows nudge the selected frame, Shift moves 10 pixels at once
+                let step = match event.state().contains(gdk::ModifierType::SHIFT_MASK) {
+                    true => 10,
+                    false => 1,
+                };
+                let nudges = [
+                    (gdk::keys::constants::Left, (-step, 0)),
+                    (gdk::keys::constants::Right, (step, 0)),
+                    (gdk::keys::constants::Up, (0, -step)),
+                    (gdk::keys::constants::Down, (0, step)),
+                ];
+                if let Some(&(_, (x, y))) = nudges.iter().find(|x| x.0 == event.keyval()) {
+                    nudge_selected_frame(&this, &ui().main_window, x, y);
+                    return Inhibit(true);
+                }
+            }
+            // Only handled while the preview has focus, so that arrow keys still
+            // work normally in the entries.
+            let steps = [
+                (gdk::keys::constants::Left, "previous_layer"),
+                (gdk::keys::constants::Right, "next_layer"),
+                (gdk::keys::constants::Up, "previous_frame"),
+                (gdk::keys::constants::Down, "next_frame"),
+            ];
+            match steps.iter().find(|x| x.0 == event.keyval()) {
+                Some(&(_, action)) => {
+                    this.sprite_actions.activate_action(action, None);
+                    Inhibit(true)
+                }
+                None => Inhibit(false),
+            }
+        });
+
         let this = result.clone();
         let gl: Rc<RefCell<Option<RenderState>>> = Rc::new(RefCell::new(None));
         draw_area.connect_draw(move |s, cairo| {
@@ -638,6 +1186,14 @@ impl SpriteInfo {
                     } else {
                         render_state.clear_cached(tex_id);
                     }
+                    let mut hover = this.hover_texture.borrow_mut();
+                    if hover.as_ref().map(|x| tex_id.0 == !0 || x.0 == tex_id) == Some(true) {
+                        *hover = None;
+                    }
+                    this.monochrome_textures.borrow_mut()
+                        .retain(|x| tex_id.0 != !0 && x.0 != tex_id);
+                    this.layer_errors.borrow_mut()
+                        .retain(|x| tex_id.0 != !0 && x.0 != tex_id);
                 }
             }
             render_state.resize_buf(rect.width() as u32, rect.height() as u32);
@@ -658,19 +1214,19 @@ impl SpriteInfo {
                         cairo.paint()
                     });
                     if let Err(e) = result {
-                        println!("Cairo error {}", e);
+                        error!("Couldn't create {}x{} cairo surface: {}", width, height, e);
+                        let text = format!(
+                            "Unable to display {}x{} image:\n{}", width, height, e,
+                        );
+                        draw_text_lines(cairo, &text);
+                    }
+                    let warnings = this.render_warnings.borrow();
+                    if !warnings.is_empty() {
+                        draw_error_badge(cairo, rect.height() as f64, &warnings);
                     }
                 }
                 Err(e) => {
-                    cairo.set_source_rgb(0.0, 0.0, 0.0);
-                    cairo.set_font_size(15.0);
-                    let text = format!("{:?}", e);
-                    for (i, line) in text.lines().enumerate() {
-                        cairo.move_to(0.0, 20.0 + 20.0 * i as f64);
-                        if let Err(e) = cairo.show_text(&line) {
-                            println!("Cairo error {}", e);
-                        }
-                    }
+                    draw_text_lines(cairo, &format!("{:?}", e));
                 }
             }
             Inhibit(true)
@@ -679,6 +1235,249 @@ impl SpriteInfo {
         result
     }
 
+    /// (Re)starts or stops the animation timer based on the play button and frame rate.
+    fn restart_playback_timer(self: &Arc<Self>) {
+        if let Some(timer) = self.playback_timer.borrow_mut().take() {
+            timer.remove();
+        }
+        if !self.play_button.is_active() {
+            return;
+        }
+        let fps = self.frame_rate.value().max(1.0);
+        let interval = std::time::Duration::from_secs_f64(1.0 / fps);
+        let this = self.clone();
+        let timer = glib::timeout_add_local(interval, move || {
+            this.advance_frame();
+            glib::Continue(true)
+        });
+        *self.playback_timer.borrow_mut() = Some(timer);
+    }
+
+    fn advance_frame(&self) {
+        let tex_id = self.tex_id();
+        let frame_count = {
+            let mut files = match self.files.try_lock() {
+                Ok(o) => o,
+                Err(_) => return,
+            };
+            match files.file(tex_id.0, tex_id.1) {
+                Ok(Some(file)) => file.frames().map(|x| x.len()).unwrap_or(0),
+                _ => 0,
+            }
+        };
+        let next = self.current_frame.load(Ordering::SeqCst) + 1;
+        let next = if next >= frame_count { 0 } else { next };
+        self.current_frame.store(next, Ordering::SeqCst);
+        self.draw_area.queue_draw();
+    }
+
+    /// Returns index of the frame under window position `x`, `y`, if any.
+    fn frame_at(&self, x: f64, y: f64) -> Option<usize> {
+        let (tex_x, tex_y) = self.preview_transform.get()?.map(x, y);
+        if tex_x < 0.0 || tex_y < 0.0 {
+            return None;
+        }
+        let (tex_x, tex_y) = (tex_x as u32, tex_y as u32);
+        let tex_id = self.tex_id();
+        let mut files = self.files.try_lock().ok()?;
+        let file = files.file(tex_id.0, tex_id.1).ok()??;
+        let div = file.texture_divisor();
+        let frames = file.frames()?;
+        let contains = |f: &anim::Frame| {
+            let left = f.tex_x as u32 / div;
+            let top = f.tex_y as u32 / div;
+            tex_x >= left && tex_x < left + f.width as u32 / div &&
+                tex_y >= top && tex_y < top + f.height as u32 / div
+        };
+        if self.play_button.is_active() {
+            let index = self.current_frame.load(Ordering::SeqCst);
+            return frames.get(index).filter(|f| contains(f)).map(|_| index);
+        }
+        frames.iter().position(contains)
+    }
+
+    /// Describes the texture pixel under window position `x`, `y`, `None` if outside
+    /// the texture.
+    fn pixel_at(&self, x: f64, y: f64) -> Option<String> {
+        let (tex_x, tex_y) = self.preview_transform.get()?.map(x, y);
+        if tex_x < 0.0 || tex_y < 0.0 {
+            return None;
+        }
+        let (tex_x, tex_y) = (tex_x as u32, tex_y as u32);
+        let tex_id = self.tex_id();
+        let mut hover = self.hover_texture.borrow_mut();
+        if hover.as_ref().map(|x| x.0) != Some(tex_id) {
+            let mut files = self.files.try_lock().ok()?;
+            let file = files.file(tex_id.0, tex_id.1).ok()??;
+            let texture = match file.texture(tex_id.2) {
+                Ok(o) => o,
+                Err(_) => return None,
+            };
+            *hover = Some((tex_id, texture));
+        }
+        let texture = &hover.as_ref()?.1;
+        if tex_x >= texture.width || tex_y >= texture.height {
+            return None;
+        }
+        let index = (tex_y * texture.width + tex_x) as usize;
+        if texture.is_paletted {
+            let value = texture.data.get(index)?;
+            Some(format!("{},{} = palette index {}", tex_x, tex_y, value))
+        } else {
+            let pixel = texture.data.get(index * 4..index * 4 + 4)?;
+            Some(format!(
+                "{},{} = ({}, {}, {}, {})", tex_x, tex_y, pixel[0], pixel[1], pixel[2], pixel[3],
+            ))
+        }
+    }
+
+    /// Selects `frame` and shows it if the animation is being played.
+    fn go_to_frame(&self, frame: usize) -> Result<(), Error> {
+        let tex_id = self.tex_id();
+        let frame_count = {
+            let mut files = self.files.try_lock()
+                .map_err(|_| anyhow!("Files are being used"))?;
+            let file = files.file(tex_id.0, tex_id.1)?
+                .ok_or_else(|| anyhow!("No sprite selected"))?;
+            file.frames().map(|x| x.len()).unwrap_or(0)
+        };
+        if frame >= frame_count {
+            return Err(match frame_count {
+                0 => anyhow!("Sprite has no frames"),
+                n => anyhow!("Frame {} is out of range, valid frames are 0..={}", frame, n - 1),
+            });
+        }
+        self.current_frame.store(frame, Ordering::SeqCst);
+        *self.selected_frames.borrow_mut() = vec![frame];
+        self.update_offset_entries();
+        self.draw_area.queue_draw();
+        Ok(())
+    }
+
+    /// Moves the selected layer by `delta`, wrapping around at either end.
+    fn step_layer(&self, delta: isize) {
+        let tex_id = self.tex_id();
+        let layer_count = {
+            let mut files = match self.files.try_lock() {
+                Ok(o) => o,
+                _ => return,
+            };
+            match files.file(tex_id.0, tex_id.1) {
+                Ok(Some(file)) => file.layer_names().len(),
+                _ => return,
+            }
+        };
+        if let Some(layer) = wrapping_step(tex_id.2, delta, layer_count) {
+            // Selecting in the list activates select_layer
+            self.selector.list.select(layer);
+        }
+    }
+
+    /// Moves the previewed frame by `delta`, wrapping around at either end.
+    fn step_frame(&self, delta: isize) {
+        let tex_id = self.tex_id();
+        let frame_count = {
+            let mut files = match self.files.try_lock() {
+                Ok(o) => o,
+                _ => return,
+            };
+            match files.file(tex_id.0, tex_id.1) {
+                Ok(Some(file)) => file.frames().map(|x| x.len()).unwrap_or(0),
+                _ => return,
+            }
+        };
+        let current = self.current_frame.load(Ordering::SeqCst);
+        if let Some(frame) = wrapping_step(current, delta, frame_count) {
+            if let Err(e) = self.go_to_frame(frame) {
+                warn!("Couldn't step to frame {}: {:?}", frame, e);
+            }
+        }
+    }
+
+    /// Selects `frame`, or with `extend` adds it to / removes it from the selection.
+    fn click_frame(&self, frame: Option<usize>, extend: bool) {
+        {
+            let mut selected = self.selected_frames.borrow_mut();
+            match (frame, extend) {
+                (Some(frame), true) => match selected.binary_search(&frame) {
+                    Ok(pos) => {
+                        selected.remove(pos);
+                    }
+                    Err(pos) => selected.insert(pos, frame),
+                },
+                (Some(frame), false) => *selected = vec![frame],
+                (None, true) => return,
+                (None, false) => selected.clear(),
+            }
+        }
+        self.update_offset_entries();
+        self.draw_area.queue_draw();
+    }
+
+    /// The selected frame if exactly one is selected.
+    fn single_selected_frame(&self) -> Option<usize> {
+        match self.selected_frames.borrow().as_slice() {
+            &[frame] => Some(frame),
+            _ => None,
+        }
+    }
+
+    /// Shows offsets of the selected frame in `offset_entries`, which can only be edited
+    /// while a single anim frame is selected.
+    fn update_offset_entries(&self) {
+        let offset = self.single_selected_frame().and_then(|frame| {
+            let tex_id = self.tex_id();
+            let mut files = self.files.try_lock().ok()?;
+            let file = files.file(tex_id.0, tex_id.1).ok()??;
+            if !file.is_anim() {
+                return None;
+            }
+            file.frames()?.get(frame).map(|f| (f.x_off, f.y_off))
+        });
+        self.updating_offset_entries.set(true);
+        let values = offset.map(|(x, y)| [x, y]).unwrap_or([0, 0]);
+        for (entry, &value) in self.offset_entries.iter().zip(values.iter()) {
+            entry.set_value(value as f64);
+            entry.set_sensitive(offset.is_some());
+        }
+        self.updating_offset_entries.set(false);
+    }
+
+    fn make_frame_rclick_menu(self: Arc<Self>, frame: usize) -> gtk::Menu {
+        let menu = gtk::Menu::new();
+        let item = gtk::MenuItem::with_label(&format!("Replace frame {}...", frame));
+        let this = self.clone();
+        item.connect_activate(move |_| {
+            replace_frame(&this, &ui().main_window, frame);
+        });
+        item.show();
+        menu.append(&item);
+        let item = gtk::MenuItem::with_label(&format!("Set frame type of frame {}...", frame));
+        let this = self.clone();
+        item.connect_activate(move |_| {
+            edit_frame_type(&this, &ui().main_window, frame);
+        });
+        item.show();
+        menu.append(&item);
+        let selected_count = self.selected_frames.borrow().len();
+        let item = gtk::MenuItem::with_label(&format!("Delete {} selected frames", selected_count));
+        item.connect_activate(move |_| {
+            delete_selected_frames(&self, &ui().main_window);
+        });
+        item.set_sensitive(selected_count != 0);
+        item.show();
+        menu.append(&item);
+        menu
+    }
+
+    /// Restores default zoom and position of the preview.
+    fn reset_view(&self) {
+        self.view_zoom.set(1.0);
+        self.view_pan.set((0.0, 0.0));
+        self.drag_position.set(None);
+        self.draw_area.queue_draw();
+    }
+
     fn draw_clear_all(&self) {
         self.draw_clear_requests.borrow_mut().push(TextureId(!0, SpriteType::Sd, !0));
     }
@@ -706,11 +1505,111 @@ impl SpriteInfo {
         cache_file: &mut files::File<'_>,
     ) -> Result<Rc<Texture2d>, Error> {
         let tex_id = self.tex_id();
-        render_state.cached_texture(tex_id, || {
-            let image = cache_file.texture(tex_id.2)
-                .with_context(|| format!("Failed to get texture {}", tex_id.2))?;
+        if let Some(error) = self.layer_error(tex_id) {
+            return Err(anyhow!("{}", error));
+        }
+        let grayscale = self.render_settings.settings().grayscale;
+        let mip_level = self.mip_level.value_as_int() as u32;
+        let result = render_state.cached_texture(tex_id, || {
+            let start_time = std::time::Instant::now();
+            let image = match mip_level {
+                0 => cache_file.texture(tex_id.2)
+                    .with_context(|| format!("Failed to get texture {}", tex_id.2))?,
+                level => {
+                    let mip = cache_file.texture_mip(tex_id.2, level).with_context(|| {
+                        format!("Failed to get mip level {} of texture {}", level, tex_id.2)
+                    })?;
+                    // Shown at full size so that the frame rectangles stay in place
+                    match cache_file.texture_size(tex_id.2) {
+                        Some(size) => upscale_nearest(&mip, size.width.into(), size.height.into()),
+                        None => mip,
+                    }
+                }
+            };
+            debug!(
+                "Decoded {}x{} texture {:?} in {:?}",
+                image.width, image.height, tex_id, start_time.elapsed(),
+            );
+            if grayscale && !image.is_paletted {
+                let monochrome = match cache_file.texture_formats().get(tex_id.2) {
+                    Some(Ok(Some(anim::TextureFormat::Monochrome))) => true,
+                    _ => false,
+                };
+                return Ok(render::luminance_texture(&image, monochrome));
+            }
             Ok(image)
-        })
+        });
+        if let Err(ref e) = result {
+            self.add_layer_error(tex_id, e);
+        }
+        result
+    }
+
+    /// Error from decoding `tex_id` earlier, if it failed.
+    fn layer_error(&self, tex_id: TextureId) -> Option<String> {
+        self.layer_errors.borrow().iter().find(|x| x.0 == tex_id).map(|x| x.1.clone())
+    }
+
+    /// Remembers that `tex_id` can't be decoded and marks it in the layer list.
+    fn add_layer_error(&self, tex_id: TextureId, error: &Error) {
+        let mut errors = self.layer_errors.borrow_mut();
+        if !errors.iter().any(|x| x.0 == tex_id) {
+            errors.push((tex_id, format!("{:?}", error)));
+        }
+        let current = self.tex_id();
+        if (current.0, current.1) == (tex_id.0, tex_id.1) {
+            self.selector.list.mark_error(tex_id.2);
+        }
+    }
+
+    fn is_monochrome(&self, tex_id: TextureId, file: &files::File<'_>) -> bool {
+        let mut cache = self.monochrome_textures.borrow_mut();
+        if let Some(&(_, value)) = cache.iter().find(|x| x.0 == tex_id) {
+            return value;
+        }
+        let value = match file.texture_formats().get(tex_id.2) {
+            Some(Ok(Some(anim::TextureFormat::Monochrome))) => true,
+            _ => false,
+        };
+        cache.push((tex_id, value));
+        value
+    }
+
+    /// Texture of the teamcolor layer, if the selected layer is diffuse and
+    /// can be tinted with it.
+    fn team_color_mask(
+        &self,
+        render_state: &mut RenderState,
+        cache_file: &mut files::File<'_>,
+    ) -> Result<Option<Rc<Texture2d>>, Error> {
+        let tex_id = self.tex_id();
+        // Grayscale textures are cached in a different format
+        if self.render_settings.settings().grayscale {
+            return Ok(None);
+        }
+        let names = cache_file.layer_names();
+        if names.get(tex_id.2).map(|x| &**x) != Some("diffuse") {
+            return Ok(None);
+        }
+        let layer = match names.iter().position(|x| x == "teamcolor") {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        if cache_file.texture_size(layer).is_none() {
+            return Ok(None);
+        }
+        let mask_id = TextureId(tex_id.0, tex_id.1, layer);
+        if self.layer_error(mask_id).is_some() {
+            return Err(anyhow!("Teamcolor layer {} can't be decoded", layer));
+        }
+        let result = render_state.cached_texture(mask_id, || {
+            cache_file.texture(layer)
+                .with_context(|| format!("Failed to get teamcolor texture {}", layer))
+        });
+        if let Err(ref e) = result {
+            self.add_layer_error(mask_id, e);
+        }
+        result.map(Some)
     }
 
     fn palette_texture(
@@ -730,7 +1629,9 @@ impl SpriteInfo {
         &self,
         render_state: &mut RenderState,
     ) -> Result<(), Error> {
-        render_state.clear_framebuf();
+        let background = self.background.get();
+        render_state.clear_framebuf(background);
+        self.render_warnings.borrow_mut().clear();
         let tex_id = self.tex_id();
         let mut files = match self.files.try_lock() {
             Ok(o) => o,
@@ -740,16 +1641,65 @@ impl SpriteInfo {
             Some(s) => s,
             None => return Ok(()),
         };
+        if file.is_anim() && file.texture_size(tex_id.2).is_none() {
+            let name = file.layer_names().get(tex_id.2).cloned()
+                .unwrap_or_else(|| format!("#{}", tex_id.2));
+            return Err(anyhow!("Layer {} has no texture", name));
+        }
+
+        // Hd2 has Hd coordinates?? BW seems to divide them too
+        let div = file.texture_divisor();
+        let frame_rect = |f: &anim::Frame| {
+            Rect::new(
+                f.tex_x as u32 / div,
+                f.tex_y as u32 / div,
+                f.width as u32 / div,
+                f.height as u32 / div,
+            )
+        };
+        let playing_frame = match self.play_button.is_active() {
+            true => file.frames().and_then(|frames| {
+                let index = self.current_frame.load(Ordering::SeqCst);
+                frames.get(index).or_else(|| frames.first()).map(frame_rect)
+            }),
+            false => None,
+        };
+        render_state.set_view_rect(playing_frame);
+        render_state.set_zoom_pan(self.view_zoom.get(), self.view_pan.get());
+        render_state.set_channel(self.channel.get());
 
         let texture = self.sprite_texture(render_state, &mut file)?;
+        self.preview_transform.set(render_state.window_to_pixel(&texture));
+        render_state.render_background(&texture, background)
+            .context("Failed to render background")?;
         let palette_texture = self.palette_texture(render_state, &mut file)?;
+        let team_color_mask = match self.render_settings.settings().team_color {
+            // A broken teamcolor layer shouldn't prevent showing the diffuse layer
+            Some(color) => match self.team_color_mask(render_state, &mut file) {
+                Ok(mask) => mask.map(|mask| (mask, color)),
+                Err(e) => {
+                    self.render_warnings.borrow_mut()
+                        .push(format!("Team color not shown: {:#}", e));
+                    None
+                }
+            },
+            None => None,
+        };
         if let Some(palette) = palette_texture {
             render_state.render_paletted(&texture, &palette)
                 .context("Failed to render paletted sprite")?;
+        } else if let Some((mask, color)) = team_color_mask {
+            render_state.render_team_color(&texture, &mask, color)
+                .context("Failed to render team color")?;
+        } else if !self.render_settings.settings().grayscale && self.is_monochrome(tex_id, &file) {
+            let tint = self.render_settings.settings().monochrome_tint;
+            render_state.render_monochrome(&texture, tint)
+                .context("Failed to render monochrome sprite")?;
         } else {
             use crate::render::SpriteMode;
             use crate::render_settings::AoDepth;
             let mode = match file.layer_names().get(tex_id.2 as usize) {
+                _ if self.render_settings.settings().grayscale => SpriteMode::Grayscale,
                 Some(x) if x == "normal" => {
                     if self.render_settings.settings().decode_normal {
                         SpriteMode::Normal
@@ -769,29 +1719,48 @@ impl SpriteInfo {
             render_state.render_sprite(&texture, mode)
                 .context("Failed to render sprite")?;
         }
-        render_state.render_lines(tex_id, &texture, || {
-            let div = match tex_id.1 {
-                // Hd2 has Hd coordinates?? BW seems to divide them too
-                SpriteType::Hd2 => 2,
-                _ => 1,
+        let selected_frames = self.selected_frames.borrow();
+        // The highlight of the frame being played is still drawn without the bounds
+        if self.frame_boxes_check.is_active() {
+            let settings = self.render_settings.settings();
+            let rgb = |c: [f32; 3]| Color(c[0], c[1], c[2], 1.0);
+            let style = LineStyle {
+                bounds: rgb(settings.bounds_color),
+                frames: rgb(settings.frame_color),
+                width: settings.line_width,
             };
-            let mut result = Vec::with_capacity(32);
-            let red = Color(1.0, 0.0, 0.0, 1.0);
-            let green = Color(0.0, 1.0, 0.0, 1.0);
-            result.push((Rect::new(0, 0, texture.width(), texture.height()), red, 0));
-            if let Some(frames) = file.frames() {
-                for f in frames {
-                    let rect = Rect::new(
-                        f.tex_x as u32 / div,
-                        f.tex_y as u32 / div,
-                        f.width as u32 / div,
-                        f.height as u32 / div,
-                    );
-                    result.push((rect, green, 1));
-                }
-            }
-            result
-        }).context("Failed to render lines")?;
+            render_state.render_lines(tex_id, &texture, &selected_frames, &style, |style| {
+                let mut result = Vec::with_capacity(32);
+                let blue = Color(0.2, 0.6, 1.0, 1.0);
+                // Frames with nonzero frame type (`unknown`) are colored by the type
+                let type_colors = [
+                    Color(1.0, 0.5, 0.0, 1.0),
+                    Color(1.0, 0.0, 1.0, 1.0),
+                    Color(0.0, 1.0, 1.0, 1.0),
+                    Color(1.0, 1.0, 1.0, 1.0),
+                ];
+                let bounds = Rect::new(0, 0, texture.width(), texture.height());
+                result.push((bounds, style.bounds, 0));
+                if let Some(frames) = file.frames() {
+                    for (i, f) in frames.iter().enumerate() {
+                        let color = match selected_frames.binary_search(&i).is_ok() {
+                            true => blue,
+                            false => match f.unknown {
+                                0 => style.frames,
+                                x => type_colors[(x as usize - 1) % type_colors.len()],
+                            },
+                        };
+                        result.push((frame_rect(f), color, 1));
+                    }
+                }
+                result
+            }).context("Failed to render lines")?;
+        }
+        if let Some(rect) = playing_frame {
+            let yellow = Color(1.0, 1.0, 0.0, 1.0);
+            render_state.render_highlight(&texture, rect, yellow)
+                .context("Failed to render lines")?;
+        }
         Ok(())
     }
 
@@ -833,10 +1802,26 @@ impl SpriteInfo {
             s.changed_type_from_event();
             s.draw_area.queue_draw();
         });
+        let steps = [
+            ("previous_layer", -1, false),
+            ("next_layer", 1, false),
+            ("previous_frame", -1, true),
+            ("next_frame", 1, true),
+        ];
+        for &(name, delta, is_frame) in &steps {
+            let s = this.clone();
+            action(group, name, true, None, move |_, _| {
+                match is_frame {
+                    true => s.step_frame(delta),
+                    false => s.step_layer(delta),
+                }
+            });
+        }
         let s = this.clone();
         action(group, "select_layer", true, Some("u"), move |_, param| {
             if let Some(layer) = param.and_then(|x| x.get::<u32>()) {
                 s.selected_layer.store(layer as usize, Ordering::SeqCst);
+                s.current_frame.store(0, Ordering::SeqCst);
                 {
                     let tex_id = s.tex_id();
                     let mut files = match s.files.try_lock() {
@@ -916,6 +1901,10 @@ impl SpriteInfo {
         });
         action(group, "frame_count", true, Some("u"), move |_, _| {
         });
+        action(group, "grp_frame_unknown", true, Some("s"), move |_, _| {
+        });
+        action(group, "layer_info", true, Some("s"), move |_, _| {
+        });
         action(group, "is_dirty", true, Some("b"), move |_, _| {
         });
     }
@@ -937,7 +1926,7 @@ impl SpriteInfo {
             self.draw_clear_requests.borrow_mut().push(tex_id);
             // To re-read the other fields
             let mut file = files.file(tex_id.0, tex_id.1).unwrap_or_else(|e| {
-                error!("Couldn't open {:?}: {}", tex_id, e);
+                self.open_failed(tex_id, &e);
                 None
             });
             self.changed_ty(tex_id, &mut file);
@@ -963,7 +1952,7 @@ impl SpriteInfo {
             dirty = files.has_changes();
             self.draw_clear_requests.borrow_mut().push(tex_id);
             let mut file = files.file(tex_id.0, tex_id.1).unwrap_or_else(|e| {
-                error!("Couldn't open {:?}: {}", tex_id, e);
+                self.open_failed(tex_id, &e);
                 None
             });
             self.changed_ty(tex_id, &mut file);
@@ -1018,7 +2007,7 @@ impl SpriteInfo {
             _ => return,
         };
         let mut file = files.file(tex_id.0, tex_id.1).unwrap_or_else(|e| {
-            error!("Couldn't open {:?}: {}", tex_id, e);
+            self.open_failed(tex_id, &e);
             None
         });
         self.changed_ty(tex_id, &mut file);
@@ -1035,10 +2024,38 @@ impl SpriteInfo {
             }
         };
         self.sprite_actions.activate_action("texture_size", Some(&variant));
+        let mip_count = match file.texture_size(tex_id.2) {
+            Some(_) => file.texture_mip_count(tex_id.2).unwrap_or(1).max(1),
+            None => 1,
+        };
+        self.mip_level.set_range(0.0, (mip_count - 1) as f64);
+        self.mip_level.set_sensitive(mip_count > 1);
+        let variant = match file.grp_frame_unknown(tex_id.2) {
+            Some(unknown) => format!("0x{:x}", unknown).to_variant(),
+            None => "".to_variant(),
+        };
+        self.sprite_actions.activate_action("grp_frame_unknown", Some(&variant));
+    }
+
+    /// Logs `error` from opening `tex_id`, and marks the sprite as broken in the sprite
+    /// list and file info.
+    fn open_failed(&self, tex_id: TextureId, error: &Error) {
+        error!("Couldn't open {:?}: {}", tex_id, error);
+        mark_sprite_error(tex_id.0);
+        *self.open_error.borrow_mut() = Some(format!("{:#}", error));
     }
 
     fn changed_ty(&self, tex_id: TextureId, file: &mut Option<files::File<'_>>) {
         let ty = tex_id.1;
+        let open_error = self.open_error.borrow_mut().take();
+        self.selected_frames.borrow_mut().clear();
+        // Files are locked by the caller, there is no frame selected to show anyway
+        self.updating_offset_entries.set(true);
+        for entry in &self.offset_entries {
+            entry.set_value(0.0);
+            entry.set_sensitive(false);
+        }
+        self.updating_offset_entries.set(false);
         self.set_layers(file);
         if let Some(ref mut file) = *file {
             let is_anim = file.is_anim();
@@ -1063,6 +2080,7 @@ impl SpriteInfo {
                     a.set_enabled(false);
                 }
             }
+            set_deref_enabled(ty == SpriteType::Sd && is_anim && file.image_ref().is_some());
             self.update_tex_size(file);
             if let Some(data) = sprite_data {
                 let variant = (data.width as u32).to_variant();
@@ -1083,6 +2101,11 @@ impl SpriteInfo {
             };
             let variant = frame_count.to_variant();
             self.sprite_actions.activate_action("frame_count", Some(&variant));
+            let variant = match is_anim {
+                true => layer_texture_info(file).to_variant(),
+                false => "".to_variant(),
+            };
+            self.sprite_actions.activate_action("layer_info", Some(&variant));
         } else {
             let variant = false.to_variant();
             self.sprite_actions.activate_action("sprite_exists", Some(&variant));
@@ -1090,10 +2113,20 @@ impl SpriteInfo {
                 a.activate(Some(&false.to_variant()));
                 a.set_enabled(false);
             }
+            set_deref_enabled(false);
             let variant = "0x0".to_variant();
             self.sprite_actions.activate_action("texture_size", Some(&variant));
+            self.mip_level.set_range(0.0, 0.0);
+            self.mip_level.set_sensitive(false);
+            let variant = "".to_variant();
+            self.sprite_actions.activate_action("grp_frame_unknown", Some(&variant));
             let variant = 0u32.to_variant();
             self.sprite_actions.activate_action("frame_count", Some(&variant));
+            let variant = match open_error {
+                Some(e) => format!("Unable to open the sprite:\n{}", e).to_variant(),
+                None => "".to_variant(),
+            };
+            self.sprite_actions.activate_action("layer_info", Some(&variant));
         }
     }
 
@@ -1108,6 +2141,12 @@ impl SpriteInfo {
                     self.selector.list.push(name);
                 }
                 layer_count = names.len();
+                let tex_id = self.tex_id();
+                for &(id, _) in self.layer_errors.borrow().iter() {
+                    if (id.0, id.1) == (tex_id.0, tex_id.1) {
+                        self.selector.list.mark_error(id.2);
+                    }
+                }
             }
             None => {
                 layer_count = 0;
@@ -1127,6 +2166,64 @@ impl SpriteInfo {
         self.bx.clone().upcast()
     }
 
+    /// Switches to the notes file of a newly opened root directory.
+    fn load_notes(&self, root: Option<&Path>) {
+        self.save_notes();
+        let notes = match root {
+            Some(root) => sprite_notes::SpriteNotes::load(root).unwrap_or_else(|e| {
+                error!("Couldn't load sprite notes: {:?}", e);
+                sprite_notes::SpriteNotes::empty()
+            }),
+            None => sprite_notes::SpriteNotes::empty(),
+        };
+        *self.notes.borrow_mut() = notes;
+        self.show_notes(None);
+    }
+
+    /// Shows notes of `sprite` in the notes view.
+    fn show_notes(&self, sprite: Option<usize>) {
+        if self.notes_sprite.get() != sprite {
+            self.save_notes();
+        }
+        self.notes_sprite.set(sprite);
+        let notes = self.notes.borrow();
+        self.loading_notes.set(true);
+        if let Some(buffer) = self.notes_view.buffer() {
+            buffer.set_text(sprite.map(|x| notes.get(x)).unwrap_or(""));
+        }
+        self.loading_notes.set(false);
+        self.notes_view.set_sensitive(sprite.is_some() && notes.can_save());
+    }
+
+    fn save_notes(&self) {
+        if let Err(e) = self.notes.borrow_mut().save() {
+            error!("Couldn't save sprite notes: {:?}", e);
+        }
+    }
+
+    /// Selects the next enabled sprite type after the current one, same as clicking
+    /// its radio button.
+    fn cycle_sprite_type(&self) {
+        let types = [
+            (SpriteType::Sd, "select_sd"),
+            (SpriteType::Hd, "select_hd"),
+            (SpriteType::Hd2, "select_hd2"),
+        ];
+        let current = types.iter()
+            .position(|x| x.0 == self.selected_type.get())
+            .unwrap_or(0);
+        for offset in 1..types.len() {
+            let (ty, name) = types[(current + offset) % types.len()];
+            let enabled = lookup_action(&self.sprite_actions, name)
+                .map(|x| x.is_enabled())
+                .unwrap_or(false);
+            if enabled {
+                self.selector.select_type(ty);
+                return;
+            }
+        }
+    }
+
     fn set_enable_animset_actions(&self, enable: bool) {
         if let Some(a) = lookup_action(&self.sprite_actions, "select_hd") {
             a.set_enabled(enable);
@@ -1158,7 +2255,15 @@ impl SpriteInfo {
                 return;
             }
         };
-        self.sprite_index.store(index, Ordering::SeqCst);
+        let old_index = self.sprite_index.swap(index, Ordering::SeqCst);
+        if old_index != index {
+            self.previous_sprite.set(Some(old_index));
+        }
+        self.show_notes(Some(index));
+        self.current_frame.store(0, Ordering::SeqCst);
+        if old_index != index {
+            self.reset_view();
+        }
         self.draw_area.queue_draw();
         match sprite {
             SpriteFiles::AnimSet(ref s) => {
@@ -1185,7 +2290,7 @@ impl SpriteInfo {
         let tex_id = self.tex_id();
         let mut files = self.files.lock();
         let mut file = files.file(tex_id.0, tex_id.1).unwrap_or_else(|e| {
-            error!("Couldn't open {:?}: {}", tex_id, e);
+            self.open_failed(tex_id, &e);
             None
         });
         self.changed_ty(tex_id, &mut file);
@@ -1207,10 +2312,33 @@ fn create_menu() -> gio::Menu {
         let file_actions = {
             let menu = gio::Menu::new();
             menu.append_item(&with_accel("_Open...", "app.open", "<Ctrl>O"));
+            menu.append_item(&with_accel("Open _folder...", "app.openDir", "<Ctrl><Shift>O"));
+            RECENT_MENU.with(|recent| {
+                update_recent_menu(recent);
+                menu.append_submenu(Some("Open _recent"), recent);
+            });
             menu.append_item(&with_accel("_Save", "app.save", "<Ctrl>S"));
+            menu.append_item(&with_accel("Show _pending changes...", "app.pendingChanges", ""));
             menu
         };
         menu.append_section(None, &file_actions);
+        let settings = {
+            let menu = gio::Menu::new();
+            menu.append_item(&with_accel("_Verify files after saving", "app.verifyAfterSave", ""));
+            menu.append_item(&with_accel("Create ._bak backups when saving", "app.backupOnSave", ""));
+            menu.append_item(&with_accel(
+                "_Temporary folder for saving...",
+                "app.saveTempDir",
+                "",
+            ));
+            menu.append_item(&with_accel(
+                "Cache decoded textures on disk",
+                "app.diskTextureCache",
+                "",
+            ));
+            menu
+        };
+        menu.append_section(None, &settings);
         let exit = {
             let menu = gio::Menu::new();
             menu.append_item(&with_accel("E_xit...", "app.exit", "<Alt>F4"));
@@ -1225,6 +2353,13 @@ fn create_menu() -> gio::Menu {
         let export_actions = {
             let menu = gio::Menu::new();
             menu.append_item(&with_accel("_Export frames...", "app.exportFrames", "<Ctrl>E"));
+            menu.append_item(&with_accel("Export a_ll frames...", "app.exportAllFrames", ""));
+            menu.append_item(&with_accel("Export _atlas PNG...", "app.exportAtlas", ""));
+            menu.append_item(&with_accel(
+                "Export _contact sheet PNG...",
+                "app.exportContactSheet",
+                "",
+            ));
             menu
         };
         menu.append_section(None, &export_actions);
@@ -1232,9 +2367,27 @@ fn create_menu() -> gio::Menu {
             let menu = gio::Menu::new();
             menu.append_item(&with_accel("_Import frames...", "app.importFrames", "<Ctrl>I"));
             menu.append_item(&with_accel("Import _GRP...", "app.importGrp", "<Ctrl>G"));
+            menu.append_item(&with_accel("Import a_tlas PNG...", "app.importAtlas", ""));
             menu
         };
         menu.append_section(None, &import_actions);
+        let frame_actions = {
+            let menu = gio::Menu::new();
+            menu.append_item(&with_accel("_Copy frame...", "app.copyFrame", "<Ctrl><Shift>C"));
+            menu.append_item(&with_accel("_Paste frame...", "app.pasteFrame", "<Ctrl><Shift>V"));
+            menu
+        };
+        menu.append_section(None, &frame_actions);
+        let tool_actions = {
+            let menu = gio::Menu::new();
+            menu.append_item(&with_accel("_Compact textures", "app.compactTextures", ""));
+            menu.append_item(&with_accel("_Tighten frame bounds", "app.tightenFrameBounds", ""));
+            menu.append_item(&with_accel("_Recompress layer...", "app.recompressLayer", ""));
+            menu.append_item(&with_accel("Set scale of all GRPs...", "app.setGrpScales", ""));
+            menu.append_item(&with_accel("Make sprite _standalone", "app.derefSprite", ""));
+            menu
+        };
+        menu.append_section(None, &tool_actions);
         menu
     };
     let anim_menu = {
@@ -1242,14 +2395,49 @@ fn create_menu() -> gio::Menu {
         let actions = {
             let menu = gio::Menu::new();
             menu.append_item(&with_accel("_Modify sprite amount...", "app.editEntryCount", ""));
+            menu.append_item(&with_accel(
+                "_List sprites missing HD/HD2...",
+                "app.listMissingHd",
+                "",
+            ));
+            menu.append_item(&with_accel(
+                "Export sprite _metadata as CSV...",
+                "app.exportMetadataCsv",
+                "",
+            ));
+            menu.append_item(&with_accel("_Verify sprites...", "app.verifySprites", ""));
+            menu
+        };
+        menu.append_section(None, &actions);
+        menu
+    };
+    let view_menu = {
+        let menu = gio::Menu::new();
+        let actions = {
+            let menu = gio::Menu::new();
+            menu.append_item(&with_accel("_Previous sprite", "app.previousSprite", "grave"));
+            menu.append_item(&with_accel(
+                "Cycle sprite _type (SD/HD/HD2)",
+                "app.cycleSpriteType",
+                "<Ctrl>T",
+            ));
             menu
         };
         menu.append_section(None, &actions);
+        let scale_menu = gio::Menu::new();
+        for percent in &["100", "125", "150", "200"] {
+            let item = gio::MenuItem::new(Some(&format!("{}%", percent)), None);
+            item.set_action_and_target_value(Some("app.uiScale"), Some(&percent.to_variant()));
+            scale_menu.append_item(&item);
+        }
+        scale_menu.freeze();
+        menu.append_submenu(Some("Interface _scale"), &scale_menu);
         menu
     };
     menu.append_submenu(Some("_File"), &file_menu);
     menu.append_submenu(Some("_Sprite"), &sprite_menu);
     menu.append_submenu(Some("_Anim"), &anim_menu);
+    menu.append_submenu(Some("_View"), &view_menu);
     if cfg!(debug_assertions) {
         let debug_menu = {
             let menu = gio::Menu::new();
@@ -1263,21 +2451,61 @@ fn create_menu() -> gio::Menu {
     menu
 }
 
+/// `current + delta` wrapped to `0..count`, None if `count` is 0.
+fn wrapping_step(current: usize, delta: isize, count: usize) -> Option<usize> {
+    if count == 0 {
+        return None;
+    }
+    let current = current.min(count - 1) as isize;
+    Some((current + delta).rem_euclid(count as isize) as usize)
+}
+
 // Requires state to not be borrowed
 fn save() -> Result<(), Error> {
     let files = STATE.with(|x| {
         let state = x.borrow();
         state.files.clone()
     });
+    let ui = ui();
+    let progress_window = gtk::Window::new(gtk::WindowType::Toplevel);
+    let progress = gtk::ProgressBar::new();
+    progress.set_show_text(true);
+    progress.set_text(Some("Saving..."));
+    progress_window.add(&progress);
+    progress_window.set_border_width(10);
+    progress_window.set_default_width(300);
+    progress_window.set_title("Saving");
+    progress_window.set_deletable(false);
+    progress_window.set_modal(true);
+    progress_window.set_transient_for(Some(&ui.main_window));
+    progress_window.show_all();
+    let pump_events = || {
+        while gtk::events_pending() {
+            gtk::main_iteration();
+        }
+    };
+    pump_events();
     let result = {
         let mut files = files.lock();
-        files.save()
+        let temp_dir = save_temp_dir();
+        files.save_with_progress(backup_on_save(), temp_dir.as_deref(), |written, total| {
+            progress.set_fraction(written as f64 / total.max(1) as f64);
+            progress.set_text(Some(&format!("Saved {} / {} files", written, total)));
+            pump_events();
+        })
+    };
+    progress_window.close();
+    let result = match result {
+        Ok(saved) if verify_after_save() => {
+            crate::files::verify_saved_files(&saved).context("Verifying saved files failed")
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
     };
     if let Err(ref e) = result {
         let msg = format!("Unable to save: {:?}", e);
-        ui().message(&msg);
+        ui.message(&msg);
     } else {
-        let ui = ui();
         if let Some(a) = lookup_action(&ui.info.sprite_actions, "is_dirty") {
             a.activate(Some(&false.to_variant()));
         }
@@ -1285,6 +2513,81 @@ fn save() -> Result<(), Error> {
     result
 }
 
+fn disk_texture_cache() -> bool {
+    select_dir::read_config_entry("disk_texture_cache").map(|x| x == "y").unwrap_or(false)
+}
+
+fn verify_after_save() -> bool {
+    select_dir::read_config_entry("verify_after_save").map(|x| x == "y").unwrap_or(false)
+}
+
+/// User's interface scale setting, see `ui_helpers::apply_ui_scale`.
+fn ui_scale_percent() -> u32 {
+    select_dir::read_config_entry_int("ui_scale")
+        .and_then(|x| u32::try_from(x).ok())
+        .filter(|&x| x != 0)
+        .unwrap_or(100)
+}
+
+fn backup_on_save() -> bool {
+    select_dir::read_config_entry("backup_on_save").map(|x| x == "y").unwrap_or(false)
+}
+
+/// Directory where files are written before replacing the old ones when saving,
+/// None to write them next to the files they replace.
+fn save_temp_dir() -> Option<PathBuf> {
+    select_dir::read_config_entry("save_temp_dir")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+}
+
+fn choose_save_temp_dir(window: &gtk::ApplicationWindow) {
+    let current = match save_temp_dir() {
+        Some(dir) => format!("Temporary files are written to {}", dir.display()),
+        None => "Temporary files are written next to the files being saved".into(),
+    };
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::None,
+        &format!(
+            "{}.\n\nA folder on another drive works, but the files have to be copied \
+            instead of moved once they have been written.",
+            current,
+        ),
+    );
+    dialog.add_button("Choose folder...", gtk::ResponseType::Accept);
+    dialog.add_button("Use default", gtk::ResponseType::Reject);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    let response = dialog.run();
+    dialog.close();
+    match response {
+        gtk::ResponseType::Accept => {
+            let chooser = gtk::FileChooserNative::new(
+                Some("Temporary folder for saving"),
+                Some(window),
+                gtk::FileChooserAction::SelectFolder,
+                Some("Select"),
+                Some("Cancel")
+            );
+            if let Some(dir) = save_temp_dir() {
+                chooser.set_current_folder(&dir);
+            }
+            if chooser.run() == gtk::ResponseType::Accept {
+                if let Some(dir) = chooser.filename() {
+                    select_dir::set_config_entry("save_temp_dir", &*dir.to_string_lossy());
+                }
+            }
+            chooser.destroy();
+        }
+        gtk::ResponseType::Reject => {
+            select_dir::set_config_entry("save_temp_dir", "");
+        }
+        _ => (),
+    }
+}
+
 // Return true if the user didn't press cancel
 fn check_unsaved_files() -> bool {
     let has_changes = {
@@ -1343,32 +2646,195 @@ fn create_actions(app: &gtk::Application, main_window: &gtk::Window) {
     let a = app.clone();
     action(app, "exit", true, move |_, _| {
         if check_unsaved_files() {
+            let ui = ui();
+            save_window_state(&ui.main_window, &ui.info);
+            ui.info.save_notes();
             a.quit()
         }
     });
     let w = main_window.clone();
     action(app, "open", true, move |_, _| {
         if check_unsaved_files() {
-            if let Some(filename) = open_file_dialog(&w) {
+            if let Some(filename) = open_file_dialog(&w, false) {
+                open(&filename);
+            }
+        }
+    });
+    let w = main_window.clone();
+    action(app, "openDir", true, move |_, _| {
+        if check_unsaved_files() {
+            if let Some(filename) = open_file_dialog(&w, true) {
                 open(&filename);
             }
         }
     });
+    let open_recent = gio::SimpleAction::new("openRecent", Some(glib::VariantTy::STRING));
+    open_recent.connect_activate(|_, param| {
+        let path = match param.and_then(|x| x.str()) {
+            Some(s) => PathBuf::from(s),
+            None => return,
+        };
+        if check_unsaved_files() {
+            open(&path);
+        }
+    });
+    app.add_action(&open_recent);
+    // Placeholder shown in an empty "Open recent" menu
+    action(app, "noRecentFiles", false, |_, _| {});
+    // Dropping a file on the window opens it, same as the open dialog.
+    let targets = [gtk::TargetEntry::new("text/uri-list", gtk::TargetFlags::OTHER_APP, 0)];
+    main_window.drag_dest_set(gtk::DestDefaults::ALL, &targets, gdk::DragAction::COPY);
+    main_window.connect_drag_data_received(|_, _, _, _, data, _, _| {
+        let path = data.uris().iter()
+            .find_map(|uri| gio::File::for_uri(uri).path());
+        if let Some(path) = path {
+            // Wait for the drop to finish before possibly showing the unsaved changes dialog.
+            glib::idle_add_local_once(move || {
+                if check_unsaved_files() {
+                    open(&path);
+                }
+            });
+        }
+    });
     action(app, "save", false, move |_, _| {
         let _ = save();
     });
+    let verify = gio::SimpleAction::new_stateful(
+        "verifyAfterSave",
+        None,
+        &verify_after_save().to_variant(),
+    );
+    verify.connect_activate(|action, _| {
+        let state = !action.state().and_then(|x| x.get::<bool>()).unwrap_or(false);
+        action.set_state(&state.to_variant());
+        select_dir::set_config_entry("verify_after_save", if state { "y" } else { "n" });
+    });
+    app.add_action(&verify);
+    let backup = gio::SimpleAction::new_stateful(
+        "backupOnSave",
+        None,
+        &backup_on_save().to_variant(),
+    );
+    backup.connect_activate(|action, _| {
+        let state = !action.state().and_then(|x| x.get::<bool>()).unwrap_or(false);
+        action.set_state(&state.to_variant());
+        select_dir::set_config_entry("backup_on_save", if state { "y" } else { "n" });
+    });
+    app.add_action(&backup);
+    action(app, "saveTempDir", true, move |_, _| {
+        choose_save_temp_dir(&ui().main_window);
+    });
+    texture_cache::set_enabled(disk_texture_cache());
+    let texture_cache_action = gio::SimpleAction::new_stateful(
+        "diskTextureCache",
+        None,
+        &disk_texture_cache().to_variant(),
+    );
+    texture_cache_action.connect_activate(|action, _| {
+        let state = !action.state().and_then(|x| x.get::<bool>()).unwrap_or(false);
+        action.set_state(&state.to_variant());
+        texture_cache::set_enabled(state);
+        select_dir::set_config_entry("disk_texture_cache", if state { "y" } else { "n" });
+    });
+    app.add_action(&texture_cache_action);
+    let ui_scale_action = gio::SimpleAction::new_stateful(
+        "uiScale",
+        Some(glib::VariantTy::STRING),
+        &ui_scale_percent().to_string().to_variant(),
+    );
+    ui_scale_action.connect_activate(|action, param| {
+        let percent = match param.and_then(|x| x.str()).and_then(|x| x.parse::<u32>().ok()) {
+            Some(s) => s,
+            None => return,
+        };
+        action.set_state(&percent.to_string().to_variant());
+        select_dir::set_config_entry("ui_scale", i64::from(percent));
+        apply_ui_scale(percent);
+    });
+    app.add_action(&ui_scale_action);
     action(app, "exportFrames", false, move |_, _| {
         let ui = ui();
         frame_export_dialog::frame_export_dialog(&ui.info, &ui.main_window);
     });
+    action(app, "pendingChanges", false, move |_, _| {
+        let ui = ui();
+        show_pending_changes(&ui.info, &ui.main_window);
+    });
+    action(app, "listMissingHd", false, move |_, _| {
+        let ui = ui();
+        list_missing_hd(&ui.info, &ui.main_window);
+    });
+    action(app, "exportMetadataCsv", false, move |_, _| {
+        let ui = ui();
+        export_metadata_csv(&ui.info, &ui.main_window);
+    });
+    action(app, "verifySprites", false, move |_, _| {
+        let ui = ui();
+        verify_sprites(&ui.info, &ui.main_window);
+    });
+    action(app, "exportAllFrames", false, move |_, _| {
+        let ui = ui();
+        frame_export_dialog::batch_export_dialog(&ui.info, &ui.main_window);
+    });
+    action(app, "exportAtlas", false, move |_, _| {
+        let ui = ui();
+        export_atlas(&ui.info, &ui.main_window);
+    });
+    action(app, "exportContactSheet", false, move |_, _| {
+        let ui = ui();
+        export_contact_sheet(&ui.info, &ui.main_window);
+    });
     action(app, "importFrames", false, move |_, _| {
         let ui = ui();
         frame_import_dialog::frame_import_dialog(&ui.info, &ui.main_window);
     });
+    action(app, "importAtlas", false, move |_, _| {
+        let ui = ui();
+        import_atlas(&ui.info, &ui.main_window);
+    });
     action(app, "importGrp", false, move |_, _| {
         let ui = ui();
         grp_import_dialog::grp_import_dialog(&ui.info, &ui.main_window);
     });
+    action(app, "previousSprite", false, move |_, _| {
+        let ui = ui();
+        if let Some(index) = ui.info.previous_sprite.get() {
+            // Selecting from the list calls select_sprite
+            ui.list.list.select(index);
+        }
+    });
+    action(app, "cycleSpriteType", false, move |_, _| {
+        let ui = ui();
+        ui.info.cycle_sprite_type();
+    });
+    action(app, "copyFrame", false, move |_, _| {
+        let ui = ui();
+        frame_copy_dialog::copy_dialog(&ui.info, &ui.main_window);
+    });
+    action(app, "pasteFrame", false, move |_, _| {
+        let ui = ui();
+        frame_copy_dialog::paste_dialog(&ui.info, &ui.main_window);
+    });
+    action(app, "compactTextures", false, move |_, _| {
+        let ui = ui();
+        compact_textures(&ui.info, &ui.main_window);
+    });
+    action(app, "derefSprite", false, move |_, _| {
+        let ui = ui();
+        deref_sprite(&ui.info, &ui.main_window);
+    });
+    action(app, "recompressLayer", false, move |_, _| {
+        let ui = ui();
+        recompress_layer(&ui.info, &ui.main_window);
+    });
+    action(app, "tightenFrameBounds", false, move |_, _| {
+        let ui = ui();
+        tighten_frame_bounds(&ui.info, &ui.main_window);
+    });
+    action(app, "setGrpScales", false, move |_, _| {
+        let ui = ui();
+        set_grp_scales(&ui.info, &ui.main_window);
+    });
     action(app, "editEntryCount", false, move |_, _| {
         let ui = ui();
         edit_entry_count::dialog(&ui.info, &ui.main_window);
@@ -1424,92 +2890,1070 @@ fn create_actions(app: &gtk::Application, main_window: &gtk::Window) {
     }
 }
 
-fn enable_file_actions(app: &gtk::Application, files: &files::Files) {
-    if let Some(a) = lookup_action(app, "save") {
-        a.set_enabled(true);
-    }
-    if let Some(a) = lookup_action(app, "importFrames") {
-        a.set_enabled(true);
-    }
-    if let Some(a) = lookup_action(app, "importGrp") {
-        a.set_enabled(true);
-    }
-    if let Some(a) = lookup_action(app, "exportFrames") {
-        a.set_enabled(true);
-    }
-    if let Some(a) = lookup_action(app, "editEntryCount") {
-        let enable = files.mainsd().is_some();
-        a.set_enabled(enable);
+fn export_metadata_csv(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let path = match save_csv_dialog(window.upcast_ref(), "sprites.csv") {
+        Some(s) => s,
+        None => return,
+    };
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        File::create(&path)
+            .with_context(|| format!("Unable to create {}", path.display()))
+            .and_then(|file| {
+                let mut out = std::io::BufWriter::new(file);
+                files.write_metadata_csv(&mut out)?;
+                std::io::Write::flush(&mut out)?;
+                Ok(())
+            })
+    };
+    match result {
+        Ok(()) => info_msg_box(window, format!("Wrote metadata to {}", path.display())),
+        Err(e) => error_msg_box(window, format!("Unable to export metadata: {:?}", e)),
     }
 }
 
-fn open(filename: &Path) {
-    let ui = ui();
-    match files::Files::init(filename) {
-        Ok((f, index)) => {
-            ui.files_changed(&f);
-            enable_file_actions(&ui.app, &f);
-            {
-                STATE.with(|x| {
-                    let state = x.borrow();
-                    let mut files = state.files.lock();
-                    *files = f;
-                });
-            }
-            ui.info.draw_clear_all();
-            ui.info.sprite_actions.activate_action("select_sd", None);
-            let index = index.unwrap_or(0);
-            ui.info.select_sprite(index);
-            ui.list.list.select(index);
+fn list_missing_hd(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    use std::fmt::Write;
+
+    let text = {
+        let files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let missing = files.sprites_missing_hd();
+        let mut text = String::new();
+        for &(sprite, ref types) in &missing {
+            let name = match files.sprites().get(sprite) {
+                Some(SpriteFiles::AnimSet(s)) => &*s.name,
+                Some(SpriteFiles::MainSdOnly { name, .. }) => &**name,
+                _ => "",
+            };
+            let types = types.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>();
+            writeln!(text, "{:03} {}: missing {}", sprite, name, types.join(", ")).unwrap();
         }
-        Err(e) => {
-            let msg = format!("Unable to open file: {:?}", e);
-            ui.message(&msg);
+        if missing.is_empty() {
+            text.push_str("All sprites have HD and HD2 files");
+        } else {
+            write!(text, "\n{} sprites are missing files", missing.len()).unwrap();
         }
+        text
+    };
+    text_window(window, "Sprites missing HD or HD2 files", &text);
+}
+
+/// Verifies the sprites in a background thread, showing the findings once done.
+fn verify_sprites(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    enum Progress {
+        Done(Vec<String>),
+        Progress(usize, usize),
     }
+
+    let progress_window = gtk::Window::new(gtk::WindowType::Toplevel);
+    let progress = gtk::ProgressBar::new();
+    progress.set_show_text(true);
+    progress.set_text(Some("Verifying..."));
+    progress_window.add(&progress);
+    progress_window.set_border_width(10);
+    progress_window.set_default_width(300);
+    progress_window.set_title("Verifying sprites");
+    progress_window.set_deletable(false);
+    progress_window.set_modal(true);
+    progress_window.set_transient_for(Some(window));
+    progress_window.show_all();
+
+    let files = info.files.clone();
+    let (send, recv) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    std::thread::spawn(move || {
+        let findings = files.lock().verify_with_progress(|done, total| {
+            let _ = send.send(Progress::Progress(done, total));
+        });
+        let _ = send.send(Progress::Done(findings));
+    });
+    let window = window.clone();
+    recv.attach(None, move |status| match status {
+        Progress::Progress(done, total) => {
+            progress.set_fraction(done as f64 / total.max(1) as f64);
+            progress.set_text(Some(&format!("Verified {} / {} sprites", done, total)));
+            glib::Continue(true)
+        }
+        Progress::Done(findings) => {
+            progress_window.close();
+            let text = match findings.is_empty() {
+                true => String::from("No problems found"),
+                false => {
+                    format!("{}\n\n{} problems found", findings.join("\n"), findings.len())
+                }
+            };
+            text_window(&window, "Sprite verification", &text);
+            glib::Continue(false)
+        }
+    });
 }
 
-fn open_file_dialog(parent: &gtk::Window) -> Option<PathBuf> {
-    let dialog = gtk::FileChooserNative::new(
-        Some("Open..."),
-        Some(parent),
-        gtk::FileChooserAction::Open,
-        Some("Open"),
-        Some("Cancel")
-    );
-    if let Some(path) = select_dir::read_config_entry("open_file") {
-        dialog.set_current_folder(&path);
+fn show_pending_changes(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    use std::fmt::Write;
+
+    fn describe(value: &Option<anim::ValuesOrRef>) -> String {
+        match value {
+            Some(anim::ValuesOrRef::Values(v)) => format!("{}x{}", v.width, v.height),
+            Some(anim::ValuesOrRef::Ref(img)) => format!("ref to image {}", img),
+            None => String::from("none"),
+        }
     }
-    dialog.set_select_multiple(false);
-    let filter = gtk::FileFilter::new();
-    filter.add_pattern("*.anim");
-    filter.add_pattern("*.dds.grp");
-    filter.add_pattern("*.dds.vr4");
-    filter.set_name(Some("Valid files"));
-    dialog.add_filter(&filter);
-    let filter = gtk::FileFilter::new();
-    filter.add_pattern("*.*");
-    filter.set_name(Some("All files"));
-    dialog.add_filter(&filter);
-    //dialog.add_button("Open", gtk::ResponseType::Accept.into());
-    //dialog.add_button("Cancel", gtk::ResponseType::Cancel.into());
-    let result: gtk::ResponseType = dialog.run().into();
-    let result = if result == gtk::ResponseType::Accept {
-        if let Some(path) = dialog.filename() {
-            if let Some(parent) = path.parent() {
-                select_dir::set_config_entry("open_file", &*parent.to_string_lossy());
+
+    let text = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let changes = files.pending_changes();
+        let mut text = String::new();
+        for change in &changes {
+            write!(text, "{:03} {:?}:", change.sprite, change.ty).unwrap();
+            if change.edited.is_some() && change.original != change.edited {
+                write!(
+                    text, " {} -> {}", describe(&change.original), describe(&change.edited),
+                ).unwrap();
+            }
+            match change.textures_replaced {
+                Some((Some(old), new)) => {
+                    write!(text, " textures replaced, {} -> {} frames", old, new).unwrap()
+                }
+                Some((None, new)) => write!(text, " new textures, {} frames", new).unwrap(),
+                None => (),
             }
+            if change.edited.is_some() && change.original == change.edited &&
+                change.textures_replaced.is_none()
+            {
+                text.push_str(" no changes");
+            }
+            text.push('\n');
         }
-        dialog.filename()
-    } else {
-        None
+        if changes.is_empty() {
+            text.push_str("No sprites have been changed");
+        } else {
+            write!(text, "\n{} sprites have unsaved changes", changes.len()).unwrap();
+        }
+        if files.has_changes() && changes.is_empty() {
+            text.push_str("\n(Other files, such as the sprite count or lighting data, have changes)");
+        }
+        text
     };
-    dialog.destroy();
-    result
+    text_window(window, "Pending changes", &text);
 }
 
-fn get_css_provider() -> gtk::CssProvider {
-    CSS.with(|x| x.clone())
+fn compact_textures(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = texture_repack::compact_textures(&mut files, tex_id.0, tex_id.1);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(result) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+            let msg = format!(
+                "Compacted textures of {:?} image {}\n\
+                Size {} -> {} bytes, reclaimed {} bytes",
+                tex_id.1, tex_id.0, result.old_size, result.new_size, result.reclaimed_bytes(),
+            );
+            info_msg_box(window, msg);
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to compact textures: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn deref_sprite(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = files.deref_sprite(tex_id.0, tex_id.1);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(()) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to make sprite standalone: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+/// "Make sprite standalone" is only available while a ref is selected.
+fn set_deref_enabled(enabled: bool) {
+    UI.with(|x| {
+        if let Some(ui) = x.borrow().as_ref() {
+            if let Some(a) = lookup_action(&ui.app, "derefSprite") {
+                a.set_enabled(enabled);
+            }
+        }
+    });
+}
+
+fn recompress_layer(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    static FORMATS: &[(anim::TextureFormat, &str)] = &[
+        (anim::TextureFormat::Dxt1, "DXT1"),
+        (anim::TextureFormat::Dxt5, "DXT5"),
+        (anim::TextureFormat::Rgba, "RGBA"),
+        (anim::TextureFormat::Monochrome, "Monochrome"),
+    ];
+    let tex_id = info.tex_id();
+    let (layer_name, current) = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let file = match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(s)) => s,
+            _ => return,
+        };
+        let name = file.layer_names().get(tex_id.2).cloned()
+            .unwrap_or_else(|| format!("#{}", tex_id.2));
+        let current = match file.texture_formats().into_iter().nth(tex_id.2) {
+            Some(Ok(Some(format))) => Some(format),
+            _ => None,
+        };
+        (name, current)
+    };
+    let current = match current {
+        Some(s) => s,
+        None => {
+            error_msg_box(window, format!("Layer {} has no valid texture", layer_name));
+            return;
+        }
+    };
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::OkCancel,
+        &format!("Re-encode texture of layer {} as:", layer_name),
+    );
+    let format = ComboBoxEnum::new(FORMATS);
+    format.set_active(&current);
+    dialog.content_area().pack_start(format.widget(), false, false, 0);
+    dialog.show_all();
+    let response = dialog.run();
+    let format = format.active();
+    dialog.close();
+    let format = match format {
+        Some(s) if response == gtk::ResponseType::Ok => s,
+        _ => return,
+    };
+    if format == anim::TextureFormat::Dxt1 {
+        let partial_alpha = {
+            let mut files = match info.files.try_lock() {
+                Ok(o) => o,
+                _ => return,
+            };
+            match files.file(tex_id.0, tex_id.1) {
+                Ok(Some(file)) => texture_repack::layer_has_partial_alpha(&file, tex_id.2),
+                Ok(None) => return,
+                Err(e) => Err(e),
+            }
+        };
+        match partial_alpha {
+            Ok(false) => (),
+            Ok(true) => {
+                let dialog = gtk::MessageDialog::new(
+                    Some(window),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Warning,
+                    gtk::ButtonsType::OkCancel,
+                    &format!(
+                        "Layer {} has semi-transparent pixels, which DXT1 will make either \
+                        fully transparent or opaque.\nContinue?",
+                        layer_name,
+                    ),
+                );
+                let response = dialog.run();
+                dialog.close();
+                if response != gtk::ResponseType::Ok {
+                    return;
+                }
+            }
+            Err(e) => {
+                error_msg_box(window, format!("Unable to recompress layer: {:?}", e));
+                return;
+            }
+        }
+    }
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result =
+            texture_repack::recompress_layer(&mut files, tex_id.0, tex_id.1, tex_id.2, format);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(result) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+            let msg = format!(
+                "Recompressed layer {}\nSize {} -> {} bytes",
+                layer_name, result.old_size, result.new_size,
+            );
+            info_msg_box(window, msg);
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to recompress layer: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn tighten_frame_bounds(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = texture_repack::tighten_frame_bounds(&mut files, tex_id.0, tex_id.1);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(result) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+            let msg = format!(
+                "Tightened bounds of {} frames of {:?} image {}\n\
+                Atlas {}x{} -> {}x{}, size {} -> {} bytes",
+                result.frames_changed, tex_id.1, tex_id.0,
+                result.old_atlas.0, result.old_atlas.1, result.new_atlas.0, result.new_atlas.1,
+                result.size.old_size, result.size.new_size,
+            );
+            info_msg_box(window, msg);
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to tighten frame bounds: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn set_grp_scales(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::None,
+        "Set the scale stored in every GRP to:",
+    );
+    dialog.add_button("1 (SD)", gtk::ResponseType::Other(1));
+    dialog.add_button("2 (HD2)", gtk::ResponseType::Other(2));
+    dialog.add_button("4 (HD)", gtk::ResponseType::Other(4));
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    let response = dialog.run();
+    dialog.close();
+    let scale = match response {
+        gtk::ResponseType::Other(x) => x as u8,
+        _ => return,
+    };
+    let tex_id = info.tex_id();
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = files.set_all_grp_scales(scale);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(changed) if changed.is_empty() => {
+            info_msg_box(window, format!("All GRPs already have scale {}", scale));
+        }
+        Ok(changed) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+            let sprites = changed.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            let msg = format!(
+                "Set scale of {} GRPs to {}\nChanged sprites: {}",
+                changed.len(), scale, sprites.join(", "),
+            );
+            info_msg_box(window, msg);
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to set GRP scale: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn export_atlas(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let filename = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let file = match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(s)) => s,
+            _ => return,
+        };
+        let layer_name = match file.is_anim() {
+            true => file.layer_names().get(tex_id.2).cloned()
+                .unwrap_or_else(|| format!("{}", tex_id.2)),
+            false => format!("frame{}", tex_id.2),
+        };
+        frame_export::atlas_filename(tex_id.0, tex_id.1, &layer_name)
+    };
+    let path = match save_png_dialog(window.upcast_ref(), &filename) {
+        Some(s) => s,
+        None => return,
+    };
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(file)) => frame_export::export_atlas(&file, tex_id.2, &path),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        }
+    };
+    if let Err(e) = result {
+        error_msg_box(window, format!("Unable to export atlas: {:?}", e));
+    }
+}
+
+fn export_contact_sheet(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let (filename, frame_count) = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let file = match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(s)) => s,
+            _ => return,
+        };
+        let layer_name = file.layer_names().get(tex_id.2).cloned()
+            .unwrap_or_else(|| format!("{}", tex_id.2));
+        let frame_count = file.frames().map(|x| x.len()).unwrap_or(0);
+        (frame_export::contact_sheet_filename(tex_id.0, tex_id.1, &layer_name), frame_count)
+    };
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::OkCancel,
+        "Contact sheet layout:",
+    );
+    let columns = gtk::SpinButton::with_range(1.0, 256.0, 1.0);
+    let default_columns = (frame_count as f64).sqrt().ceil().max(1.0);
+    columns.set_value(
+        select_dir::read_config_entry_int("contact_sheet_columns")
+            .map(|x| x as f64)
+            .unwrap_or(default_columns),
+    );
+    let padding = gtk::SpinButton::with_range(0.0, 64.0, 1.0);
+    padding.set_value(
+        select_dir::read_config_entry_int("contact_sheet_padding").unwrap_or(4) as f64,
+    );
+    let grid = gtk::Grid::new();
+    grid.set_column_spacing(5);
+    grid.attach(&gtk::Label::new(Some("Columns")), 0, 0, 1, 1);
+    grid.attach(&columns, 1, 0, 1, 1);
+    grid.attach(&gtk::Label::new(Some("Cell padding")), 0, 1, 1, 1);
+    grid.attach(&padding, 1, 1, 1, 1);
+    dialog.content_area().pack_start(&grid, false, false, 0);
+    dialog.show_all();
+    let response = dialog.run();
+    let columns = columns.value_as_int() as u32;
+    let padding = padding.value_as_int() as u32;
+    dialog.close();
+    if response != gtk::ResponseType::Ok {
+        return;
+    }
+    select_dir::set_config_entry("contact_sheet_columns", i64::from(columns));
+    select_dir::set_config_entry("contact_sheet_padding", i64::from(padding));
+    let path = match save_png_dialog(window.upcast_ref(), &filename) {
+        Some(s) => s,
+        None => return,
+    };
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(file)) => {
+                frame_export::export_contact_sheet(&file, tex_id.2, &path, columns, padding)
+            }
+            Ok(None) => return,
+            Err(e) => Err(e),
+        }
+    };
+    if let Err(e) = result {
+        error_msg_box(window, format!("Unable to export contact sheet: {:?}", e));
+    }
+}
+
+fn import_atlas(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let path = match open_png_dialog(window.upcast_ref()) {
+        Some(s) => s,
+        None => return,
+    };
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = frame_import::import_atlas(&mut files, tex_id.0, tex_id.1, tex_id.2, &path);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(()) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to import atlas: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn replace_frame(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow, frame: usize) {
+    let tex_id = info.tex_id();
+    let path = match open_png_dialog(window.upcast_ref()) {
+        Some(s) => s,
+        None => return,
+    };
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = frame_import::replace_frame(
+            &mut files, tex_id.0, tex_id.1, tex_id.2, frame, &path,
+        );
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(()) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to replace frame {}: {:?}", frame, e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn edit_frame_type(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow, frame: usize) {
+    let tex_id = info.tex_id();
+    let current = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        match files.file(tex_id.0, tex_id.1) {
+            Ok(Some(file)) => file.frames().and_then(|x| x.get(frame)).map(|x| x.unknown),
+            _ => None,
+        }
+    };
+    let current = match current {
+        Some(s) => s,
+        None => return,
+    };
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::OkCancel,
+        &format!("Frame type of frame {}:", frame),
+    );
+    let entry = IntEntry::new(IntSize::Int32);
+    entry.set_value(current);
+    dialog.content_area().pack_start(entry.widget(), false, false, 0);
+    dialog.show_all();
+    let response = dialog.run();
+    let value = entry.get_value();
+    dialog.close();
+    if response != gtk::ResponseType::Ok {
+        return;
+    }
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = files.set_frame_unknown(tex_id.0, tex_id.1, frame, value);
+        if let Ok(true) = result {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(true) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+        }
+        Ok(false) => (),
+        Err(e) => {
+            error_msg_box(window, format!("Unable to set type of frame {}: {:?}", frame, e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+/// Moves the selected frame by `x`, `y` pixels from its current offset.
+fn nudge_selected_frame(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow, x: i16, y: i16) {
+    if !info.offset_entries[0].is_sensitive() {
+        return;
+    }
+    let x_off = (info.offset_entries[0].value_as_int() as i16).saturating_add(x);
+    let y_off = (info.offset_entries[1].value_as_int() as i16).saturating_add(y);
+    set_selected_frame_offset(info, window, x_off, y_off);
+}
+
+fn set_selected_frame_offset(
+    info: &Arc<SpriteInfo>,
+    window: &gtk::ApplicationWindow,
+    x_off: i16,
+    y_off: i16,
+) {
+    let frame = match info.single_selected_frame() {
+        Some(s) => s,
+        None => return,
+    };
+    let tex_id = info.tex_id();
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        // Not calling changed_ty, as that would clear the frame selection while nudging
+        let result = files.set_frame_offset(tex_id.0, tex_id.1, frame, x_off, y_off);
+        if let Ok(true) = result {
+            info.draw_clear_all();
+        }
+        result
+    };
+    match result {
+        Ok(true) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+        }
+        Ok(false) => (),
+        Err(e) => {
+            error_msg_box(window, format!("Unable to move frame {}: {:?}", frame, e));
+        }
+    }
+    info.update_offset_entries();
+    info.draw_area.queue_draw();
+}
+
+fn delete_selected_frames(info: &Arc<SpriteInfo>, window: &gtk::ApplicationWindow) {
+    let tex_id = info.tex_id();
+    let frames = info.selected_frames.borrow().clone();
+    let result = {
+        let mut files = match info.files.try_lock() {
+            Ok(o) => o,
+            _ => return,
+        };
+        let result = files.remove_frames(tex_id.0, tex_id.1, &frames);
+        if result.is_ok() {
+            info.draw_clear_all();
+            if let Ok(mut file) = files.file(tex_id.0, tex_id.1) {
+                info.changed_ty(tex_id, &mut file);
+            }
+        }
+        result
+    };
+    match result {
+        Ok(skipped) => {
+            if let Some(a) = lookup_action(&info.sprite_actions, "is_dirty") {
+                a.activate(Some(&true.to_variant()));
+            }
+            if !skipped.is_empty() {
+                let msg = format!(
+                    "The frames were not deleted from {:?}, as its frame count differs",
+                    skipped,
+                );
+                info_msg_box(window, msg);
+            }
+        }
+        Err(e) => {
+            error_msg_box(window, format!("Unable to delete frames: {:?}", e));
+        }
+    }
+    info.draw_area.queue_draw();
+}
+
+fn enable_file_actions(app: &gtk::Application, files: &files::Files) {
+    if let Some(a) = lookup_action(app, "save") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "importFrames") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "importGrp") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "exportFrames") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "exportAllFrames") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "listMissingHd") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "pendingChanges") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "exportMetadataCsv") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "verifySprites") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "exportAtlas") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "importAtlas") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "exportContactSheet") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "compactTextures") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "copyFrame") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "tightenFrameBounds") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "recompressLayer") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "setGrpScales") {
+        a.set_enabled(!files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "previousSprite") {
+        a.set_enabled(true);
+    }
+    if let Some(a) = lookup_action(app, "cycleSpriteType") {
+        a.set_enabled(files.is_anim());
+    }
+    if let Some(a) = lookup_action(app, "editEntryCount") {
+        let enable = files.mainsd().is_some();
+        a.set_enabled(enable);
+    }
+}
+
+/// Maximum amount of paths in the "Open recent" menu.
+const RECENT_FILES_LIMIT: usize = 10;
+
+/// Recently opened files and directories, most recent first.
+fn recent_files() -> Vec<PathBuf> {
+    select_dir::read_config_entry_strings("recent_files")
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn add_recent_file(path: &Path) {
+    let mut recent = recent_files();
+    recent.retain(|x| x != path);
+    recent.insert(0, path.into());
+    recent.truncate(RECENT_FILES_LIMIT);
+    let value = recent.iter()
+        .map(|x| x.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    select_dir::set_config_entry("recent_files", value);
+    RECENT_MENU.with(|menu| update_recent_menu(menu));
+}
+
+/// Fills `menu` with items opening recent files that still exist.
+fn update_recent_menu(menu: &gio::Menu) {
+    menu.remove_all();
+    let mut added: Vec<PathBuf> = Vec::new();
+    for path in recent_files() {
+        if added.contains(&path) || !path.exists() {
+            continue;
+        }
+        // Labels would otherwise treat underscores as mnemonics
+        let label = path.to_string_lossy().replace('_', "__");
+        let item = gio::MenuItem::new(Some(&label), None);
+        item.set_action_and_target_value(
+            Some("app.openRecent"),
+            Some(&path.to_string_lossy().to_variant()),
+        );
+        menu.append_item(&item);
+        added.push(path);
+    }
+    if added.is_empty() {
+        menu.append_item(&gio::MenuItem::new(Some("No recent files"), Some("app.noRecentFiles")));
+    }
+}
+
+fn open(filename: &Path) {
+    let ui = ui();
+    match files::Files::init(filename) {
+        Ok((f, index)) => {
+            // Reopening the tree root opens all of it again, while single files
+            // don't have a root.
+            add_recent_file(f.root_path().unwrap_or(filename));
+            // Restore the sprite that was selected when the same file was last closed
+            let reopened = select_dir::read_config_entry("last_file")
+                .map(|x| Path::new(&x) == filename)
+                .unwrap_or(false);
+            let index = index.or_else(|| {
+                select_dir::read_config_entry_int("last_sprite")
+                    .filter(|_| reopened)
+                    .map(|x| x as usize)
+                    .filter(|&x| x < f.sprites().len())
+            });
+            select_dir::set_config_entry("last_file", &*filename.to_string_lossy());
+            let sprite_type = f.preferred_sprite_type();
+            ui.files_changed(&f);
+            enable_file_actions(&ui.app, &f);
+            {
+                STATE.with(|x| {
+                    let state = x.borrow();
+                    let mut files = state.files.lock();
+                    *files = f;
+                });
+            }
+            ui.info.draw_clear_all();
+            ui.info.sprite_actions.activate_action("select_sd", None);
+            let index = index.unwrap_or(0);
+            ui.info.select_sprite(index);
+            if sprite_type != SpriteType::Sd {
+                // Trees without mainsd can't show SD
+                ui.info.selector.select_type(sprite_type);
+            }
+            ui.list.list.select(index);
+            ui.info.previous_sprite.set(None);
+        }
+        Err(e) => {
+            let msg = format!("Unable to open file: {:?}", e);
+            ui.message(&msg);
+        }
+    }
+}
+
+fn save_png_dialog(parent: &gtk::Window, default_name: &str) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserNative::new(
+        Some("Save as..."),
+        Some(parent),
+        gtk::FileChooserAction::Save,
+        Some("Save"),
+        Some("Cancel")
+    );
+    if let Some(path) = select_dir::read_config_entry("save_png") {
+        dialog.set_current_folder(&path);
+    }
+    dialog.set_current_name(default_name);
+    dialog.set_do_overwrite_confirmation(true);
+    let filter = gtk::FileFilter::new();
+    filter.add_pattern("*.png");
+    filter.set_name(Some("PNG images"));
+    dialog.add_filter(&filter);
+    let result: gtk::ResponseType = dialog.run().into();
+    let result = if result == gtk::ResponseType::Accept {
+        if let Some(path) = dialog.filename() {
+            if let Some(parent) = path.parent() {
+                select_dir::set_config_entry("save_png", &*parent.to_string_lossy());
+            }
+        }
+        dialog.filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    result
+}
+
+fn save_csv_dialog(parent: &gtk::Window, default_name: &str) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserNative::new(
+        Some("Save as..."),
+        Some(parent),
+        gtk::FileChooserAction::Save,
+        Some("Save"),
+        Some("Cancel")
+    );
+    if let Some(path) = select_dir::read_config_entry("save_csv") {
+        dialog.set_current_folder(&path);
+    }
+    dialog.set_current_name(default_name);
+    dialog.set_do_overwrite_confirmation(true);
+    let filter = gtk::FileFilter::new();
+    filter.add_pattern("*.csv");
+    filter.set_name(Some("CSV files"));
+    dialog.add_filter(&filter);
+    let result: gtk::ResponseType = dialog.run().into();
+    let result = if result == gtk::ResponseType::Accept {
+        if let Some(path) = dialog.filename() {
+            if let Some(parent) = path.parent() {
+                select_dir::set_config_entry("save_csv", &*parent.to_string_lossy());
+            }
+        }
+        dialog.filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    result
+}
+
+fn open_png_dialog(parent: &gtk::Window) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserNative::new(
+        Some("Open..."),
+        Some(parent),
+        gtk::FileChooserAction::Open,
+        Some("Open"),
+        Some("Cancel")
+    );
+    if let Some(path) = select_dir::read_config_entry("save_png") {
+        dialog.set_current_folder(&path);
+    }
+    dialog.set_select_multiple(false);
+    let filter = gtk::FileFilter::new();
+    filter.add_pattern("*.png");
+    filter.set_name(Some("PNG images"));
+    dialog.add_filter(&filter);
+    let result: gtk::ResponseType = dialog.run().into();
+    let result = if result == gtk::ResponseType::Accept {
+        dialog.filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    result
+}
+
+/// If `select_folder` is set, selects a directory containing the files
+/// in same layout as they are in CASC.
+fn open_file_dialog(parent: &gtk::Window, select_folder: bool) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserNative::new(
+        Some("Open..."),
+        Some(parent),
+        match select_folder {
+            true => gtk::FileChooserAction::SelectFolder,
+            false => gtk::FileChooserAction::Open,
+        },
+        Some("Open"),
+        Some("Cancel")
+    );
+    if let Some(path) = select_dir::read_config_entry("open_file") {
+        dialog.set_current_folder(&path);
+    }
+    dialog.set_select_multiple(false);
+    if !select_folder {
+        let filter = gtk::FileFilter::new();
+        filter.add_pattern("*.anim");
+        filter.add_pattern("*.dds.grp");
+        filter.add_pattern("*.dds.vr4");
+        filter.set_name(Some("Valid files"));
+        dialog.add_filter(&filter);
+        let filter = gtk::FileFilter::new();
+        filter.add_pattern("*.*");
+        filter.set_name(Some("All files"));
+        dialog.add_filter(&filter);
+    }
+    //dialog.add_button("Open", gtk::ResponseType::Accept.into());
+    //dialog.add_button("Cancel", gtk::ResponseType::Cancel.into());
+    let result: gtk::ResponseType = dialog.run().into();
+    let result = if result == gtk::ResponseType::Accept {
+        if let Some(path) = dialog.filename() {
+            if let Some(parent) = path.parent() {
+                select_dir::set_config_entry("open_file", &*parent.to_string_lossy());
+            }
+        }
+        dialog.filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    result
+}
+
+fn get_css_provider() -> gtk::CssProvider {
+    CSS.with(|x| x.clone())
 }
 
 fn init_css_provider() -> gtk::CssProvider {
@@ -1537,6 +3981,7 @@ fn init_css_provider() -> gtk::CssProvider {
 }
 
 fn create_ui(app: &gtk::Application) -> Ui {
+    apply_ui_scale(ui_scale_percent());
     app.set_menubar(Some(&create_menu()));
 
     let window = gtk::ApplicationWindow::new(app);
@@ -1560,7 +4005,13 @@ fn create_ui(app: &gtk::Application) -> Ui {
         });
     });
     window.set_title(&title(None, false));
-    window.resize(800, 600);
+    restore_window_geometry(&window);
+    let i = info.clone();
+    window.connect_delete_event(move |w, _| {
+        save_window_state(w, &i);
+        i.save_notes();
+        Inhibit(false)
+    });
 
     let style_ctx = window.style_context();
     let css = crate::get_css_provider();
@@ -1574,6 +4025,48 @@ fn create_ui(app: &gtk::Application) -> Ui {
     }
 }
 
+/// Restores size and position saved by `save_window_state`,
+/// clamped to fit on the monitor.
+fn restore_window_geometry(window: &gtk::ApplicationWindow) {
+    let saved = |key| select_dir::read_config_entry_int(key).map(|x| x as i32);
+    let mut width = saved("window_width").unwrap_or_else(|| scaled(800));
+    let mut height = saved("window_height").unwrap_or_else(|| scaled(600));
+    let workarea = gdk::Display::default()
+        .and_then(|display| display.primary_monitor().or_else(|| display.monitor(0)))
+        .map(|monitor| monitor.workarea());
+    if let Some(ref area) = workarea {
+        width = width.min(area.width());
+        height = height.min(area.height());
+    }
+    window.resize(width.max(200), height.max(200));
+    if let (Some(mut x), Some(mut y)) = (saved("window_x"), saved("window_y")) {
+        if let Some(ref area) = workarea {
+            x = x.max(area.x()).min(area.x() + area.width() - width);
+            y = y.max(area.y()).min(area.y() + area.height() - height);
+        }
+        window.move_(x, y);
+    }
+    if saved("window_maximized") == Some(1) {
+        window.maximize();
+    }
+}
+
+fn save_window_state(window: &gtk::ApplicationWindow, info: &SpriteInfo) {
+    let maximized = window.is_maximized();
+    // Keep the unmaximized geometry
+    if !maximized {
+        let (width, height) = window.size();
+        let (x, y) = window.position();
+        select_dir::set_config_entry("window_width", width as i64);
+        select_dir::set_config_entry("window_height", height as i64);
+        select_dir::set_config_entry("window_x", x as i64);
+        select_dir::set_config_entry("window_y", y as i64);
+    }
+    select_dir::set_config_entry("window_maximized", maximized as i64);
+    // Only restored if the same file is opened again
+    select_dir::set_config_entry("last_sprite", info.sprite_index.load(Ordering::SeqCst) as i64);
+}
+
 fn label_section<O: IsA<gtk::Widget>>(name: &str, obj: &O) -> gtk::Box {
     let frame = gtk::Frame::new(Some(name));
     obj.set_margin_top(obj.margin_top() + 2);
@@ -1586,6 +4079,23 @@ fn label_section<O: IsA<gtk::Widget>>(name: &str, obj: &O) -> gtk::Box {
     bx
 }
 
+/// Shows a longer, selectable text in a scrollable window.
+fn text_window<W: IsA<gtk::Window>>(parent: &W, title: &str, text: &str) {
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    let buffer = gtk::TextBuffer::new(None::<&gtk::TextTagTable>);
+    buffer.set_text(text);
+    let view = gtk::TextView::with_buffer(&buffer);
+    view.set_editable(false);
+    view.set_monospace(true);
+    let scroll = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+    scroll.add(&view);
+    window.add(&scroll);
+    window.set_title(title);
+    window.set_default_size(scaled(500), scaled(400));
+    window.set_transient_for(Some(parent));
+    window.show_all();
+}
+
 fn info_msg_box<W: IsA<gtk::Window>, S: AsRef<str>>(window: &W, msg: S) {
     let dialog = gtk::MessageDialog::new(
         Some(window),
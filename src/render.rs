@@ -3,7 +3,7 @@ use std::rc::Rc;
 
 use anyhow::{anyhow, Context, Error};
 use cgmath::conv::array4x4;
-use cgmath::{Matrix4, vec4};
+use cgmath::{Matrix4, SquareMatrix, vec4};
 use glium::backend::glutin::headless::Headless;
 use glium::index::{IndexBuffer, PrimitiveType};
 use glium::texture::{self, ClientFormat, Texture1d, Texture2d};
@@ -15,17 +15,69 @@ use crate::anim::RawTexture;
 use crate::gl;
 use crate::shaders::{self, Program};
 
+/// Maximum amount of textures kept in the GL texture cache.
+/// The least recently used texture is dropped once the limit is reached.
+pub const MAX_CACHED_TEXTURES: usize = 24;
+
+/// Transform from window coordinates to texture pixels, see `RenderState::window_to_pixel`.
+#[derive(Copy, Clone, Debug)]
+pub struct WindowToPixel(Matrix4<f32>);
+
+impl WindowToPixel {
+    pub fn map(&self, x: f64, y: f64) -> (f32, f32) {
+        let pos = self.0 * vec4(x as f32, y as f32, 0.0, 1.0);
+        (pos.x, pos.y)
+    }
+}
+
 pub struct RenderState {
     gl: gl::Context,
     draw_params: DrawParams,
 }
 
+/// What is shown behind the sprite.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Background {
+    Black,
+    Magenta,
+    /// Gray squares with fixed size in window pixels, making transparency visible.
+    Checkerboard,
+}
+
+/// Size of `Background::Checkerboard` squares in window pixels.
+const CHECKERBOARD_CELL_SIZE: f32 = 8.0;
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum SpriteMode {
     Raw,
     Ao,
     Depth,
     Normal,
+    /// Single-channel texture from `luminance_texture`.
+    Grayscale,
+}
+
+/// Channel of the rendered sprite that is shown, others than `Rgba` are shown as grayscale.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Channel {
+    Rgba,
+    Alpha,
+    Red,
+    Green,
+    Blue,
+}
+
+impl Channel {
+    /// Value of the `channel` uniform in sprite fragment shaders.
+    fn uniform_value(self) -> i32 {
+        match self {
+            Channel::Rgba => 0,
+            Channel::Alpha => 1,
+            Channel::Red => 2,
+            Channel::Green => 3,
+            Channel::Blue => 4,
+        }
+    }
 }
 
 impl RenderState {
@@ -58,11 +110,31 @@ impl RenderState {
             &shaders::SPRITE_VERTEX,
             &shaders::NORMAL_FRAGMENT,
         );
+        let grayscale_program = Program::new(
+            gl.facade(),
+            &shaders::SPRITE_VERTEX,
+            &shaders::GRAYSCALE_FRAGMENT,
+        );
+        let team_color_program = Program::new(
+            gl.facade(),
+            &shaders::SPRITE_VERTEX,
+            &shaders::TEAM_COLOR_FRAGMENT,
+        );
+        let monochrome_program = Program::new(
+            gl.facade(),
+            &shaders::SPRITE_VERTEX,
+            &shaders::MONOCHROME_FRAGMENT,
+        );
         let paletted_program = Program::new(
             gl.facade(),
             &shaders::PALETTED_VERTEX,
             &shaders::PALETTED_FRAGMENT,
         );
+        let checkerboard_program = Program::new(
+            gl.facade(),
+            &shaders::SPRITE_VERTEX,
+            &shaders::CHECKERBOARD_FRAGMENT,
+        );
         let lines = DrawLines::new(&mut gl);
         RenderState {
             gl,
@@ -73,10 +145,18 @@ impl RenderState {
                 ao_program,
                 depth_program,
                 normal_program,
+                grayscale_program,
+                team_color_program,
+                monochrome_program,
                 paletted_program,
+                checkerboard_program,
                 cached_textures: Vec::new(),
                 cached_palette: None,
                 lines,
+                view_rect: None,
+                zoom: 1.0,
+                pan: (0.0, 0.0),
+                channel: Channel::Rgba,
             },
         }
     }
@@ -101,43 +181,82 @@ impl RenderState {
         self.gl.framebuf_bytes()
     }
 
-    pub fn clear_framebuf(&mut self) {
+    pub fn clear_framebuf(&mut self, background: Background) {
         let (mut buf, _facade) = self.gl.framebuf();
-        buf.clear_color(0.0, 0.0, 0.0, 1.0);
+        match background {
+            Background::Magenta => buf.clear_color(1.0, 0.0, 1.0, 1.0),
+            Background::Black | Background::Checkerboard => buf.clear_color(0.0, 0.0, 0.0, 1.0),
+        }
     }
 
-    pub fn render_sprite(&mut self, texture: &Texture2d, mode: SpriteMode) -> Result<(), Error> {
+    /// Draws the background under area where `texture` will be rendered.
+    /// Solid backgrounds are already drawn by `clear_framebuf`.
+    pub fn render_background(
+        &mut self,
+        texture: &Texture2d,
+        background: Background,
+    ) -> Result<(), Error> {
+        if background != Background::Checkerboard {
+            return Ok(());
+        }
         let glium_params = glium::draw_parameters::DrawParameters {
-            blend: glium::Blend::alpha_blending(),
+            scissor: self.scissor_rect(texture),
             ..Default::default()
         };
+        let tex_to_window = self.texture_to_window(texture);
+        let (mut buf, facade) = self.gl.framebuf();
+        let uniforms = uniform! {
+            transform: array4x4(tex_to_window),
+            cell_size: CHECKERBOARD_CELL_SIZE,
+        };
+        buf.draw(
+            &self.draw_params.vertices,
+            &self.draw_params.indices,
+            self.draw_params.checkerboard_program.program(facade),
+            &uniforms,
+            &glium_params,
+        )?;
+        Ok(())
+    }
+
+    /// Zooms the view to show only `rect` (in texture pixels) of the texture,
+    /// scaled to fill the view. `None` shows the entire texture.
+    pub fn set_view_rect(&mut self, rect: Option<Rect>) {
+        self.draw_params.view_rect = rect;
+    }
+
+    /// Scales the rendered image by `zoom` around the view center, and then
+    /// moves it by `pan` window pixels.
+    pub fn set_zoom_pan(&mut self, zoom: f32, pan: (f32, f32)) {
+        self.draw_params.zoom = zoom;
+        self.draw_params.pan = pan;
+    }
+
+    /// Selects the channel shown by the sprite rendering functions.
+    /// Has no effect on AO, depth, normal and grayscale modes of `render_sprite`.
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.draw_params.channel = channel;
+    }
+
+    pub fn render_sprite(&mut self, texture: &Texture2d, mode: SpriteMode) -> Result<(), Error> {
+        let glium_params = self.draw_parameters(texture);
         let sampler = glium::uniforms::Sampler::new(texture)
             .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
             .minify_filter(glium::uniforms::MinifySamplerFilter::Linear);
 
+        let tex_to_window = self.texture_to_window(texture);
         let (mut buf, facade) = self.gl.framebuf();
-        let (buf_width, buf_height) = self.gl.buf_dimensions();
-        // scale to view, scale + transform view to
-        let tex_width = texture.width() as f32;
-        let tex_height = texture.height() as f32;
-        let mut render_width = tex_width.min(buf_width as f32);
-        let mut render_height = tex_height.min(buf_height as f32);
-        // Keep aspect ratio
-        if render_width / tex_width < render_height / tex_height {
-            render_height = (render_width / tex_width) * tex_height;
-        } else {
-            render_width = (render_height / tex_height) * tex_width;
-        }
-        let tex_to_window = self.to_window_matrix(render_width, render_height);
         let uniforms = uniform! {
             transform: array4x4(tex_to_window),
             tex: sampler,
+            channel: self.draw_params.channel.uniform_value(),
         };
         let program = match mode {
             SpriteMode::Raw => self.draw_params.program.program(facade),
             SpriteMode::Ao => self.draw_params.ao_program.program(facade),
             SpriteMode::Depth => self.draw_params.depth_program.program(facade),
             SpriteMode::Normal => self.draw_params.normal_program.program(facade),
+            SpriteMode::Grayscale => self.draw_params.grayscale_program.program(facade),
         };
         buf.draw(
             &self.draw_params.vertices,
@@ -154,10 +273,7 @@ impl RenderState {
         texture: &Texture2d,
         palette: &Texture1d,
     ) -> Result<(), Error> {
-        let glium_params = glium::draw_parameters::DrawParameters {
-            blend: glium::Blend::alpha_blending(),
-            ..Default::default()
-        };
+        let glium_params = self.draw_parameters(texture);
         let sampler = glium::uniforms::Sampler::new(texture)
             .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
             .minify_filter(glium::uniforms::MinifySamplerFilter::Linear);
@@ -165,24 +281,13 @@ impl RenderState {
             .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
             .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest);
 
+        let tex_to_window = self.texture_to_window(texture);
         let (mut buf, facade) = self.gl.framebuf();
-        let (buf_width, buf_height) = self.gl.buf_dimensions();
-        // scale to view, scale + transform view to
-        let tex_width = texture.width() as f32;
-        let tex_height = texture.height() as f32;
-        let mut render_width = tex_width.min(buf_width as f32);
-        let mut render_height = tex_height.min(buf_height as f32);
-        // Keep aspect ratio
-        if render_width / tex_width < render_height / tex_height {
-            render_height = (render_width / tex_width) * tex_height;
-        } else {
-            render_width = (render_height / tex_height) * tex_width;
-        }
-        let tex_to_window = self.to_window_matrix(render_width, render_height);
         let uniforms = uniform! {
             transform: array4x4(tex_to_window),
             tex: sampler,
             palette: palette_sampler,
+            channel: self.draw_params.channel.uniform_value(),
         };
         buf.draw(
             &self.draw_params.vertices,
@@ -194,6 +299,181 @@ impl RenderState {
         Ok(())
     }
 
+    /// Renders `texture` with its colors multiplied by `team_color` where
+    /// the alpha of `mask` (the teamcolor layer) is set.
+    pub fn render_team_color(
+        &mut self,
+        texture: &Texture2d,
+        mask: &Texture2d,
+        team_color: [f32; 3],
+    ) -> Result<(), Error> {
+        let glium_params = self.draw_parameters(texture);
+        let sampler = glium::uniforms::Sampler::new(texture)
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Linear);
+        let mask_sampler = glium::uniforms::Sampler::new(mask)
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Linear);
+
+        let tex_to_window = self.texture_to_window(texture);
+        let (mut buf, facade) = self.gl.framebuf();
+        let uniforms = uniform! {
+            transform: array4x4(tex_to_window),
+            tex: sampler,
+            mask: mask_sampler,
+            team_color: team_color,
+            channel: self.draw_params.channel.uniform_value(),
+        };
+        buf.draw(
+            &self.draw_params.vertices,
+            &self.draw_params.indices,
+            self.draw_params.team_color_program.program(facade),
+            &uniforms,
+            &glium_params,
+        )?;
+        Ok(())
+    }
+
+    /// Renders a monochrome layer with its colors multiplied by `tint`.
+    pub fn render_monochrome(&mut self, texture: &Texture2d, tint: [f32; 3]) -> Result<(), Error> {
+        let glium_params = self.draw_parameters(texture);
+        let sampler = glium::uniforms::Sampler::new(texture)
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Linear);
+
+        let tex_to_window = self.texture_to_window(texture);
+        let (mut buf, facade) = self.gl.framebuf();
+        let uniforms = uniform! {
+            transform: array4x4(tex_to_window),
+            tex: sampler,
+            tint: tint,
+            channel: self.draw_params.channel.uniform_value(),
+        };
+        buf.draw(
+            &self.draw_params.vertices,
+            &self.draw_params.indices,
+            self.draw_params.monochrome_program.program(facade),
+            &uniforms,
+            &glium_params,
+        )?;
+        Ok(())
+    }
+
+    fn draw_parameters(&self, texture: &Texture2d) -> glium::DrawParameters<'static> {
+        glium::draw_parameters::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            scissor: self.scissor_rect(texture),
+            ..Default::default()
+        }
+    }
+
+    /// Size in window pixels that the texture (or the view rect) is rendered at.
+    fn render_size(&self, texture: &Texture2d) -> (f32, f32) {
+        let (buf_width, buf_height) = self.gl.buf_dimensions();
+        // scale to view, scale + transform view to
+        let (width, height, max_width, max_height) = match self.draw_params.view_rect {
+            Some(rect) => (
+                rect.width.max(1) as f32,
+                rect.height.max(1) as f32,
+                buf_width as f32,
+                buf_height as f32,
+            ),
+            None => {
+                let tex_width = texture.width() as f32;
+                let tex_height = texture.height() as f32;
+                (
+                    tex_width,
+                    tex_height,
+                    tex_width.min(buf_width as f32),
+                    tex_height.min(buf_height as f32),
+                )
+            }
+        };
+        let mut render_width = max_width;
+        let mut render_height = max_height;
+        // Keep aspect ratio
+        if render_width / width < render_height / height {
+            render_height = (render_width / width) * height;
+        } else {
+            render_width = (render_height / height) * width;
+        }
+        (render_width, render_height)
+    }
+
+    /// With a view rect set, clips drawing to the area where the rect is shown.
+    /// Otherwise clips to the view if it is zoomed or panned.
+    fn scissor_rect(&self, texture: &Texture2d) -> Option<glium::Rect> {
+        let (buf_width, buf_height) = self.gl.buf_dimensions();
+        let (buf_width, buf_height) = (buf_width as f32, buf_height as f32);
+        let zoom = self.draw_params.zoom;
+        let pan = self.draw_params.pan;
+        let (left, bottom, right, top) = if self.draw_params.view_rect.is_some() {
+            let (render_width, render_height) = self.render_size(texture);
+            let width = render_width * zoom;
+            let height = render_height * zoom;
+            let left = (buf_width - width) / 2.0 + pan.0;
+            let bottom = (buf_height - height) / 2.0 + pan.1;
+            (left, bottom, left + width, bottom + height)
+        } else if zoom != 1.0 || pan != (0.0, 0.0) {
+            (0.0, 0.0, buf_width, buf_height)
+        } else {
+            return None;
+        };
+        let left = left.max(0.0).min(buf_width);
+        let bottom = bottom.max(0.0).min(buf_height);
+        let right = right.max(left).min(buf_width);
+        let top = top.max(bottom).min(buf_height);
+        Some(glium::Rect {
+            left: left as u32,
+            bottom: bottom as u32,
+            width: (right - left) as u32,
+            height: (top - bottom) as u32,
+        })
+    }
+
+    /// Transform applying zoom and pan in window coordinates.
+    fn zoom_pan_matrix(&self) -> Matrix4<f32> {
+        let (buf_width, buf_height) = self.gl.buf_dimensions();
+        let buf_stride = self.gl.stride();
+        let zoom = self.draw_params.zoom;
+        let (pan_x, pan_y) = self.draw_params.pan;
+        // Center of the view, same as the shift in `to_window_matrix`
+        let center_x = -1.0 + buf_width as f32 / buf_stride as f32;
+        let shift_x = center_x + pan_x * 2.0 / buf_stride as f32 - center_x * zoom;
+        let shift_y = pan_y * 2.0 / buf_height as f32;
+        Matrix4::from_cols(
+            vec4(zoom,      0.0,        0.0,    0.0),
+            vec4(0.0,       zoom,       0.0,    0.0),
+            vec4(0.0,       0.0,        1.0,    0.0),
+            vec4(shift_x,   shift_y,    0.0,    1.0),
+        )
+    }
+
+    /// Transform from the (-1, -1), (1, 1) quad covering the texture to window.
+    fn texture_to_window(&self, texture: &Texture2d) -> Matrix4<f32> {
+        let (render_width, render_height) = self.render_size(texture);
+        let window = self.zoom_pan_matrix() * self.to_window_matrix(render_width, render_height);
+        match self.draw_params.view_rect {
+            Some(rect) => {
+                let tex_width = texture.width() as f32;
+                let tex_height = texture.height() as f32;
+                let width = rect.width.max(1) as f32;
+                let height = rect.height.max(1) as f32;
+                let scale_x = tex_width / width;
+                let scale_y = tex_height / height;
+                let center_x = -1.0 + (2.0 * rect.x as f32 + width) / tex_width;
+                let center_y = -1.0 + (2.0 * rect.y as f32 + height) / tex_height;
+                window * Matrix4::from_cols(
+                    vec4(scale_x,               0.0,                    0.0,    0.0),
+                    vec4(0.0,                   scale_y,                0.0,    0.0),
+                    vec4(0.0,                   0.0,                    1.0,    0.0),
+                    vec4(-center_x * scale_x,   -center_y * scale_y,    0.0,    1.0),
+                )
+            }
+            None => window,
+        }
+    }
+
     fn to_window_matrix(&self, width: f32, height: f32) -> Matrix4<f32> {
         // (render_width / buf_width) * (buf_width / buf_stride)
         let (buf_width, buf_height) = self.gl.buf_dimensions();
@@ -210,52 +490,77 @@ impl RenderState {
         )
     }
 
-    pub fn render_lines<F: FnOnce() -> Vec<(Rect, Color, u8)>>(
+    /// Transform from texture pixel coordinates to window.
+    fn pixel_to_window(&self, texture: &Texture2d) -> Matrix4<f32> {
+        let tex_width = texture.width() as f32;
+        let tex_height = texture.height() as f32;
+        let pixel_to_tex = Matrix4::from_cols(
+            vec4(2.0 / tex_width,   0.0,                0.0,    0.0),
+            vec4(0.0,               2.0 / tex_height,   0.0,    0.0),
+            vec4(0.0,               0.0,                1.0,    0.0),
+            vec4(-1.0,              -1.0,               0.0,    1.0),
+        );
+        self.texture_to_window(texture) * pixel_to_tex
+    }
+
+    /// Maps window positions to texture pixels of `texture` as currently rendered.
+    pub fn window_to_pixel(&self, texture: &Texture2d) -> Option<WindowToPixel> {
+        let (_, buf_height) = self.gl.buf_dimensions();
+        let buf_stride = self.gl.stride();
+        let window_to_gl = Matrix4::from_cols(
+            vec4(2.0 / buf_stride as f32,   0.0,                        0.0,    0.0),
+            vec4(0.0,                       2.0 / buf_height as f32,    0.0,    0.0),
+            vec4(0.0,                       0.0,                        1.0,    0.0),
+            vec4(-1.0,                      -1.0,                       0.0,    1.0),
+        );
+        self.pixel_to_window(texture).invert()
+            .map(|inverse| WindowToPixel(inverse * window_to_gl))
+    }
+
+    /// The lines are cached per texture, `selected_frames` or `style` changing causes
+    /// them to be regenerated.
+    pub fn render_lines<F: FnOnce(&LineStyle) -> Vec<(Rect, Color, u8)>>(
         &mut self,
         tex_id: TextureId,
         texture: &Texture2d,
+        selected_frames: &[usize],
+        style: &LineStyle,
         gen_lines: F,
     ) -> Result<(), Error> {
         let glium_params = glium::draw_parameters::DrawParameters {
-            blend: glium::Blend::alpha_blending(),
-            ..Default::default()
+            line_width: Some(style.width),
+            ..self.draw_parameters(texture)
         };
+        let transform = self.pixel_to_window(texture);
         let (mut buf, facade) = self.gl.framebuf();
-        let (buf_width, buf_height) = self.gl.buf_dimensions();
-        let lines =
-            self.draw_params.lines.texture_lines.buffer_for_texture(facade, &tex_id, gen_lines);
+        let lines = self.draw_params.lines.texture_lines
+            .buffer_for_texture(facade, &tex_id, selected_frames, style, gen_lines);
+        let uniforms = uniform! {
+            transform: array4x4(transform),
+        };
+        buf.draw(
+            &lines.vertices,
+            &lines.indices,
+            self.draw_params.lines.program.program(facade),
+            &uniforms,
+            &glium_params,
+        )?;
+        Ok(())
+    }
 
-        let buf_stride = self.gl.stride();
-        let tex_width = texture.width() as f32;
-        let tex_height = texture.height() as f32;
-        let mut render_width = tex_width.min(buf_width as f32);
-        let mut render_height = tex_height.min(buf_height as f32);
-        // Keep aspect ratio
-        if render_width / tex_width < render_height / tex_height {
-            render_height = (render_width / tex_width) * tex_height;
-        } else {
-            render_width = (render_height / tex_height) * tex_width;
-        }
-        // (render_width / buf_width) * (buf_width / buf_stride)
-        let scale_x = render_width / buf_stride as f32;
-        let scale_y = render_height / buf_height as f32;
-        let shift_x = -1.0 + buf_width as f32 / buf_stride as f32;
-        let shift_y = 0.0;
-        let tex_to_window = Matrix4::from_cols(
-            vec4(scale_x,   0.0,        0.0,    0.0),
-            vec4(0.0,       scale_y,    0.0,    0.0),
-            vec4(0.0,       0.0,        1.0,    0.0),
-            vec4(shift_x,   shift_y,    0.0,    1.0),
-        );
-        let pixel_to_tex = Matrix4::from_cols(
-            vec4(2.0 / tex_width,   0.0,                0.0,    0.0),
-            vec4(0.0,               2.0 / tex_height,   0.0,    0.0),
-            vec4(0.0,               0.0,                1.0,    0.0),
-            vec4(-1.0,              -1.0,               0.0,    1.0),
-        );
+    /// Draws an uncached rectangle outline, e.g. for highlighting a single frame.
+    pub fn render_highlight(
+        &mut self,
+        texture: &Texture2d,
+        rect: Rect,
+        color: Color,
+    ) -> Result<(), Error> {
+        let glium_params = self.draw_parameters(texture);
+        let transform = self.pixel_to_window(texture);
+        let (mut buf, facade) = self.gl.framebuf();
+        let lines = LineBuffer::new(facade, &[(rect, color, 0)]);
         let uniforms = uniform! {
-            //transform: array4x4(pixel_to_tex * tex_to_window),
-            transform: array4x4(tex_to_window * pixel_to_tex),
+            transform: array4x4(transform),
         };
         buf.draw(
             &lines.vertices,
@@ -274,7 +579,10 @@ impl RenderState {
         let cached_textures = &mut self.draw_params.cached_textures;
         let cached = cached_textures.iter().position(|x| x.1 == tex_id);
         if let Some(index) = cached {
-            Ok(cached_textures[index].0.clone())
+            // Move to back so that the least recently used entry is first.
+            let entry = cached_textures.remove(index);
+            cached_textures.push(entry);
+            Ok(cached_textures.last().unwrap().0.clone())
         } else {
             let facade = self.gl.facade();
             let image = gen_image()
@@ -310,7 +618,7 @@ impl RenderState {
             if clear {
                 cached_textures.clear();
             }
-            cached_textures.push((Rc::new(texture), tex_id));
+            push_lru(cached_textures, (Rc::new(texture), tex_id), MAX_CACHED_TEXTURES);
             Ok(cached_textures.last().unwrap().0.clone())
         }
     }
@@ -343,6 +651,37 @@ impl RenderState {
     }
 }
 
+/// Converts a RGBA texture to one byte per pixel, to be drawn with `SpriteMode::Grayscale`.
+/// Monochrome layers only store their data in alpha, other layers use the RGB luminance.
+/// (The result is uploaded as a single-channel texture like paletted ones are)
+pub fn luminance_texture(image: &RawTexture, monochrome: bool) -> RawTexture {
+    let data = image.data.chunks_exact(4)
+        .map(|x| match monochrome {
+            true => x[3],
+            false => {
+                let sum = u32::from(x[0]) * 299 + u32::from(x[1]) * 587 + u32::from(x[2]) * 114;
+                (sum / 1000) as u8
+            }
+        })
+        .collect();
+    RawTexture {
+        data,
+        width: image.width,
+        height: image.height,
+        is_paletted: true,
+    }
+}
+
+/// Appends `value` to `cache`, removing the oldest entries
+/// if there would be more than `limit` of them.
+fn push_lru<T>(cache: &mut Vec<T>, value: T, limit: usize) {
+    let limit = limit.max(1);
+    if cache.len() >= limit {
+        cache.drain(..cache.len() + 1 - limit);
+    }
+    cache.push(value);
+}
+
 struct DrawParams {
     vertices: VertexBuffer<gl::Vertex>,
     indices: IndexBuffer<u32>,
@@ -351,16 +690,25 @@ struct DrawParams {
     ao_program: Program,
     depth_program: Program,
     normal_program: Program,
+    grayscale_program: Program,
+    team_color_program: Program,
+    monochrome_program: Program,
     paletted_program: Program,
+    checkerboard_program: Program,
     cached_textures: Vec<(Rc<Texture2d>, TextureId)>,
     cached_palette: Option<Rc<Texture1d>>,
+    view_rect: Option<Rect>,
+    zoom: f32,
+    pan: (f32, f32),
+    channel: Channel,
 }
 
 /// sprite_id, type, layer
 #[derive(Eq, Copy, Clone, PartialEq, Debug)]
 pub struct TextureId(pub usize, pub SpriteType, pub usize);
 
-struct TextureLines(Vec<(TextureId, LineBuffer)>);
+/// Texture, frames that were selected and style used when the lines were generated, lines
+struct TextureLines(Vec<(TextureId, Vec<usize>, LineStyle, LineBuffer)>);
 
 struct DrawLines {
     texture_lines: TextureLines,
@@ -368,57 +716,24 @@ struct DrawLines {
 }
 
 impl TextureLines {
-    fn buffer_for_texture<F: FnOnce() -> Vec<(Rect, Color, u8)>>(
+    fn buffer_for_texture<F: FnOnce(&LineStyle) -> Vec<(Rect, Color, u8)>>(
         &mut self,
         facade: &Headless,
         tex_id: &TextureId,
+        selected_frames: &[usize],
+        style: &LineStyle,
         init: F,
     ) -> &mut LineBuffer {
+        if let Some(pos) = self.0.iter().position(|x| x.0 == *tex_id) {
+            if self.0[pos].1 != selected_frames || self.0[pos].2 != *style {
+                self.0.remove(pos);
+            }
+        }
         match self.0.iter().position(|x| x.0 == *tex_id) {
-            Some(s) => &mut self.0[s].1,
+            Some(s) => &mut self.0[s].3,
             None => {
-                let rects = init();
-                let mut vertices = Vec::with_capacity(rects.len() * 4);
-                for &(ref rect, color, ty) in rects.iter() {
-                    let color = [color.0, color.1, color.2, color.3];
-                    let left = rect.x as f32;
-                    let top = rect.y as f32;
-                    let right = left + rect.width as f32;
-                    let bottom = top + rect.height as f32;
-                    vertices.extend([
-                        gl::LineVertex {
-                            pos: [left, top],
-                            color,
-                            ty,
-                        },
-                        gl::LineVertex {
-                            pos: [right, top],
-                            color,
-                            ty,
-                        },
-                        gl::LineVertex {
-                            pos: [left, bottom],
-                            color,
-                            ty,
-                        },
-                        gl::LineVertex {
-                            pos: [right, bottom],
-                            color,
-                            ty,
-                        },
-                    ].iter().cloned());
-                }
-                let mut indices = Vec::with_capacity(rects.len() * 8);
-                for i in 0..rects.len() {
-                    let i = i as u32 * 4;
-                    indices.extend(
-                        [i, i + 1, i + 1, i + 3, i + 3, i + 2, i + 2, i].iter().cloned()
-                    );
-                }
-                let vertices = VertexBuffer::new(facade, &vertices)
-                    .expect("Couldn't create vertex buffer");
-                let indices = IndexBuffer::new(facade, PrimitiveType::LinesList, &indices)
-                    .expect("Couldn't create vertex buffer");
+                let rects = init(style);
+                let buffer = LineBuffer::new(facade, &rects);
 
                 // Hacky, clear cache when sprite id changes, so the sprite can be reloaded
                 // by clicking away and back.
@@ -427,12 +742,9 @@ impl TextureLines {
                     self.0.clear();
                 }
 
-                self.0.push((tex_id.clone(), LineBuffer {
-                    vertices,
-                    indices,
-                }));
+                self.0.push((tex_id.clone(), selected_frames.to_vec(), *style, buffer));
                 let pos = self.0.len() - 1;
-                &mut self.0[pos].1
+                &mut self.0[pos].3
             }
         }
     }
@@ -443,6 +755,56 @@ struct LineBuffer {
     indices: IndexBuffer<u32>,
 }
 
+impl LineBuffer {
+    fn new(facade: &Headless, rects: &[(Rect, Color, u8)]) -> LineBuffer {
+        let mut vertices = Vec::with_capacity(rects.len() * 4);
+        for &(ref rect, color, ty) in rects.iter() {
+            let color = [color.0, color.1, color.2, color.3];
+            let left = rect.x as f32;
+            let top = rect.y as f32;
+            let right = left + rect.width as f32;
+            let bottom = top + rect.height as f32;
+            vertices.extend([
+                gl::LineVertex {
+                    pos: [left, top],
+                    color,
+                    ty,
+                },
+                gl::LineVertex {
+                    pos: [right, top],
+                    color,
+                    ty,
+                },
+                gl::LineVertex {
+                    pos: [left, bottom],
+                    color,
+                    ty,
+                },
+                gl::LineVertex {
+                    pos: [right, bottom],
+                    color,
+                    ty,
+                },
+            ].iter().cloned());
+        }
+        let mut indices = Vec::with_capacity(rects.len() * 8);
+        for i in 0..rects.len() {
+            let i = i as u32 * 4;
+            indices.extend(
+                [i, i + 1, i + 1, i + 3, i + 3, i + 2, i + 2, i].iter().cloned()
+            );
+        }
+        let vertices = VertexBuffer::new(facade, &vertices)
+            .expect("Couldn't create vertex buffer");
+        let indices = IndexBuffer::new(facade, PrimitiveType::LinesList, &indices)
+            .expect("Couldn't create vertex buffer");
+        LineBuffer {
+            vertices,
+            indices,
+        }
+    }
+}
+
 impl DrawLines {
     fn new(gl: &mut gl::Context) -> DrawLines {
         let program = Program::new(gl.facade(), &shaders::LINE_VERTEX, &shaders::LINE_FRAGMENT);
@@ -457,9 +819,18 @@ fn sprite_render_program(gl: &mut gl::Context) -> Program {
     Program::new(gl.facade(), &shaders::SPRITE_VERTEX, &shaders::SPRITE_FRAGMENT)
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Color(pub f32, pub f32, pub f32, pub f32);
 
+/// Colors and width of the texture and frame bounds drawn by `render_lines`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LineStyle {
+    pub bounds: Color,
+    pub frames: Color,
+    /// In window pixels. Drivers may not support widths other than 1.
+    pub width: f32,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub struct Rect {
     pub x: u32,
@@ -478,3 +849,29 @@ impl Rect {
         }
     }
 }
+
+#[test]
+fn test_push_lru() {
+    let mut cache = Vec::new();
+    for i in 0..5 {
+        push_lru(&mut cache, i, 3);
+    }
+    assert_eq!(cache, vec![2, 3, 4]);
+    push_lru(&mut cache, 5, 2);
+    assert_eq!(cache, vec![4, 5]);
+}
+
+#[test]
+fn test_luminance_texture() {
+    let image = RawTexture {
+        data: vec![255, 255, 255, 0, 255, 0, 0, 128, 0, 0, 0, 255],
+        width: 3,
+        height: 1,
+        is_paletted: false,
+    };
+    let mono = luminance_texture(&image, true);
+    assert_eq!(mono.data, vec![0, 128, 255]);
+    assert!(mono.is_paletted);
+    let gray = luminance_texture(&image, false);
+    assert_eq!(gray.data, vec![255, 76, 0]);
+}
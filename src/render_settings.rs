@@ -7,6 +7,7 @@ use gtk::prelude::*;
 use crate::combo_box_enum::ComboBoxEnum;
 use crate::ui_helpers::*;
 use crate::label_section;
+use crate::select_dir::{read_config_entry, read_config_entry_int, set_config_entry};
 
 pub struct RenderSettingsWidget {
     root: gtk::Box,
@@ -17,6 +18,16 @@ pub struct RenderSettingsWidget {
 pub struct RenderSettings {
     pub decode_normal: bool,
     pub ao_depth_mode: AoDepth,
+    pub grayscale: bool,
+    /// Tints the diffuse layer with this color, using the teamcolor layer as a mask.
+    pub team_color: Option<[f32; 3]>,
+    /// Color that monochrome layers are multiplied with.
+    pub monochrome_tint: [f32; 3],
+    /// Color of the texture bounds drawn over the sprite.
+    pub bounds_color: [f32; 3],
+    /// Color of frames with frame type 0 drawn over the sprite.
+    pub frame_color: [f32; 3],
+    pub line_width: f32,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -26,6 +37,29 @@ pub enum AoDepth {
     Depth,
 }
 
+/// Player 1 red
+const DEFAULT_TEAM_COLOR: [f32; 3] = [0.957, 0.016, 0.016];
+
+/// White, which shows monochrome layers unchanged.
+const DEFAULT_MONOCHROME_TINT: [f32; 3] = [1.0, 1.0, 1.0];
+
+const DEFAULT_BOUNDS_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+const DEFAULT_FRAME_COLOR: [f32; 3] = [0.0, 1.0, 0.0];
+
+fn color_button_rgb(button: &gtk::ColorButton) -> [f32; 3] {
+    let rgba = button.rgba();
+    [rgba.red() as f32, rgba.green() as f32, rgba.blue() as f32]
+}
+
+/// Color saved to config entry `config_key`, or `default` if there is none.
+fn saved_color(config_key: &str, default: [f32; 3]) -> gdk::RGBA {
+    read_config_entry(config_key)
+        .and_then(|x| gdk::RGBA::parse(&x).ok())
+        .unwrap_or_else(|| {
+            gdk::RGBA::new(default[0].into(), default[1].into(), default[2].into(), 1.0)
+        })
+}
+
 impl RenderSettingsWidget {
     pub fn new() -> Rc<RenderSettingsWidget> {
         let normal_decode = gtk::CheckButton::with_label("Decode normal layer");
@@ -47,8 +81,66 @@ impl RenderSettingsWidget {
             - Ambient Occlusion: Displays only AO data\n\
             - Depth: Displays only depth data\n\
             See readme.txt for details on how the data is encoded."));
+        let grayscale = gtk::CheckButton::with_label("Grayscale");
+        grayscale.set_valign(gtk::Align::Start);
+        grayscale.set_tooltip_text(Some("\
+            When checked, displays the selected layer as a grayscale image instead of RGBA.\n\
+            Monochrome layers display their mask, other layers their luminance."));
+        let team_color = gtk::CheckButton::with_label("Team color");
+        team_color.set_valign(gtk::Align::Start);
+        team_color.set_tooltip_text(Some("\
+            When checked, displays the diffuse layer tinted with the selected player color \
+            where the teamcolor layer is set, as the game would render it."));
+        let team_color_button = gtk::ColorButton::with_rgba(&gdk::RGBA::new(
+            DEFAULT_TEAM_COLOR[0].into(),
+            DEFAULT_TEAM_COLOR[1].into(),
+            DEFAULT_TEAM_COLOR[2].into(),
+            1.0,
+        ));
+        team_color_button.set_valign(gtk::Align::Start);
+        team_color_button.set_sensitive(false);
+        let monochrome_tint = saved_color("monochrome_tint", DEFAULT_MONOCHROME_TINT);
+        let monochrome_tint_button = gtk::ColorButton::with_rgba(&monochrome_tint);
+        monochrome_tint_button.set_valign(gtk::Align::Start);
+        let monochrome_tint_section = box_horizontal(&[
+            &gtk::Label::new(Some("Monochrome tint")),
+            &monochrome_tint_button,
+        ]);
+        monochrome_tint_section.set_valign(gtk::Align::Start);
+        monochrome_tint_section.set_tooltip_text(Some("\
+            Color that monochrome layers are multiplied with when they are displayed."));
+        let bounds_color_button = gtk::ColorButton::with_rgba(
+            &saved_color("overlay_bounds_color", DEFAULT_BOUNDS_COLOR),
+        );
+        bounds_color_button.set_valign(gtk::Align::Start);
+        bounds_color_button.set_tooltip_text(Some("Color of the texture bounds"));
+        let frame_color_button = gtk::ColorButton::with_rgba(
+            &saved_color("overlay_frame_color", DEFAULT_FRAME_COLOR),
+        );
+        frame_color_button.set_valign(gtk::Align::Start);
+        frame_color_button.set_tooltip_text(Some("\
+            Color of the frame bounds. Selected frames and frames with a nonzero \
+            frame type use fixed colors."));
+        let line_width = gtk::SpinButton::with_range(1.0, 8.0, 1.0);
+        line_width.set_value(read_config_entry_int("overlay_line_width").unwrap_or(1) as f64);
+        line_width.set_valign(gtk::Align::Start);
+        line_width.set_tooltip_text(Some("\
+            Width of the bounds in pixels. Depending on the graphics driver, \
+            widths other than 1 may not be supported."));
+        let overlay_section = box_horizontal(&[
+            &gtk::Label::new(Some("Bounds")),
+            &bounds_color_button,
+            &frame_color_button,
+            &line_width,
+        ]);
+        overlay_section.set_valign(gtk::Align::Start);
         let bx = box_horizontal(&[
             &normal_decode,
+            &grayscale,
+            &team_color,
+            &team_color_button,
+            &monochrome_tint_section,
+            &overlay_section,
             &ao_depth_mode,
         ]);
         let root = label_section("Rendering settings", &bx);
@@ -57,6 +149,12 @@ impl RenderSettingsWidget {
             settings: RefCell::new(RenderSettings {
                 decode_normal: false,
                 ao_depth_mode: AoDepth::Raw,
+                grayscale: false,
+                team_color: None,
+                monochrome_tint: color_button_rgb(&monochrome_tint_button),
+                bounds_color: color_button_rgb(&bounds_color_button),
+                frame_color: color_button_rgb(&frame_color_button),
+                line_width: line_width.value() as f32,
             }),
         });
         let this2 = this.clone();
@@ -71,6 +169,58 @@ impl RenderSettingsWidget {
             this2.settings.borrow_mut().decode_normal = s.is_active();
             crate::ui().info.draw_area.queue_draw();
         });
+        let this2 = this.clone();
+        grayscale.connect_toggled(move |s| {
+            this2.settings.borrow_mut().grayscale = s.is_active();
+            // Cached textures have to be uploaded again in the other format
+            let info = &crate::ui().info;
+            info.draw_clear_all();
+            info.draw_area.queue_draw();
+        });
+        let this2 = this.clone();
+        let button = team_color_button.clone();
+        team_color.connect_toggled(move |s| {
+            button.set_sensitive(s.is_active());
+            this2.settings.borrow_mut().team_color = match s.is_active() {
+                true => Some(color_button_rgb(&button)),
+                false => None,
+            };
+            crate::ui().info.draw_area.queue_draw();
+        });
+        let this2 = this.clone();
+        team_color_button.connect_color_set(move |s| {
+            let mut settings = this2.settings.borrow_mut();
+            if settings.team_color.is_some() {
+                settings.team_color = Some(color_button_rgb(s));
+            }
+            drop(settings);
+            crate::ui().info.draw_area.queue_draw();
+        });
+        let this2 = this.clone();
+        monochrome_tint_button.connect_color_set(move |s| {
+            this2.settings.borrow_mut().monochrome_tint = color_button_rgb(s);
+            set_config_entry("monochrome_tint", s.rgba().to_str().as_str());
+            crate::ui().info.draw_area.queue_draw();
+        });
+        // The cached lines are regenerated once they are drawn with the new colors
+        let this2 = this.clone();
+        bounds_color_button.connect_color_set(move |s| {
+            this2.settings.borrow_mut().bounds_color = color_button_rgb(s);
+            set_config_entry("overlay_bounds_color", s.rgba().to_str().as_str());
+            crate::ui().info.draw_area.queue_draw();
+        });
+        let this2 = this.clone();
+        frame_color_button.connect_color_set(move |s| {
+            this2.settings.borrow_mut().frame_color = color_button_rgb(s);
+            set_config_entry("overlay_frame_color", s.rgba().to_str().as_str());
+            crate::ui().info.draw_area.queue_draw();
+        });
+        let this2 = this.clone();
+        line_width.connect_value_changed(move |s| {
+            this2.settings.borrow_mut().line_width = s.value() as f32;
+            set_config_entry("overlay_line_width", s.value_as_int());
+            crate::ui().info.draw_area.queue_draw();
+        });
 
         this
     }
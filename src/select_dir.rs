@@ -17,6 +17,15 @@ const APP_INFO: AppInfo = AppInfo {
     author: "Animosity",
 };
 
+/// Directory last used in any `SelectDir` / `SelectFile`, used when the
+/// widget doesn't have a path yet.
+const LAST_DIR_ENTRY: &str = "last_dir";
+
+/// Directory for cached data that can be regenerated, created if it doesn't exist.
+pub fn cache_dir(name: &str) -> Option<PathBuf> {
+    app_dirs::app_dir(AppDataType::UserCache, &APP_INFO, name).ok()
+}
+
 fn config_filename() -> Option<PathBuf> {
     let dir = app_dirs::app_root(AppDataType::UserData, &APP_INFO).ok()?;
     Some(dir.join("select_dir.json"))
@@ -34,6 +43,17 @@ pub fn read_config_entry_int(id: &str) -> Option<i64> {
     Some(json.as_object()?.get(id)?.as_i64()?)
 }
 
+/// Reads a list of strings, skipping any values that aren't strings.
+pub fn read_config_entry_strings(id: &str) -> Vec<String> {
+    let read = || -> Option<Vec<String>> {
+        let mut file = fs::File::open(config_filename()?).ok()?;
+        let json: serde_json::Value = serde_json::from_reader(&mut file).ok()?;
+        let list = json.as_object()?.get(id)?.as_array()?;
+        Some(list.iter().filter_map(|x| x.as_str()).map(|x| x.into()).collect())
+    };
+    read().unwrap_or_default()
+}
+
 // Nice return value
 pub fn set_config_entry<V: Into<serde_json::Value>> (id: &str, value: V) -> Option<()> {
     fn update_json(
@@ -113,12 +133,16 @@ impl SelectDir {
         let e = entry.clone();
         let w = window.clone();
         button.connect_clicked(move |_| {
-            let dir = e.text();
+            let dir = match e.text() {
+                x if x.is_empty() => read_config_entry(LAST_DIR_ENTRY).unwrap_or_default(),
+                x => x.into(),
+            };
             if let Some(path) = choose_dir_dialog(&w, &dir) {
                 let val = path.to_string_lossy();
                 e.set_text(&val);
                 e.emit_move_cursor(gtk::MovementStep::BufferEnds, 1, false);
                 set_config_entry(&select_id, &*val);
+                set_config_entry(LAST_DIR_ENTRY, &*val);
             }
         });
 
@@ -169,12 +193,18 @@ impl SelectFile {
         let o = on_change_handlers.clone();
         button.connect_clicked(move |_| {
             let dir = e.text();
-            let dir = Path::new(&*dir).parent().map(|x| x.to_string_lossy().into_owned());
+            let dir = Path::new(&*dir).parent()
+                .map(|x| x.to_string_lossy().into_owned())
+                .filter(|x| !x.is_empty())
+                .or_else(|| read_config_entry(LAST_DIR_ENTRY));
             if let Some(path) = choose_file_dialog(&w, &dir, filter_name, filter_pattern) {
                 let val = path.to_string_lossy();
                 e.set_text(&val);
                 e.emit_move_cursor(gtk::MovementStep::BufferEnds, 1, false);
                 set_config_entry(&select_id, &*val);
+                if let Some(parent) = path.parent() {
+                    set_config_entry(LAST_DIR_ENTRY, &*parent.to_string_lossy());
+                }
                 let mut handlers = o.borrow_mut();
                 for h in handlers.iter_mut() {
                     h(&val);
@@ -220,7 +250,10 @@ fn choose_file_dialog(
     }
     dialog.set_select_multiple(false);
     let filter = gtk::FileFilter::new();
-    filter.add_pattern(pattern);
+    // Multiple patterns can be separated with ';'
+    for pattern in pattern.split(';') {
+        filter.add_pattern(pattern);
+    }
     filter.set_name(Some(name));
     dialog.add_filter(&filter);
     let filter = gtk::FileFilter::new();
@@ -8,8 +8,29 @@ use glium::backend::glutin::headless::Headless;
 pub struct Shader {
     filename: &'static str,
     default: &'static str,
+    /// Inserted after the `#version` line of the source.
+    prelude: &'static str,
 }
 
+/// Defines `isolate_channel(vec4)` for the fragment shaders that can show a single channel.
+const ISOLATE_CHANNEL: &str = "\
+uniform int channel;
+
+// 0: all channels, 1: alpha, 2-4: red, green or blue, shown as opaque grayscale.
+vec4 isolate_channel(vec4 texel) {
+    if (channel == 1) {
+        return vec4(vec3(texel.a), 1.0);
+    } else if (channel == 2) {
+        return vec4(vec3(texel.r), 1.0);
+    } else if (channel == 3) {
+        return vec4(vec3(texel.g), 1.0);
+    } else if (channel == 4) {
+        return vec4(vec3(texel.b), 1.0);
+    }
+    return texel;
+}
+";
+
 pub struct Program {
     program: glium::program::Program,
     vertex: &'static Shader,
@@ -19,12 +40,25 @@ pub struct Program {
 }
 
 impl Shader {
-    const fn new(filename: &'static str, default: &'static str) -> Shader {
+    const fn new(
+        filename: &'static str,
+        default: &'static str,
+        prelude: &'static str,
+    ) -> Shader {
         Shader {
             filename,
             default,
+            prelude,
         }
     }
+
+    fn source(&self, text: &str) -> String {
+        let (version, rest) = match text.find('\n') {
+            Some(pos) => text.split_at(pos + 1),
+            None => (text, ""),
+        };
+        format!("{}{}{}", version, self.prelude, rest)
+    }
 }
 
 impl Program {
@@ -35,8 +69,8 @@ impl Program {
             loop {
                 let result = glium::program::Program::from_source(
                     facade,
-                    &vertex_file.0,
-                    &fragment_file.0,
+                    &vertex.source(&vertex_file.0),
+                    &fragment.source(&fragment_file.0),
                     None,
                 );
                 match result {
@@ -70,8 +104,8 @@ impl Program {
         }
         let program = glium::program::Program::from_source(
             facade,
-            vertex.default,
-            fragment.default,
+            &vertex.source(vertex.default),
+            &fragment.source(fragment.default),
             None,
         ).expect("Couldn't compile program");
         Program {
@@ -111,8 +145,8 @@ impl Program {
                     }
                     let result = glium::program::Program::from_source(
                         facade,
-                        &vertex_text,
-                        &fragment_text,
+                        &self.vertex.source(&vertex_text),
+                        &self.fragment.source(&fragment_text),
                         None,
                     );
                     match result {
@@ -191,17 +225,24 @@ fn shader_path(shader: &Shader) -> PathBuf {
 
 macro_rules! shader {
     ($filename:expr) => {
-        Shader::new($filename, include_str!(concat!("shaders/", $filename)))
+        shader!($filename, "")
+    };
+    ($filename:expr, $prelude:expr) => {
+        Shader::new($filename, include_str!(concat!("shaders/", $filename)), $prelude)
     };
 }
 
 pub static SPRITE_VERTEX: Shader = shader!("sprite_vertex.glsl");
-pub static SPRITE_FRAGMENT: Shader = shader!("sprite_fragment.glsl");
+pub static SPRITE_FRAGMENT: Shader = shader!("sprite_fragment.glsl", ISOLATE_CHANNEL);
 pub static AO_FRAGMENT: Shader = shader!("ao_fragment.glsl");
 pub static DEPTH_FRAGMENT: Shader = shader!("depth_fragment.glsl");
 pub static NORMAL_FRAGMENT: Shader = shader!("normal_fragment.glsl");
+pub static GRAYSCALE_FRAGMENT: Shader = shader!("grayscale_fragment.glsl");
+pub static TEAM_COLOR_FRAGMENT: Shader = shader!("team_color_fragment.glsl", ISOLATE_CHANNEL);
+pub static MONOCHROME_FRAGMENT: Shader = shader!("monochrome_fragment.glsl", ISOLATE_CHANNEL);
 // Same as sprite vertex
 pub static PALETTED_VERTEX: Shader = shader!("sprite_vertex.glsl");
-pub static PALETTED_FRAGMENT: Shader = shader!("paletted_fragment.glsl");
+pub static PALETTED_FRAGMENT: Shader = shader!("paletted_fragment.glsl", ISOLATE_CHANNEL);
 pub static LINE_VERTEX: Shader = shader!("line_vertex.glsl");
 pub static LINE_FRAGMENT: Shader = shader!("line_fragment.glsl");
+pub static CHECKERBOARD_FRAGMENT: Shader = shader!("checkerboard_fragment.glsl");
@@ -0,0 +1,12 @@
+#version 130
+
+out vec4 color;
+
+// Size of a single square in window pixels
+uniform float cell_size;
+
+void main() {
+    vec2 cell = floor(gl_FragCoord.xy / cell_size);
+    float value = mod(cell.x + cell.y, 2.0) == 0.0 ? 0.8 : 0.55;
+    color = vec4(value, value, value, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 130
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform vec3 tint;
+void main() {
+    vec4 texel = texture(tex, v_tex_coords);
+    color.rgb = texel.rgb * tint;
+    color.a = texel.a;
+    color = isolate_channel(color);
+}
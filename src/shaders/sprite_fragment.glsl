@@ -4,7 +4,6 @@ in vec2 v_tex_coords;
 out vec4 color;
 
 uniform sampler2D tex;
-
 void main() {
-    color = texture(tex, v_tex_coords);
+    color = isolate_channel(texture(tex, v_tex_coords));
 }
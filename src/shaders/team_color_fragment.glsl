@@ -0,0 +1,15 @@
+#version 130
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform sampler2D mask;
+uniform vec3 team_color;
+void main() {
+    vec4 diffuse = texture(tex, v_tex_coords);
+    float strength = texture(mask, v_tex_coords).a;
+    color.rgb = mix(diffuse.rgb, diffuse.rgb * team_color, strength);
+    color.a = diffuse.a;
+    color = isolate_channel(color);
+}
@@ -0,0 +1,120 @@
+//! Freeform notes about sprites, kept in a JSON file in the root directory of the
+//! opened anim tree instead of the anim files.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::Error;
+
+/// Name of the notes file in the root directory.
+pub const NOTES_FILENAME: &str = "animosity_notes.json";
+
+pub struct SpriteNotes {
+    /// None if there is no root directory to write the notes to.
+    path: Option<PathBuf>,
+    notes: BTreeMap<usize, String>,
+    dirty: bool,
+}
+
+impl SpriteNotes {
+    /// Notes that can't be saved anywhere.
+    pub fn empty() -> SpriteNotes {
+        SpriteNotes {
+            path: None,
+            notes: BTreeMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Reads the notes file in `root`, which doesn't have to exist yet.
+    pub fn load(root: &Path) -> Result<SpriteNotes, Error> {
+        let path = root.join(NOTES_FILENAME);
+        let notes = match fs::read(&path) {
+            Ok(data) => parse_notes(&data)
+                .with_context(|| format!("Unable to parse {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Unable to read {}", path.display()));
+            }
+        };
+        Ok(SpriteNotes {
+            path: Some(path),
+            notes,
+            dirty: false,
+        })
+    }
+
+    pub fn can_save(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn get(&self, sprite: usize) -> &str {
+        self.notes.get(&sprite).map(|x| &**x).unwrap_or("")
+    }
+
+    /// Sets notes of `sprite`, empty text removes them.
+    pub fn set(&mut self, sprite: usize, text: &str) {
+        if self.get(sprite) == text {
+            return;
+        }
+        if text.is_empty() {
+            self.notes.remove(&sprite);
+        } else {
+            self.notes.insert(sprite, text.into());
+        }
+        self.dirty = true;
+    }
+
+    /// Writes the notes file if the notes have changed since they were last saved.
+    pub fn save(&mut self) -> Result<(), Error> {
+        let path = match self.path {
+            Some(ref s) if self.dirty => s,
+            _ => return Ok(()),
+        };
+        let notes = self.notes.iter()
+            .map(|(sprite, text)| (sprite.to_string(), serde_json::Value::from(&**text)))
+            .collect::<serde_json::Map<_, _>>();
+        let json = serde_json::json!({ "notes": notes });
+        let data = serde_json::to_vec_pretty(&json)?;
+        fs::write(path, data).with_context(|| format!("Unable to write {}", path.display()))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn parse_notes(data: &[u8]) -> Result<BTreeMap<usize, String>, Error> {
+    let json: serde_json::Value = serde_json::from_slice(data)?;
+    let notes = json.get("notes")
+        .and_then(|x| x.as_object())
+        .ok_or_else(|| anyhow!("Expected an object 'notes'"))?;
+    notes.iter()
+        .map(|(key, value)| {
+            let sprite = key.parse::<usize>()
+                .map_err(|_| anyhow!("Invalid sprite id '{}'", key))?;
+            let text = value.as_str()
+                .ok_or_else(|| anyhow!("Notes of sprite {} are not a string", sprite))?;
+            Ok((sprite, text.into()))
+        })
+        .collect()
+}
+
+#[test]
+fn test_notes_round_trip() {
+    let dir = std::env::temp_dir().join(format!("animosity_notes_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut notes = SpriteNotes::load(&dir).unwrap();
+    assert_eq!(notes.get(12), "");
+    notes.set(12, "Needs HD2 cleanup");
+    notes.set(3, "Done");
+    notes.set(3, "");
+    notes.save().unwrap();
+    let notes = SpriteNotes::load(&dir).unwrap();
+    assert_eq!(notes.get(12), "Needs HD2 cleanup");
+    assert_eq!(notes.get(3), "");
+    assert!(parse_notes(br#"{"notes": {"x": "a"}}"#).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
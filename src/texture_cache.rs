@@ -0,0 +1,278 @@
+//! Optional on-disk cache of decoded anim textures, so that revisiting sprites of large
+//! files doesn't have to decode them again in later sessions.
+//!
+//! Entries are keyed by source file path, sprite type, sprite and layer, and store the
+//! size and modification time of the source file; an entry is only used if those
+//! still match.
+//!
+//! Once the cache is larger than `MAX_CACHE_BYTES`, the oldest entries are removed.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::UNIX_EPOCH;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::anim::RgbaTexture;
+use crate::select_dir;
+use crate::SpriteType;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const MAGIC: &[u8; 4] = b"ATC1";
+
+/// Total size of the entries that is kept after writing a new entry.
+const MAX_CACHE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub struct CacheKey<'a> {
+    pub path: &'a Path,
+    pub ty: SpriteType,
+    pub sprite: usize,
+    pub layer: usize,
+}
+
+impl<'a> CacheKey<'a> {
+    fn text(&self) -> String {
+        format!("{}|{:?}|{}|{}", self.path.display(), self.ty, self.sprite, self.layer)
+    }
+}
+
+/// Size and modification time of the source file.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct SourceStamp {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+}
+
+fn source_stamp(path: &Path) -> Option<SourceStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(SourceStamp {
+        size: metadata.len(),
+        mtime_secs: mtime.as_secs(),
+        mtime_nanos: mtime.subsec_nanos(),
+    })
+}
+
+/// FNV-1a, as the file name has to stay same between builds.
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn entry_path(dir: &Path, key_text: &str) -> PathBuf {
+    dir.join(format!("{:016x}.bin", hash(key_text)))
+}
+
+/// Returns the cached texture for `key` if caching is enabled and a valid entry exists,
+/// otherwise calls `decode` and caches its result.
+pub fn cached<E, F>(key: CacheKey<'_>, decode: F) -> Result<RgbaTexture, E>
+where F: FnOnce() -> Result<RgbaTexture, E>,
+{
+    if !ENABLED.load(Ordering::Relaxed) {
+        return decode();
+    }
+    match select_dir::cache_dir("textures") {
+        Some(dir) => cached_in(&dir, MAX_CACHE_BYTES, key, decode),
+        None => decode(),
+    }
+}
+
+fn cached_in<E, F>(
+    dir: &Path,
+    max_bytes: u64,
+    key: CacheKey<'_>,
+    decode: F,
+) -> Result<RgbaTexture, E>
+where F: FnOnce() -> Result<RgbaTexture, E>,
+{
+    let key_text = key.text();
+    let path = entry_path(dir, &key_text);
+    let stamp = match source_stamp(key.path) {
+        Some(s) => s,
+        None => return decode(),
+    };
+    let existing = fs::File::open(&path).ok()
+        .and_then(|file| read_entry(BufReader::new(file), &key_text, stamp).ok().flatten());
+    if let Some(texture) = existing {
+        return Ok(texture);
+    }
+    let texture = decode()?;
+    if let Err(e) = store(&path, &key_text, stamp, &texture) {
+        warn!("Unable to write texture cache {}: {}", path.display(), e);
+    }
+    if let Err(e) = evict(dir, max_bytes) {
+        warn!("Unable to clean up texture cache {}: {}", dir.display(), e);
+    }
+    Ok(texture)
+}
+
+/// Removes the least recently written entries of `dir` until they take at most `max_bytes`.
+fn evict(dir: &Path, max_bytes: u64) -> io::Result<()> {
+    let mut entries = Vec::new();
+    let mut total = 0u64;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|x| x.to_str()) != Some("bin") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        total += metadata.len();
+        entries.push((metadata.modified()?, metadata.len(), path));
+    }
+    if total <= max_bytes {
+        return Ok(());
+    }
+    entries.sort_by_key(|x| x.0);
+    for (_, size, path) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        fs::remove_file(&path)?;
+        total -= size;
+    }
+    Ok(())
+}
+
+fn store(path: &Path, key_text: &str, stamp: SourceStamp, texture: &RgbaTexture) -> io::Result<()> {
+    // Write to a temporary file first so that a partially written entry is never read.
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut out = BufWriter::new(fs::File::create(&tmp_path)?);
+        write_entry(&mut out, key_text, stamp, texture)?;
+        out.flush()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+fn write_entry<W: Write>(
+    mut out: W,
+    key_text: &str,
+    stamp: SourceStamp,
+    texture: &RgbaTexture,
+) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_u32::<LE>(key_text.len() as u32)?;
+    out.write_all(key_text.as_bytes())?;
+    out.write_u64::<LE>(stamp.size)?;
+    out.write_u64::<LE>(stamp.mtime_secs)?;
+    out.write_u32::<LE>(stamp.mtime_nanos)?;
+    out.write_u32::<LE>(texture.width)?;
+    out.write_u32::<LE>(texture.height)?;
+    out.write_all(&texture.data)
+}
+
+/// Returns None if the entry is for a different key or an older version of the source file.
+fn read_entry<R: Read>(
+    mut read: R,
+    key_text: &str,
+    stamp: SourceStamp,
+) -> io::Result<Option<RgbaTexture>> {
+    let mut magic = [0u8; 4];
+    read.read_exact(&mut magic)?;
+    let key_len = read.read_u32::<LE>()? as usize;
+    if &magic != MAGIC || key_len != key_text.len() {
+        return Ok(None);
+    }
+    let mut key = vec![0u8; key_len];
+    read.read_exact(&mut key)?;
+    let entry_stamp = SourceStamp {
+        size: read.read_u64::<LE>()?,
+        mtime_secs: read.read_u64::<LE>()?,
+        mtime_nanos: read.read_u32::<LE>()?,
+    };
+    if key != key_text.as_bytes() || entry_stamp != stamp {
+        return Ok(None);
+    }
+    let width = read.read_u32::<LE>()?;
+    let height = read.read_u32::<LE>()?;
+    let mut data = Vec::new();
+    read.read_to_end(&mut data)?;
+    if data.len() as u64 != u64::from(width) * u64::from(height) * 4 {
+        return Ok(None);
+    }
+    Ok(Some(RgbaTexture {
+        data,
+        width,
+        height,
+    }))
+}
+
+#[test]
+fn entry_round_trip() {
+    let stamp = SourceStamp {
+        size: 1000,
+        mtime_secs: 1_500_000_000,
+        mtime_nanos: 5,
+    };
+    let texture = RgbaTexture {
+        data: (0..32).collect(),
+        width: 4,
+        height: 2,
+    };
+    let mut out = Vec::new();
+    write_entry(&mut out, "mainSD.anim|Sd|3|0", stamp, &texture).unwrap();
+    let result = read_entry(&out[..], "mainSD.anim|Sd|3|0", stamp).unwrap().unwrap();
+    assert_eq!((result.width, result.height), (4, 2));
+    assert_eq!(result.data, texture.data);
+    // Different key or a modified source file
+    assert!(read_entry(&out[..], "mainSD.anim|Sd|4|0", stamp).unwrap().is_none());
+    let modified = SourceStamp {
+        mtime_nanos: 6,
+        ..stamp
+    };
+    assert!(read_entry(&out[..], "mainSD.anim|Sd|3|0", modified).unwrap().is_none());
+    // Truncated data
+    assert!(read_entry(&out[..out.len() - 1], "mainSD.anim|Sd|3|0", stamp).unwrap().is_none());
+}
+
+#[test]
+fn cached_hit_miss_and_stale() {
+    let dir = std::env::temp_dir()
+        .join(format!("animosity_texture_cache_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("mainSD.anim");
+    fs::write(&source, [0u8; 4]).unwrap();
+    let key = || CacheKey {
+        path: &source,
+        ty: SpriteType::Sd,
+        sprite: 3,
+        layer: 0,
+    };
+    let decode_count = std::cell::Cell::new(0);
+    let decode = || -> Result<RgbaTexture, ()> {
+        decode_count.set(decode_count.get() + 1);
+        Ok(RgbaTexture {
+            data: vec![decode_count.get(); 16],
+            width: 2,
+            height: 2,
+        })
+    };
+    // Miss decodes and stores the result, hit returns it without decoding
+    let result = cached_in(&dir, u64::MAX, key(), decode).unwrap();
+    assert_eq!((decode_count.get(), result.data[0]), (1, 1));
+    let result = cached_in(&dir, u64::MAX, key(), decode).unwrap();
+    assert_eq!((decode_count.get(), result.data[0]), (1, 1));
+    // Modified source gets decoded again
+    fs::write(&source, [0u8; 8]).unwrap();
+    let result = cached_in(&dir, u64::MAX, key(), decode).unwrap();
+    assert_eq!((decode_count.get(), result.data[0]), (2, 2));
+    // Entry larger than the cap is removed right away
+    let other = CacheKey {
+        sprite: 4,
+        ..key()
+    };
+    cached_in(&dir, 8, other, decode).unwrap();
+    cached_in(&dir, u64::MAX, key(), decode).unwrap();
+    assert_eq!(decode_count.get(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
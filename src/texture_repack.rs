@@ -0,0 +1,710 @@
+use std::convert::TryFrom;
+
+use anyhow::Context;
+
+use crate::anim;
+use crate::anim_encoder::{self, FrameCoords};
+use crate::files::{File, Files};
+use crate::frame_import::{self, Bounds};
+use crate::{Error, SpriteType};
+
+/// Graphics of an anim sprite, decoded to RGBA and split to frames.
+pub struct DecodedFrames {
+    pub layer_names: Vec<String>,
+    /// Format for each layer, None if the layer has no texture.
+    pub formats: Vec<Option<anim::TextureFormat>>,
+    pub frames: Vec<DecodedFrame>,
+    /// Total size of the textures that were decoded.
+    pub texture_bytes: u64,
+    /// `File::texture_divisor` of the sprite; frame coordinates are this many
+    /// times larger than texture pixels.
+    pub divisor: u32,
+}
+
+#[derive(Clone)]
+pub struct DecodedFrame {
+    pub frame: anim::Frame,
+    /// `(frame.width / divisor) * (frame.height / divisor)` RGBA pixels for each layer,
+    /// with `divisor` of the sprite the frame is from. None if the layer has no texture.
+    pub layers: Vec<Option<Vec<u8>>>,
+}
+
+/// A frame copied from one sprite to be pasted into another.
+#[derive(Clone)]
+pub struct CopiedFrame {
+    pub sprite: usize,
+    pub ty: SpriteType,
+    pub index: usize,
+    pub layer_names: Vec<String>,
+    /// `DecodedFrames::divisor` of the source sprite.
+    pub divisor: u32,
+    pub frame: DecodedFrame,
+}
+
+pub struct CompactResult {
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+pub struct TightenResult {
+    pub frames_changed: usize,
+    /// Width and height of the textures before and after.
+    pub old_atlas: (u32, u32),
+    pub new_atlas: (u32, u32),
+    pub size: CompactResult,
+}
+
+impl CompactResult {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.old_size.saturating_sub(self.new_size)
+    }
+}
+
+pub fn decode_frames(file: &File<'_>) -> Result<DecodedFrames, Error> {
+    if !file.is_anim() {
+        return Err(anyhow!("Not an anim sprite"));
+    }
+    let frames = file.frames()
+        .ok_or_else(|| anyhow!("Sprite has no frames"))?;
+    let layer_names = file.layer_names().into_owned();
+    let divisor = file.texture_divisor();
+    let mut formats = Vec::with_capacity(layer_names.len());
+    let mut texture_bytes = 0u64;
+    let mut layer_data = Vec::with_capacity(layer_names.len());
+    let texture_formats = file.texture_formats();
+    for layer in 0..layer_names.len() {
+        let format = match texture_formats.get(layer) {
+            Some(Ok(format)) => *format,
+            Some(Err(e)) => return Err(anyhow!("Layer {} texture format: {:?}", layer, e)),
+            None => None,
+        };
+        let format = match (format, file.texture_size(layer)) {
+            (Some(format), Some(size)) => {
+                texture_bytes += u64::from(size.size);
+                format
+            }
+            _ => {
+                formats.push(None);
+                layer_data.push(None);
+                continue;
+            }
+        };
+        formats.push(Some(format));
+        let texture = file.texture(layer)
+            .with_context(|| format!("Couldn't read texture for layer {}", layer))?;
+        layer_data.push(Some(texture));
+    }
+    let frames = frames.iter().map(|frame| {
+        let layers = layer_data.iter()
+            .map(|texture| texture.as_ref().map(|tex| frame_region(tex, frame, divisor)))
+            .collect();
+        DecodedFrame {
+            frame: frame.clone(),
+            layers,
+        }
+    }).collect();
+    Ok(DecodedFrames {
+        layer_names,
+        formats,
+        frames,
+        texture_bytes,
+        divisor,
+    })
+}
+
+/// Lays out and encodes `decoded` to a new set of textures.
+pub fn encode_frames(decoded: &DecodedFrames) -> Result<anim::TexChanges, Error> {
+    let has_graphics = decoded.frames.iter()
+        .any(|x| x.frame.width != 0 && x.frame.height != 0);
+    if !has_graphics {
+        return Err(anyhow!("Sprite has no graphics"));
+    }
+    // E.g. every frame was replaced with copies from a sprite that lacks the layer.
+    let empty_layer = decoded.formats.iter().enumerate()
+        .filter(|(_, format)| format.is_some())
+        .map(|(layer, _)| layer)
+        .find(|&layer| {
+            decoded.frames.iter().all(|x| {
+                x.layers.get(layer).and_then(|x| x.as_ref()).map(|x| x.is_empty()).unwrap_or(true)
+            })
+        });
+    if let Some(layer) = empty_layer {
+        let name = decoded.layer_names.get(layer).map(|x| &**x).unwrap_or("");
+        return Err(anyhow!("No frame has graphics for layer {} ({})", layer, name));
+    }
+    let mut layout = anim_encoder::Layout::new();
+    // Frames must not share DXT blocks at the texture resolution either.
+    let alignment = anim_encoder::DEFAULT_ALIGNMENT.max(4 * decoded.divisor);
+    layout.set_alignment(alignment, decoded.divisor)?;
+    for (i, frame) in decoded.frames.iter().enumerate() {
+        for (layer, data) in frame.layers.iter().enumerate() {
+            let data = match data {
+                Some(s) if decoded.formats.get(layer).map(|x| x.is_some()) == Some(true) => s,
+                _ => continue,
+            };
+            layout.add_frame(layer, i, data.clone(), FrameCoords {
+                x_offset: frame.frame.x_off.into(),
+                y_offset: frame.frame.y_off.into(),
+                width: frame.frame.width.into(),
+                height: frame.frame.height.into(),
+            });
+        }
+    }
+    let mut changes = layout.layout().encode(0, &decoded.formats, decoded.divisor);
+    // Trailing frames that had no graphics are not known to the layout.
+    changes.frames.resize_with(decoded.frames.len(), || anim::Frame {
+        tex_x: 0,
+        tex_y: 0,
+        x_off: 0,
+        y_off: 0,
+        width: 0,
+        height: 0,
+        unknown: 0,
+    });
+    for (new, old) in changes.frames.iter_mut().zip(decoded.frames.iter()) {
+        if new.width == 0 || new.height == 0 {
+            new.x_off = old.frame.x_off;
+            new.y_off = old.frame.y_off;
+        }
+        new.unknown = old.frame.unknown;
+    }
+    Ok(changes)
+}
+
+/// Rebuilds the textures of an anim sprite so that they only contain
+/// the regions referenced by its frames.
+///
+/// Texels that no frame points to (e.g. left over from deleted frames)
+/// are dropped, and the frames are laid out to a new, ideally smaller, atlas.
+pub fn compact_textures(
+    files: &mut Files,
+    sprite: usize,
+    ty: SpriteType,
+) -> Result<CompactResult, Error> {
+    let file = files.file(sprite, ty)?
+        .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+    let decoded = decode_frames(&file)?;
+    let dimensions = file.sprite_values()
+        .map(|x| (x.width, x.height))
+        .unwrap_or((0, 0));
+    let changes = encode_frames(&decoded)?;
+    let new_size = changes.textures.iter()
+        .filter_map(|x| x.as_ref())
+        .map(|x| x.1.len() as u64)
+        .sum();
+    files.set_tex_changes(sprite, ty, changes, dimensions);
+    Ok(CompactResult {
+        old_size: decoded.texture_bytes,
+        new_size,
+    })
+}
+
+/// Shrinks every frame of an anim sprite to the area that has non-transparent pixels
+/// in any of its layers, and lays the frames out to a new atlas.
+///
+/// For sprites that were imported without cropping the frames.
+pub fn tighten_frame_bounds(
+    files: &mut Files,
+    sprite: usize,
+    ty: SpriteType,
+) -> Result<TightenResult, Error> {
+    let file = files.file(sprite, ty)?
+        .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+    let mut decoded = decode_frames(&file)?;
+    let dimensions = file.sprite_values()
+        .map(|x| (x.width, x.height))
+        .unwrap_or((0, 0));
+    let old_atlas = (0..decoded.layer_names.len())
+        .filter_map(|i| file.texture_size(i))
+        .map(|x| (u32::from(x.width), u32::from(x.height)))
+        .next()
+        .unwrap_or((0, 0));
+    let mut frames_changed = 0;
+    for frame in &mut decoded.frames {
+        if tighten_frame(frame, &decoded.formats, decoded.divisor) {
+            frames_changed += 1;
+        }
+    }
+    let changes = encode_frames(&decoded)?;
+    let new_atlas = changes.textures.iter()
+        .filter_map(|x| x.as_ref())
+        .map(|x| (u32::from(x.0.width), u32::from(x.0.height)))
+        .next()
+        .unwrap_or((0, 0));
+    let new_size = changes.textures.iter()
+        .filter_map(|x| x.as_ref())
+        .map(|x| x.1.len() as u64)
+        .sum();
+    files.set_tex_changes(sprite, ty, changes, dimensions);
+    Ok(TightenResult {
+        frames_changed,
+        old_atlas,
+        new_atlas,
+        size: CompactResult {
+            old_size: decoded.texture_bytes,
+            new_size,
+        },
+    })
+}
+
+/// True if the texture of `layer` has alpha values other than fully transparent or
+/// fully opaque, which DXT1 can't store.
+pub fn layer_has_partial_alpha(file: &File<'_>, layer: usize) -> Result<bool, Error> {
+    let texture = file.texture(layer)
+        .with_context(|| format!("Couldn't read texture for layer {}", layer))?;
+    if texture.is_paletted {
+        return Ok(false);
+    }
+    Ok(has_partial_alpha(&texture.data))
+}
+
+fn has_partial_alpha(rgba: &[u8]) -> bool {
+    rgba.chunks_exact(4).any(|x| x[3] != 0 && x[3] != 255)
+}
+
+/// Re-encodes the texture of `layer` as `format`, keeping the frames and the
+/// other layers as they are.
+pub fn recompress_layer(
+    files: &mut Files,
+    sprite: usize,
+    ty: SpriteType,
+    layer: usize,
+    format: anim::TextureFormat,
+) -> Result<CompactResult, Error> {
+    let file = files.file(sprite, ty)?
+        .ok_or_else(|| anyhow!("No file for sprite {}/{:?}", sprite, ty))?;
+    if !file.is_anim() {
+        return Err(anyhow!("Not an anim sprite"));
+    }
+    let old_size = file.texture_size(layer)
+        .ok_or_else(|| anyhow!("Layer {} has no texture", layer))?
+        .size;
+    let texture = file.texture(layer)
+        .with_context(|| format!("Couldn't read texture for layer {}", layer))?;
+    if texture.is_paletted {
+        return Err(anyhow!("Paletted textures are not supported"));
+    }
+    let frames = file.frames()
+        .ok_or_else(|| anyhow!("Sprite has no frames"))?
+        .to_vec();
+    let mut textures = file.raw_textures()?;
+    let dimensions = file.sprite_values()
+        .map(|x| (x.width, x.height))
+        .unwrap_or((0, 0));
+    let data = anim_encoder::encode(&texture.data, texture.width, texture.height, format);
+    let new_size = data.len() as u32;
+    let new_texture = anim::Texture {
+        offset: 0,
+        size: new_size,
+        width: u16::try_from(texture.width)?,
+        height: u16::try_from(texture.height)?,
+    };
+    match textures.get_mut(layer) {
+        Some(tex) => *tex = Some((new_texture, data)),
+        None => return Err(anyhow!("Layer {} doesn't exist", layer)),
+    }
+    files.set_tex_changes(sprite, ty, anim::TexChanges { frames, textures }, dimensions);
+    Ok(CompactResult {
+        old_size: old_size.into(),
+        new_size: new_size.into(),
+    })
+}
+
+/// Crops `frame` to the union of the bounding boxes of its encoded layers,
+/// moving the offsets so that the graphics stay in place.
+/// Returns false if the frame was already tight.
+fn tighten_frame(
+    frame: &mut DecodedFrame,
+    formats: &[Option<anim::TextureFormat>],
+    divisor: u32,
+) -> bool {
+    let width = u32::from(frame.frame.width) / divisor;
+    let height = u32::from(frame.frame.height) / divisor;
+    if width == 0 || height == 0 {
+        return false;
+    }
+    let mut union: Option<Bounds> = None;
+    for (layer, data) in frame.layers.iter().enumerate() {
+        let data = match data {
+            Some(s) if formats.get(layer).map(|x| x.is_some()) == Some(true) => s,
+            _ => continue,
+        };
+        let bounds = frame_import::rgba_bounds(data, width, height, 0);
+        if bounds.right <= bounds.left || bounds.bottom <= bounds.top {
+            continue;
+        }
+        union = Some(match union {
+            Some(old) => Bounds {
+                left: old.left.min(bounds.left),
+                top: old.top.min(bounds.top),
+                right: old.right.max(bounds.right),
+                bottom: old.bottom.max(bounds.bottom),
+            },
+            None => bounds,
+        });
+    }
+    let mut bounds = union.unwrap_or(Bounds {
+        left: 0,
+        top: 0,
+        right: 0,
+        bottom: 0,
+    });
+    // Keep left / top even like import does, HD2 doesn't like odd x/y.
+    bounds.left &= !1;
+    bounds.top &= !1;
+    if bounds.left == 0 && bounds.top == 0 && bounds.right == width && bounds.bottom == height {
+        return false;
+    }
+    for data in frame.layers.iter_mut().filter_map(|x| x.as_mut()) {
+        *data = frame_import::bound_data(data, width, height, &bounds).data;
+    }
+    let f = &mut frame.frame;
+    f.x_off = f.x_off.saturating_add((bounds.left * divisor) as i16);
+    f.y_off = f.y_off.saturating_add((bounds.top * divisor) as i16);
+    f.width = ((bounds.right - bounds.left) * divisor) as u16;
+    f.height = ((bounds.bottom - bounds.top) * divisor) as u16;
+    true
+}
+
+/// Multiplier of frame coordinates of a sprite type, relative to SD.
+///
+/// HD2 frames use HD coordinates, see `type_scale` for their texture resolution.
+fn coordinate_scale(ty: SpriteType) -> u32 {
+    match ty {
+        SpriteType::Sd => 1,
+        SpriteType::Hd | SpriteType::Hd2 => 4,
+    }
+}
+
+/// Texture resolution multiplier of a sprite type, relative to SD.
+///
+/// `divisor` is `File::texture_divisor` of the sprite, which for HD2 comes from
+/// the anim header.
+pub fn type_scale(ty: SpriteType, divisor: u32) -> u32 {
+    (coordinate_scale(ty) / divisor.max(1)).max(1)
+}
+
+/// Converts a copied frame to be usable with sprite of type `ty` and layers `layer_names`.
+/// `divisor` is `File::texture_divisor` of the destination sprite.
+///
+/// Layers are matched by name, and the graphics are resized if the resolution differs.
+pub fn convert_copied_frame(
+    copied: &CopiedFrame,
+    ty: SpriteType,
+    divisor: u32,
+    layer_names: &[String],
+) -> DecodedFrame {
+    let src_scale = coordinate_scale(copied.ty);
+    let dest_scale = coordinate_scale(ty);
+    let src = &copied.frame.frame;
+    let resize = |x: u32| x * dest_scale / src_scale;
+    let width = resize(src.width.into());
+    let height = resize(src.height.into());
+    let src_px = (u32::from(src.width) / copied.divisor, u32::from(src.height) / copied.divisor);
+    let dest_px = (width / divisor, height / divisor);
+    let layers = layer_names.iter().map(|name| {
+        let index = copied.layer_names.iter().position(|x| x == name)?;
+        let data = copied.frame.layers.get(index)?.as_ref()?;
+        if src_px == dest_px || dest_px.0 == 0 || dest_px.1 == 0 {
+            return Some(data.clone());
+        }
+        let image: image::RgbaImage =
+            image::ImageBuffer::from_raw(src_px.0, src_px.1, data.clone())?;
+        let resized = image::imageops::resize(
+            &image,
+            dest_px.0,
+            dest_px.1,
+            image::imageops::FilterType::Lanczos3,
+        );
+        Some(resized.into_raw())
+    }).collect();
+    let resize_i = |x: i16| (i32::from(x) * dest_scale as i32 / src_scale as i32) as i16;
+    DecodedFrame {
+        frame: anim::Frame {
+            tex_x: 0,
+            tex_y: 0,
+            x_off: resize_i(src.x_off),
+            y_off: resize_i(src.y_off),
+            width: width as u16,
+            height: height as u16,
+            unknown: src.unknown,
+        },
+        layers,
+    }
+}
+
+/// Returns indices of the frames in `current` whose size, offset or pixels differ from
+/// the first sprite of `reference`. Layers are matched by name, and frames past the end
+/// of `reference` are always counted as differing.
+///
+/// `ref_divisor` is the texture divisor of `reference`, see `File::texture_divisor`.
+pub fn differing_frames(
+    current: &DecodedFrames,
+    reference: &anim::Anim,
+    ref_divisor: u32,
+) -> Result<Vec<usize>, Error> {
+    let ref_frames = reference.frames(0)
+        .ok_or_else(|| anyhow!("Reference has no frames"))?;
+    let ref_sizes = reference.texture_sizes(0).unwrap_or(&[]);
+    let mut ref_textures = Vec::with_capacity(current.layer_names.len());
+    for name in &current.layer_names {
+        let layer = reference.layer_names().iter()
+            .position(|x| x == name)
+            .filter(|&i| ref_sizes.get(i).map(|x| x.is_some()).unwrap_or(false));
+        let texture = match layer {
+            Some(layer) => {
+                let texture = reference.texture(0, layer).with_context(|| {
+                    format!("Couldn't read reference texture for layer {}", name)
+                })?;
+                Some(anim::RawTexture::from(texture))
+            }
+            None => None,
+        };
+        ref_textures.push(texture);
+    }
+    let result = current.frames.iter()
+        .enumerate()
+        .filter(|&(i, frame)| match ref_frames.get(i) {
+            Some(ref_frame) => !frame_matches(frame, ref_frame, &ref_textures, ref_divisor),
+            None => true,
+        })
+        .map(|(i, _)| i)
+        .collect();
+    Ok(result)
+}
+
+fn frame_matches(
+    frame: &DecodedFrame,
+    ref_frame: &anim::Frame,
+    ref_textures: &[Option<anim::RawTexture>],
+    ref_divisor: u32,
+) -> bool {
+    let a = &frame.frame;
+    if a.width != ref_frame.width || a.height != ref_frame.height ||
+        a.x_off != ref_frame.x_off || a.y_off != ref_frame.y_off
+    {
+        return false;
+    }
+    frame.layers.iter().zip(ref_textures).all(|(data, texture)| match (data, texture) {
+        (Some(data), Some(texture)) => *data == frame_region(texture, ref_frame, ref_divisor),
+        (None, None) => true,
+        _ => false,
+    })
+}
+
+/// Copies the RGBA area used by `frame` out of `texture`, dividing the frame
+/// coordinates by `divisor` (`File::texture_divisor`) first.
+/// Parts that are outside the texture are left transparent.
+fn frame_region(texture: &anim::RawTexture, frame: &anim::Frame, divisor: u32) -> Vec<u8> {
+    let width = u32::from(frame.width) / divisor;
+    let height = u32::from(frame.height) / divisor;
+    let mut out = vec![0; (width * height * 4) as usize];
+    let tex_x = u32::from(frame.tex_x) / divisor;
+    let tex_y = u32::from(frame.tex_y) / divisor;
+    if tex_x >= texture.width {
+        return out;
+    }
+    let copy_width = width.min(texture.width - tex_x) as usize * 4;
+    for y in 0..height {
+        let src_y = tex_y + y;
+        if src_y >= texture.height {
+            break;
+        }
+        let src = ((src_y * texture.width + tex_x) * 4) as usize;
+        let dest = (y * width * 4) as usize;
+        out[dest..][..copy_width].copy_from_slice(&texture.data[src..][..copy_width]);
+    }
+    out
+}
+
+#[test]
+fn test_frame_region() {
+    let texture = anim::RawTexture {
+        data: (0..4 * 4 * 4).map(|x| x as u8).collect(),
+        width: 4,
+        height: 4,
+        is_paletted: false,
+    };
+    let frame = anim::Frame {
+        tex_x: 2,
+        tex_y: 3,
+        x_off: 0,
+        y_off: 0,
+        width: 3,
+        height: 2,
+        unknown: 0,
+    };
+    let region = frame_region(&texture, &frame, 1);
+    assert_eq!(region.len(), 3 * 2 * 4);
+    assert_eq!(&region[..8], &texture.data[(3 * 4 + 2) * 4..][..8]);
+    // Out of bounds pixels are transparent
+    assert!(region[8..].iter().all(|&x| x == 0));
+    // HD2 frame coordinates are in HD units
+    let frame = anim::Frame {
+        tex_x: 4,
+        tex_y: 2,
+        width: 4,
+        height: 4,
+        ..frame
+    };
+    let region = frame_region(&texture, &frame, 2);
+    assert_eq!(region.len(), 2 * 2 * 4);
+    assert_eq!(&region[..8], &texture.data[(4 + 2) * 4..][..8]);
+    assert_eq!(&region[8..], &texture.data[(2 * 4 + 2) * 4..][..8]);
+}
+
+#[test]
+fn test_convert_copied_frame() {
+    let names = |x: &[&str]| x.iter().map(|&x| String::from(x)).collect::<Vec<_>>();
+    let copied = CopiedFrame {
+        sprite: 0,
+        ty: SpriteType::Hd2,
+        index: 0,
+        layer_names: names(&["diffuse", "teamcolor"]),
+        divisor: 2,
+        frame: DecodedFrame {
+            frame: anim::Frame {
+                tex_x: 8,
+                tex_y: 8,
+                x_off: 4,
+                y_off: -2,
+                width: 4,
+                height: 4,
+                unknown: 1,
+            },
+            layers: vec![Some(vec![255; 2 * 2 * 4]), None],
+        },
+    };
+    let layer_names = names(&["diffuse", "normal"]);
+    // HD2 frame of 4x4 HD units has 2x2 pixels
+    let result = convert_copied_frame(&copied, SpriteType::Hd, 1, &layer_names);
+    assert_eq!(result.frame.width, 4);
+    assert_eq!(result.frame.height, 4);
+    assert_eq!(result.frame.x_off, 4);
+    assert_eq!(result.frame.y_off, -2);
+    assert_eq!(result.frame.unknown, 1);
+    assert_eq!(result.layers.len(), 2);
+    assert_eq!(result.layers[0].as_ref().map(|x| x.len()), Some(4 * 4 * 4));
+    assert!(result.layers[1].is_none());
+    let result = convert_copied_frame(&copied, SpriteType::Sd, 1, &layer_names);
+    assert_eq!((result.frame.width, result.frame.x_off), (1, 1));
+    assert_eq!(result.layers[0].as_ref().map(|x| x.len()), Some(4));
+    assert_eq!(type_scale(SpriteType::Hd2, 2), 2);
+    assert_eq!(type_scale(SpriteType::Hd2, 4), 1);
+}
+
+#[test]
+fn test_frame_matches() {
+    let texture = anim::RawTexture {
+        data: (0..4 * 4 * 4).map(|x| x as u8).collect(),
+        width: 4,
+        height: 4,
+        is_paletted: false,
+    };
+    let ref_frame = anim::Frame {
+        tex_x: 2,
+        tex_y: 0,
+        x_off: 1,
+        y_off: 1,
+        width: 2,
+        height: 2,
+        unknown: 0,
+    };
+    // Same pixels from a different position in the texture still match
+    let mut frame = DecodedFrame {
+        frame: anim::Frame {
+            tex_x: 0,
+            tex_y: 0,
+            ..ref_frame.clone()
+        },
+        layers: vec![Some(frame_region(&texture, &ref_frame, 1))],
+    };
+    let textures = [Some(texture)];
+    assert!(frame_matches(&frame, &ref_frame, &textures, 1));
+    // Reference with a different texture divisor has different pixels
+    assert!(!frame_matches(&frame, &ref_frame, &textures, 2));
+    frame.frame.x_off = 2;
+    assert!(!frame_matches(&frame, &ref_frame, &textures, 1));
+    frame.frame.x_off = 1;
+    frame.layers[0].as_mut().unwrap()[0] ^= 1;
+    assert!(!frame_matches(&frame, &ref_frame, &textures, 1));
+    frame.layers[0] = None;
+    assert!(!frame_matches(&frame, &ref_frame, &textures, 1));
+}
+
+#[test]
+fn test_has_partial_alpha() {
+    assert!(!has_partial_alpha(&[10, 20, 30, 255, 0, 0, 0, 0]));
+    assert!(has_partial_alpha(&[10, 20, 30, 255, 0, 0, 0, 128]));
+}
+
+#[test]
+fn test_tighten_frame() {
+    // 4x4 frame with one opaque pixel at (2, 1) in the first layer,
+    // and the second layer at (3, 3)
+    let pixel = |x: usize, y: usize, width: usize| (y * width + x) * 4;
+    let mut first = vec![0u8; 4 * 4 * 4];
+    first[pixel(2, 1, 4) + 3] = 255;
+    let mut second = vec![0u8; 4 * 4 * 4];
+    second[pixel(3, 3, 4)] = 7;
+    second[pixel(3, 3, 4) + 3] = 255;
+    let mut frame = DecodedFrame {
+        frame: anim::Frame {
+            tex_x: 0,
+            tex_y: 0,
+            x_off: -2,
+            y_off: -2,
+            width: 4,
+            height: 4,
+            unknown: 0,
+        },
+        layers: vec![Some(first), Some(second), None],
+    };
+    let formats = [Some(anim::TextureFormat::Dxt5), Some(anim::TextureFormat::Dxt5), None];
+    let mut hd2_frame = frame.clone();
+    assert!(tighten_frame(&mut frame, &formats, 1));
+    // Left / top are rounded down to even
+    assert_eq!((frame.frame.x_off, frame.frame.y_off), (0, -2));
+    assert_eq!((frame.frame.width, frame.frame.height), (2, 4));
+    let second = frame.layers[1].as_ref().unwrap();
+    assert_eq!(second.len(), 2 * 4 * 4);
+    assert_eq!(second[pixel(1, 3, 2)], 7);
+    assert!(!tighten_frame(&mut frame, &formats, 1));
+
+    // Same pixels in a HD2 frame, which is 8x8 in HD units
+    hd2_frame.frame.width = 8;
+    hd2_frame.frame.height = 8;
+    assert!(tighten_frame(&mut hd2_frame, &formats, 2));
+    assert_eq!((hd2_frame.frame.x_off, hd2_frame.frame.y_off), (2, -2));
+    assert_eq!((hd2_frame.frame.width, hd2_frame.frame.height), (4, 8));
+    assert_eq!(hd2_frame.layers[1].as_ref().map(|x| x.len()), Some(2 * 4 * 4));
+}
+
+#[test]
+fn test_encode_frames_missing_layer() {
+    let frame = |layers| DecodedFrame {
+        frame: anim::Frame {
+            tex_x: 0,
+            tex_y: 0,
+            x_off: 0,
+            y_off: 0,
+            width: 4,
+            height: 4,
+            unknown: 0,
+        },
+        layers,
+    };
+    let mut decoded = DecodedFrames {
+        layer_names: vec!["diffuse".into(), "teamcolor".into()],
+        formats: vec![Some(anim::TextureFormat::Dxt5), Some(anim::TextureFormat::Dxt5)],
+        frames: vec![frame(vec![Some(vec![255; 4 * 4 * 4]), None])],
+        texture_bytes: 0,
+        divisor: 1,
+    };
+    let error = encode_frames(&decoded).unwrap_err().to_string();
+    assert!(error.contains("layer 1 (teamcolor)"), "{}", error);
+    decoded.frames.push(frame(vec![Some(vec![255; 4 * 4 * 4]), Some(vec![0; 4 * 4 * 4])]));
+    let changes = encode_frames(&decoded).unwrap();
+    assert_eq!(changes.frames.len(), 2);
+    assert!(changes.textures.iter().all(|x| x.is_some()));
+}
@@ -0,0 +1,204 @@
+//! Small previews of each sprite's first SD frame for the sprite list.
+//!
+//! Thumbnails are generated lazily on the main thread, one sprite per idle callback,
+//! for rows that have become visible.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use gtk::gdk_pixbuf::{Colorspace, Pixbuf};
+use gtk::prelude::*;
+
+use crate::files::Files;
+use crate::recurse_checked_mutex::Mutex;
+use crate::SpriteType;
+
+/// Maximum width and height of a thumbnail.
+pub const THUMBNAIL_SIZE: u32 = 32;
+
+pub struct Thumbnails {
+    files: Arc<Mutex<Files>>,
+    store: gtk::ListStore,
+    column: u32,
+    /// Sprites whose thumbnail has been either generated or queued.
+    requested: RefCell<Vec<bool>>,
+    queue: RefCell<VecDeque<usize>>,
+    idle_running: Cell<bool>,
+    placeholder: Option<Pixbuf>,
+}
+
+impl Thumbnails {
+    pub fn new(files: Arc<Mutex<Files>>, store: gtk::ListStore, column: u32) -> Rc<Thumbnails> {
+        let placeholder = gtk::IconTheme::default()
+            .and_then(|theme| {
+                let flags = gtk::IconLookupFlags::FORCE_SIZE;
+                theme.load_icon("image-missing", THUMBNAIL_SIZE as i32, flags).ok().flatten()
+            });
+        Rc::new(Thumbnails {
+            files,
+            store,
+            column,
+            requested: RefCell::new(Vec::new()),
+            queue: RefCell::new(VecDeque::new()),
+            idle_running: Cell::new(false),
+            placeholder,
+        })
+    }
+
+    /// Forgets all thumbnails, called when the list is filled with a new set of sprites.
+    pub fn reset(&self, sprite_count: usize) {
+        self.queue.borrow_mut().clear();
+        *self.requested.borrow_mut() = vec![false; sprite_count];
+    }
+
+    /// Queues thumbnails of rows `first..=last` that haven't been generated yet.
+    pub fn request(self: &Rc<Self>, first: usize, last: usize) {
+        {
+            let mut requested = self.requested.borrow_mut();
+            let mut queue = self.queue.borrow_mut();
+            let last = last.min(requested.len().saturating_sub(1));
+            for sprite in first..=last {
+                if let Some(req) = requested.get_mut(sprite) {
+                    if !*req {
+                        *req = true;
+                        queue.push_back(sprite);
+                    }
+                }
+            }
+            if queue.is_empty() || self.idle_running.get() {
+                return;
+            }
+        }
+        self.idle_running.set(true);
+        let this = self.clone();
+        glib::idle_add_local(move || this.generate_next());
+    }
+
+    fn generate_next(&self) -> glib::Continue {
+        let sprite = match self.queue.borrow_mut().pop_front() {
+            Some(s) => s,
+            None => {
+                self.idle_running.set(false);
+                return glib::Continue(false);
+            }
+        };
+        let thumbnail = {
+            let mut files = match self.files.try_lock() {
+                Ok(o) => o,
+                Err(_) => {
+                    // Files are busy (e.g. saving), request the rows again on next draw
+                    // instead of waiting for them here.
+                    let mut requested = self.requested.borrow_mut();
+                    let mut queue = self.queue.borrow_mut();
+                    for sprite in std::iter::once(sprite).chain(queue.drain(..)) {
+                        if let Some(req) = requested.get_mut(sprite) {
+                            *req = false;
+                        }
+                    }
+                    self.idle_running.set(false);
+                    return glib::Continue(false);
+                }
+            };
+            sprite_thumbnail(&mut files, sprite)
+        };
+        let pixbuf = match thumbnail {
+            Some((data, width, height)) => Some(Pixbuf::from_mut_slice(
+                data,
+                Colorspace::Rgb,
+                true,
+                8,
+                width as i32,
+                height as i32,
+                width as i32 * 4,
+            )),
+            None => self.placeholder.clone(),
+        };
+        if let Some(pixbuf) = pixbuf {
+            let path = gtk::TreePath::from_indicesv(&[sprite as i32]);
+            if let Some(iter) = self.store.iter(&path) {
+                self.store.set_value(&iter, self.column, &pixbuf.to_value());
+            }
+        }
+        glib::Continue(true)
+    }
+}
+
+/// Downscaled RGBA of the first SD frame's diffuse layer, returned with its width and height.
+/// None for refs and sprites without a texture.
+fn sprite_thumbnail(files: &mut Files, sprite: usize) -> Option<(Vec<u8>, u32, u32)> {
+    let file = files.file(sprite, SpriteType::Sd).ok()??;
+    if file.image_ref().is_some() {
+        return None;
+    }
+    let texture = file.texture(0).ok()?;
+    let rect = match file.frames().and_then(|x| x.first()) {
+        Some(f) if file.is_anim() => {
+            (f.tex_x.into(), f.tex_y.into(), f.width.into(), f.height.into())
+        }
+        _ => (0, 0, texture.width, texture.height),
+    };
+    let rgba = match texture.is_paletted {
+        true => paletted_to_rgba(&texture.data, file.palette()?),
+        false => texture.data,
+    };
+    downscale(&rgba, texture.width, texture.height, rect, THUMBNAIL_SIZE)
+}
+
+/// Converts palette indices to RGBA using a RGB0 palette, index 0 being transparent.
+fn paletted_to_rgba(data: &[u8], palette: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 4);
+    for &index in data {
+        let color = palette.get(index as usize * 4..index as usize * 4 + 3).unwrap_or(&[0; 3]);
+        out.extend_from_slice(color);
+        out.push(if index == 0 { 0 } else { 255 });
+    }
+    out
+}
+
+/// Scales `rect` (x, y, width, height) of a RGBA image to fit in `max_size` x `max_size`,
+/// without filtering. Returns None if the rect is empty or outside the image.
+///
+/// Images that already fit are not scaled up.
+fn downscale(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    rect: (u32, u32, u32, u32),
+    max_size: u32,
+) -> Option<(Vec<u8>, u32, u32)> {
+    let (x, y, rect_width, rect_height) = rect;
+    let rect_width = rect_width.min(width.saturating_sub(x));
+    let rect_height = rect_height.min(height.saturating_sub(y));
+    if rect_width == 0 || rect_height == 0 {
+        return None;
+    }
+    let larger = rect_width.max(rect_height).max(max_size);
+    let out_width = (rect_width * max_size / larger).max(1);
+    let out_height = (rect_height * max_size / larger).max(1);
+    let mut out = Vec::with_capacity((out_width * out_height * 4) as usize);
+    for out_y in 0..out_height {
+        let src_y = y + out_y * rect_height / out_height;
+        for out_x in 0..out_width {
+            let src_x = x + out_x * rect_width / out_width;
+            let pos = (src_y * width + src_x) as usize * 4;
+            out.extend_from_slice(data.get(pos..pos + 4)?);
+        }
+    }
+    Some((out, out_width, out_height))
+}
+
+#[test]
+fn test_downscale() {
+    // 8x4 image where each pixel's red is its x coordinate
+    let data = (0..4).flat_map(|_| (0..8u8).flat_map(|x| vec![x, 0, 0, 255])).collect::<Vec<_>>();
+    let (out, width, height) = downscale(&data, 8, 4, (0, 0, 8, 4), 4).unwrap();
+    assert_eq!((width, height), (4, 2));
+    assert_eq!(out.chunks(4).map(|x| x[0]).collect::<Vec<_>>(), vec![0, 2, 4, 6, 0, 2, 4, 6]);
+    // Small rects are kept at their size
+    let (out, width, height) = downscale(&data, 8, 4, (5, 1, 2, 2), 4).unwrap();
+    assert_eq!((width, height), (2, 2));
+    assert_eq!(out.chunks(4).map(|x| x[0]).collect::<Vec<_>>(), vec![5, 6, 5, 6]);
+    assert!(downscale(&data, 8, 4, (8, 0, 2, 2), 4).is_none());
+}
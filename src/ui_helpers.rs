@@ -1,8 +1,58 @@
+use std::cell::Cell;
+
 use gtk;
 use gtk::prelude::*;
 
 use crate::frame_export_dialog::SavedCheckbox;
 
+/// Font DPI used at 96 dpi, GTK stores it in 1/1024 units.
+const DEFAULT_XFT_DPI: i32 = 96 * 1024;
+
+thread_local! {
+    static BASE_XFT_DPI: Cell<Option<i32>> = Cell::new(None);
+}
+
+/// Font DPI that the system had set before `apply_ui_scale` changed it.
+fn base_xft_dpi(settings: &gtk::Settings) -> i32 {
+    BASE_XFT_DPI.with(|base| match base.get() {
+        Some(dpi) => dpi,
+        None => {
+            let dpi = settings.property::<i32>("gtk-xft-dpi");
+            let dpi = if dpi > 0 { dpi } else { DEFAULT_XFT_DPI };
+            base.set(Some(dpi));
+            dpi
+        }
+    })
+}
+
+/// Scales all text of the application by `percent` of the system font DPI.
+pub fn apply_ui_scale(percent: u32) {
+    if let Some(settings) = gtk::Settings::default() {
+        let base = base_xft_dpi(&settings);
+        let dpi = (i64::from(base) * i64::from(percent.max(25)) / 100) as i32;
+        let _ = settings.set_property("gtk-xft-dpi", dpi);
+    }
+}
+
+/// Factor for fixed widget sizes, so that they stay proportional to the text on
+/// high-DPI displays.
+///
+/// GTK already multiplies sizes by the integer monitor scale factor, which doesn't
+/// help when the system only raises font DPI (Common on X11 4K setups), or when
+/// the user has a larger interface scale set. Font DPI covers both cases.
+pub fn ui_scale() -> f64 {
+    let dpi = gtk::Settings::default()
+        .map(|x| x.property::<i32>("gtk-xft-dpi"))
+        .filter(|&x| x > 0)
+        .unwrap_or(DEFAULT_XFT_DPI);
+    (f64::from(dpi) / f64::from(DEFAULT_XFT_DPI)).max(1.0)
+}
+
+/// `size` in pixels at 96 dpi, scaled by `ui_scale`.
+pub fn scaled(size: i32) -> i32 {
+    (f64::from(size) * ui_scale()).round() as i32
+}
+
 pub trait BoxableWidget {
     fn expands(&self) -> bool {
         false
@@ -104,6 +154,10 @@ impl CheckEnabledSection {
     pub fn is_active(&self) -> bool {
         self.check.is_active()
     }
+
+    pub fn connect_toggled<F: Fn() + 'static>(&self, func: F) {
+        self.check.connect_toggled(func);
+    }
 }
 
 pub fn label_section_with_enable_check<O: IsA<gtk::Widget>>(
@@ -99,7 +99,7 @@ impl SpriteLighting {
         tree_scroll.set_hscrollbar_policy(gtk::PolicyType::Never);
         tree_scroll.set_propagate_natural_width(true);
         tree_scroll.set_propagate_natural_height(true);
-        tree_scroll.set_min_content_height(100);
+        tree_scroll.set_min_content_height(scaled(100));
         let bx = box_vertical(&[
             &enabled as &dyn BoxableWidget,
             &box_expand(&tree_scroll),